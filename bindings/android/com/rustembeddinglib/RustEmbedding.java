@@ -0,0 +1,28 @@
+package com.rustembeddinglib;
+
+import java.nio.charset.StandardCharsets;
+
+public final class RustEmbedding {
+    static {
+        System.loadLibrary("rust_embedding_lib");
+    }
+
+    private RustEmbedding() {}
+
+    public static native void initModel(String configPath, String tokenizerPath,
+                                        String weightsPath, boolean approximateGelu);
+
+    public static native void initModelFromBuffers(byte[] config, byte[] tokenizer,
+                                                   byte[] weights, boolean approximateGelu);
+
+    public static native void freeModel();
+
+    public static native int getEmbeddingDim();
+
+    /** Embeds UTF-8 encoded text. */
+    public static native float[] generateEmbeddings(byte[] text);
+
+    public static float[] generateEmbeddings(String text) {
+        return generateEmbeddings(text.getBytes(StandardCharsets.UTF_8));
+    }
+}
@@ -9,8 +9,18 @@ NS_ASSUME_NONNULL_BEGIN
                   weightsPath:(NSString *)weightsPath 
              approximateGelu:(BOOL)approximateGelu;
 
++ (BOOL)freeModel;
+
++ (BOOL)registerModelWithName:(NSString *)name
+                   configPath:(NSString *)configPath
+                tokenizerPath:(NSString *)tokenizerPath
+                  weightsPath:(NSString *)weightsPath
+             approximateGelu:(BOOL)approximateGelu;
+
 + (NSArray<NSNumber *> *)generateEmbeddingsFromText:(NSString *)text;
 
++ (NSArray<NSNumber *> *)generateEmbeddingsFromText:(NSString *)text modelName:(NSString *)name;
+
 @end
 
 NS_ASSUME_NONNULL_END
@@ -7,16 +7,43 @@
                 tokenizerPath:(NSString *)tokenizerPath 
                   weightsPath:(NSString *)weightsPath 
              approximateGelu:(BOOL)approximateGelu {
+    if (get_abi_version() != EMBED_ABI_VERSION) {
+        NSLog(@"Error: rust_embedding_lib %s has ABI version %u, expected %u",
+              get_version(), get_abi_version(), EMBED_ABI_VERSION);
+        return;
+    }
     const char *cConfigPath = [configPath UTF8String];
     const char *cTokenizerPath = [tokenizerPath UTF8String];
     const char *cWeightsPath = [weightsPath UTF8String];
-    init_model(cConfigPath, cTokenizerPath, cWeightsPath, approximateGelu);
+    if (init_model(cConfigPath, cTokenizerPath, cWeightsPath, approximateGelu) != EMBED_OK) {
+        NSLog(@"Error: %s", last_error_message());
+    }
+}
+
++ (BOOL)freeModel {
+    return free_model() == EMBED_OK;
+}
+
++ (BOOL)registerModelWithName:(NSString *)name
+                   configPath:(NSString *)configPath
+                tokenizerPath:(NSString *)tokenizerPath
+                  weightsPath:(NSString *)weightsPath
+             approximateGelu:(BOOL)approximateGelu {
+    return register_model([name UTF8String], [configPath UTF8String], [tokenizerPath UTF8String],
+                          [weightsPath UTF8String], approximateGelu) == EMBED_OK;
 }
 
 + (NSArray<NSNumber *> *)generateEmbeddingsFromText:(NSString *)text {
     const char *cText = [text UTF8String];
-    EmbeddingResult result = generate_embeddings(cText);
+    return [self arrayFromResult:generate_embeddings(cText)];
+}
+
++ (NSArray<NSNumber *> *)generateEmbeddingsFromText:(NSString *)text modelName:(NSString *)name {
+    const char *cText = [text UTF8String];
+    return [self arrayFromResult:generate_embeddings_for([name UTF8String], cText)];
+}
 
++ (NSArray<NSNumber *> *)arrayFromResult:(EmbeddingResult)result {
     if (result.error != NULL) {
         NSString *errorString = [NSString stringWithUTF8String:result.error];
         NSLog(@"Error: %@", errorString);
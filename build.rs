@@ -0,0 +1,18 @@
+fn main() {
+    #[cfg(feature = "node")]
+    napi_build::setup();
+
+    // The model directory compiled in by the `embedded-model` feature, made absolute since
+    // `include_bytes!` resolves relative paths against the source file
+    if std::env::var_os("CARGO_FEATURE_EMBEDDED_MODEL").is_some() {
+        println!("cargo:rerun-if-env-changed=RUST_EMBEDDING_LIB_MODEL_DIR");
+        let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let dir = std::env::var("RUST_EMBEDDING_LIB_MODEL_DIR")
+            .unwrap_or_else(|_| "models/gte-small".to_string());
+        let dir = std::path::Path::new(&manifest_dir).join(dir);
+        for file in ["config.json", "tokenizer.json", "model.safetensors"] {
+            println!("cargo:rerun-if-changed={}", dir.join(file).display());
+        }
+        println!("cargo:rustc-env=EMBEDDED_MODEL_DIR={}", dir.display());
+    }
+}
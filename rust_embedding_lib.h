@@ -4,29 +4,588 @@
 #include <ostream>
 #include <new>
 
+/// A CLAP model (contrastive language-audio pretraining), e.g. `laion/clap-htsat-unfused`: an
+/// audio encoder and a text encoder trained so that a sound and a description of it embed
+/// close together. Embedding voice memos or sound clips with [`AudioEmbedder::embed_audio`]
+/// and queries with [`AudioEmbedder::embed_text`] allows searching audio by what it sounds
+/// like. Both return unit-length vectors, so their dot product is their cosine similarity.
+///
+/// The text side of a CLAP model is its own encoder, so its vectors are only comparable with
+/// this model's audio vectors, not with those of an [`Embedder`](crate::Embedder).
+struct AudioEmbedder;
+
+/// Stops a long-running job from another thread, e.g. on a user's cancel button. Clones share
+/// the same flag; hand one to the job and keep one to call [`CancelToken::cancel`] on.
+///
+/// Jobs check the token between steps, so the step in flight completes first. They return
+/// what completed before the cancellation, which [`CancelToken::is_cancelled`] tells apart
+/// from a complete result. A cancelled token stays cancelled; use a new one for the next job.
+struct CancelToken;
+
+/// An in-memory semantic search index.
+struct Corpus;
+
+/// A principal component analysis fitted on a sample of embeddings, to reduce vectors to the
+/// directions of largest variance: fewer dimensions to store and to compare in a search,
+/// for little loss in ranking quality.
+///
+/// Fit it once on a representative sample, [`save`](Pca::save) it, and
+/// [`transform`](Pca::transform) every vector indexed or queried afterwards with the same
+/// one. It can also run as the last step of a model's post-processing through
+/// [`Transform::Pca`](crate::Transform::Pca).
+struct Pca;
+
+/// A static embedding model in the model2vec layout: one precomputed vector per token, averaged
+/// over the tokens of a text. There is no encoder to run, so embedding takes microseconds
+/// instead of milliseconds, for somewhat lower quality; suited to search-as-you-type and
+/// autocomplete, or as the fast member of a [`SizeRouter`](crate::SizeRouter) or
+/// [`Fallback`](crate::Fallback).
+///
+/// Texts are tokenized without special tokens, unknown tokens are skipped and only the first
+/// 512 tokens count.
+struct StaticEmbedder;
+
 template<typename T = void>
 struct Lazy;
 
 template<typename T = void>
 struct Lazy;
 
+/// Version of the C ABI: the layout of every `#[repr(C)]` struct (`EmbeddingResult`,
+/// `EmbedTimings`, ...), the constants and the function signatures. Existing fields and
+/// arguments are never reordered, resized or removed without bumping it, so a host that finds
+/// `get_abi_version()` differing from the value in the header it was built with must not make
+/// any other call.
+constexpr static const uint32_t EMBED_ABI_VERSION = 1;
+
+/// Status codes returned by the FFI functions; `last_error_message` describes a failure.
+constexpr static const int32_t EMBED_OK = 0;
+
+/// A required pointer argument was null.
+constexpr static const int32_t EMBED_ERR_NULL_POINTER = 1;
+
+/// A string argument was not valid UTF-8 (UTF-16 for the `_utf16` functions).
+constexpr static const int32_t EMBED_ERR_INVALID_UTF8 = 2;
+
+/// No model is loaded, or none is registered under the given name.
+constexpr static const int32_t EMBED_ERR_NO_MODEL = 3;
+
+/// An argument (mode, size, JSON, ...) was out of range or malformed.
+constexpr static const int32_t EMBED_ERR_INVALID_ARGUMENT = 4;
+
+/// Reading or writing a file failed.
+constexpr static const int32_t EMBED_ERR_IO = 5;
+
+/// Loading or running the model failed.
+constexpr static const int32_t EMBED_ERR_MODEL = 6;
+
+/// The library panicked; the call was abandoned instead of unwinding into the host.
+constexpr static const int32_t EMBED_ERR_PANIC = 7;
+
+/// The text had no tokens to embed (empty, whitespace only, ...); see `allow_empty` in the
+/// embed options.
+constexpr static const int32_t EMBED_ERR_EMPTY_INPUT = 8;
+
+/// The operation was cancelled through its `CancelToken`; what completed before is returned.
+constexpr static const int32_t EMBED_ERR_CANCELLED = 9;
+
+/// The call ran past its timeout.
+constexpr static const int32_t EMBED_ERR_TIMEOUT = 10;
+
+/// Pool from the final encoder layer (`n` is ignored).
+constexpr static const uint32_t LAYERS_LAST = 0;
+
+/// Pool from the single hidden state with index `n` (0 is the embedding output).
+constexpr static const uint32_t LAYERS_INDEX = 1;
+
+/// Concatenate the last `n` encoder layers.
+constexpr static const uint32_t LAYERS_CONCAT_LAST = 2;
+
+/// Average the last `n` encoder layers.
+constexpr static const uint32_t LAYERS_MEAN_LAST = 3;
+
+/// Return every window embedding of a document, concatenated.
+constexpr static const uint32_t DOCUMENT_CHUNKS = 0;
+
+/// Return the token-weighted average of a document's window embeddings.
+constexpr static const uint32_t DOCUMENT_MEAN = 1;
+
+/// `dtype` values of a `TypedEmbeddingResult`.
+constexpr static const uint32_t DTYPE_F32 = 0;
+
+constexpr static const uint32_t DTYPE_F16 = 1;
+
+constexpr static const uint32_t DTYPE_F64 = 2;
+
+/// `mode` values of `set_power_mode`.
+constexpr static const uint32_t POWER_PERFORMANCE = 0;
+
+constexpr static const uint32_t POWER_LOW = 1;
+
+/// Low power only while running on battery.
+constexpr static const uint32_t POWER_AUTO = 2;
+
+/// `max_level` values of `set_log_callback`, from least to most verbose.
+constexpr static const uint32_t LOG_ERROR = 1;
+
+constexpr static const uint32_t LOG_WARN = 2;
+
+constexpr static const uint32_t LOG_INFO = 3;
+
+constexpr static const uint32_t LOG_DEBUG = 4;
+
+constexpr static const uint32_t LOG_TRACE = 5;
+
+/// `mode` values of `set_attention`.
+constexpr static const uint32_t ATTENTION_FULL = 0;
+
+/// Queries in blocks of `block_size`, for long inputs.
+constexpr static const uint32_t ATTENTION_CHUNKED = 1;
+
+/// `strategy` values of `set_truncation`.
+constexpr static const uint32_t TRUNCATION_HEAD = 0;
+
+constexpr static const uint32_t TRUNCATION_TAIL = 1;
+
+/// Half the tokens from the beginning of the text, half from the end.
+constexpr static const uint32_t TRUNCATION_HEAD_TAIL = 2;
+
+/// `fusion` values of `corpus_search_hybrid`.
+constexpr static const uint32_t FUSION_RECIPROCAL_RANK = 0;
+
+/// `parameter` times the cosine similarity plus the rest times the BM25 score scaled to the
+/// best one.
+constexpr static const uint32_t FUSION_WEIGHTED = 1;
+
+/// Called once a `reload_model` finishes, with whether the new model was swapped in.
+using ReloadCallback = void(*)(bool success, void *user_data);
+
+/// Returns the preprocessed version of `text` as a NUL-terminated string owned by the host, or
+/// null to leave `text` unchanged.
+using PreprocessCallback = char*(*)(const char *text, void *user_data);
+
+/// Releases a string returned by a `PreprocessCallback` once it has been copied.
+using PreprocessReleaseCallback = void(*)(char *text, void *user_data);
+
+/// Receives one diagnostic: its `LOG_*` level, the module it came from and the formatted
+/// message. Both strings are only valid during the call.
+using LogCallback = void(*)(uint32_t level, const char *target, const char *message, void *user_data);
+
+/// Receives the progress of a batch job, see `set_progress_callback`.
+using ProgressCallback = void(*)(uintptr_t completed, uintptr_t total, void *user_data);
+
+/// Transforms an embedding in place, after any JSON-configured transforms.
+using PostprocessCallback = void(*)(float *embedding, uintptr_t len, void *user_data);
+
+/// On success `embeddings` points to `len` floats and `error` is null, otherwise `error` holds
+/// the message (also available from `last_error_message`). A cancelled call fails with
+/// `EMBED_ERR_CANCELLED` but still holds the embeddings completed before it stopped. The
+/// caller owns both until handing the result to `free_embeddings`, once.
 struct EmbeddingResult {
   const float *embeddings;
   uintptr_t len;
   const char *error;
 };
 
+struct TypedEmbeddingResult {
+  /// `len` elements of the type given by `dtype` (f16 values are raw IEEE half bits).
+  const void *data;
+  uintptr_t len;
+  uint32_t dtype;
+  const char *error;
+};
+
+/// Per-stage durations of one call, in microseconds.
+struct EmbedTimings {
+  uint64_t queue_wait_us;
+  uint64_t tokenize_us;
+  uint64_t forward_us;
+  uint64_t pool_us;
+  uint64_t postprocess_us;
+};
+
+struct SearchMatch {
+  const char *id;
+  /// Cosine similarity between the document and the query.
+  float score;
+};
+
+/// On success `matches` points to `len` matches, best first, and `error` is null. The caller
+/// owns both until handing the result to `free_search_result`, once.
+struct SearchResult {
+  const SearchMatch *matches;
+  uintptr_t len;
+  const char *error;
+};
+
+struct SplitChunk {
+  const char *text;
+  /// Byte offsets of the chunk in the input text.
+  uintptr_t start;
+  uintptr_t end;
+  uintptr_t n_tokens;
+};
+
+struct SplitResult {
+  const SplitChunk *chunks;
+  uintptr_t len;
+  const char *error;
+};
+
+struct TokenIdsResult {
+  const uint32_t *ids;
+  uintptr_t len;
+  const char *error;
+};
+
+struct DecodeResult {
+  const char *text;
+  const char *error;
+};
+
 
 
 extern "C" {
 
-bool init_model(const char *config_path_raw,
-                const char *tokenizer_path_raw,
-                const char *weights_path_raw,
-                bool approximate_gelu);
+const char *last_error_message();
+
+int32_t last_error_code();
+
+const char *get_version();
+
+uint32_t get_abi_version();
+
+int32_t init_model(const char *config_path_raw,
+                   const char *tokenizer_path_raw,
+                   const char *weights_path_raw,
+                   bool approximate_gelu);
+
+char *validate_model(const char *config_path_raw,
+                     const char *tokenizer_path_raw,
+                     const char *weights_path_raw);
+
+char *quantize_weights(const char *weights_path_raw,
+                       const char *output_path_raw,
+                       const char *quant_type_raw);
+
+int32_t init_model_utf16(const uint16_t *config_path,
+                         uintptr_t config_path_len,
+                         const uint16_t *tokenizer_path,
+                         uintptr_t tokenizer_path_len,
+                         const uint16_t *weights_path,
+                         uintptr_t weights_path_len,
+                         bool approximate_gelu);
+
+int32_t init_model_on_device(const char *config_path_raw,
+                             const char *tokenizer_path_raw,
+                             const char *weights_path_raw,
+                             bool approximate_gelu,
+                             const char *device_raw);
+
+int32_t init_model_from_bytes(const char *config_json,
+                              const char *tokenizer_json,
+                              const uint8_t *weights,
+                              uintptr_t weights_len,
+                              bool approximate_gelu);
+
+#if defined(RUST_EMBEDDING_LIB_EMBEDDED_MODEL)
+int32_t init_embedded_model();
+#endif
+
+#if defined(RUST_EMBEDDING_LIB_HUB)
+int32_t init_model_from_hub(const char *repo_raw, bool approximate_gelu);
+#endif
+
+#if defined(RUST_EMBEDDING_LIB_HUB)
+int32_t set_hub_token(const char *token);
+#endif
+
+#if defined(RUST_EMBEDDING_LIB_HUB)
+int32_t set_hub_proxy(const char *proxy);
+#endif
+
+#if defined(RUST_EMBEDDING_LIB_HUB)
+int32_t set_hub_cache_dir(const char *path);
+#endif
+
+#if defined(RUST_EMBEDDING_LIB_HUB)
+int32_t set_hub_offline(bool offline);
+#endif
+
+#if defined(RUST_EMBEDDING_LIB_ORT)
+int32_t init_onnx_model(const char *config_path_raw,
+                        const char *tokenizer_path_raw,
+                        const char *model_path_raw);
+#endif
+
+int32_t reload_model(const char *config_path_raw,
+                     const char *tokenizer_path_raw,
+                     const char *weights_path_raw,
+                     bool approximate_gelu,
+                     ReloadCallback on_complete,
+                     void *user_data);
+
+int32_t register_model(const char *name,
+                       const char *config_path_raw,
+                       const char *tokenizer_path_raw,
+                       const char *weights_path_raw,
+                       bool approximate_gelu);
+
+int32_t register_model_with_adapter(const char *name,
+                                    const char *config_path_raw,
+                                    const char *tokenizer_path_raw,
+                                    const char *weights_path_raw,
+                                    const char *adapter_dir_raw,
+                                    bool approximate_gelu);
+
+int32_t unregister_model(const char *name);
+
+void set_preprocessor(PreprocessCallback process,
+                      PreprocessReleaseCallback release,
+                      void *user_data);
+
+int32_t set_audit_log(const char *config_json);
+
+int32_t set_log_callback(LogCallback callback, uint32_t max_level, void *user_data);
+
+int32_t set_progress_callback(ProgressCallback callback, void *user_data);
+
+int32_t set_postprocessing(const char *name,
+                           const char *transforms_json,
+                           PostprocessCallback callback,
+                           void *user_data);
+
+int32_t set_attention(const char *name, uint32_t mode, uintptr_t block_size);
+
+int32_t set_task_prefixes(const char *name, const char *prefixes_json);
+
+int32_t set_truncation(const char *name, uintptr_t max_length, uint32_t strategy);
+
+int32_t set_text_cleanup(const char *name, const char *cleanup_json);
+
+int32_t set_cache(const char *name, uintptr_t capacity);
+
+char *get_cache_stats(const char *name);
+
+int32_t warmup(const char *name, uintptr_t max_batch, uintptr_t max_tokens);
+
+#if defined(RUST_EMBEDDING_LIB_SQLITE)
+int32_t set_disk_cache(const char *name, const char *path);
+#endif
+
+int32_t free_model();
+
+int32_t set_num_threads(uintptr_t num_threads);
+
+int32_t set_power_mode(uint32_t mode, uintptr_t max_threads);
+
+char *get_system_info();
+
+char *get_model_info();
+
+char *get_memory_usage();
+
+char *run_bench(const char *config_json);
+
+char *evaluate_retrieval(const char *dataset_dir, const char *config_json);
+
+char *get_stats();
+
+void reset_stats();
+
+void free_string(char *text);
 
 EmbeddingResult generate_embeddings(const char *text);
 
+EmbeddingResult generate_embeddings_utf16(const uint16_t *text, uintptr_t len);
+
+int32_t generate_embeddings_into(const char *text, float *buf, uintptr_t buf_len);
+
+int32_t enable_batching(uintptr_t max_batch_size, uint64_t max_wait_us);
+
+int32_t disable_batching();
+
+EmbeddingResult generate_embeddings_for(const char *name, const char *text);
+
+EmbeddingResult generate_embeddings_batch(const char *const *texts,
+                                          uintptr_t count,
+                                          uintptr_t parallelism);
+
+EmbeddingResult generate_embeddings_with_timeout(const char *text, uint64_t timeout_ms);
+
+EmbeddingResult generate_embeddings_batch_with_timeout(const char *const *texts,
+                                                       uintptr_t count,
+                                                       uint64_t timeout_ms);
+
+CancelToken *create_cancel_token();
+
+int32_t cancel(const CancelToken *token);
+
+void free_cancel_token(CancelToken *token);
+
+EmbeddingResult generate_embeddings_batch_cancellable(const char *const *texts,
+                                                      uintptr_t count,
+                                                      const CancelToken *token);
+
+TypedEmbeddingResult generate_embeddings_with_options(const char *text, const char *options_json);
+
+TypedEmbeddingResult generate_embeddings_with_timings(const char *text,
+                                                      const char *options_json,
+                                                      EmbedTimings *timings);
+
+void free_typed_embeddings(TypedEmbeddingResult result);
+
+char *explain_embedding(const char *text, const char *options_json);
+
+EmbeddingResult generate_embeddings_from_layers(const char *text, uint32_t mode, uintptr_t n);
+
+EmbeddingResult generate_document_embeddings(const char *text, uintptr_t overlap, uint32_t mode);
+
+EmbeddingResult generate_pair_embeddings(const char *text_a, const char *text_b);
+
+uintptr_t get_embedding_dim();
+
+float cosine_similarity(const float *a, const float *b, uintptr_t len);
+
+float dot_product(const float *a, const float *b, uintptr_t len);
+
+float euclidean_distance(const float *a, const float *b, uintptr_t len);
+
+EmbeddingResult similarity_matrix(const float *a,
+                                  uintptr_t a_count,
+                                  const float *b,
+                                  uintptr_t b_count,
+                                  uintptr_t dim);
+
+EmbeddingResult centroid(const float *vectors, uintptr_t count, uintptr_t dim);
+
+EmbeddingResult weighted_average(const float *vectors,
+                                 const float *weights,
+                                 uintptr_t count,
+                                 uintptr_t dim);
+
+EmbeddingResult slerp(const float *a, const float *b, uintptr_t len, float t);
+
+Corpus *new_corpus(const char *name);
+
+Corpus *new_hnsw_corpus(const char *name,
+                        uintptr_t m,
+                        uintptr_t ef_construction,
+                        uintptr_t ef_search);
+
+Corpus *load_corpus(const char *path, const char *name);
+
+int32_t corpus_save(const Corpus *corpus, const char *path);
+
+int32_t corpus_add(const Corpus *corpus, const char *id, const char *text);
+
+int32_t corpus_add_with_metadata(const Corpus *corpus,
+                                 const char *id,
+                                 const char *text,
+                                 const char *metadata_json);
+
+int32_t corpus_upsert(const Corpus *corpus,
+                      const char *id,
+                      const char *text,
+                      const char *metadata_json);
+
+int32_t corpus_delete(const Corpus *corpus, const char *id);
+
+int32_t corpus_compact(const Corpus *corpus);
+
+char *corpus_metadata(const Corpus *corpus, const char *id);
+
+SearchResult corpus_search(const Corpus *corpus, const char *query, uintptr_t k);
+
+SearchResult corpus_search_filtered(const Corpus *corpus,
+                                    const char *query,
+                                    uintptr_t k,
+                                    const char *filter_json);
+
+SearchResult corpus_search_lexical(const Corpus *corpus, const char *query, uintptr_t k);
+
+SearchResult corpus_search_hybrid(const Corpus *corpus,
+                                  const char *query,
+                                  uintptr_t k,
+                                  uint32_t fusion,
+                                  float parameter);
+
+uintptr_t corpus_len(const Corpus *corpus);
+
+void free_corpus(Corpus *corpus);
+
+void free_search_result(SearchResult result);
+
+Pca *pca_fit(const float *vectors, uintptr_t count, uintptr_t dim, uintptr_t n_components);
+
+Pca *load_pca(const char *path);
+
+int32_t pca_save(const Pca *pca, const char *path);
+
+uintptr_t pca_output_dim(const Pca *pca);
+
+EmbeddingResult pca_transform(const Pca *pca,
+                              const float *vectors,
+                              uintptr_t count,
+                              uintptr_t dim);
+
+void free_pca(Pca *pca);
+
+StaticEmbedder *load_static_model(const char *config_path,
+                                  const char *tokenizer_path,
+                                  const char *weights_path);
+
+EmbeddingResult static_model_embed(const StaticEmbedder *model, const char *text);
+
+uintptr_t static_model_dim(const StaticEmbedder *model);
+
+void free_static_model(StaticEmbedder *model);
+
+AudioEmbedder *load_audio_model(const char *config_path,
+                                const char *tokenizer_path,
+                                const char *weights_path);
+
+EmbeddingResult audio_model_embed_pcm(const AudioEmbedder *model,
+                                      const float *samples,
+                                      uintptr_t len,
+                                      uintptr_t channels,
+                                      uint32_t sample_rate);
+
+EmbeddingResult audio_model_embed_wav(const AudioEmbedder *model,
+                                      const uint8_t *wav,
+                                      uintptr_t len);
+
+EmbeddingResult audio_model_embed_text(const AudioEmbedder *model, const char *text);
+
+uintptr_t audio_model_dim(const AudioEmbedder *model);
+
+void free_audio_model(AudioEmbedder *model);
+
+SplitResult split_text(const char *text,
+                       uintptr_t max_tokens,
+                       uintptr_t overlap,
+                       bool respect_sentences);
+
+void free_split_result(SplitResult result);
+
+intptr_t count_tokens(const char *text, bool add_special_tokens);
+
+TokenIdsResult encode_text(const char *text, bool add_special_tokens);
+
+DecodeResult decode_tokens(const uint32_t *ids, uintptr_t len, bool skip_special_tokens);
+
+void free_token_ids(TokenIdsResult result);
+
+void free_decode_result(DecodeResult result);
+
+intptr_t run_pipeline(const char *config_json, const char *input_path, const char *output_path);
+
+int32_t run_pipeline_cancellable(const char *config_json,
+                                 const char *input_path,
+                                 const char *output_path,
+                                 const CancelToken *token,
+                                 uintptr_t *written);
+
 void free_embeddings(EmbeddingResult result);
 
 } // extern "C"
@@ -0,0 +1,684 @@
+use crate::clap::{AudioModel, ClapConfig, TextModel};
+use crate::embedder::{load_weights, normalize};
+use crate::error::{Error, Result};
+use crate::provider::EmbeddingProvider;
+use candle::{Device, Tensor};
+use std::f64::consts::PI;
+use std::path::Path;
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+
+// CLAP's feature extractor: 48 kHz audio in windows of 10 s, 1024-point FFTs every 480 samples
+// under a Hann window, and mel bands from 50 Hz to 14 kHz.
+const SAMPLE_RATE: u32 = 48_000;
+const WINDOW_SAMPLES: usize = 10 * SAMPLE_RATE as usize;
+const N_FFT: usize = 1024;
+const HOP_LENGTH: usize = 480;
+const MIN_FREQUENCY: f64 = 50.0;
+const MAX_FREQUENCY: f64 = 14_000.0;
+
+// Windows run through the audio encoder at once.
+const WINDOWS_PER_BATCH: usize = 8;
+
+// Zero crossings of the resampling filter on each side of a sample, at the lower rate.
+const ZERO_CROSSINGS: usize = 16;
+
+/// Mono audio at some sample rate, the input of [`AudioEmbedder`]. Samples are nominally in
+/// `[-1, 1]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Audio {
+    samples: Vec<f32>,
+    sample_rate: u32,
+}
+
+impl Audio {
+    /// Audio from `channels` interleaved channels of float samples, averaged into one.
+    pub fn from_pcm(samples: &[f32], channels: usize, sample_rate: u32) -> Result<Self> {
+        if channels == 0 || sample_rate == 0 || !samples.len().is_multiple_of(channels) {
+            return Err(Error::InvalidArgument(format!(
+                "{} samples do not make frames of {channels} channels at {sample_rate} Hz",
+                samples.len()
+            )));
+        }
+        let samples = match channels {
+            1 => samples.to_vec(),
+            _ => samples
+                .chunks_exact(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+                .collect(),
+        };
+        Ok(Audio {
+            samples,
+            sample_rate,
+        })
+    }
+
+    /// Decode the bytes of a WAV file holding integer PCM of 8, 16, 24 or 32 bits or float PCM
+    /// of 32 or 64 bits, with any number of channels.
+    pub fn from_wav(bytes: &[u8]) -> Result<Self> {
+        let invalid = |reason: &str| Error::InvalidArgument(format!("invalid WAV file: {reason}"));
+        if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err(invalid("no RIFF/WAVE header"));
+        }
+        let u16_at = |bytes: &[u8], i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let u32_at = |bytes: &[u8], i: usize| {
+            u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+        };
+        // Format tag, channels, sample rate and bits per sample
+        let mut format: Option<(u16, usize, u32, usize)> = None;
+        let mut pos = 12;
+        while pos + 8 <= bytes.len() {
+            let size = u32_at(bytes, pos + 4) as usize;
+            // Streamed files may claim more data than they hold
+            let body = &bytes[pos + 8..(pos + 8).saturating_add(size).min(bytes.len())];
+            match &bytes[pos..pos + 4] {
+                b"fmt " => {
+                    if body.len() < 16 {
+                        return Err(invalid("short fmt chunk"));
+                    }
+                    let mut tag = u16_at(body, 0);
+                    // WAVE_FORMAT_EXTENSIBLE names the format in its subformat GUID
+                    if tag == 0xFFFE && body.len() >= 26 {
+                        tag = u16_at(body, 24);
+                    }
+                    format = Some((
+                        tag,
+                        u16_at(body, 2) as usize,
+                        u32_at(body, 4),
+                        u16_at(body, 14) as usize,
+                    ));
+                }
+                b"data" => {
+                    let (tag, channels, sample_rate, bits) =
+                        format.ok_or_else(|| invalid("data before the fmt chunk"))?;
+                    let width = bits / 8;
+                    let samples: Vec<f32> = match (tag, bits) {
+                        (1, 8) => body.iter().map(|&b| (b as f32 - 128.0) / 128.0).collect(),
+                        (1, 16) => body
+                            .chunks_exact(2)
+                            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+                            .collect(),
+                        (1, 24) => body
+                            .chunks_exact(3)
+                            .map(|b| {
+                                (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32
+                                    / 8_388_608.0
+                            })
+                            .collect(),
+                        (1, 32) => body
+                            .chunks_exact(4)
+                            .map(|b| {
+                                i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32
+                                    / 2_147_483_648.0
+                            })
+                            .collect(),
+                        (3, 32) => body
+                            .chunks_exact(4)
+                            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                            .collect(),
+                        (3, 64) => body
+                            .chunks_exact(8)
+                            .map(|b| f64::from_le_bytes(b.try_into().unwrap()) as f32)
+                            .collect(),
+                        _ => {
+                            return Err(invalid(&format!(
+                                "unsupported format {tag} with {bits} bits per sample"
+                            )))
+                        }
+                    };
+                    // A truncated file may end in the middle of a frame
+                    let frames = body.len() / (width * channels.max(1));
+                    return Audio::from_pcm(&samples[..frames * channels], channels, sample_rate);
+                }
+                _ => {}
+            }
+            // Chunks are padded to an even size
+            pos = pos.saturating_add(8 + size + size % 2);
+        }
+        Err(invalid("no data chunk"))
+    }
+
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn duration_secs(&self) -> f64 {
+        self.samples.len() as f64 / self.sample_rate as f64
+    }
+
+    /// The audio at `sample_rate`, through a windowed-sinc filter that also removes
+    /// frequencies above half the new rate when lowering it. Audio that isn't empty keeps at
+    /// least one sample, however short it is.
+    pub fn resample(&self, sample_rate: u32) -> Audio {
+        if sample_rate == self.sample_rate || self.samples.is_empty() {
+            return Audio {
+                samples: self.samples.clone(),
+                sample_rate,
+            };
+        }
+        let ratio = sample_rate as f64 / self.sample_rate as f64;
+        // The filter's cutoff relative to the input's Nyquist frequency
+        let cutoff = ratio.min(1.0);
+        let half_width = (ZERO_CROSSINGS as f64 / cutoff).ceil();
+        let len = self.samples.len() as i64;
+        let out_len = ((self.samples.len() as f64 * ratio).round() as usize).max(1);
+        let samples = (0..out_len)
+            .map(|i| {
+                let t = i as f64 / ratio;
+                let first = ((t - half_width).ceil() as i64).max(0);
+                let last = ((t + half_width).floor() as i64).min(len - 1);
+                let mut sum = 0.0;
+                for j in first..=last {
+                    let x = t - j as f64;
+                    let sinc = match x * cutoff {
+                        0.0 => 1.0,
+                        y => (PI * y).sin() / (PI * y),
+                    };
+                    let window = 0.5 + 0.5 * (PI * x / half_width).cos();
+                    sum += self.samples[j as usize] as f64 * cutoff * sinc * window;
+                }
+                sum as f32
+            })
+            .collect();
+        Audio {
+            samples,
+            sample_rate,
+        }
+    }
+}
+
+/// A CLAP model (contrastive language-audio pretraining), e.g. `laion/clap-htsat-unfused`: an
+/// audio encoder and a text encoder trained so that a sound and a description of it embed
+/// close together. Embedding voice memos or sound clips with [`AudioEmbedder::embed_audio`]
+/// and queries with [`AudioEmbedder::embed_text`] allows searching audio by what it sounds
+/// like. Both return unit-length vectors, so their dot product is their cosine similarity.
+///
+/// The text side of a CLAP model is its own encoder, so its vectors are only comparable with
+/// this model's audio vectors, not with those of an [`Embedder`](crate::Embedder).
+pub struct AudioEmbedder {
+    audio: AudioModel,
+    text: TextModel,
+    tokenizer: Tokenizer,
+    // The slaney-style mel filter bank, `N_FFT / 2 + 1` rows of one weight per mel bin.
+    mel_filters: Vec<f32>,
+    dim: usize,
+    device: Device,
+}
+
+impl AudioEmbedder {
+    /// Load a CLAP model from the `config.json`, `tokenizer.json` and weights of a
+    /// transformers `ClapModel`; the weights are read as [`Embedder::load`](crate::Embedder::load)
+    /// reads them. Checkpoints with feature fusion (`enable_fusion`) are not supported.
+    pub fn load(
+        config_path: impl AsRef<Path>,
+        tokenizer_path: impl AsRef<Path>,
+        weights_path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let config: ClapConfig = serde_json::from_slice(&std::fs::read(config_path)?)?;
+        let device = Device::Cpu;
+        let vb = load_weights(weights_path.as_ref(), &device)?;
+        let audio = AudioModel::load(vb.clone(), &config)?;
+        let text = TextModel::load(vb, &config)?;
+
+        let mut tokenizer = Tokenizer::from_bytes(std::fs::read(tokenizer_path)?)?;
+        let text_config = &config.text_config;
+        let pad_token = tokenizer
+            .id_to_token(text_config.pad_token_id as u32)
+            .ok_or_else(|| {
+                Error::InvalidArgument("the tokenizer has no padding token".to_string())
+            })?;
+        tokenizer.with_padding(Some(PaddingParams {
+            pad_id: text_config.pad_token_id as u32,
+            pad_token,
+            ..PaddingParams::default()
+        }));
+        // Positions start after the padding token's
+        tokenizer.with_truncation(Some(TruncationParams {
+            max_length: text_config.max_position_embeddings - text_config.pad_token_id - 1,
+            ..TruncationParams::default()
+        }))?;
+
+        Ok(AudioEmbedder {
+            mel_filters: mel_filters(audio.num_mel_bins()),
+            audio,
+            text,
+            tokenizer,
+            dim: config.projection_dim,
+            device,
+        })
+    }
+
+    /// Length of the vectors produced.
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Embed `audio`. It is resampled to the model's 48 kHz and seen 10 s at a time: shorter
+    /// audio is repeated to fill the window, and longer audio is covered by consecutive windows,
+    /// the last ending with the audio, whose embeddings are averaged.
+    pub fn embed_audio(&self, audio: &Audio) -> Result<Vec<f32>> {
+        if audio.samples.is_empty() {
+            return Err(Error::EmptyInput);
+        }
+        let samples = audio.resample(SAMPLE_RATE).samples;
+        let bins = self.audio.num_mel_bins();
+        let mut sum = vec![0f32; self.dim];
+        let windows = windows(&samples);
+        for batch in windows.chunks(WINDOWS_PER_BATCH) {
+            let mut features = Vec::new();
+            for window in batch {
+                features.extend(log_mel_spectrogram(window, &self.mel_filters, bins));
+            }
+            let frames = features.len() / (batch.len() * bins);
+            let mel = Tensor::from_vec(features, (batch.len(), frames, bins), &self.device)?;
+            for mut embedding in self.audio.forward(&mel)?.to_vec2::<f32>()? {
+                normalize(&mut embedding);
+                sum.iter_mut()
+                    .zip(&embedding)
+                    .for_each(|(acc, x)| *acc += x);
+            }
+        }
+        normalize(&mut sum);
+        Ok(sum)
+    }
+
+    /// [`AudioEmbedder::embed_audio`] for the bytes of a WAV file (see [`Audio::from_wav`]).
+    pub fn embed_wav(&self, bytes: &[u8]) -> Result<Vec<f32>> {
+        self.embed_audio(&Audio::from_wav(bytes)?)
+    }
+
+    /// Embed `text` with the text encoder, for comparing with audio embeddings.
+    pub fn embed_text(&self, text: &str) -> Result<Vec<f32>> {
+        let mut embeddings = self.embed_texts(&[text])?;
+        Ok(embeddings.remove(0))
+    }
+
+    /// [`AudioEmbedder::embed_text`] for every text, returning the embeddings in input order.
+    pub fn embed_texts<S: AsRef<str>>(&self, texts: &[S]) -> Result<Vec<Vec<f32>>> {
+        let texts: Vec<&str> = texts.iter().map(AsRef::as_ref).collect();
+        if texts.iter().any(|text| text.trim().is_empty()) {
+            return Err(Error::EmptyInput);
+        }
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+        let encodings = self.tokenizer.encode_batch(texts, true)?;
+        let len = encodings[0].get_ids().len();
+        let ids: Vec<u32> = encodings
+            .iter()
+            .flat_map(|e| e.get_ids().to_vec())
+            .collect();
+        let mask: Vec<u32> = encodings
+            .iter()
+            .flat_map(|e| e.get_attention_mask().to_vec())
+            .collect();
+        let ids = Tensor::from_vec(ids, (encodings.len(), len), &self.device)?;
+        let mask = Tensor::from_vec(mask, (encodings.len(), len), &self.device)?;
+        let mut embeddings = self.text.forward(&ids, &mask)?.to_vec2::<f32>()?;
+        embeddings
+            .iter_mut()
+            .for_each(|embedding| normalize(embedding));
+        Ok(embeddings)
+    }
+}
+
+impl EmbeddingProvider for AudioEmbedder {
+    fn name(&self) -> &str {
+        "clap"
+    }
+
+    fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        self.embed_texts(texts)
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        self.embed_text(text)
+    }
+}
+
+// Splits 48 kHz audio into the windows the model sees, as described on `embed_audio`. Short
+// audio is repeated a whole number of times and padded with silence, as CLAP's feature
+// extractor does.
+fn windows(samples: &[f32]) -> Vec<Vec<f32>> {
+    if samples.len() <= WINDOW_SAMPLES {
+        let mut window = samples.repeat(WINDOW_SAMPLES / samples.len());
+        window.resize(WINDOW_SAMPLES, 0.0);
+        return vec![window];
+    }
+    let mut starts: Vec<usize> = (0..=samples.len() - WINDOW_SAMPLES)
+        .step_by(WINDOW_SAMPLES)
+        .collect();
+    if !samples.len().is_multiple_of(WINDOW_SAMPLES) {
+        starts.push(samples.len() - WINDOW_SAMPLES);
+    }
+    starts
+        .into_iter()
+        .map(|start| samples[start..start + WINDOW_SAMPLES].to_vec())
+        .collect()
+}
+
+// Mels on the slaney scale: linear below 1 kHz, logarithmic above.
+fn hertz_to_mel(hertz: f64) -> f64 {
+    let log_step = 27.0 / 6.4f64.ln();
+    match hertz {
+        h if h < 1000.0 => 3.0 * h / 200.0,
+        h => 15.0 + (h / 1000.0).ln() * log_step,
+    }
+}
+
+fn mel_to_hertz(mel: f64) -> f64 {
+    let log_step = 27.0 / 6.4f64.ln();
+    match mel {
+        m if m < 15.0 => 200.0 * m / 3.0,
+        m => 1000.0 * ((m - 15.0) / log_step).exp(),
+    }
+}
+
+// Triangular filters evenly spaced in mels, each scaled to unit area as librosa's slaney
+// normalization does, `N_FFT / 2 + 1` rows of `bins` weights, like transformers'
+// `mel_filter_bank(norm="slaney", mel_scale="slaney")`.
+fn mel_filters(bins: usize) -> Vec<f32> {
+    let (low, high) = (hertz_to_mel(MIN_FREQUENCY), hertz_to_mel(MAX_FREQUENCY));
+    let edges: Vec<f64> = (0..bins + 2)
+        .map(|i| mel_to_hertz(low + (high - low) * i as f64 / (bins + 1) as f64))
+        .collect();
+    let frequencies = N_FFT / 2 + 1;
+    let mut filters = vec![0f32; frequencies * bins];
+    for (f, row) in filters.chunks_exact_mut(bins).enumerate() {
+        let hertz = f as f64 * (SAMPLE_RATE / 2) as f64 / (frequencies - 1) as f64;
+        for (m, weight) in row.iter_mut().enumerate() {
+            let rising = (hertz - edges[m]) / (edges[m + 1] - edges[m]);
+            let falling = (edges[m + 2] - hertz) / (edges[m + 2] - edges[m + 1]);
+            let area = 2.0 / (edges[m + 2] - edges[m]);
+            *weight = (rising.min(falling).max(0.0) * area) as f32;
+        }
+    }
+    filters
+}
+
+// In-place radix-2 FFT of a power-of-two length.
+fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let twiddles: Vec<(f64, f64)> = (0..n / 2)
+        .map(|k| (-2.0 * PI * k as f64 / n as f64).sin_cos())
+        .collect();
+    let mut len = 2;
+    while len <= n {
+        let step = n / len;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = twiddles[k * step];
+                let (a, b) = (start + k, start + k + len / 2);
+                let re_b = re[b] * cos - im[b] * sin;
+                let im_b = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - re_b;
+                im[b] = im[a] - im_b;
+                re[a] += re_b;
+                im[a] += im_b;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+// The power spectrogram of `samples` through `filters` in decibels, `(frames, bins)`: frames
+// are centered on every `HOP_LENGTH`-th sample, the signal reflected at its ends.
+fn log_mel_spectrogram(samples: &[f32], filters: &[f32], bins: usize) -> Vec<f32> {
+    let pad = N_FFT / 2;
+    let len = samples.len();
+    let padded: Vec<f64> = (0..len + 2 * pad)
+        .map(|i| {
+            let source = match i {
+                i if i < pad => pad - i,
+                i if i >= len + pad => 2 * (len - 1) + pad - i,
+                i => i - pad,
+            };
+            samples[source] as f64
+        })
+        .collect();
+    let window: Vec<f64> = (0..N_FFT)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f64 / N_FFT as f64).cos())
+        .collect();
+
+    let frames = 1 + (padded.len() - N_FFT) / HOP_LENGTH;
+    let mut mel = Vec::with_capacity(frames * bins);
+    let (mut re, mut im) = (vec![0.0; N_FFT], vec![0.0; N_FFT]);
+    let mut power = vec![0.0; N_FFT / 2 + 1];
+    for frame in 0..frames {
+        let start = frame * HOP_LENGTH;
+        for (i, (x, w)) in padded[start..start + N_FFT].iter().zip(&window).enumerate() {
+            re[i] = x * w;
+            im[i] = 0.0;
+        }
+        fft(&mut re, &mut im);
+        for (f, p) in power.iter_mut().enumerate() {
+            *p = re[f] * re[f] + im[f] * im[f];
+        }
+        for m in 0..bins {
+            let energy: f64 = power
+                .iter()
+                .enumerate()
+                .map(|(f, p)| p * filters[f * bins + m] as f64)
+                .sum();
+            mel.push((10.0 * energy.max(1e-10).log10()) as f32);
+        }
+    }
+    mel
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle::DType;
+    use candle_nn::{VarBuilder, VarMap};
+
+    fn tone(hertz: f64, sample_rate: u32, secs: f64) -> Vec<f32> {
+        (0..(sample_rate as f64 * secs) as usize)
+            .map(|i| (0.5 * (2.0 * PI * hertz * i as f64 / sample_rate as f64).sin()) as f32)
+            .collect()
+    }
+
+    fn wav(format: u16, channels: u16, sample_rate: u32, bits: u16, data: &[u8]) -> Vec<u8> {
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend((36 + data.len() as u32).to_le_bytes());
+        bytes.extend(b"WAVEfmt ");
+        bytes.extend(16u32.to_le_bytes());
+        bytes.extend(format.to_le_bytes());
+        bytes.extend(channels.to_le_bytes());
+        bytes.extend(sample_rate.to_le_bytes());
+        let block = channels * bits / 8;
+        bytes.extend((sample_rate * block as u32).to_le_bytes());
+        bytes.extend(block.to_le_bytes());
+        bytes.extend(bits.to_le_bytes());
+        // An odd-sized chunk the reader skips
+        bytes.extend(b"LIST");
+        bytes.extend(3u32.to_le_bytes());
+        bytes.extend([1, 2, 3, 0]);
+        bytes.extend(b"data");
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn test_audio_decoding() {
+        let stereo: Vec<u8> = [16384i16, -16384, 32767, 32767]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let audio = Audio::from_wav(&wav(1, 2, 16_000, 16, &stereo)).unwrap();
+        assert_eq!(16_000, audio.sample_rate());
+        assert_eq!(&[0.0, 32767.0 / 32768.0], audio.samples());
+
+        let floats: Vec<u8> = [0.25f32, -1.0]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let audio = Audio::from_wav(&wav(3, 1, 48_000, 32, &floats)).unwrap();
+        assert_eq!(&[0.25, -1.0], audio.samples());
+        let audio =
+            Audio::from_wav(&wav(1, 1, 8_000, 24, &[0, 0, 0x80, 0xff, 0xff, 0x7f])).unwrap();
+        assert_eq!(&[-1.0, 8_388_607.0 / 8_388_608.0], audio.samples());
+        let audio = Audio::from_wav(&wav(1, 1, 8_000, 8, &[0, 128, 255])).unwrap();
+        assert_eq!(&[-1.0, 0.0, 127.0 / 128.0], audio.samples());
+        assert!((audio.duration_secs() - 3.0 / 8_000.0).abs() < 1e-12);
+
+        assert!(Audio::from_wav(&wav(2, 1, 8_000, 4, &[0])).is_err());
+        assert!(Audio::from_wav(b"RIFF\0\0\0\0WAVE").is_err());
+        assert!(Audio::from_wav(b"not a wav file").is_err());
+        assert!(Audio::from_pcm(&[0.0; 3], 2, 16_000).is_err());
+        assert_eq!(
+            &[0.5],
+            Audio::from_pcm(&[1.0, 0.0], 2, 16_000).unwrap().samples()
+        );
+
+        // Resampling keeps the tone and the duration
+        let audio = Audio::from_pcm(&tone(440.0, 16_000, 0.5), 1, 16_000).unwrap();
+        let resampled = audio.resample(SAMPLE_RATE);
+        assert_eq!(24_000, resampled.samples().len());
+        let expected = tone(440.0, SAMPLE_RATE, 0.5);
+        // Away from the edges, where the filter runs out of input
+        for (x, y) in resampled.samples()[1000..23_000]
+            .iter()
+            .zip(&expected[1000..23_000])
+        {
+            assert!((x - y).abs() < 1e-3, "{x} {y}");
+        }
+        let back = resampled.resample(16_000);
+        assert_eq!(audio.samples().len(), back.samples().len());
+    }
+
+    #[test]
+    fn test_log_mel_spectrogram() {
+        assert!((hertz_to_mel(1000.0) - 15.0).abs() < 1e-12);
+        assert!((mel_to_hertz(hertz_to_mel(5000.0)) - 5000.0).abs() < 1e-6);
+
+        let bins = 64;
+        let filters = mel_filters(bins);
+        let window = windows(&tone(1000.0, SAMPLE_RATE, 10.0)).remove(0);
+        let mel = log_mel_spectrogram(&window, &filters, bins);
+        // One frame every 10 ms, the first centered on the first sample
+        assert_eq!(1001 * bins, mel.len());
+        let frame = &mel[500 * bins..501 * bins];
+        let loudest = (0..bins)
+            .max_by(|&a, &b| frame[a].total_cmp(&frame[b]))
+            .unwrap();
+        let low = hertz_to_mel(MIN_FREQUENCY);
+        let step = (hertz_to_mel(MAX_FREQUENCY) - low) / (bins + 1) as f64;
+        let center = mel_to_hertz(low + step * (loudest + 1) as f64);
+        assert!((center - 1000.0).abs() < 100.0, "{center}");
+
+        // Short audio repeats, long audio is windowed to its end
+        let short = windows(&[1.0; 200_000]);
+        assert_eq!(1, short.len());
+        assert_eq!(400_000, short[0].iter().filter(|&&x| x == 1.0).count());
+        let long: Vec<f32> = (0..1_100_000).map(|i| i as f32).collect();
+        let long = windows(&long);
+        assert_eq!(3, long.len());
+        assert_eq!(1_099_999.0, long[2][WINDOW_SAMPLES - 1]);
+        assert_eq!(WINDOW_SAMPLES as f32, long[1][0]);
+    }
+
+    #[test]
+    fn test_audio_embedder() {
+        let dir = std::env::temp_dir().join(format!("clap-model-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // A tiny random CLAP model: two Swin stages, the first with a shifted block
+        let config = r#"{
+            "projection_dim": 8,
+            "text_config": {
+                "vocab_size": 30522, "hidden_size": 16, "num_hidden_layers": 1,
+                "num_attention_heads": 2, "intermediate_size": 32, "hidden_act": "gelu",
+                "hidden_dropout_prob": 0.1, "max_position_embeddings": 130,
+                "type_vocab_size": 1, "initializer_range": 0.02, "layer_norm_eps": 1e-12,
+                "pad_token_id": 0, "model_type": "clap_text_model"
+            },
+            "audio_config": {
+                "num_mel_bins": 16, "spec_size": 128, "depths": [2, 1],
+                "num_attention_heads": [1, 2], "patch_embeds_hidden_size": 8,
+                "hidden_size": 16
+            }
+        }"#;
+        std::fs::write(dir.join("config.json"), config).unwrap();
+        let clap_config: ClapConfig = serde_json::from_str(config).unwrap();
+        let varmap = VarMap::new();
+        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+        AudioModel::load(vb.clone(), &clap_config).unwrap();
+        TextModel::load(vb, &clap_config).unwrap();
+        varmap.save(dir.join("model.safetensors")).unwrap();
+        let model = AudioEmbedder::load(
+            dir.join("config.json"),
+            "models/gte-small/tokenizer.json",
+            dir.join("model.safetensors"),
+        )
+        .unwrap();
+        assert_eq!(8, model.dim());
+
+        let unit = |v: &[f32]| (v.iter().map(|x| x * x).sum::<f32>() - 1.0).abs() < 1e-5;
+        let samples = tone(440.0, 16_000, 2.0);
+        let audio = Audio::from_pcm(&samples, 1, 16_000).unwrap();
+        let embedding = model.embed_audio(&audio).unwrap();
+        assert_eq!(8, embedding.len());
+        assert!(unit(&embedding));
+        // The same audio as stereo 16-bit WAV
+        let data: Vec<u8> = samples
+            .iter()
+            .flat_map(|&s| {
+                let s = (s * 32767.0).round() as i16;
+                [s, s]
+            })
+            .flat_map(i16::to_le_bytes)
+            .collect();
+        let from_wav = model.embed_wav(&wav(1, 2, 16_000, 16, &data)).unwrap();
+        let similarity = crate::similarity::cosine_similarity(&embedding, &from_wav).unwrap();
+        assert!(similarity > 0.999, "{similarity}");
+        let long = Audio::from_pcm(&tone(440.0, 16_000, 25.0), 1, 16_000).unwrap();
+        assert!(unit(&model.embed_audio(&long).unwrap()));
+        assert!(matches!(
+            model.embed_audio(&Audio::from_pcm(&[], 1, 16_000).unwrap()),
+            Err(Error::EmptyInput)
+        ));
+        // Resampled to a quarter of a sample, which still makes one
+        let blip = Audio::from_pcm(&[0.5], 1, 192_000).unwrap();
+        assert_eq!(1, blip.resample(48_000).samples().len());
+        assert!(unit(&model.embed_audio(&blip).unwrap()));
+
+        let texts = model
+            .embed_texts(&["a dog barking", "rain on a window"])
+            .unwrap();
+        assert_eq!(2, texts.len());
+        assert!(texts.iter().all(|text| text.len() == 8 && unit(text)));
+        let single = model.embed_text("rain on a window").unwrap();
+        assert!(single
+            .iter()
+            .zip(&texts[1])
+            .all(|(a, b)| (a - b).abs() < 1e-5));
+        assert!(matches!(model.embed_text(" "), Err(Error::EmptyInput)));
+
+        let fused = config.replace(r#""depths""#, r#""enable_fusion": true, "depths""#);
+        std::fs::write(dir.join("config.json"), fused).unwrap();
+        assert!(AudioEmbedder::load(
+            dir.join("config.json"),
+            "models/gte-small/tokenizer.json",
+            dir.join("model.safetensors"),
+        )
+        .is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,199 @@
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Where an [`AuditLog`] writes and when it rotates.
+///
+/// Serialized as JSON, e.g. `{"path": "embed-audit.jsonl", "max_bytes": 10485760,
+/// "max_files": 5}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditConfig {
+    pub path: PathBuf,
+    /// Rotate once the file would grow past this size; 0 never rotates.
+    #[serde(default)]
+    pub max_bytes: u64,
+    /// Rotated files kept next to `path` as `path.1` (newest) to `path.<max_files>`; older
+    /// ones are deleted. With 0 the log is truncated on rotation.
+    #[serde(default)]
+    pub max_files: usize,
+}
+
+impl AuditConfig {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        AuditConfig {
+            path: path.into(),
+            max_bytes: 0,
+            max_files: 0,
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+/// One line of an [`AuditLog`]. The input itself is never written, only its SHA-256, so a
+/// reported text can be matched against the log without the log holding any content.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    /// `"embed"`, `"embed_batch"` or `"embed_document"`.
+    pub operation: String,
+    pub model: String,
+    /// Hex SHA-256 of the input as the caller passed it, before any preprocessing.
+    pub input_sha256: String,
+    pub latency_us: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller: Option<String>,
+    pub ok: bool,
+}
+
+/// An append-only JSON lines file recording embed operations, shared by every model it is
+/// installed on (see [`Embedder::set_audit_log`](crate::Embedder::set_audit_log)).
+pub struct AuditLog {
+    config: AuditConfig,
+    file: Mutex<(File, u64)>,
+}
+
+impl AuditLog {
+    pub fn open(config: AuditConfig) -> Result<Self> {
+        let file = open_append(&config.path)?;
+        let size = file.metadata()?.len();
+        Ok(AuditLog {
+            config,
+            file: Mutex::new((file, size)),
+        })
+    }
+
+    pub fn config(&self) -> &AuditConfig {
+        &self.config
+    }
+
+    /// Append an entry for an operation on `input`, rotating first if it would not fit.
+    pub fn record(
+        &self,
+        operation: &str,
+        model: &str,
+        input: &str,
+        latency: Duration,
+        caller: Option<&str>,
+        ok: bool,
+    ) -> Result<()> {
+        let entry = AuditEntry {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.as_millis() as u64),
+            operation: operation.to_string(),
+            model: model.to_string(),
+            input_sha256: sha256_hex(input),
+            latency_us: latency.as_micros() as u64,
+            caller: caller.map(str::to_string),
+            ok,
+        };
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+
+        let mut guard = self.file.lock().unwrap();
+        let (file, size) = &mut *guard;
+        let max_bytes = self.config.max_bytes;
+        if max_bytes > 0 && *size > 0 && *size + line.len() as u64 > max_bytes {
+            *file = self.rotate()?;
+            *size = 0;
+        }
+        // One write per line, so concurrent processes appending to the file don't interleave
+        file.write_all(&line)?;
+        *size += line.len() as u64;
+        Ok(())
+    }
+
+    // Shift `path.N` to `path.N+1`, dropping the oldest, and start a fresh file.
+    fn rotate(&self) -> Result<File> {
+        let path = &self.config.path;
+        let max_files = self.config.max_files;
+        if max_files == 0 {
+            return Ok(File::create(path)?);
+        }
+        remove_if_exists(&rotated_path(path, max_files))?;
+        for n in (1..max_files).rev() {
+            let from = rotated_path(path, n);
+            if from.exists() {
+                std::fs::rename(from, rotated_path(path, n + 1))?;
+            }
+        }
+        std::fs::rename(path, rotated_path(path, 1))?;
+        open_append(path)
+    }
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{n}"));
+    PathBuf::from(rotated)
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Error::Io(e)),
+        _ => Ok(()),
+    }
+}
+
+fn sha256_hex(input: &str) -> String {
+    Sha256::digest(input.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_log_rotation() {
+        let dir = std::env::temp_dir().join(format!("audit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+        let config = AuditConfig {
+            path: path.clone(),
+            max_bytes: 400,
+            max_files: 2,
+        };
+        let log = AuditLog::open(config).unwrap();
+
+        for i in 0..10 {
+            let latency = Duration::from_micros(i);
+            log.record("embed", "default", "hello", latency, Some("tests"), true)
+                .unwrap();
+        }
+
+        let current = std::fs::read_to_string(&path).unwrap();
+        let entries: Vec<AuditEntry> = current
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+            entries[0].input_sha256
+        );
+        assert_eq!(Some("tests"), entries[0].caller.as_deref());
+        assert_eq!(Some(9), entries.last().map(|entry| entry.latency_us));
+        assert!(current.len() <= 400);
+        assert!(rotated_path(&path, 1).exists());
+        assert!(rotated_path(&path, 2).exists());
+        assert!(!rotated_path(&path, 3).exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,290 @@
+use crate::embedder::Embedder;
+use crate::error::{Error, Result};
+use crate::options::EmbedOptions;
+use crate::Instant;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// How a [`MicroBatcher`] coalesces requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchConfig {
+    /// Most requests run in one forward pass.
+    pub max_batch_size: usize,
+    /// Longest the first request of a batch waits for others to join it.
+    pub max_wait: Duration,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            max_batch_size: 32,
+            max_wait: Duration::from_millis(5),
+        }
+    }
+}
+
+/// Where the worker takes the model from for each batch, so a swapped model is picked up.
+pub type ModelSource = Box<dyn Fn() -> Option<Arc<Embedder>> + Send>;
+
+/// What a [`MicroBatcher`] has done so far, from [`MicroBatcher::stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatcherStats {
+    /// Requests queued and not yet taken into a batch.
+    pub queue_depth: usize,
+    /// `batch_sizes[n - 1]` batches of `n` requests have been flushed to the model.
+    pub batch_sizes: Vec<u64>,
+}
+
+// Updated by callers and the worker alike
+#[derive(Default)]
+struct Counters {
+    queue_depth: AtomicUsize,
+    batch_sizes: Mutex<Vec<u64>>,
+}
+
+// The embedding of a job and the number of tokens it came from
+type Reply = Result<(Vec<f32>, usize)>;
+
+struct Job {
+    text: String,
+    queued: Instant,
+    reply: mpsc::Sender<Reply>,
+}
+
+/// Coalesces concurrent single-text requests into batched forward passes.
+///
+/// [`MicroBatcher::embed`] enqueues the text and blocks; a worker thread collects requests
+/// until the batch is full or the oldest has waited `max_wait`, embeds them together and
+/// hands every caller its own vector, the same one [`Embedder::embed`] would return.
+pub struct MicroBatcher {
+    queue: Option<mpsc::Sender<Job>>,
+    worker: Option<JoinHandle<()>>,
+    counters: Arc<Counters>,
+}
+
+impl MicroBatcher {
+    pub fn new(embedder: Arc<Embedder>, config: BatchConfig) -> Result<Self> {
+        MicroBatcher::with_source(Box::new(move || Some(Arc::clone(&embedder))), config)
+    }
+
+    pub fn with_source(source: ModelSource, config: BatchConfig) -> Result<Self> {
+        if config.max_batch_size == 0 {
+            return Err(Error::InvalidArgument(
+                "max_batch_size must be at least 1".to_string(),
+            ));
+        }
+        let (queue, jobs) = mpsc::channel();
+        let counters = Arc::new(Counters {
+            queue_depth: AtomicUsize::new(0),
+            batch_sizes: Mutex::new(vec![0; config.max_batch_size]),
+        });
+        let worker_counters = Arc::clone(&counters);
+        let worker = std::thread::Builder::new()
+            .name("embed-batcher".to_string())
+            .spawn(move || run_worker(source, config, jobs, &worker_counters))?;
+        Ok(MicroBatcher {
+            queue: Some(queue),
+            worker: Some(worker),
+            counters,
+        })
+    }
+
+    pub fn stats(&self) -> BatcherStats {
+        BatcherStats {
+            queue_depth: self.counters.queue_depth.load(Ordering::Relaxed),
+            batch_sizes: self.counters.batch_sizes.lock().unwrap().clone(),
+        }
+    }
+
+    pub fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        Ok(self.embed_with_usage(text)?.0)
+    }
+
+    // The embedding of `text` and the number of tokens the model read for it
+    pub(crate) fn embed_with_usage(&self, text: &str) -> Result<(Vec<f32>, usize)> {
+        let result = self.submit(text)?;
+        result.recv().map_err(|_| closed())?
+    }
+
+    /// Queue every text before waiting on any, so they can share batches rather than each
+    /// waiting out `max_wait` in turn. Embeddings come back in input order.
+    pub fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        let results = texts
+            .iter()
+            .map(|text| self.submit(text))
+            .collect::<Result<Vec<_>>>()?;
+        results
+            .into_iter()
+            .map(|result| Ok(result.recv().map_err(|_| closed())??.0))
+            .collect()
+    }
+
+    // Queue `text` for the worker, returning where its embedding will arrive
+    fn submit(&self, text: &str) -> Result<mpsc::Receiver<Reply>> {
+        let (reply, result) = mpsc::channel();
+        let job = Job {
+            text: text.to_string(),
+            queued: Instant::now(),
+            reply,
+        };
+        let queue = self.queue.as_ref().ok_or_else(closed)?;
+        self.counters.queue_depth.fetch_add(1, Ordering::Relaxed);
+        if queue.send(job).is_err() {
+            self.counters.queue_depth.fetch_sub(1, Ordering::Relaxed);
+            return Err(closed());
+        }
+        Ok(result)
+    }
+}
+
+fn closed() -> Error {
+    Error::Batch("the batching worker has stopped".to_string())
+}
+
+impl Drop for MicroBatcher {
+    fn drop(&mut self) {
+        // Closing the queue lets the worker finish the requests already queued and exit.
+        drop(self.queue.take());
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn run_worker(
+    source: ModelSource,
+    config: BatchConfig,
+    jobs: mpsc::Receiver<Job>,
+    counters: &Counters,
+) {
+    while let Ok(first) = jobs.recv() {
+        let deadline = Instant::now() + config.max_wait;
+        let mut batch = vec![first];
+        while batch.len() < config.max_batch_size {
+            match jobs.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(job) => batch.push(job),
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        counters
+            .queue_depth
+            .fetch_sub(batch.len(), Ordering::Relaxed);
+        counters.batch_sizes.lock().unwrap()[batch.len() - 1] += 1;
+
+        match source() {
+            Some(embedder) => run_batch(&embedder, batch),
+            None => {
+                for job in batch {
+                    let _ = job
+                        .reply
+                        .send(Err(Error::Batch("Model not initialized".to_string())));
+                }
+            }
+        }
+    }
+}
+
+fn run_batch(embedder: &Embedder, batch: Vec<Job>) {
+    let options = EmbedOptions::default();
+
+    // A text that fails to tokenize only fails its own request
+    let mut jobs = Vec::with_capacity(batch.len());
+    let mut ids = Vec::with_capacity(batch.len());
+    for job in batch {
+        match embedder.tokenize(&job.text, &options) {
+            Ok(tokens) => {
+                ids.push(tokens);
+                jobs.push(job);
+            }
+            Err(e) => {
+                let result =
+                    embedder.audited("embed", [job.text.as_str()], None, job.queued, Err(e));
+                let _ = job.reply.send(result);
+            }
+        }
+    }
+
+    match embedder.embed_ids_batch(&ids, options.layers, options.pooling) {
+        Ok(embeddings) => {
+            for ((job, embedding), ids) in jobs.into_iter().zip(embeddings).zip(&ids) {
+                let result = embedder
+                    .postprocess(embedding, &options)
+                    .map(|embedding| (embedding.to_f32(), ids.len()));
+                let result =
+                    embedder.audited("embed", [job.text.as_str()], None, job.queued, result);
+                let _ = job.reply.send(result);
+            }
+        }
+        Err(e) => {
+            let message = e.to_string();
+            for job in jobs {
+                let result = Err(Error::Batch(message.clone()));
+                let result =
+                    embedder.audited("embed", [job.text.as_str()], None, job.queued, result);
+                let _ = job.reply.send(result);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_micro_batcher() {
+        let embedder = Arc::new(
+            Embedder::load(
+                "models/gte-small/config.json",
+                "models/gte-small/tokenizer.json",
+                "models/gte-small/model.safetensors",
+                false,
+            )
+            .unwrap(),
+        );
+        let batcher = MicroBatcher::new(
+            Arc::clone(&embedder),
+            BatchConfig {
+                max_batch_size: 4,
+                max_wait: Duration::from_millis(50),
+            },
+        )
+        .unwrap();
+
+        let texts = ["first request", "second", "a third, longer request"];
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = texts
+                .iter()
+                .map(|text| scope.spawn(|| batcher.embed(text).unwrap()))
+                .collect();
+            for (text, handle) in texts.iter().zip(handles) {
+                let expected = embedder.embed(text).unwrap();
+                let batched = handle.join().unwrap();
+                assert!(expected
+                    .iter()
+                    .zip(&batched)
+                    .all(|(a, b)| (a - b).abs() < 1e-4));
+            }
+        });
+
+        let batched = batcher.embed_batch(&texts).unwrap();
+        let stats = batcher.stats();
+        assert_eq!(0, stats.queue_depth);
+        let batched_requests: u64 = (1..)
+            .zip(&stats.batch_sizes)
+            .map(|(size, count)| size * count)
+            .sum();
+        assert_eq!(6, batched_requests);
+        assert_eq!(texts.len(), batched.len());
+        for (text, batched) in texts.iter().zip(&batched) {
+            let expected = embedder.embed(text).unwrap();
+            assert!(expected
+                .iter()
+                .zip(batched)
+                .all(|(a, b)| (a - b).abs() < 1e-4));
+        }
+    }
+}
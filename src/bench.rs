@@ -0,0 +1,183 @@
+use crate::device::DeviceKind;
+use crate::embedder::Embedder;
+use crate::error::{Error, Result};
+use crate::options::{EmbedOptions, OutputDtype};
+use crate::Instant;
+use serde::{Deserialize, Serialize};
+
+/// Settings of a [`bench`] run, which measures every batch size at every sequence length for
+/// every dtype. Serialized as JSON, e.g.
+/// `{"batch_sizes": [1, 32], "seq_lens": [128], "iterations": 20, "dtypes": ["f32", "f16"]}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BenchConfig {
+    pub batch_sizes: Vec<usize>,
+    /// Tokens per input, special tokens included.
+    pub seq_lens: Vec<usize>,
+    /// Timed batches per case, after one untimed warmup batch.
+    pub iterations: usize,
+    pub dtypes: Vec<OutputDtype>,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        BenchConfig {
+            batch_sizes: vec![1, 8, 32],
+            seq_lens: vec![16, 128],
+            iterations: 10,
+            dtypes: vec![OutputDtype::F32],
+        }
+    }
+}
+
+impl BenchConfig {
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+/// Throughput and latency of one case of a [`bench`] run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BenchResult {
+    pub device: DeviceKind,
+    pub dtype: OutputDtype,
+    pub batch_size: usize,
+    pub seq_len: usize,
+    /// Tokens the model saw per second, without any padding the tokenizer adds.
+    pub tokens_per_sec: f64,
+    pub texts_per_sec: f64,
+    /// Latency of a whole batch.
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+// Synthetic inputs repeat a word that is a single token in BERT vocabularies.
+const WORD: &str = "the";
+
+/// Embed synthetic inputs with `embedder` at the sizes `config` lists, to pick batch sizes and
+/// dtypes empirically. Caches and the audit log are bypassed, so every batch runs the model.
+/// Results come in the order dtype, sequence length, batch size.
+pub fn bench(embedder: &Embedder, config: &BenchConfig) -> Result<Vec<BenchResult>> {
+    if config.iterations == 0 || config.batch_sizes.contains(&0) {
+        return Err(Error::InvalidArgument(
+            "benchmarks need at least one iteration and batch sizes of at least 1".to_string(),
+        ));
+    }
+    let mut embedder = embedder.clone();
+    embedder.set_cache(None);
+    #[cfg(feature = "sqlite")]
+    embedder.set_disk_cache(None);
+    embedder.set_audit_log(None, "");
+
+    let mut results = Vec::new();
+    for &dtype in &config.dtypes {
+        let options = EmbedOptions {
+            dtype,
+            ..EmbedOptions::default()
+        };
+        for &seq_len in &config.seq_lens {
+            let text = synthetic_text(&embedder, seq_len)?;
+            let tokens = embedder.count_tokens(&text, true)?;
+            for &batch_size in &config.batch_sizes {
+                let texts = vec![text.as_str(); batch_size];
+                embedder.embed_batch(&texts, &options)?;
+                let mut latencies = Vec::with_capacity(config.iterations);
+                for _ in 0..config.iterations {
+                    let start = Instant::now();
+                    embedder.embed_batch(&texts, &options)?;
+                    latencies.push(start.elapsed().as_secs_f64());
+                }
+                let total: f64 = latencies.iter().sum();
+                let batches = config.iterations as f64;
+                latencies.sort_unstable_by(f64::total_cmp);
+                results.push(BenchResult {
+                    device: embedder.device(),
+                    dtype,
+                    batch_size,
+                    seq_len,
+                    tokens_per_sec: (tokens * batch_size) as f64 * batches / total,
+                    texts_per_sec: batch_size as f64 * batches / total,
+                    p50_ms: percentile(&latencies, 0.5) * 1e3,
+                    p90_ms: percentile(&latencies, 0.9) * 1e3,
+                    p99_ms: percentile(&latencies, 0.99) * 1e3,
+                    max_ms: latencies[latencies.len() - 1] * 1e3,
+                });
+            }
+        }
+    }
+    Ok(results)
+}
+
+// A text of exactly `seq_len` tokens with the special tokens.
+fn synthetic_text(embedder: &Embedder, seq_len: usize) -> Result<String> {
+    let special = embedder.count_tokens("", true)?;
+    let max_len = embedder.config().max_position_embeddings;
+    if seq_len <= special || seq_len > max_len {
+        return Err(Error::InvalidArgument(format!(
+            "sequence lengths must be between {} and {max_len}",
+            special + 1
+        )));
+    }
+    Ok(vec![WORD; seq_len - special].join(" "))
+}
+
+// Nearest-rank percentile of sorted values.
+fn percentile(sorted: &[f64], q: f64) -> f64 {
+    let rank = ((q * sorted.len() as f64).ceil() as usize).max(1);
+    sorted[rank - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench() {
+        let embedder = Embedder::load(
+            "models/gte-small/config.json",
+            "models/gte-small/tokenizer.json",
+            "models/gte-small/model.safetensors",
+            false,
+        )
+        .unwrap();
+        let config = BenchConfig::from_json(
+            r#"{"batch_sizes": [1, 2], "seq_lens": [8], "iterations": 3,
+                "dtypes": ["f32", "f16"]}"#,
+        )
+        .unwrap();
+        let results = bench(&embedder, &config).unwrap();
+        assert_eq!(
+            vec![
+                (OutputDtype::F32, 1),
+                (OutputDtype::F32, 2),
+                (OutputDtype::F16, 1),
+                (OutputDtype::F16, 2)
+            ],
+            results
+                .iter()
+                .map(|r| (r.dtype, r.batch_size))
+                .collect::<Vec<_>>()
+        );
+        for result in &results {
+            assert_eq!(DeviceKind::Cpu, result.device);
+            assert!(result.p50_ms <= result.p90_ms && result.p90_ms <= result.max_ms);
+            assert!((result.tokens_per_sec / result.texts_per_sec - 8.0).abs() < 1e-6);
+        }
+        assert_eq!(
+            8,
+            embedder
+                .count_tokens(&synthetic_text(&embedder, 8).unwrap(), true)
+                .unwrap()
+        );
+
+        let too_short = BenchConfig {
+            seq_lens: vec![2],
+            ..config.clone()
+        };
+        assert!(bench(&embedder, &too_short).is_err());
+        assert!(BenchConfig::from_json(r#"{"batch_size": 4}"#).is_err());
+        assert_eq!(3.0, percentile(&[1.0, 2.0, 3.0, 4.0], 0.75));
+    }
+}
@@ -0,0 +1,664 @@
+// BERT encoder adapted from candle-transformers' `models::bert`, extended so the
+// pooling code can reach the per-layer hidden states.
+use candle::quantized::{QMatMul, QTensor};
+use candle::{DType, Device, Result, Tensor};
+use candle_nn::{embedding, layer_norm, Embedding, LayerNorm, Module, VarBuilder};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+pub const DTYPE: DType = DType::F32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HiddenAct {
+    Gelu,
+    GeluApproximate,
+    Relu,
+}
+
+impl HiddenAct {
+    pub(crate) fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        match self {
+            // https://github.com/huggingface/transformers/blob/cd4584e3c809bb9e1392ccd3fe38b40daba5519a/src/transformers/activations.py#L213
+            HiddenAct::Gelu => xs.gelu_erf(),
+            HiddenAct::GeluApproximate => xs.gelu(),
+            HiddenAct::Relu => xs.relu(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PositionEmbeddingType {
+    #[default]
+    Absolute,
+}
+
+/// How self-attention is computed. Both give the same result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Attention {
+    /// Scores for every query/key pair at once, fastest for the usual sequence lengths.
+    #[default]
+    Full,
+    /// Queries in blocks of `block_size`, so only `block_size * seq_len` scores per head are
+    /// held at a time instead of `seq_len^2`. Makes 2k-8k token inputs fit in memory.
+    Chunked { block_size: usize },
+}
+
+// https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/configuration_bert.py#L1
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Config {
+    pub vocab_size: usize,
+    pub hidden_size: usize,
+    pub num_hidden_layers: usize,
+    pub num_attention_heads: usize,
+    pub intermediate_size: usize,
+    pub hidden_act: HiddenAct,
+    pub hidden_dropout_prob: f64,
+    pub max_position_embeddings: usize,
+    pub type_vocab_size: usize,
+    pub initializer_range: f64,
+    pub layer_norm_eps: f64,
+    pub pad_token_id: usize,
+    #[serde(default)]
+    pub position_embedding_type: PositionEmbeddingType,
+    #[serde(default)]
+    pub use_cache: bool,
+    pub classifier_dropout: Option<f64>,
+    pub model_type: Option<String>,
+}
+
+impl Config {
+    // The name and shape of every tensor `BertModel::load` reads, without a model prefix.
+    pub(crate) fn tensor_shapes(&self) -> Vec<(String, Vec<usize>)> {
+        let hidden = self.hidden_size;
+        let mut shapes = vec![
+            (
+                "embeddings.word_embeddings.weight".to_string(),
+                vec![self.vocab_size, hidden],
+            ),
+            (
+                "embeddings.position_embeddings.weight".to_string(),
+                vec![self.max_position_embeddings, hidden],
+            ),
+            (
+                "embeddings.token_type_embeddings.weight".to_string(),
+                vec![self.type_vocab_size, hidden],
+            ),
+            ("embeddings.LayerNorm.weight".to_string(), vec![hidden]),
+            ("embeddings.LayerNorm.bias".to_string(), vec![hidden]),
+        ];
+        for index in 0..self.num_hidden_layers {
+            // Linear weights are stored as [out, in], layer norms as [hidden]
+            let mut push = |name: &str, weight: Vec<usize>| {
+                let name = format!("encoder.layer.{index}.{name}");
+                shapes.push((format!("{name}.bias"), vec![weight[0]]));
+                shapes.push((format!("{name}.weight"), weight));
+            };
+            for name in ["query", "key", "value"] {
+                push(&format!("attention.self.{name}"), vec![hidden, hidden]);
+            }
+            push("attention.output.dense", vec![hidden, hidden]);
+            push("attention.output.LayerNorm", vec![hidden]);
+            push("intermediate.dense", vec![self.intermediate_size, hidden]);
+            push("output.dense", vec![hidden, self.intermediate_size]);
+            push("output.LayerNorm", vec![hidden]);
+        }
+        shapes
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            vocab_size: 30522,
+            hidden_size: 768,
+            num_hidden_layers: 12,
+            num_attention_heads: 12,
+            intermediate_size: 3072,
+            hidden_act: HiddenAct::Gelu,
+            hidden_dropout_prob: 0.1,
+            max_position_embeddings: 512,
+            type_vocab_size: 2,
+            initializer_range: 0.02,
+            layer_norm_eps: 1e-12,
+            pad_token_id: 0,
+            position_embedding_type: PositionEmbeddingType::Absolute,
+            use_cache: true,
+            classifier_dropout: None,
+            model_type: Some("bert".to_string()),
+        }
+    }
+}
+
+// https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L180
+#[derive(Clone)]
+struct BertEmbeddings {
+    word_embeddings: Embedding,
+    position_embeddings: Embedding,
+    token_type_embeddings: Embedding,
+    layer_norm: LayerNorm,
+    position_offset: usize,
+}
+
+impl BertEmbeddings {
+    fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        let word_embeddings = embedding(
+            config.vocab_size,
+            config.hidden_size,
+            vb.pp("word_embeddings"),
+        )?;
+        let position_embeddings = embedding(
+            config.max_position_embeddings,
+            config.hidden_size,
+            vb.pp("position_embeddings"),
+        )?;
+        let token_type_embeddings = embedding(
+            config.type_vocab_size,
+            config.hidden_size,
+            vb.pp("token_type_embeddings"),
+        )?;
+        let layer_norm = layer_norm(
+            config.hidden_size,
+            config.layer_norm_eps,
+            vb.pp("LayerNorm"),
+        )?;
+        Ok(Self {
+            word_embeddings,
+            position_embeddings,
+            token_type_embeddings,
+            layer_norm,
+            position_offset: 0,
+        })
+    }
+
+    fn forward(&self, input_ids: &Tensor, token_type_ids: &Tensor) -> Result<Tensor> {
+        let (_bsize, seq_len) = input_ids.dims2()?;
+        let input_embeddings = self.word_embeddings.forward(input_ids)?;
+        let token_type_embeddings = self.token_type_embeddings.forward(token_type_ids)?;
+        let embeddings = (&input_embeddings + token_type_embeddings)?;
+        let offset = self.position_offset as u32;
+        let position_ids = (offset..offset + seq_len as u32).collect::<Vec<_>>();
+        let position_ids = Tensor::new(&position_ids[..], input_ids.device())?;
+        let embeddings =
+            embeddings.broadcast_add(&self.position_embeddings.forward(&position_ids)?)?;
+        self.layer_norm.forward(&embeddings)
+    }
+}
+
+/// Quantized weights by their full name, e.g. `encoder.layer.0.attention.self.query.weight`.
+/// The linear layers they belong to run quantized matmuls instead of reading the weight from
+/// the `VarBuilder`.
+pub(crate) type QuantizedWeights = HashMap<String, Arc<QTensor>>;
+
+// A linear layer with a dense or quantized weight, stored as `(out, in)` like torch.
+#[derive(Clone)]
+enum Linear {
+    Dense(candle_nn::Linear),
+    Quantized { weight: QMatMul, bias: Tensor },
+}
+
+impl Linear {
+    fn load(
+        in_size: usize,
+        out_size: usize,
+        vb: VarBuilder,
+        quantized: &QuantizedWeights,
+    ) -> Result<Self> {
+        match quantized.get(&format!("{}.weight", vb.prefix())) {
+            Some(weight) => {
+                if weight.shape().dims() != [out_size, in_size] {
+                    candle::bail!(
+                        "quantized weight {}.weight has shape {:?}, expected {:?}",
+                        vb.prefix(),
+                        weight.shape(),
+                        (out_size, in_size)
+                    );
+                }
+                Ok(Linear::Quantized {
+                    weight: QMatMul::from_arc(Arc::clone(weight))?,
+                    bias: vb.get(out_size, "bias")?,
+                })
+            }
+            None => Ok(Linear::Dense(candle_nn::linear(in_size, out_size, vb)?)),
+        }
+    }
+}
+
+fn tensor_bytes(tensor: &Tensor) -> usize {
+    tensor.elem_count() * tensor.dtype().size_in_bytes()
+}
+
+fn layer_norm_bytes(layer_norm: &LayerNorm) -> usize {
+    tensor_bytes(layer_norm.weight()) + layer_norm.bias().map_or(0, tensor_bytes)
+}
+
+impl Linear {
+    fn weight_bytes(&self) -> usize {
+        match self {
+            Linear::Dense(linear) => {
+                tensor_bytes(linear.weight()) + linear.bias().map_or(0, tensor_bytes)
+            }
+            Linear::Quantized { weight, bias } => {
+                let weight = match weight {
+                    QMatMul::QTensor(weight) => weight.storage_size_in_bytes(),
+                    QMatMul::Tensor(weight) => tensor_bytes(weight),
+                };
+                weight + tensor_bytes(bias)
+            }
+        }
+    }
+}
+
+impl Module for Linear {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        match self {
+            Linear::Dense(linear) => linear.forward(xs),
+            Linear::Quantized { weight, bias } => {
+                weight.forward(&xs.contiguous()?)?.broadcast_add(bias)
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct BertSelfAttention {
+    query: Linear,
+    key: Linear,
+    value: Linear,
+    num_attention_heads: usize,
+    attention_head_size: usize,
+    attention: Attention,
+}
+
+impl BertSelfAttention {
+    fn load(vb: VarBuilder, config: &Config, quantized: &QuantizedWeights) -> Result<Self> {
+        let attention_head_size = config.hidden_size / config.num_attention_heads;
+        let all_head_size = config.num_attention_heads * attention_head_size;
+        let hidden_size = config.hidden_size;
+        let query = Linear::load(hidden_size, all_head_size, vb.pp("query"), quantized)?;
+        let value = Linear::load(hidden_size, all_head_size, vb.pp("value"), quantized)?;
+        let key = Linear::load(hidden_size, all_head_size, vb.pp("key"), quantized)?;
+        Ok(Self {
+            query,
+            key,
+            value,
+            num_attention_heads: config.num_attention_heads,
+            attention_head_size,
+            attention: Attention::Full,
+        })
+    }
+
+    fn transpose_for_scores(&self, xs: &Tensor) -> Result<Tensor> {
+        let mut new_x_shape = xs.dims().to_vec();
+        new_x_shape.pop();
+        new_x_shape.push(self.num_attention_heads);
+        new_x_shape.push(self.attention_head_size);
+        let xs = xs.reshape(new_x_shape.as_slice())?.transpose(1, 2)?;
+        xs.contiguous()
+    }
+
+    // The context vectors, and with `output_attentions` the attention probabilities averaged
+    // over the heads, `(batch, seq_len, seq_len)`.
+    fn forward(
+        &self,
+        hidden_states: &Tensor,
+        attention_bias: Option<&Tensor>,
+        output_attentions: bool,
+    ) -> Result<(Tensor, Option<Tensor>)> {
+        let query_layer = self.query.forward(hidden_states)?;
+        let key_layer = self.key.forward(hidden_states)?;
+        let value_layer = self.value.forward(hidden_states)?;
+
+        let query_layer = self.transpose_for_scores(&query_layer)?;
+        let key_layer = self.transpose_for_scores(&key_layer)?;
+        let value_layer = self.transpose_for_scores(&value_layer)?;
+
+        let key_layer = key_layer.t()?;
+        let seq_len = query_layer.dim(2)?;
+        let (context_layer, attention_probs) = match self.attention {
+            Attention::Chunked { block_size } if block_size < seq_len => {
+                let mut contexts = Vec::new();
+                let mut probs = Vec::new();
+                for start in (0..seq_len).step_by(block_size) {
+                    let query = query_layer.narrow(2, start, block_size.min(seq_len - start))?;
+                    let (context, block_probs) =
+                        self.attend(&query, &key_layer, &value_layer, attention_bias)?;
+                    contexts.push(context);
+                    if output_attentions {
+                        probs.push(block_probs.mean(1)?);
+                    }
+                }
+                let probs = output_attentions
+                    .then(|| Tensor::cat(&probs, 1))
+                    .transpose()?;
+                (Tensor::cat(&contexts, 2)?, probs)
+            }
+            _ => {
+                let (context, probs) =
+                    self.attend(&query_layer, &key_layer, &value_layer, attention_bias)?;
+                (
+                    context,
+                    output_attentions.then(|| probs.mean(1)).transpose()?,
+                )
+            }
+        };
+        let context_layer = context_layer.transpose(1, 2)?.contiguous()?;
+        Ok((
+            context_layer.flatten_from(candle::D::Minus2)?,
+            attention_probs,
+        ))
+    }
+
+    // Softmax attention of `query` over all keys, with the attention probabilities; `key_t` is
+    // already transposed.
+    fn attend(
+        &self,
+        query: &Tensor,
+        key_t: &Tensor,
+        value: &Tensor,
+        attention_bias: Option<&Tensor>,
+    ) -> Result<(Tensor, Tensor)> {
+        let attention_scores = query.matmul(key_t)?;
+        let attention_scores = (attention_scores / (self.attention_head_size as f64).sqrt())?;
+        let attention_scores = match attention_bias {
+            Some(bias) => attention_scores.broadcast_add(bias)?,
+            None => attention_scores,
+        };
+        let attention_probs = candle_nn::ops::softmax(&attention_scores, candle::D::Minus1)?;
+        Ok((attention_probs.matmul(value)?, attention_probs))
+    }
+}
+
+// Shared by the attention output and the feed-forward output: dense + residual + LayerNorm.
+#[derive(Clone)]
+struct BertResidualOutput {
+    dense: Linear,
+    layer_norm: LayerNorm,
+}
+
+impl BertResidualOutput {
+    fn load(
+        vb: VarBuilder,
+        in_size: usize,
+        config: &Config,
+        quantized: &QuantizedWeights,
+    ) -> Result<Self> {
+        let dense = Linear::load(in_size, config.hidden_size, vb.pp("dense"), quantized)?;
+        let layer_norm = layer_norm(
+            config.hidden_size,
+            config.layer_norm_eps,
+            vb.pp("LayerNorm"),
+        )?;
+        Ok(Self { dense, layer_norm })
+    }
+
+    fn forward(&self, hidden_states: &Tensor, input_tensor: &Tensor) -> Result<Tensor> {
+        let hidden_states = self.dense.forward(hidden_states)?;
+        self.layer_norm.forward(&(hidden_states + input_tensor)?)
+    }
+}
+
+// https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L470
+#[derive(Clone)]
+struct BertLayer {
+    self_attention: BertSelfAttention,
+    self_output: BertResidualOutput,
+    intermediate: Linear,
+    intermediate_act: HiddenAct,
+    output: BertResidualOutput,
+}
+
+impl BertLayer {
+    fn load(vb: VarBuilder, config: &Config, quantized: &QuantizedWeights) -> Result<Self> {
+        let self_attention = BertSelfAttention::load(vb.pp("attention.self"), config, quantized)?;
+        let self_output = BertResidualOutput::load(
+            vb.pp("attention.output"),
+            config.hidden_size,
+            config,
+            quantized,
+        )?;
+        let intermediate = Linear::load(
+            config.hidden_size,
+            config.intermediate_size,
+            vb.pp("intermediate.dense"),
+            quantized,
+        )?;
+        let output =
+            BertResidualOutput::load(vb.pp("output"), config.intermediate_size, config, quantized)?;
+        Ok(Self {
+            self_attention,
+            self_output,
+            intermediate,
+            intermediate_act: config.hidden_act,
+            output,
+        })
+    }
+
+    fn forward(
+        &self,
+        hidden_states: &Tensor,
+        attention_bias: Option<&Tensor>,
+        output_attentions: bool,
+    ) -> Result<(Tensor, Option<Tensor>)> {
+        let (self_outputs, attention_probs) =
+            self.self_attention
+                .forward(hidden_states, attention_bias, output_attentions)?;
+        let attention_output = self.self_output.forward(&self_outputs, hidden_states)?;
+        let intermediate_output = self.intermediate.forward(&attention_output)?;
+        let intermediate_output = self.intermediate_act.forward(&intermediate_output)?;
+        let layer_output = self
+            .output
+            .forward(&intermediate_output, &attention_output)?;
+        Ok((layer_output, attention_probs))
+    }
+}
+
+// https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L874
+#[derive(Clone)]
+pub struct BertModel {
+    embeddings: BertEmbeddings,
+    layers: Vec<BertLayer>,
+    hidden_size: usize,
+    intermediate_size: usize,
+    // The estimated activation bytes of the latest forward pass, shared by clones.
+    peak_activation_bytes: Arc<AtomicUsize>,
+    pub device: Device,
+}
+
+impl BertModel {
+    pub fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
+        Self::load_quantized(vb, config, &QuantizedWeights::new())
+    }
+
+    /// [`BertModel::load`] with the linear layers whose weights are in `quantized` running
+    /// quantized matmuls.
+    pub(crate) fn load_quantized(
+        vb: VarBuilder,
+        config: &Config,
+        quantized: &QuantizedWeights,
+    ) -> Result<Self> {
+        match Self::load_with_prefix(vb.clone(), config, quantized) {
+            Ok(model) => Ok(model),
+            Err(err) => match &config.model_type {
+                // Checkpoints exported from a task head nest the encoder under e.g. `bert.`.
+                Some(model_type) => {
+                    Self::load_with_prefix(vb.pp(model_type), config, quantized).map_err(|_| err)
+                }
+                None => Err(err),
+            },
+        }
+    }
+
+    fn load_with_prefix(
+        vb: VarBuilder,
+        config: &Config,
+        quantized: &QuantizedWeights,
+    ) -> Result<Self> {
+        let embeddings = BertEmbeddings::load(vb.pp("embeddings"), config)?;
+        let layers = (0..config.num_hidden_layers)
+            .map(|index| {
+                BertLayer::load(vb.pp(format!("encoder.layer.{index}")), config, quantized)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            embeddings,
+            layers,
+            hidden_size: config.hidden_size,
+            intermediate_size: config.intermediate_size,
+            peak_activation_bytes: Arc::default(),
+            device: vb.device().clone(),
+        })
+    }
+
+    pub fn num_hidden_layers(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Bytes of the weights as loaded, quantized weights at their quantized size.
+    pub fn weight_bytes(&self) -> usize {
+        let embeddings = &self.embeddings;
+        let mut bytes = tensor_bytes(embeddings.word_embeddings.embeddings())
+            + tensor_bytes(embeddings.position_embeddings.embeddings())
+            + tensor_bytes(embeddings.token_type_embeddings.embeddings())
+            + layer_norm_bytes(&embeddings.layer_norm);
+        for layer in &self.layers {
+            let attention = &layer.self_attention;
+            bytes += attention.query.weight_bytes()
+                + attention.key.weight_bytes()
+                + attention.value.weight_bytes()
+                + layer.self_output.dense.weight_bytes()
+                + layer_norm_bytes(&layer.self_output.layer_norm)
+                + layer.intermediate.weight_bytes()
+                + layer.output.dense.weight_bytes()
+                + layer_norm_bytes(&layer.output.layer_norm);
+        }
+        bytes
+    }
+
+    /// Estimated bytes of the activations alive at once at the peak of the latest forward
+    /// pass of this model or a clone of it, 0 before the first.
+    pub fn peak_activation_bytes(&self) -> usize {
+        self.peak_activation_bytes.load(Ordering::Relaxed)
+    }
+
+    // Estimates the peak from the input shape, following the forward pass: within a layer,
+    // either attention holds the layer input, the query, key and value projections before and
+    // after splitting into heads and the scores of one block of queries through scaling,
+    // masking and softmax, or the feed-forward part holds the layer input, the attention
+    // output before and after its LayerNorm and the intermediate activations before and
+    // after the nonlinearity. Hidden states returned to the caller are held on top.
+    fn record_activations(&self, input_ids: &Tensor, all_hidden_states: bool) -> Result<()> {
+        let (batch, seq_len) = input_ids.dims2()?;
+        let hidden = batch * seq_len * self.hidden_size;
+        let layer = match self.layers.first() {
+            Some(layer) => {
+                let attention = &layer.self_attention;
+                let block = match attention.attention {
+                    Attention::Chunked { block_size } => block_size.min(seq_len),
+                    Attention::Full => seq_len,
+                };
+                let scores = batch * attention.num_attention_heads * block * seq_len;
+                let intermediate = batch * seq_len * self.intermediate_size;
+                (7 * hidden + 4 * scores).max(3 * hidden + 2 * intermediate)
+            }
+            None => 0,
+        };
+        let kept = match all_hidden_states {
+            true => self.layers.len() * hidden,
+            false => hidden,
+        };
+        // Plus the attention bias
+        let elements = layer + kept + batch * seq_len;
+        self.peak_activation_bytes
+            .store(elements * DTYPE.size_in_bytes(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Number positions from `offset` instead of 0, as RoBERTa-style models do: their first
+    /// token takes the position after the padding token's, `pad_token_id + 1`.
+    pub(crate) fn set_position_offset(&mut self, offset: usize) {
+        self.embeddings.position_offset = offset;
+    }
+
+    /// Switch every layer to the given attention implementation.
+    pub fn set_attention(&mut self, attention: Attention) {
+        for layer in self.layers.iter_mut() {
+            layer.self_attention.attention = attention;
+        }
+    }
+
+    /// `attention_mask` is `(batch, seq_len)` with 1 for real tokens and 0 for padding; `None`
+    /// attends to every position.
+    pub fn forward(
+        &self,
+        input_ids: &Tensor,
+        token_type_ids: &Tensor,
+        attention_mask: Option<&Tensor>,
+    ) -> Result<Tensor> {
+        let attention_bias = attention_mask.map(attention_bias).transpose()?;
+        let mut hidden_states = self.embeddings.forward(input_ids, token_type_ids)?;
+        for layer in self.layers.iter() {
+            hidden_states = layer
+                .forward(&hidden_states, attention_bias.as_ref(), false)?
+                .0
+        }
+        self.record_activations(input_ids, false)?;
+        Ok(hidden_states)
+    }
+
+    /// Runs the encoder and returns the final hidden states with the attention probabilities
+    /// of every layer averaged over its heads, `(batch, seq_len, seq_len)` with a row per
+    /// query, first layer first.
+    pub fn forward_attentions(
+        &self,
+        input_ids: &Tensor,
+        token_type_ids: &Tensor,
+        attention_mask: Option<&Tensor>,
+    ) -> Result<(Tensor, Vec<Tensor>)> {
+        let attention_bias = attention_mask.map(attention_bias).transpose()?;
+        let mut hidden_states = self.embeddings.forward(input_ids, token_type_ids)?;
+        let mut attentions = Vec::with_capacity(self.layers.len());
+        for layer in self.layers.iter() {
+            let (next, attention_probs) =
+                layer.forward(&hidden_states, attention_bias.as_ref(), true)?;
+            hidden_states = next;
+            attentions.extend(attention_probs);
+        }
+        self.record_activations(input_ids, false)?;
+        Ok((hidden_states, attentions))
+    }
+
+    /// Runs the encoder and returns every hidden state, following the transformers
+    /// convention: index 0 is the embedding output, index `i` the output of layer `i`.
+    pub fn forward_hidden_states(
+        &self,
+        input_ids: &Tensor,
+        token_type_ids: &Tensor,
+        attention_mask: Option<&Tensor>,
+    ) -> Result<Vec<Tensor>> {
+        let attention_bias = attention_mask.map(attention_bias).transpose()?;
+        let mut hidden_states = Vec::with_capacity(self.layers.len() + 1);
+        hidden_states.push(self.embeddings.forward(input_ids, token_type_ids)?);
+        for layer in self.layers.iter() {
+            let (next, _) = layer.forward(
+                &hidden_states[hidden_states.len() - 1],
+                attention_bias.as_ref(),
+                false,
+            )?;
+            hidden_states.push(next);
+        }
+        self.record_activations(input_ids, true)?;
+        Ok(hidden_states)
+    }
+}
+
+// Turns a `(batch, seq_len)` 0/1 mask into a `(batch, 1, 1, seq_len)` bias added to the attention
+// scores, so padded positions get no weight after the softmax.
+fn attention_bias(attention_mask: &Tensor) -> Result<Tensor> {
+    let (batch, seq_len) = attention_mask.dims2()?;
+    let mask = attention_mask.to_dtype(DTYPE)?;
+    ((1.0 - mask)? * f32::MIN as f64)?.reshape((batch, 1, 1, seq_len))
+}
@@ -0,0 +1,740 @@
+use clap::{Parser, ValueEnum};
+use rust_embedding_lib::{
+    bench, quantize_model, read_csv_documents, read_jsonl_documents, read_text_documents,
+    write_npy, write_npz, BenchConfig, CsvColumns, DeviceKind, DevicePool, Document, EmbedOptions,
+    Embedder, Error, JsonlSink, OutputDtype, Pipeline, PipelineConfig, Pooling, QuantType, Result,
+};
+#[cfg(feature = "arrow")]
+use rust_embedding_lib::{embedding_record_batch, embedding_schema, ParquetWriter};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+/// Embed every line or JSONL document of a file and write the vectors as JSONL, CSV, NumPy
+/// arrays or (with the `arrow` feature) Arrow and Parquet tables.
+#[derive(Debug, Parser)]
+#[command(
+    name = "rust-embed",
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    embed: Args,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Measure throughput and latency on synthetic inputs.
+    Bench(BenchArgs),
+    /// Write a copy of a model with quantized weights, loaded like any other weights file.
+    Quantize(QuantizeArgs),
+    /// Run documents through an ingestion pipeline described by a JSON config and write the
+    /// records as JSON lines.
+    Pipeline(PipelineArgs),
+    /// Serve models over HTTP with the OpenAI embeddings API (`POST /v1/embeddings`).
+    #[cfg(feature = "server")]
+    Serve(ServeArgs),
+    /// Serve models to other processes over a Unix socket or Windows named pipe.
+    #[cfg(feature = "ipc")]
+    Listen(ListenArgs),
+}
+
+#[derive(Debug, clap::Args)]
+struct BenchArgs {
+    /// Model directory, as for embedding.
+    #[arg(short, long)]
+    model: PathBuf,
+    /// Use the tanh approximation of GELU.
+    #[arg(long)]
+    approximate_gelu: bool,
+    /// Devices to measure, e.g. `cpu,cuda:0` or `auto`.
+    #[arg(long, value_delimiter = ',', default_value = "cpu")]
+    devices: Vec<DeviceKind>,
+    #[arg(short, long, value_delimiter = ',', default_value = "1,8,32")]
+    batch_sizes: Vec<usize>,
+    /// Tokens per input, special tokens included.
+    #[arg(short, long, value_delimiter = ',', default_value = "16,128")]
+    seq_lens: Vec<usize>,
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "f32")]
+    dtypes: Vec<DtypeArg>,
+    /// Timed batches per case, after one warmup batch.
+    #[arg(short, long, default_value_t = 10)]
+    iterations: usize,
+}
+
+#[derive(Debug, clap::Args)]
+struct QuantizeArgs {
+    /// Model directory, as for embedding.
+    #[arg(short, long)]
+    model: PathBuf,
+    /// The GGUF file to write, e.g. `model.gguf` in a directory with the model's config and
+    /// tokenizer.
+    #[arg(short, long)]
+    output: PathBuf,
+    /// `q8_0` or `q4_0`.
+    #[arg(long = "type", default_value = "q8_0")]
+    quant_type: QuantType,
+}
+
+#[derive(Debug, clap::Args)]
+struct PipelineArgs {
+    /// Model directory, as for embedding.
+    #[arg(short, long)]
+    model: PathBuf,
+    /// Use the tanh approximation of GELU.
+    #[arg(long)]
+    approximate_gelu: bool,
+    /// The pipeline as JSON, e.g. `{"chunk": {"max_tokens": 256}, "embed": {"normalize": true}}`.
+    #[arg(short, long)]
+    config: PathBuf,
+    /// Documents to ingest, `-` for stdin.
+    #[arg(short, long, default_value = "-")]
+    input: PathBuf,
+    #[arg(short, long, value_enum, default_value_t = DocumentFormatArg::Jsonl)]
+    format: DocumentFormatArg,
+    /// Output file, stdout if omitted.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[cfg(feature = "server")]
+#[derive(Debug, clap::Args)]
+struct ServeArgs {
+    /// A model directory to serve, as `NAME=DIR` or `DIR` (named after the directory).
+    /// Repeat for several models; the first one answers requests that name none.
+    #[arg(short, long = "model", required = true)]
+    models: Vec<String>,
+    /// Use the tanh approximation of GELU.
+    #[arg(long)]
+    approximate_gelu: bool,
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    addr: String,
+    /// Coalesce concurrent single-input requests into batches of up to this many texts.
+    #[arg(long)]
+    max_batch_size: Option<usize>,
+    /// Longest a request waits for others to join its batch.
+    #[arg(long, default_value_t = 5)]
+    max_wait_ms: u64,
+    /// Warm up at inputs of up to this many tokens before reporting ready.
+    #[arg(long, default_value_t = 128)]
+    warmup_tokens: usize,
+}
+
+#[cfg(feature = "ipc")]
+#[derive(Debug, clap::Args)]
+struct ListenArgs {
+    /// Socket path, or pipe name such as `\\.\pipe\rust-embed` on Windows.
+    path: String,
+    /// A model directory to serve, as `NAME=DIR` or `DIR` (named after the directory).
+    /// Repeat for several models; the first one answers requests that name none.
+    #[arg(short, long = "model", required = true)]
+    models: Vec<String>,
+    /// Use the tanh approximation of GELU.
+    #[arg(long)]
+    approximate_gelu: bool,
+}
+
+#[derive(Debug, clap::Args)]
+struct Args {
+    /// Model directory with config.json, tokenizer.json and model.safetensors (or
+    /// model.safetensors.index.json and its shards, or pytorch_model.bin).
+    // Optional only so subcommands can be given without it
+    #[arg(short, long, required = true)]
+    model: Option<PathBuf>,
+    /// Use the tanh approximation of GELU.
+    #[arg(long)]
+    approximate_gelu: bool,
+    /// Input file, `-` for stdin.
+    #[arg(default_value = "-")]
+    input: PathBuf,
+    #[arg(long, value_enum, default_value_t = InputFormat::Lines)]
+    input_format: InputFormat,
+    /// Output file, stdout if omitted.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Jsonl)]
+    format: OutputFormat,
+    /// Texts embedded per forward pass.
+    #[arg(short, long, default_value_t = 32)]
+    batch_size: usize,
+    /// Run a replica of the model on each of these devices, e.g. `cuda:0,cuda:1`, `cpu,cpu`
+    /// for two CPU workers or `auto` for the best one available, and deal batches out to them.
+    #[arg(long, value_delimiter = ',')]
+    devices: Vec<DeviceKind>,
+    /// Overrides the pooling of `--options`.
+    #[arg(long, value_enum)]
+    pooling: Option<PoolingArg>,
+    /// Scale every vector to unit length.
+    #[arg(long)]
+    normalize: bool,
+    /// Embed options as JSON, e.g. `{"prefix": "passage: ", "max_length": 256}`.
+    #[arg(long)]
+    options: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum InputFormat {
+    /// One text per non-empty line, with its line number as the id.
+    Lines,
+    /// `{"id": "...", "text": "..."}` per line.
+    Jsonl,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DocumentFormatArg {
+    /// `{"id": "...", "text": "...", "metadata": {...}}` per line.
+    Jsonl,
+    /// A header row with the columns `id` and `text`.
+    Csv,
+    /// Plain text, one document per paragraph.
+    Text,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// `{"id": "...", "embedding": [...]}` per line.
+    Jsonl,
+    /// A header row `id,0,1,...` and one row per text.
+    Csv,
+    /// A NumPy `float32` array of shape `(texts, dim)`, without the ids.
+    Npy,
+    /// A NumPy archive with the arrays `embeddings` and `ids`.
+    Npz,
+    /// An Arrow IPC file with the columns `id`, `text` and `embedding`.
+    #[cfg(feature = "arrow")]
+    Arrow,
+    /// A Parquet file with the columns `id`, `text` and `embedding`.
+    #[cfg(feature = "arrow")]
+    Parquet,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum PoolingArg {
+    Mean,
+    Cls,
+    Max,
+}
+
+impl From<PoolingArg> for Pooling {
+    fn from(pooling: PoolingArg) -> Self {
+        match pooling {
+            PoolingArg::Mean => Pooling::Mean,
+            PoolingArg::Cls => Pooling::Cls,
+            PoolingArg::Max => Pooling::Max,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DtypeArg {
+    F32,
+    F16,
+    F64,
+}
+
+impl From<DtypeArg> for OutputDtype {
+    fn from(dtype: DtypeArg) -> Self {
+        match dtype {
+            DtypeArg::F32 => OutputDtype::F32,
+            DtypeArg::F16 => OutputDtype::F16,
+            DtypeArg::F64 => OutputDtype::F64,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Bench(args)) => return exit_code(run_bench(args)),
+        Some(Command::Quantize(args)) => return exit_code(quantize(args)),
+        Some(Command::Pipeline(args)) => return exit_code(run_pipeline(args)),
+        #[cfg(feature = "server")]
+        Some(Command::Serve(args)) => return exit_code(serve(args)),
+        #[cfg(feature = "ipc")]
+        Some(Command::Listen(args)) => return exit_code(listen(args)),
+        None => {}
+    }
+    exit_code(run(cli.embed))
+}
+
+fn exit_code(result: Result<()>) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("rust-embed: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<()> {
+    let mut options = match &args.options {
+        Some(json) => EmbedOptions::from_json(json)?,
+        None => EmbedOptions::default(),
+    };
+    if let Some(pooling) = args.pooling {
+        options.pooling = pooling.into();
+    }
+    options.normalize |= args.normalize;
+    if args.batch_size == 0 {
+        return Err(Error::InvalidArgument(
+            "--batch-size must be at least 1".to_string(),
+        ));
+    }
+
+    let model = args.model.as_deref().expect("--model is required");
+    let pool = match args.devices.as_slice() {
+        [] => DevicePool::new(vec![load(model, args.approximate_gelu)?])?,
+        devices => DevicePool::load(
+            model.join("config.json"),
+            model.join("tokenizer.json"),
+            weights(model),
+            args.approximate_gelu,
+            devices,
+        )?,
+    }
+    .with_shard_size(args.batch_size)?;
+
+    let input: Box<dyn BufRead> = if args.input == Path::new("-") {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(&args.input)?))
+    };
+    let documents: Box<dyn Iterator<Item = Result<Document>>> = match args.input_format {
+        InputFormat::Lines => Box::new(read_lines(input)),
+        InputFormat::Jsonl => Box::new(read_jsonl_documents(input)),
+    };
+    let output: Box<dyn Write + Send> = match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let mut writer = VectorWriter::new(BufWriter::new(output), args.format);
+
+    // A forward pass for every replica at a time
+    let batch_size = args.batch_size * pool.replicas().len();
+    let mut batch = Vec::with_capacity(batch_size);
+    for document in documents {
+        batch.push(document?);
+        if batch.len() == batch_size {
+            embed_batch(&pool, &options, &mut batch, &mut writer)?;
+        }
+    }
+    embed_batch(&pool, &options, &mut batch, &mut writer)?;
+    writer.finish()
+}
+
+fn run_bench(args: BenchArgs) -> Result<()> {
+    let config = BenchConfig {
+        batch_sizes: args.batch_sizes,
+        seq_lens: args.seq_lens,
+        iterations: args.iterations,
+        dtypes: args.dtypes.into_iter().map(Into::into).collect(),
+    };
+    println!(
+        "{:<8} {:<5} {:>6} {:>7} {:>12} {:>10} {:>9} {:>9} {:>9}",
+        "device", "dtype", "batch", "seq_len", "tokens/s", "texts/s", "p50 ms", "p90 ms", "p99 ms"
+    );
+    for &device in &args.devices {
+        let embedder = Embedder::load_on_device(
+            args.model.join("config.json"),
+            args.model.join("tokenizer.json"),
+            weights(&args.model),
+            args.approximate_gelu,
+            device,
+        )?;
+        for result in bench(&embedder, &config)? {
+            println!(
+                "{:<8} {:<5} {:>6} {:>7} {:>12.0} {:>10.1} {:>9.2} {:>9.2} {:>9.2}",
+                result.device.to_string(),
+                format!("{:?}", result.dtype).to_lowercase(),
+                result.batch_size,
+                result.seq_len,
+                result.tokens_per_sec,
+                result.texts_per_sec,
+                result.p50_ms,
+                result.p90_ms,
+                result.p99_ms
+            );
+        }
+    }
+    Ok(())
+}
+
+fn quantize(args: QuantizeArgs) -> Result<()> {
+    let report = quantize_model(weights(&args.model), &args.output, args.quant_type)?;
+    eprintln!(
+        "quantized {} of {} tensors to {}: {:.1} MB -> {:.1} MB",
+        report.quantized,
+        report.tensors,
+        report.quant_type,
+        report.original_bytes as f64 / 1e6,
+        report.quantized_bytes as f64 / 1e6
+    );
+    Ok(())
+}
+
+fn run_pipeline(args: PipelineArgs) -> Result<()> {
+    let config = PipelineConfig::from_json(&std::fs::read_to_string(&args.config)?)?;
+    let embedder = load(&args.model, args.approximate_gelu)?;
+    let pipeline = Pipeline::new(&embedder, config)?;
+
+    let input: Box<dyn BufRead> = if args.input == Path::new("-") {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(&args.input)?))
+    };
+    let documents: Box<dyn Iterator<Item = Result<Document>>> = match args.format {
+        DocumentFormatArg::Jsonl => Box::new(read_jsonl_documents(input)),
+        DocumentFormatArg::Csv => {
+            Box::new(read_csv_documents(input, &CsvColumns::new("id", "text"))?)
+        }
+        DocumentFormatArg::Text => Box::new(read_text_documents(input)),
+    };
+    let output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+    let written = pipeline.run(documents, &mut JsonlSink(BufWriter::new(output)))?;
+    eprintln!("rust-embed: wrote {written} records");
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+fn serve(args: ServeArgs) -> Result<()> {
+    use rust_embedding_lib::{BatchConfig, EmbeddingServer, ServerConfig};
+    use std::time::Duration;
+
+    let batch = args.max_batch_size.map(|max_batch_size| BatchConfig {
+        max_batch_size,
+        max_wait: Duration::from_millis(args.max_wait_ms),
+    });
+    let mut server = EmbeddingServer::new(ServerConfig {
+        batch,
+        warmup_tokens: args.warmup_tokens,
+    });
+    for (name, embedder) in load_named(&args.models, args.approximate_gelu)? {
+        server.add_model(name, embedder)?;
+    }
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind(&args.addr).await?;
+        eprintln!("rust-embed: listening on http://{}", listener.local_addr()?);
+        server.serve(listener).await
+    })
+}
+
+#[cfg(feature = "ipc")]
+fn listen(args: ListenArgs) -> Result<()> {
+    let mut server = rust_embedding_lib::IpcServer::new();
+    for (name, embedder) in load_named(&args.models, args.approximate_gelu)? {
+        server.add_model(name, embedder);
+    }
+    eprintln!("rust-embed: listening on {}", args.path);
+    #[cfg(unix)]
+    return server.serve_unix(&args.path);
+    #[cfg(windows)]
+    return server.serve_pipe(&args.path);
+}
+
+// Models given as `NAME=DIR` or `DIR`, in order
+#[cfg(any(feature = "server", feature = "ipc"))]
+fn load_named(
+    models: &[String],
+    approximate_gelu: bool,
+) -> Result<Vec<(String, std::sync::Arc<Embedder>)>> {
+    models
+        .iter()
+        .map(|model| {
+            let (name, dir) = match model.split_once('=') {
+                Some((name, dir)) => (name.to_string(), PathBuf::from(dir)),
+                None => {
+                    let dir = PathBuf::from(model);
+                    let name = dir.file_name().unwrap_or(dir.as_os_str());
+                    (name.to_string_lossy().into_owned(), dir)
+                }
+            };
+            Ok((name, std::sync::Arc::new(load(&dir, approximate_gelu)?)))
+        })
+        .collect()
+}
+
+fn load(dir: &Path, approximate_gelu: bool) -> Result<Embedder> {
+    Embedder::load(
+        dir.join("config.json"),
+        dir.join("tokenizer.json"),
+        weights(dir),
+        approximate_gelu,
+    )
+}
+
+fn weights(dir: &Path) -> PathBuf {
+    [
+        "model.safetensors",
+        "model.safetensors.index.json",
+        "pytorch_model.bin",
+        "model.gguf",
+    ]
+    .iter()
+    .map(|name| dir.join(name))
+    .find(|path| path.exists())
+    .unwrap_or_else(|| dir.join("model.safetensors"))
+}
+
+fn read_lines(input: impl BufRead) -> impl Iterator<Item = Result<Document>> {
+    input
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(index, line)| {
+            Ok(Document {
+                id: (index + 1).to_string(),
+                text: line?,
+                metadata: Default::default(),
+            })
+        })
+}
+
+fn embed_batch(
+    pool: &DevicePool,
+    options: &EmbedOptions,
+    batch: &mut Vec<Document>,
+    writer: &mut VectorWriter<impl Write + Send>,
+) -> Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let texts: Vec<&str> = batch
+        .iter()
+        .map(|document| document.text.as_str())
+        .collect();
+    let embeddings = pool.embed_batch(&texts, options)?;
+    for (document, embedding) in batch.iter().zip(embeddings) {
+        writer.write(&document.id, &document.text, &embedding.to_f32())?;
+    }
+    batch.clear();
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct JsonlRow<'a> {
+    id: &'a str,
+    embedding: &'a [f32],
+}
+
+// Rows per Arrow record batch, which is also a Parquet row group
+#[cfg(feature = "arrow")]
+const TABLE_ROWS: usize = 8192;
+
+struct VectorWriter<W: Write + Send> {
+    // Taken by the table writer once the dimension is known
+    out: Option<W>,
+    format: OutputFormat,
+    header_written: bool,
+    // NumPy arrays are written in one go by `finish`, tables every `TABLE_ROWS` rows
+    dim: Option<usize>,
+    vectors: Vec<f32>,
+    ids: Vec<String>,
+    texts: Vec<String>,
+    #[cfg(feature = "arrow")]
+    table: Option<TableWriter<W>>,
+}
+
+impl<W: Write + Send> VectorWriter<W> {
+    fn new(out: W, format: OutputFormat) -> Self {
+        VectorWriter {
+            out: Some(out),
+            format,
+            header_written: false,
+            dim: None,
+            vectors: Vec::new(),
+            ids: Vec::new(),
+            texts: Vec::new(),
+            #[cfg(feature = "arrow")]
+            table: None,
+        }
+    }
+
+    fn write(&mut self, id: &str, text: &str, embedding: &[f32]) -> Result<()> {
+        match self.format {
+            OutputFormat::Jsonl => {
+                let out = self.out.as_mut().unwrap();
+                serde_json::to_writer(&mut *out, &JsonlRow { id, embedding })?;
+                out.write_all(b"\n")?;
+            }
+            OutputFormat::Csv => {
+                let mut csv = csv::WriterBuilder::new().from_writer(self.out.as_mut().unwrap());
+                if !self.header_written {
+                    let header = (0..embedding.len()).map(|i| i.to_string());
+                    csv.write_record(std::iter::once("id".to_string()).chain(header))?;
+                    self.header_written = true;
+                }
+                let values = embedding.iter().map(|x| x.to_string());
+                csv.write_record(std::iter::once(id.to_string()).chain(values))?;
+                csv.flush()?;
+            }
+            OutputFormat::Npy | OutputFormat::Npz => self.buffer(id, None, embedding)?,
+            #[cfg(feature = "arrow")]
+            OutputFormat::Arrow | OutputFormat::Parquet => {
+                self.buffer(id, Some(text), embedding)?;
+                if self.ids.len() == TABLE_ROWS {
+                    self.write_table()?;
+                }
+            }
+        }
+        #[cfg(not(feature = "arrow"))]
+        let _ = text;
+        Ok(())
+    }
+
+    fn buffer(&mut self, id: &str, text: Option<&str>, embedding: &[f32]) -> Result<()> {
+        let dim = *self.dim.get_or_insert(embedding.len());
+        if embedding.len() != dim {
+            return Err(Error::InvalidArgument(format!(
+                "embedding of dimension {} in an array of dimension {dim}",
+                embedding.len()
+            )));
+        }
+        self.vectors.extend_from_slice(embedding);
+        self.ids.push(id.to_string());
+        self.texts.extend(text.map(str::to_string));
+        Ok(())
+    }
+
+    // Write the buffered rows as a record batch, starting the file if needed.
+    #[cfg(feature = "arrow")]
+    fn write_table(&mut self) -> Result<()> {
+        let dim = self.dim.unwrap_or(0);
+        if self.table.is_none() {
+            let out = self.out.take().unwrap();
+            self.table = Some(TableWriter::new(out, self.format, dim)?);
+        }
+        let batch = embedding_record_batch(&self.ids, &self.texts, dim, &self.vectors)?;
+        self.table.as_mut().unwrap().write(&batch)?;
+        self.ids.clear();
+        self.texts.clear();
+        self.vectors.clear();
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        let dim = self.dim.unwrap_or(0);
+        match self.format {
+            OutputFormat::Jsonl | OutputFormat::Csv => {}
+            OutputFormat::Npy => write_npy(self.out.as_mut().unwrap(), dim, &self.vectors)?,
+            OutputFormat::Npz => {
+                let out = self.out.as_mut().unwrap();
+                write_npz(out, dim, &self.vectors, Some(&self.ids))?
+            }
+            #[cfg(feature = "arrow")]
+            OutputFormat::Arrow | OutputFormat::Parquet => {
+                if !self.ids.is_empty() || self.table.is_none() {
+                    self.write_table()?;
+                }
+                self.out = Some(self.table.take().unwrap().finish()?);
+            }
+        }
+        Ok(self.out.unwrap().flush()?)
+    }
+}
+
+#[cfg(feature = "arrow")]
+enum TableWriter<W: Write + Send> {
+    Arrow(arrow_ipc::writer::FileWriter<W>),
+    Parquet(ParquetWriter<W>),
+}
+
+#[cfg(feature = "arrow")]
+impl<W: Write + Send> TableWriter<W> {
+    fn new(out: W, format: OutputFormat, dim: usize) -> Result<Self> {
+        Ok(match format {
+            OutputFormat::Parquet => TableWriter::Parquet(ParquetWriter::new(out, dim)?),
+            _ => {
+                let schema = embedding_schema(dim)?;
+                TableWriter::Arrow(arrow_ipc::writer::FileWriter::try_new(out, &schema)?)
+            }
+        })
+    }
+
+    fn write(&mut self, batch: &arrow_array::RecordBatch) -> Result<()> {
+        match self {
+            TableWriter::Arrow(writer) => Ok(writer.write(batch)?),
+            TableWriter::Parquet(writer) => writer.write(batch),
+        }
+    }
+
+    fn finish(self) -> Result<W> {
+        match self {
+            TableWriter::Arrow(mut writer) => {
+                writer.finish()?;
+                Ok(writer.into_inner()?)
+            }
+            TableWriter::Parquet(writer) => writer.finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_lines_and_write_vectors() {
+        let documents: Vec<Document> = read_lines("first\n\n  \nsecond\n".as_bytes())
+            .collect::<Result<_>>()
+            .unwrap();
+        let ids: Vec<&str> = documents.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(vec!["1", "4"], ids);
+        assert_eq!("second", documents[1].text);
+
+        let mut out = Vec::new();
+        let mut writer = VectorWriter::new(&mut out, OutputFormat::Csv);
+        writer.write("a,b", "", &[0.5, -1.0]).unwrap();
+        writer.write("c", "", &[0.0, 2.0]).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(
+            "id,0,1\n\"a,b\",0.5,-1\nc,0,2\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        let mut out = Vec::new();
+        let mut writer = VectorWriter::new(&mut out, OutputFormat::Jsonl);
+        writer.write("a", "", &[0.5]).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(
+            "{\"id\":\"a\",\"embedding\":[0.5]}\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        let mut out = Vec::new();
+        let mut writer = VectorWriter::new(&mut out, OutputFormat::Npy);
+        writer.write("a", "", &[0.5, 1.0]).unwrap();
+        assert!(writer.write("b", "", &[0.5]).is_err());
+        writer.finish().unwrap();
+        assert!(out.starts_with(b"\x93NUMPY"));
+        assert_eq!(
+            1.0,
+            f32::from_le_bytes(out[out.len() - 4..].try_into().unwrap())
+        );
+
+        #[cfg(feature = "arrow")]
+        for (format, magic) in [
+            (OutputFormat::Arrow, &b"ARROW1"[..]),
+            (OutputFormat::Parquet, b"PAR1"),
+        ] {
+            let mut out = Vec::new();
+            let mut writer = VectorWriter::new(&mut out, format);
+            writer.write("a", "first", &[0.5, 1.0]).unwrap();
+            writer.finish().unwrap();
+            assert!(out.starts_with(magic) && out.ends_with(magic));
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use crate::error::{Error, Result};
+use crate::hnsw::read_u32;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+// The usual Okapi BM25 parameters: how quickly repeated terms saturate, and how much longer
+// documents are penalized.
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+/// How [`Corpus::search_hybrid`](crate::Corpus::search_hybrid) combines the dense and the
+/// lexical (BM25) rankings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fusion {
+    /// Reciprocal rank fusion: a document scores `1 / (k + rank)` in each ranking it appears
+    /// in. Needs no tuning, since it ignores how the two kinds of scores are scaled; 60 is the
+    /// usual `k`.
+    ReciprocalRank { k: f32 },
+    /// `dense_weight * cosine + (1 - dense_weight) * bm25`, with the BM25 scores divided by
+    /// the best one so both lie in about the same range.
+    Weighted { dense_weight: f32 },
+}
+
+impl Default for Fusion {
+    fn default() -> Self {
+        Fusion::ReciprocalRank { k: 60.0 }
+    }
+}
+
+impl Fusion {
+    pub(crate) fn validate(&self) -> Result<()> {
+        let valid = match *self {
+            Fusion::ReciprocalRank { k } => k >= 0.0,
+            Fusion::Weighted { dense_weight } => (0.0..=1.0).contains(&dense_weight),
+        };
+        if !valid {
+            return Err(Error::InvalidArgument(format!("invalid fusion {self:?}")));
+        }
+        Ok(())
+    }
+
+    /// Merge rankings of `(slot, score)`, best first, into scores by slot.
+    pub(crate) fn fuse(
+        &self,
+        dense: &[(usize, f32)],
+        lexical: &[(usize, f32)],
+    ) -> Vec<(usize, f32)> {
+        let mut scores: HashMap<usize, f32> = HashMap::new();
+        match *self {
+            Fusion::ReciprocalRank { k } => {
+                for ranking in [dense, lexical] {
+                    for (rank, &(slot, _)) in ranking.iter().enumerate() {
+                        *scores.entry(slot).or_default() += 1.0 / (k + rank as f32 + 1.0);
+                    }
+                }
+            }
+            Fusion::Weighted { dense_weight } => {
+                for &(slot, score) in dense {
+                    *scores.entry(slot).or_default() += dense_weight * score;
+                }
+                let best = lexical.first().map_or(0.0, |&(_, score)| score);
+                for &(slot, score) in lexical.iter().filter(|_| best > 0.0) {
+                    *scores.entry(slot).or_default() += (1.0 - dense_weight) * score / best;
+                }
+            }
+        }
+        scores.into_iter().collect()
+    }
+}
+
+/// The words of `text` with how often each occurs, sorted: lowercased runs of alphanumeric
+/// characters, without stemming or stop words.
+pub(crate) fn term_counts(text: &str) -> Vec<(String, u32)> {
+    let mut counts: HashMap<String, u32> = HashMap::new();
+    for word in text.split(|c: char| !c.is_alphanumeric()) {
+        if !word.is_empty() {
+            *counts.entry(word.to_lowercase()).or_default() += 1;
+        }
+    }
+    let mut counts: Vec<(String, u32)> = counts.into_iter().collect();
+    counts.sort_unstable();
+    counts
+}
+
+// An inverted index over the documents of a corpus, by slot. Removed slots keep their postings,
+// like the corpus's tombstones, but no longer count towards the statistics.
+#[derive(Default)]
+pub(crate) struct Bm25 {
+    terms: HashMap<String, u32>,
+    words: Vec<String>,
+    // The slots containing each term.
+    postings: Vec<Vec<u32>>,
+    // Live documents containing each term.
+    doc_freq: Vec<u32>,
+    // Each slot's terms and their counts, by term.
+    docs: Vec<Vec<(u32, u32)>>,
+    lengths: Vec<u32>,
+    removed: Vec<bool>,
+    live: usize,
+    total_length: u64,
+}
+
+impl Bm25 {
+    /// Index the next slot, which contains `counts` from [`term_counts`].
+    pub(crate) fn push(&mut self, counts: &[(String, u32)]) {
+        let slot = self.docs.len() as u32;
+        let mut doc: Vec<(u32, u32)> = counts
+            .iter()
+            .map(|(word, count)| {
+                let term = match self.terms.get(word) {
+                    Some(&term) => term,
+                    None => {
+                        let term = self.words.len() as u32;
+                        self.terms.insert(word.clone(), term);
+                        self.words.push(word.clone());
+                        self.postings.push(Vec::new());
+                        self.doc_freq.push(0);
+                        term
+                    }
+                };
+                self.postings[term as usize].push(slot);
+                self.doc_freq[term as usize] += 1;
+                (term, *count)
+            })
+            .collect();
+        doc.sort_unstable();
+        let length = counts.iter().map(|(_, count)| count).sum();
+        self.docs.push(doc);
+        self.lengths.push(length);
+        self.removed.push(false);
+        self.live += 1;
+        self.total_length += u64::from(length);
+    }
+
+    pub(crate) fn remove(&mut self, slot: usize) {
+        if std::mem::replace(&mut self.removed[slot], true) {
+            return;
+        }
+        for &(term, _) in &self.docs[slot] {
+            self.doc_freq[term as usize] -= 1;
+        }
+        self.live -= 1;
+        self.total_length -= u64::from(self.lengths[slot]);
+    }
+
+    /// The terms of `slot` as given to [`Bm25::push`].
+    pub(crate) fn counts(&self, slot: usize) -> Vec<(String, u32)> {
+        self.docs[slot]
+            .iter()
+            .map(|&(term, count)| (self.words[term as usize].clone(), count))
+            .collect()
+    }
+
+    /// Up to `k` slots for which `accept` holds with their BM25 score for `query`, best first.
+    /// Slots without any of the query's terms are left out.
+    pub(crate) fn search(
+        &self,
+        query: &str,
+        k: usize,
+        accept: &dyn Fn(usize) -> bool,
+    ) -> Vec<(usize, f32)> {
+        if self.live == 0 {
+            return Vec::new();
+        }
+        let average_length = self.total_length as f32 / self.live as f32;
+        let mut scores: HashMap<usize, f32> = HashMap::new();
+        for (word, _) in term_counts(query) {
+            let Some(&term) = self.terms.get(&word) else {
+                continue;
+            };
+            let doc_freq = self.doc_freq[term as usize] as f32;
+            let idf = (1.0 + (self.live as f32 - doc_freq + 0.5) / (doc_freq + 0.5)).ln();
+            for &slot in &self.postings[term as usize] {
+                let slot = slot as usize;
+                if self.removed[slot] || !accept(slot) {
+                    continue;
+                }
+                let doc = &self.docs[slot];
+                let count = match doc.binary_search_by_key(&term, |&(term, _)| term) {
+                    Ok(i) => doc[i].1 as f32,
+                    Err(_) => continue,
+                };
+                let length = self.lengths[slot] as f32 / average_length.max(f32::MIN_POSITIVE);
+                let saturation = count * (K1 + 1.0) / (count + K1 * (1.0 - B + B * length));
+                *scores.entry(slot).or_default() += idf * saturation;
+            }
+        }
+        let mut scores: Vec<(usize, f32)> = scores.into_iter().collect();
+        scores.sort_unstable_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        scores.truncate(k);
+        scores
+    }
+
+    /// Write the vocabulary, then every slot's terms, for [`Bm25::read`].
+    pub(crate) fn write(&self, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(&(self.words.len() as u32).to_le_bytes())?;
+        for word in &self.words {
+            writer.write_all(&(word.len() as u32).to_le_bytes())?;
+            writer.write_all(word.as_bytes())?;
+        }
+        for doc in &self.docs {
+            writer.write_all(&(doc.len() as u32).to_le_bytes())?;
+            for &(term, count) in doc {
+                writer.write_all(&term.to_le_bytes())?;
+                writer.write_all(&count.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read an index of `slots` slots written by [`Bm25::write`], removing the `removed` ones.
+    pub(crate) fn read(reader: &mut impl Read, removed: &[bool], file_len: u64) -> Result<Self> {
+        let corrupt =
+            || Error::InvalidArgument("not a saved corpus: bad lexical index".to_string());
+        let vocabulary = read_u32(reader)? as u64;
+        if vocabulary > file_len {
+            return Err(corrupt());
+        }
+        let words = (0..vocabulary)
+            .map(|_| {
+                let len = read_u32(reader)? as u64;
+                if len > file_len {
+                    return Err(corrupt());
+                }
+                let mut bytes = vec![0; len as usize];
+                reader.read_exact(&mut bytes)?;
+                String::from_utf8(bytes).map_err(|_| corrupt())
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut bm25 = Bm25::default();
+        for &removed in removed {
+            let len = read_u32(reader)? as u64;
+            if len > file_len {
+                return Err(corrupt());
+            }
+            let counts = (0..len)
+                .map(|_| {
+                    let term = read_u32(reader)? as usize;
+                    let count = read_u32(reader)?;
+                    let word = words.get(term).ok_or_else(corrupt)?;
+                    Ok((word.clone(), count))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            bm25.push(&counts);
+            if removed {
+                bm25.remove(bm25.docs.len() - 1);
+            }
+        }
+        Ok(bm25)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bm25() {
+        let texts = [
+            "The cat sat on the mat.",
+            "Dogs and cats: a guide to pets",
+            "Stock markets fell sharply; the market closed lower",
+            "cat cat cat",
+        ];
+        let mut bm25 = Bm25::default();
+        for text in texts {
+            bm25.push(&term_counts(text));
+        }
+        assert_eq!(
+            vec![("cat".to_string(), 1), ("mat".to_string(), 1)],
+            term_counts("Mat, cat!")
+        );
+
+        // Repeats count, but saturate; rarer terms weigh more
+        let hits = bm25.search("cat", 10, &|_| true);
+        assert_eq!(vec![3, 0], hits.iter().map(|hit| hit.0).collect::<Vec<_>>());
+        let hits = bm25.search("the market", 10, &|_| true);
+        assert_eq!(2, hits[0].0);
+        assert!(bm25.search("unknown words", 10, &|_| true).is_empty());
+        assert_eq!(
+            vec![0],
+            bm25.search("cat", 10, &|slot| slot != 3)
+                .iter()
+                .map(|hit| hit.0)
+                .collect::<Vec<_>>()
+        );
+
+        bm25.remove(3);
+        assert_eq!(0, bm25.search("cat", 10, &|_| true)[0].0);
+        let mut saved = Vec::new();
+        bm25.write(&mut saved).unwrap();
+        let removed = [false, false, false, true];
+        let loaded = Bm25::read(&mut saved.as_slice(), &removed, saved.len() as u64).unwrap();
+        assert_eq!(
+            bm25.search("the cat market", 10, &|_| true),
+            loaded.search("the cat market", 10, &|_| true)
+        );
+        assert_eq!(term_counts("cat cat cat"), loaded.counts(3));
+
+        let dense = [(0, 0.9), (1, 0.8)];
+        let lexical = [(1, 4.0), (2, 2.0)];
+        let mut fused = Fusion::default().fuse(&dense, &lexical);
+        fused.sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
+        assert_eq!(1, fused[0].0);
+        let fused = Fusion::Weighted { dense_weight: 0.5 }.fuse(&dense, &lexical);
+        let score = |slot| fused.iter().find(|hit| hit.0 == slot).unwrap().1;
+        assert!((score(1) - 0.9).abs() < 1e-6 && (score(2) - 0.25).abs() < 1e-6);
+        assert!(Fusion::Weighted { dense_weight: 2.0 }.validate().is_err());
+    }
+}
@@ -0,0 +1,177 @@
+use crate::options::{EmbedOptions, Embedding, OutputDtype};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Counters of an [`EmbeddingCache`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub capacity: usize,
+    /// Approximate memory held by the entries, keys and bookkeeping included.
+    pub bytes: usize,
+}
+
+pub(crate) type CacheKey = [u8; 32];
+
+// Entries with the tick of their last use; `order` maps ticks back to keys so the least
+// recently used entry is the first one.
+#[derive(Default)]
+struct Lru {
+    entries: HashMap<CacheKey, (Embedding, u64)>,
+    order: BTreeMap<u64, CacheKey>,
+    tick: u64,
+    bytes: usize,
+}
+
+// The memory an entry takes: its embedding, and its key in both maps with its tick.
+fn entry_bytes(embedding: &Embedding) -> usize {
+    let value_size = match embedding.dtype() {
+        OutputDtype::F16 => 2,
+        OutputDtype::F32 => 4,
+        OutputDtype::F64 => 8,
+    };
+    embedding.len() * value_size + 2 * (std::mem::size_of::<CacheKey>() + 8)
+}
+
+/// An in-process least-recently-used cache of embeddings, installed on a model with
+/// [`Embedder::set_cache`](crate::Embedder::set_cache).
+///
+/// Entries are keyed by a SHA-256 of the input text and the [`EmbedOptions`] that affect the
+/// result, so an identical input skips inference entirely.
+pub struct EmbeddingCache {
+    capacity: usize,
+    lru: Mutex<Lru>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl EmbeddingCache {
+    /// A cache holding up to `capacity` embeddings; `0` caches nothing.
+    pub fn new(capacity: usize) -> Self {
+        EmbeddingCache {
+            capacity,
+            lru: Mutex::new(Lru::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let lru = self.lru.lock().unwrap();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: lru.entries.len(),
+            capacity: self.capacity,
+            bytes: lru.bytes,
+        }
+    }
+
+    /// Drop every entry, keeping the counters.
+    pub fn clear(&self) {
+        let mut lru = self.lru.lock().unwrap();
+        lru.entries.clear();
+        lru.order.clear();
+        lru.bytes = 0;
+    }
+
+    pub(crate) fn get(&self, key: &CacheKey) -> Option<Embedding> {
+        let mut guard = self.lru.lock().unwrap();
+        let lru = &mut *guard;
+        lru.tick += 1;
+        let tick = lru.tick;
+        let Some((embedding, used)) = lru.entries.get_mut(key) else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        let previous = std::mem::replace(used, tick);
+        let embedding = embedding.clone();
+        lru.order.remove(&previous);
+        lru.order.insert(tick, *key);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(embedding)
+    }
+
+    pub(crate) fn insert(&self, key: CacheKey, embedding: Embedding) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut guard = self.lru.lock().unwrap();
+        let lru = &mut *guard;
+        lru.tick += 1;
+        let tick = lru.tick;
+        lru.bytes += entry_bytes(&embedding);
+        if let Some((previous, used)) = lru.entries.insert(key, (embedding, tick)) {
+            lru.bytes -= entry_bytes(&previous);
+            lru.order.remove(&used);
+        }
+        lru.order.insert(tick, key);
+        while lru.entries.len() > self.capacity {
+            let (_, oldest) = lru.order.pop_first().unwrap();
+            if let Some((evicted, _)) = lru.entries.remove(&oldest) {
+                lru.bytes -= entry_bytes(&evicted);
+            }
+        }
+    }
+}
+
+/// The key of `text` embedded with `options`: every option but the audit log's `caller`
+/// changes the result.
+pub(crate) fn cache_key(text: &str, options: &EmbedOptions) -> CacheKey {
+    let options = EmbedOptions {
+        caller: None,
+        ..options.clone()
+    };
+    Sha256::new()
+        .chain_update(format!("{options:?}"))
+        .chain_update([0])
+        .chain_update(text)
+        .finalize()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_eviction() {
+        let cache = EmbeddingCache::new(2);
+        let options = EmbedOptions::default();
+        let key = |text| cache_key(text, &options);
+        let embedding = |x| Embedding::F32(vec![x]);
+
+        assert_eq!(None, cache.get(&key("a")));
+        cache.insert(key("a"), embedding(1.0));
+        cache.insert(key("b"), embedding(2.0));
+        assert_eq!(Some(embedding(1.0)), cache.get(&key("a")));
+        // "b" is the least recently used, so it makes room for "c"
+        cache.insert(key("c"), embedding(3.0));
+        assert_eq!(None, cache.get(&key("b")));
+        assert_eq!(Some(embedding(3.0)), cache.get(&key("c")));
+
+        let stats = cache.stats();
+        assert_eq!(
+            (2, 2, 2, 2),
+            (stats.hits, stats.misses, stats.entries, stats.capacity)
+        );
+        assert_eq!(2 * entry_bytes(&embedding(0.0)), stats.bytes);
+        cache.clear();
+        assert_eq!(0, cache.stats().bytes);
+        let caller = EmbedOptions {
+            caller: Some("tests".to_string()),
+            ..EmbedOptions::default()
+        };
+        assert_eq!(key("a"), cache_key("a", &caller));
+        let normalized = EmbedOptions {
+            normalize: true,
+            ..EmbedOptions::default()
+        };
+        assert_ne!(key("a"), cache_key("a", &normalized));
+    }
+}
@@ -0,0 +1,44 @@
+use crate::Instant;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Stops a long-running job from another thread, e.g. on a user's cancel button. Clones share
+/// the same flag; hand one to the job and keep one to call [`CancelToken::cancel`] on.
+///
+/// Jobs check the token between steps, so the step in flight completes first. They return
+/// what completed before the cancellation, which [`CancelToken::is_cancelled`] tells apart
+/// from a complete result. A cancelled token stays cancelled; use a new one for the next job.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    /// A token that also cancels itself once `timeout` has passed.
+    pub fn with_timeout(timeout: Duration) -> Self {
+        CancelToken {
+            deadline: Some(Instant::now() + timeout),
+            ..CancelToken::default()
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed) || self.is_timed_out()
+    }
+
+    /// Whether the token's timeout has passed, as opposed to it being cancelled.
+    pub fn is_timed_out(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
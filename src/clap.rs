@@ -0,0 +1,739 @@
+// CLAP audio and text towers adapted from transformers' `models::clap`: an HTSAT audio encoder,
+// a Swin transformer over log-mel spectrograms, and a RoBERTa text encoder, each followed by a
+// projection into the space the two share.
+use crate::bert::{self, BertModel, HiddenAct};
+use candle::{Device, Module, Result, Tensor, D};
+use candle_nn::{
+    conv2d, layer_norm, linear, linear_no_bias, Conv2d, Conv2dConfig, Init, LayerNorm, Linear,
+    VarBuilder,
+};
+use serde::Deserialize;
+
+// https://github.com/huggingface/transformers/blob/5f4ecf2d9f867a1255131d2461d75793c0cf1db2/src/transformers/models/clap/configuration_clap.py#L168
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub(crate) struct AudioConfig {
+    pub window_size: usize,
+    pub num_mel_bins: usize,
+    pub spec_size: usize,
+    pub hidden_act: HiddenAct,
+    pub patch_size: usize,
+    pub patch_stride: [usize; 2],
+    pub hidden_size: usize,
+    pub depths: Vec<usize>,
+    pub num_attention_heads: Vec<usize>,
+    pub enable_fusion: bool,
+    pub patch_embed_input_channels: usize,
+    pub patch_embeds_hidden_size: usize,
+    pub enable_patch_layer_norm: bool,
+    pub qkv_bias: bool,
+    pub mlp_ratio: f64,
+    pub layer_norm_eps: f64,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            window_size: 8,
+            num_mel_bins: 64,
+            spec_size: 256,
+            hidden_act: HiddenAct::Gelu,
+            patch_size: 4,
+            patch_stride: [4, 4],
+            hidden_size: 768,
+            depths: vec![2, 2, 6, 2],
+            num_attention_heads: vec![4, 8, 16, 32],
+            enable_fusion: false,
+            patch_embed_input_channels: 1,
+            patch_embeds_hidden_size: 96,
+            enable_patch_layer_norm: true,
+            qkv_bias: true,
+            mlp_ratio: 4.0,
+            layer_norm_eps: 1e-5,
+        }
+    }
+}
+
+impl AudioConfig {
+    // The spectrogram is folded into a square image `freq_ratio` bands of mel bins high.
+    fn freq_ratio(&self) -> usize {
+        self.spec_size / self.num_mel_bins
+    }
+
+    // Frames of the spectrogram the image holds; shorter spectrograms are stretched to it.
+    pub(crate) fn spec_width(&self) -> usize {
+        self.spec_size * self.freq_ratio()
+    }
+
+    // Patches along each side of the image at the first stage.
+    fn grid_size(&self) -> usize {
+        self.spec_size / self.patch_size
+    }
+
+    // The layouts the encoder below supports, which every released CLAP checkpoint uses.
+    fn check(&self) -> Result<()> {
+        let stages = self.depths.len();
+        if self.enable_fusion {
+            candle::bail!("CLAP checkpoints with feature fusion are not supported")
+        }
+        if stages == 0 || self.num_attention_heads.len() != stages {
+            candle::bail!("CLAP audio config needs a number of heads for each of its stages")
+        }
+        if self.patch_embed_input_channels != 1
+            || self.patch_stride != [self.patch_size, self.patch_size]
+        {
+            candle::bail!("CLAP audio patches must be single-channel and not overlap")
+        }
+        if self.num_mel_bins == 0 || !self.spec_size.is_multiple_of(self.num_mel_bins) {
+            candle::bail!(
+                "CLAP spec_size {} is not a multiple of num_mel_bins {}",
+                self.spec_size,
+                self.num_mel_bins
+            )
+        }
+        // Every stage must split into whole windows
+        let last_grid = self.window_size << (stages - 1);
+        if !self.spec_size.is_multiple_of(self.patch_size)
+            || !self.grid_size().is_multiple_of(last_grid)
+        {
+            candle::bail!(
+                "CLAP spec_size {} does not split into windows of {} at every stage",
+                self.spec_size,
+                self.window_size
+            )
+        }
+        if self.patch_embeds_hidden_size << (stages - 1) != self.hidden_size {
+            candle::bail!(
+                "CLAP audio hidden_size {} does not match the last stage's",
+                self.hidden_size
+            )
+        }
+        Ok(())
+    }
+}
+
+fn default_projection_dim() -> usize {
+    512
+}
+
+fn default_projection_hidden_act() -> HiddenAct {
+    HiddenAct::Relu
+}
+
+// https://github.com/huggingface/transformers/blob/5f4ecf2d9f867a1255131d2461d75793c0cf1db2/src/transformers/models/clap/configuration_clap.py#L297
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub(crate) struct ClapConfig {
+    pub text_config: bert::Config,
+    pub audio_config: AudioConfig,
+    #[serde(default = "default_projection_dim")]
+    pub projection_dim: usize,
+    #[serde(default = "default_projection_hidden_act")]
+    pub projection_hidden_act: HiddenAct,
+}
+
+// Maps a tower's pooled output into the shared space: linear, activation, linear.
+#[derive(Clone)]
+struct Projection {
+    linear1: Linear,
+    activation: HiddenAct,
+    linear2: Linear,
+}
+
+impl Projection {
+    fn load(vb: VarBuilder, in_size: usize, config: &ClapConfig) -> Result<Self> {
+        let dim = config.projection_dim;
+        Ok(Self {
+            linear1: linear(in_size, dim, vb.pp("linear1"))?,
+            activation: config.projection_hidden_act,
+            linear2: linear(dim, dim, vb.pp("linear2"))?,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let xs = self.activation.forward(&self.linear1.forward(xs)?)?;
+        self.linear2.forward(&xs)
+    }
+}
+
+// https://github.com/huggingface/transformers/blob/5f4ecf2d9f867a1255131d2461d75793c0cf1db2/src/transformers/models/clap/modeling_clap.py#L1591
+/// The text tower: the RoBERTa encoder's pooled first token, projected.
+#[derive(Clone)]
+pub(crate) struct TextModel {
+    encoder: BertModel,
+    pooler: Linear,
+    projection: Projection,
+}
+
+impl TextModel {
+    pub(crate) fn load(vb: VarBuilder, config: &ClapConfig) -> Result<Self> {
+        let text_config = &config.text_config;
+        let mut encoder = BertModel::load(vb.pp("text_model"), text_config)?;
+        encoder.set_position_offset(text_config.pad_token_id + 1);
+        let hidden_size = text_config.hidden_size;
+        Ok(Self {
+            encoder,
+            pooler: linear(hidden_size, hidden_size, vb.pp("text_model.pooler.dense"))?,
+            projection: Projection::load(vb.pp("text_projection"), hidden_size, config)?,
+        })
+    }
+
+    /// `(batch, projection_dim)` vectors, not yet scaled to unit length.
+    pub(crate) fn forward(&self, input_ids: &Tensor, attention_mask: &Tensor) -> Result<Tensor> {
+        let token_type_ids = input_ids.zeros_like()?;
+        let hidden_states =
+            self.encoder
+                .forward(input_ids, &token_type_ids, Some(attention_mask))?;
+        let first = hidden_states.narrow(1, 0, 1)?.squeeze(1)?;
+        let pooled = self.pooler.forward(&first)?.tanh()?;
+        self.projection.forward(&pooled)
+    }
+}
+
+// Rolls `xs` by `shift` positions towards the start along `dim`, like `torch.roll(xs, -shift)`.
+fn roll(xs: &Tensor, shift: usize, dim: usize) -> Result<Tensor> {
+    let len = xs.dim(dim)?;
+    let shift = shift % len;
+    if shift == 0 {
+        return Ok(xs.clone());
+    }
+    Tensor::cat(
+        &[
+            &xs.narrow(dim, shift, len - shift)?,
+            &xs.narrow(dim, 0, shift)?,
+        ],
+        dim,
+    )
+}
+
+// `(batch, height, width, channels)` into `(batch * windows, window^2, channels)`, windows in
+// row-major order.
+fn window_partition(xs: &Tensor, window: usize) -> Result<Tensor> {
+    let (batch, height, width, channels) = xs.dims4()?;
+    xs.reshape((
+        batch,
+        height / window,
+        window,
+        width / window,
+        window,
+        channels,
+    ))?
+    .permute((0, 1, 3, 2, 4, 5))?
+    .reshape((
+        batch * (height / window) * (width / window),
+        window * window,
+        channels,
+    ))
+}
+
+// The inverse of `window_partition`.
+fn window_reverse(
+    windows: &Tensor,
+    window: usize,
+    batch: usize,
+    height: usize,
+    width: usize,
+) -> Result<Tensor> {
+    let channels = windows.dim(D::Minus1)?;
+    windows
+        .reshape((
+            batch,
+            height / window,
+            width / window,
+            window,
+            window,
+            channels,
+        ))?
+        .permute((0, 1, 3, 2, 4, 5))?
+        .reshape((batch, height, width, channels))
+}
+
+// The index into the relative position bias table of every pair of positions in a window,
+// `window^2 x window^2` row-major.
+fn relative_position_index(window: usize) -> Vec<u32> {
+    let span = 2 * window - 1;
+    let mut index = Vec::with_capacity(window.pow(4));
+    for i in 0..window * window {
+        for j in 0..window * window {
+            let row = i / window + window - 1 - j / window;
+            let col = i % window + window - 1 - j % window;
+            index.push((row * span + col) as u32);
+        }
+    }
+    index
+}
+
+// For windows of a `size x size` grid rolled by `shift`, -100 between positions that came from
+// different regions of the unrolled grid and 0 within one, `(windows, window^2, window^2)`.
+fn shifted_window_mask(size: usize, window: usize, shift: usize) -> Vec<f32> {
+    let region = |i: usize| match i {
+        i if i < size - window => 0,
+        i if i < size - shift => 1,
+        _ => 2,
+    };
+    let windows = size / window;
+    let mut mask = Vec::with_capacity(windows * windows * window.pow(4));
+    for window_row in 0..windows {
+        for window_col in 0..windows {
+            let regions: Vec<usize> = (0..window * window)
+                .map(|p| {
+                    let row = window_row * window + p / window;
+                    let col = window_col * window + p % window;
+                    region(row) * 3 + region(col)
+                })
+                .collect();
+            for a in &regions {
+                mask.extend(regions.iter().map(|b| if a == b { 0.0 } else { -100.0 }));
+            }
+        }
+    }
+    mask
+}
+
+// https://github.com/huggingface/transformers/blob/5f4ecf2d9f867a1255131d2461d75793c0cf1db2/src/transformers/models/clap/modeling_clap.py#L349
+#[derive(Clone)]
+struct WindowAttention {
+    query: Linear,
+    key: Linear,
+    value: Linear,
+    output: Linear,
+    num_heads: usize,
+    head_size: usize,
+    // `(heads, window^2, window^2)`, gathered from the learned table once.
+    relative_position_bias: Tensor,
+}
+
+impl WindowAttention {
+    fn load(
+        vb: VarBuilder,
+        dim: usize,
+        num_heads: usize,
+        window: usize,
+        config: &AudioConfig,
+    ) -> Result<Self> {
+        let projection = |name: &str| match config.qkv_bias {
+            true => linear(dim, dim, vb.pp("self").pp(name)),
+            false => linear_no_bias(dim, dim, vb.pp("self").pp(name)),
+        };
+        let tokens = window * window;
+        let table = vb.pp("self").get(
+            ((2 * window - 1).pow(2), num_heads),
+            "relative_position_bias_table",
+        )?;
+        let index = Tensor::new(relative_position_index(window), vb.device())?;
+        let relative_position_bias = table
+            .index_select(&index, 0)?
+            .reshape((tokens, tokens, num_heads))?
+            .permute((2, 0, 1))?
+            .contiguous()?;
+        Ok(Self {
+            query: projection("query")?,
+            key: projection("key")?,
+            value: projection("value")?,
+            output: linear(dim, dim, vb.pp("output.dense"))?,
+            num_heads,
+            head_size: dim / num_heads,
+            relative_position_bias,
+        })
+    }
+
+    // `windows` is `(batch * windows, window^2, dim)`; `mask` is `(windows, window^2, window^2)`.
+    fn forward(&self, windows: &Tensor, mask: Option<&Tensor>) -> Result<Tensor> {
+        let (count, tokens, dim) = windows.dims3()?;
+        let heads = |xs: Tensor| {
+            xs.reshape((count, tokens, self.num_heads, self.head_size))?
+                .transpose(1, 2)?
+                .contiguous()
+        };
+        let query = heads(self.query.forward(windows)?)?;
+        let key = heads(self.key.forward(windows)?)?;
+        let value = heads(self.value.forward(windows)?)?;
+
+        let scores = (query.matmul(&key.t()?)? / (self.head_size as f64).sqrt())?;
+        let scores = scores.broadcast_add(&self.relative_position_bias)?;
+        let scores = match mask {
+            Some(mask) => {
+                let per_image = mask.dim(0)?;
+                scores
+                    .reshape((count / per_image, per_image, self.num_heads, tokens, tokens))?
+                    .broadcast_add(&mask.unsqueeze(1)?)?
+                    .reshape((count, self.num_heads, tokens, tokens))?
+            }
+            None => scores,
+        };
+        let probs = candle_nn::ops::softmax_last_dim(&scores)?;
+        let context = probs
+            .matmul(&value)?
+            .transpose(1, 2)?
+            .reshape((count, tokens, dim))?;
+        self.output.forward(&context)
+    }
+}
+
+// https://github.com/huggingface/transformers/blob/5f4ecf2d9f867a1255131d2461d75793c0cf1db2/src/transformers/models/clap/modeling_clap.py#L548
+#[derive(Clone)]
+struct SwinLayer {
+    layernorm_before: LayerNorm,
+    attention: WindowAttention,
+    layernorm_after: LayerNorm,
+    intermediate: Linear,
+    activation: HiddenAct,
+    output: Linear,
+    size: usize,
+    window: usize,
+    shift: usize,
+    mask: Option<Tensor>,
+}
+
+impl SwinLayer {
+    fn load(
+        vb: VarBuilder,
+        dim: usize,
+        num_heads: usize,
+        size: usize,
+        shifted: bool,
+        config: &AudioConfig,
+    ) -> Result<Self> {
+        // A grid no larger than a window is one window, with nothing to shift
+        let window = config.window_size.min(size);
+        let shift = match shifted && size > window {
+            true => window / 2,
+            false => 0,
+        };
+        let mask = match shift {
+            0 => None,
+            _ => {
+                let windows = (size / window).pow(2);
+                let tokens = window * window;
+                let mask = shifted_window_mask(size, window, shift);
+                Some(Tensor::from_vec(
+                    mask,
+                    (windows, tokens, tokens),
+                    vb.device(),
+                )?)
+            }
+        };
+        let hidden = (dim as f64 * config.mlp_ratio) as usize;
+        let eps = config.layer_norm_eps;
+        Ok(Self {
+            layernorm_before: layer_norm(dim, eps, vb.pp("layernorm_before"))?,
+            attention: WindowAttention::load(vb.pp("attention"), dim, num_heads, window, config)?,
+            layernorm_after: layer_norm(dim, eps, vb.pp("layernorm_after"))?,
+            intermediate: linear(dim, hidden, vb.pp("intermediate.dense"))?,
+            activation: config.hidden_act,
+            output: linear(hidden, dim, vb.pp("output.dense"))?,
+            size,
+            window,
+            shift,
+            mask,
+        })
+    }
+
+    // `xs` is `(batch, size^2, dim)`, the grid in row-major order.
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let (batch, tokens, dim) = xs.dims3()?;
+        let size = self.size;
+        let grid = self
+            .layernorm_before
+            .forward(xs)?
+            .reshape((batch, size, size, dim))?;
+        let grid = roll(&roll(&grid, self.shift, 1)?, self.shift, 2)?;
+        let windows = window_partition(&grid, self.window)?;
+        let windows = self.attention.forward(&windows, self.mask.as_ref())?;
+        let grid = window_reverse(&windows, self.window, batch, size, size)?;
+        let grid = roll(&roll(&grid, size - self.shift, 1)?, size - self.shift, 2)?;
+        let xs = (xs + grid.reshape((batch, tokens, dim))?)?;
+
+        let hidden = self.layernorm_after.forward(&xs)?;
+        let hidden = self
+            .activation
+            .forward(&self.intermediate.forward(&hidden)?)?;
+        xs + self.output.forward(&hidden)?
+    }
+}
+
+// https://github.com/huggingface/transformers/blob/5f4ecf2d9f867a1255131d2461d75793c0cf1db2/src/transformers/models/clap/modeling_clap.py#L770
+// Halves the grid along each side, concatenating every 2x2 block and projecting it to twice
+// the channels.
+#[derive(Clone)]
+struct PatchMerging {
+    norm: LayerNorm,
+    reduction: Linear,
+}
+
+impl PatchMerging {
+    fn load(vb: VarBuilder, dim: usize) -> Result<Self> {
+        Ok(Self {
+            norm: layer_norm(4 * dim, 1e-5, vb.pp("norm"))?,
+            reduction: linear_no_bias(4 * dim, 2 * dim, vb.pp("reduction"))?,
+        })
+    }
+
+    fn forward(&self, xs: &Tensor, size: usize) -> Result<Tensor> {
+        let (batch, _, dim) = xs.dims3()?;
+        let half = size / 2;
+        // Blocks in the order (0, 0), (1, 0), (0, 1), (1, 1) by row and column offset
+        let xs = xs
+            .reshape((batch, half, 2, half, 2, dim))?
+            .permute((0, 1, 3, 4, 2, 5))?
+            .reshape((batch, half * half, 4 * dim))?;
+        self.reduction.forward(&self.norm.forward(&xs)?)
+    }
+}
+
+#[derive(Clone)]
+struct Stage {
+    blocks: Vec<SwinLayer>,
+    downsample: Option<PatchMerging>,
+    size: usize,
+}
+
+// Bicubic interpolation weights stretching `from` frames to `to`, `(to, from)`, matching
+// `torch.nn.functional.interpolate(mode="bicubic", align_corners=True)` along one axis.
+fn bicubic_matrix(from: usize, to: usize) -> Vec<f32> {
+    const A: f64 = -0.75;
+    let near = |x: f64| ((A + 2.0) * x - (A + 3.0)) * x * x + 1.0;
+    let far = |x: f64| ((A * x - 5.0 * A) * x + 8.0 * A) * x - 4.0 * A;
+    let scale = match to {
+        0 | 1 => 0.0,
+        _ => (from - 1) as f64 / (to - 1) as f64,
+    };
+    let mut matrix = vec![0f32; to * from];
+    for (i, row) in matrix.chunks_exact_mut(from).enumerate() {
+        let position = scale * i as f64;
+        let index = position.floor() as i64;
+        let t = position - index as f64;
+        let weights = [far(t + 1.0), near(t), near(1.0 - t), far(2.0 - t)];
+        for (offset, weight) in (-1..=2).zip(weights) {
+            let source = (index + offset).clamp(0, from as i64 - 1) as usize;
+            row[source] += weight as f32;
+        }
+    }
+    matrix
+}
+
+// https://github.com/huggingface/transformers/blob/5f4ecf2d9f867a1255131d2461d75793c0cf1db2/src/transformers/models/clap/modeling_clap.py#L907
+/// The audio tower: the HTSAT encoder's mean token, projected.
+#[derive(Clone)]
+pub(crate) struct AudioModel {
+    // The batch norm over mel bins, folded into a scale and shift per bin.
+    scale: Tensor,
+    shift: Tensor,
+    patch_embed: Conv2d,
+    patch_norm: Option<LayerNorm>,
+    stages: Vec<Stage>,
+    norm: LayerNorm,
+    projection: Projection,
+    config: AudioConfig,
+    device: Device,
+}
+
+impl AudioModel {
+    pub(crate) fn load(vb: VarBuilder, config: &ClapConfig) -> Result<Self> {
+        let projection = Projection::load(
+            vb.pp("audio_projection"),
+            config.audio_config.hidden_size,
+            config,
+        )?;
+        let config = &config.audio_config;
+        config.check()?;
+        let vb = vb.pp("audio_model.audio_encoder");
+
+        let bins = config.num_mel_bins;
+        let batch_norm = vb.pp("batch_norm");
+        let mean = batch_norm.get_with_hints(bins, "running_mean", Init::Const(0.))?;
+        let var = batch_norm.get_with_hints(bins, "running_var", Init::Const(1.))?;
+        let weight = batch_norm.get_with_hints(bins, "weight", Init::Const(1.))?;
+        let bias = batch_norm.get_with_hints(bins, "bias", Init::Const(0.))?;
+        let scale = (weight / (var + 1e-5)?.sqrt()?)?;
+        let shift = (bias - (&mean * &scale)?)?;
+
+        let embed_dim = config.patch_embeds_hidden_size;
+        let patch_embed = conv2d(
+            config.patch_embed_input_channels,
+            embed_dim,
+            config.patch_size,
+            Conv2dConfig {
+                stride: config.patch_size,
+                ..Default::default()
+            },
+            vb.pp("patch_embed.proj"),
+        )?;
+        let patch_norm = match config.enable_patch_layer_norm {
+            true => Some(layer_norm(embed_dim, 1e-5, vb.pp("patch_embed.norm"))?),
+            false => None,
+        };
+
+        let stages_count = config.depths.len();
+        let mut stages = Vec::with_capacity(stages_count);
+        for (index, (&depth, &num_heads)) in config
+            .depths
+            .iter()
+            .zip(&config.num_attention_heads)
+            .enumerate()
+        {
+            let vb = vb.pp(format!("layers.{index}"));
+            let dim = embed_dim << index;
+            let size = config.grid_size() >> index;
+            let blocks = (0..depth)
+                .map(|block| {
+                    let vb = vb.pp(format!("blocks.{block}"));
+                    SwinLayer::load(vb, dim, num_heads, size, block % 2 == 1, config)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let downsample = match index + 1 < stages_count {
+                true => Some(PatchMerging::load(vb.pp("downsample"), dim)?),
+                false => None,
+            };
+            stages.push(Stage {
+                blocks,
+                downsample,
+                size,
+            });
+        }
+        Ok(Self {
+            scale: scale.reshape((1, 1, bins))?,
+            shift: shift.reshape((1, 1, bins))?,
+            patch_embed,
+            patch_norm,
+            stages,
+            norm: layer_norm(config.hidden_size, 1e-5, vb.pp("norm"))?,
+            projection,
+            config: config.clone(),
+            device: vb.device().clone(),
+        })
+    }
+
+    pub(crate) fn num_mel_bins(&self) -> usize {
+        self.config.num_mel_bins
+    }
+
+    /// `mel` is `(batch, frames, num_mel_bins)` log-mel spectrograms of at most
+    /// `spec_size * spec_size / num_mel_bins` frames; returns `(batch, projection_dim)`
+    /// vectors, not yet scaled to unit length.
+    pub(crate) fn forward(&self, mel: &Tensor) -> Result<Tensor> {
+        let (batch, frames, bins) = mel.dims3()?;
+        let config = &self.config;
+        let width = config.spec_width();
+        if bins != config.num_mel_bins || frames > width {
+            candle::bail!(
+                "CLAP audio input is {frames}x{bins}, expected at most {width}x{}",
+                config.num_mel_bins
+            )
+        }
+        let mel = mel.broadcast_mul(&self.scale)?.broadcast_add(&self.shift)?;
+        let mel = match frames < width {
+            true => {
+                let stretch = Tensor::from_vec(
+                    bicubic_matrix(frames, width),
+                    (1, width, frames),
+                    &self.device,
+                )?;
+                stretch.broadcast_matmul(&mel)?
+            }
+            false => mel,
+        };
+
+        // Fold the frames into `freq_ratio` consecutive segments stacked as rows of mel bins,
+        // lowest bin first, making a square image
+        let ratio = config.freq_ratio();
+        let side = config.spec_size;
+        let image = mel
+            .reshape((batch, ratio, width / ratio, bins))?
+            .permute((0, 1, 3, 2))?
+            .reshape((batch, 1, side, side))?;
+        let patches = self
+            .patch_embed
+            .forward(&image)?
+            .flatten_from(2)?
+            .transpose(1, 2)?;
+        let mut xs = match &self.patch_norm {
+            Some(norm) => norm.forward(&patches)?,
+            None => patches.contiguous()?,
+        };
+        for stage in &self.stages {
+            for block in &stage.blocks {
+                xs = block.forward(&xs)?;
+            }
+            if let Some(downsample) = &stage.downsample {
+                xs = downsample.forward(&xs, stage.size)?;
+            }
+        }
+        let pooled = self.norm.forward(&xs)?.mean(1)?;
+        self.projection.forward(&pooled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_helpers() {
+        // Window 2: 3x3 relative offsets, (row, col) offset (0, 0) at index 4
+        assert_eq!(
+            vec![4, 3, 1, 0, 5, 4, 2, 1, 7, 6, 4, 3, 8, 7, 5, 4],
+            relative_position_index(2)
+        );
+
+        // A 4x4 grid in 2x2 windows rolled by 1: the last window mixes four regions
+        let mask = shifted_window_mask(4, 2, 1);
+        assert_eq!(4 * 16, mask.len());
+        assert!(mask[..16].iter().all(|&x| x == 0.0));
+        assert_eq!(&[0.0, -100.0, -100.0, -100.0], &mask[48..52]);
+
+        let device = Device::Cpu;
+        let grid = Tensor::arange(0f32, 2.0 * 4.0 * 4.0 * 3.0, &device)
+            .unwrap()
+            .reshape((2, 4, 4, 3))
+            .unwrap();
+        let windows = window_partition(&grid, 2).unwrap();
+        assert_eq!((8, 4, 3), windows.dims3().unwrap());
+        // The second window of the first image starts at row 0, column 2
+        assert_eq!(
+            vec![6.0, 7.0, 8.0],
+            windows
+                .get(1)
+                .unwrap()
+                .get(0)
+                .unwrap()
+                .to_vec1::<f32>()
+                .unwrap()
+        );
+        let restored = window_reverse(&windows, 2, 2, 4, 4).unwrap();
+        assert_eq!(
+            grid.flatten_all().unwrap().to_vec1::<f32>().unwrap(),
+            restored.flatten_all().unwrap().to_vec1::<f32>().unwrap()
+        );
+        let rolled = roll(&grid, 1, 1).unwrap();
+        let back = roll(&rolled, 3, 1).unwrap();
+        assert_eq!(
+            grid.get(0)
+                .unwrap()
+                .get(1)
+                .unwrap()
+                .to_vec2::<f32>()
+                .unwrap(),
+            rolled
+                .get(0)
+                .unwrap()
+                .get(0)
+                .unwrap()
+                .to_vec2::<f32>()
+                .unwrap()
+        );
+        assert_eq!(
+            grid.flatten_all().unwrap().to_vec1::<f32>().unwrap(),
+            back.flatten_all().unwrap().to_vec1::<f32>().unwrap()
+        );
+
+        // Stretching keeps the end frames and a constant signal
+        let matrix = bicubic_matrix(5, 9);
+        assert_eq!(1.0, matrix[0]);
+        assert_eq!(1.0, matrix[9 * 5 - 1]);
+        for row in matrix.chunks(5) {
+            assert!((row.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        }
+        // Every other output frame lands on an input frame
+        assert!((matrix[2 * 5 + 1] - 1.0).abs() < 1e-6);
+    }
+}
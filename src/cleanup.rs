@@ -0,0 +1,99 @@
+use crate::error::Result;
+use serde::Deserialize;
+use std::borrow::Cow;
+use tokenizers::NormalizedString;
+
+/// A Unicode normalization form (see UAX #15).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnicodeForm {
+    /// Canonical composition: the same text always has the same code points.
+    Nfc,
+    /// Compatibility composition: also folds ligatures, full-width forms, non-breaking spaces
+    /// and the like into their plain equivalents.
+    Nfkc,
+}
+
+/// Cleanup run on every input before any other preprocessing, so text from messy sources
+/// (scraped HTML, OCR, PDFs) embeds the same as its clean equivalent. Everything is off by
+/// default; the steps run in the order of the fields.
+///
+/// Over FFI it is given as JSON, e.g.
+/// `{"strip_control": true, "normalization": "nfkc", "collapse_whitespace": true}`.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TextCleanup {
+    /// Remove control characters other than whitespace, and invisible formatting characters:
+    /// zero-width spaces and joiners, soft hyphens and byte order marks.
+    pub strip_control: bool,
+    pub normalization: Option<UnicodeForm>,
+    pub lowercase: bool,
+    /// Replace every run of whitespace with a single space, and trim both ends.
+    pub collapse_whitespace: bool,
+}
+
+impl TextCleanup {
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        if self.strip_control && text.chars().any(is_invisible) {
+            text = Cow::Owned(text.chars().filter(|&c| !is_invisible(c)).collect());
+        }
+        if let Some(form) = self.normalization {
+            let mut normalized = NormalizedString::from(text.as_ref());
+            match form {
+                UnicodeForm::Nfc => normalized.nfc(),
+                UnicodeForm::Nfkc => normalized.nfkc(),
+            };
+            if normalized.get() != text {
+                text = Cow::Owned(normalized.get().to_string());
+            }
+        }
+        if self.lowercase && text.chars().any(char::is_uppercase) {
+            text = Cow::Owned(text.to_lowercase());
+        }
+        if self.collapse_whitespace {
+            let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+            if collapsed != text {
+                text = Cow::Owned(collapsed);
+            }
+        }
+        text
+    }
+}
+
+fn is_invisible(c: char) -> bool {
+    (c.is_control() && !c.is_whitespace())
+        || matches!(
+            c,
+            '\u{00AD}' | '\u{200B}'..='\u{200D}' | '\u{2060}' | '\u{FEFF}'
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cleanup() {
+        let messy = "  Caf\u{0065}\u{0301}\u{00A0}\u{FB01}ne\u{200B} \u{0007}dining\r\n\tOCR  ";
+        assert_eq!(messy, TextCleanup::default().apply(messy));
+
+        let nfc = TextCleanup {
+            normalization: Some(UnicodeForm::Nfc),
+            ..Default::default()
+        };
+        assert!(nfc.apply(messy).contains("Caf\u{00E9}\u{00A0}\u{FB01}"));
+
+        let all = TextCleanup::from_json(
+            r#"{"strip_control": true, "normalization": "nfkc", "lowercase": true,
+                "collapse_whitespace": true}"#,
+        )
+        .unwrap();
+        assert_eq!("caf\u{00E9} fine dining ocr", all.apply(messy));
+        assert!(TextCleanup::from_json(r#"{"normalization": "nfx"}"#).is_err());
+    }
+}
@@ -0,0 +1,910 @@
+use crate::bm25::{term_counts, Bm25, Fusion};
+use crate::cancel::CancelToken;
+use crate::embedder::normalize;
+use crate::error::{Error, Result};
+use crate::filter::Filter;
+use crate::hnsw::{read_u32, read_u64, Hnsw, HnswConfig, Vectors};
+use crate::kernels::dot;
+use crate::pipeline::{Document, Metadata};
+use crate::progress::{embed_in_steps, Progress};
+use crate::provider::EmbeddingProvider;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+const MAGIC: &[u8; 8] = b"RECORPUS";
+// Files of any other version are refused rather than migrated.
+const FORMAT_VERSION: u32 = 4;
+
+// Embedded when saving and again when loading: a corpus only loads with a provider that puts
+// this text in (nearly) the same place as the one that built it.
+const FINGERPRINT_TEXT: &str = "The quick brown fox jumps over the lazy dog.";
+const FINGERPRINT_MIN_SIMILARITY: f32 = 0.99;
+
+// Candidates taken from each ranking for a hybrid search of up to this many results; more for
+// larger searches.
+const FUSION_DEPTH: usize = 50;
+
+/// A document returned by [`Corpus::search`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub id: String,
+    /// Cosine similarity between the document and the query; the BM25 score for lexical
+    /// searches and the fused score for hybrid ones.
+    pub score: f32,
+    pub metadata: Metadata,
+}
+
+// Documents by slot. A deleted or replaced document leaves a tombstone: its slot stays (HNSW
+// searches still route through it) but is skipped by searches until compaction drops it.
+#[derive(Default)]
+struct Index {
+    ids: Vec<String>,
+    metadata: Vec<Metadata>,
+    // Normalized embeddings, `dim` floats per slot, one after another.
+    vectors: Vec<f32>,
+    dim: usize,
+    hnsw: Option<Hnsw>,
+    lexical: Bm25,
+    deleted: Vec<bool>,
+    // The slot of every live document.
+    slots: HashMap<String, usize>,
+}
+
+impl Index {
+    fn push(&mut self, id: String, metadata: Metadata, embedding: &[f32], terms: &[(String, u32)]) {
+        if let Some(slot) = self.slots.insert(id.clone(), self.ids.len()) {
+            self.deleted[slot] = true;
+            self.lexical.remove(slot);
+        }
+        self.ids.push(id);
+        self.metadata.push(metadata);
+        self.deleted.push(false);
+        self.vectors.extend_from_slice(embedding);
+        self.lexical.push(terms);
+        if let Some(hnsw) = &mut self.hnsw {
+            hnsw.insert(Vectors {
+                data: &self.vectors,
+                dim: self.dim,
+            });
+        }
+    }
+
+    fn remove(&mut self, id: &str) -> bool {
+        match self.slots.remove(id) {
+            Some(slot) => {
+                self.deleted[slot] = true;
+                self.lexical.remove(slot);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn tombstones(&self) -> usize {
+        self.ids.len() - self.slots.len()
+    }
+
+    // Drop the tombstones, relinking the HNSW graph from the stored vectors
+    fn compact(&mut self) {
+        let old = std::mem::take(self);
+        *self = Index {
+            dim: old.dim,
+            hnsw: old.hnsw.as_ref().map(|hnsw| Hnsw::new(hnsw.config())),
+            ..Index::default()
+        };
+        let slots = old.ids.into_iter().zip(old.metadata).enumerate();
+        for (slot, (id, metadata)) in slots.filter(|(slot, _)| !old.deleted[*slot]) {
+            let vector = &old.vectors[slot * old.dim..(slot + 1) * old.dim];
+            self.push(id, metadata, vector, &old.lexical.counts(slot));
+        }
+    }
+
+    fn accepts(&self, slot: usize, filter: Option<&Filter>) -> bool {
+        !self.deleted[slot] && filter.is_none_or(|filter| filter.matches(&self.metadata[slot]))
+    }
+
+    // The `k` slots most similar to `query`, best first, with their cosine similarity
+    fn dense_search(
+        &self,
+        query: &[f32],
+        k: usize,
+        filter: Option<&Filter>,
+    ) -> Result<Vec<(usize, f32)>> {
+        if query.len() != self.dim {
+            return Err(Error::InvalidArgument(format!(
+                "a query of length {} can't search a corpus of length {}",
+                query.len(),
+                self.dim
+            )));
+        }
+        let mut query = query.to_vec();
+        normalize(&mut query);
+        let accept = |i: usize| self.accepts(i, filter);
+
+        Ok(match &self.hnsw {
+            Some(hnsw) => {
+                let vectors = Vectors {
+                    data: &self.vectors,
+                    dim: self.dim,
+                };
+                let hits = hnsw.search(vectors, &query, k, &|node| accept(node as usize));
+                hits.into_iter()
+                    .map(|(node, score)| (node as usize, score))
+                    .collect()
+            }
+            None => {
+                let mut scores: Vec<(usize, f32)> = self
+                    .vectors
+                    .chunks_exact(self.dim)
+                    .enumerate()
+                    .filter(|&(i, _)| accept(i))
+                    .map(|(i, vector)| (i, dot(vector, &query)))
+                    .collect();
+                let best_first = |a: &(usize, f32), b: &(usize, f32)| b.1.total_cmp(&a.1);
+                if k < scores.len() {
+                    scores.select_nth_unstable_by(k - 1, best_first);
+                    scores.truncate(k);
+                }
+                scores.sort_unstable_by(best_first);
+                scores
+            }
+        })
+    }
+
+    fn hits(&self, scores: Vec<(usize, f32)>) -> Vec<SearchHit> {
+        scores
+            .into_iter()
+            .map(|(i, score)| SearchHit {
+                id: self.ids[i].clone(),
+                score,
+                metadata: self.metadata[i].clone(),
+            })
+            .collect()
+    }
+}
+
+/// An in-memory semantic search index: add documents, then find the ones closest in meaning
+/// to a query, without wiring up a vector database.
+///
+/// Documents are embedded with the given provider. [`Corpus::new`] searches with an exact scan
+/// over every vector, which is fine up to around 100k documents; [`Corpus::with_hnsw`] links
+/// them into an HNSW graph as they are added, so search stays sub-millisecond at a larger
+/// scale in exchange for occasionally missing a close match.
+///
+/// Documents can carry JSON metadata, which [`Corpus::search_filtered`] restricts results by.
+///
+/// Every document's words are also kept in a BM25 index, for [`Corpus::search_lexical`] and
+/// for [`Corpus::search_hybrid`], which fuses both rankings: exact names, codes and rare terms
+/// that embeddings blur are found by the lexical side.
+///
+/// [`Corpus::upsert_documents`] and [`Corpus::delete`] change a corpus in place: the old
+/// vectors are marked deleted and skipped by searches, and once they make up half of the
+/// index it is compacted, relinking the HNSW graph from the stored vectors without embedding
+/// anything again. [`Corpus::compact`] does so on demand, e.g. at a quiet moment.
+pub struct Corpus {
+    provider: Arc<dyn EmbeddingProvider>,
+    index: RwLock<Index>,
+}
+
+impl Corpus {
+    pub fn new(provider: Arc<dyn EmbeddingProvider>) -> Self {
+        Corpus {
+            provider,
+            index: RwLock::new(Index::default()),
+        }
+    }
+
+    /// A corpus searched through an approximate nearest neighbor graph.
+    pub fn with_hnsw(provider: Arc<dyn EmbeddingProvider>, config: HnswConfig) -> Result<Self> {
+        config.validate()?;
+        let index = Index {
+            hnsw: Some(Hnsw::new(config)),
+            ..Index::default()
+        };
+        Ok(Corpus {
+            provider,
+            index: RwLock::new(index),
+        })
+    }
+
+    /// Embed `text` and add it under `id`, which must not be in the corpus yet.
+    pub fn add(&self, id: &str, text: &str) -> Result<()> {
+        self.add_batch(&[(id, text)])
+    }
+
+    /// Embed `(id, text)` documents as one batch and add them. Nothing is added if an id is
+    /// already in the corpus or repeated, or if embedding fails.
+    pub fn add_batch<I: AsRef<str>, T: AsRef<str>>(&self, documents: &[(I, T)]) -> Result<()> {
+        let documents = documents
+            .iter()
+            .map(|(id, text)| (id.as_ref(), text.as_ref(), Metadata::new()));
+        self.insert(documents.collect(), false, None)?;
+        Ok(())
+    }
+
+    /// [`Corpus::add_batch`] for documents with metadata.
+    pub fn add_documents(&self, documents: &[Document]) -> Result<()> {
+        self.insert(document_parts(documents), false, None)?;
+        Ok(())
+    }
+
+    /// [`Corpus::add_documents`] in steps that stop once `cancel` is cancelled. The documents
+    /// embedded by then are added, the rest are not; returns how many were added, always the
+    /// first ones.
+    pub fn add_documents_cancellable(
+        &self,
+        documents: &[Document],
+        cancel: &CancelToken,
+    ) -> Result<usize> {
+        self.insert(document_parts(documents), false, Some(cancel))
+    }
+
+    /// Embed documents as one batch, adding new ids and replacing the text and metadata of ids
+    /// already in the corpus. Nothing changes if an id is repeated or embedding fails.
+    pub fn upsert_documents(&self, documents: &[Document]) -> Result<()> {
+        self.insert(document_parts(documents), true, None)?;
+        Ok(())
+    }
+
+    /// Remove document `id`, returning whether it was in the corpus.
+    pub fn delete(&self, id: &str) -> bool {
+        let mut index = self.index.write().unwrap();
+        let removed = index.remove(id);
+        compact_if_sparse(&mut index);
+        removed
+    }
+
+    /// Drop deleted and replaced documents from memory now rather than when they make up
+    /// half of the index.
+    pub fn compact(&self) {
+        self.index.write().unwrap().compact();
+    }
+
+    /// The metadata of document `id`, if it is in the corpus.
+    pub fn metadata(&self, id: &str) -> Option<Metadata> {
+        let index = self.index.read().unwrap();
+        let slot = *index.slots.get(id)?;
+        Some(index.metadata[slot].clone())
+    }
+
+    // Returns the number of documents inserted, all of them unless `cancel` stopped the
+    // embedding early.
+    fn insert(
+        &self,
+        mut documents: Vec<(&str, &str, Metadata)>,
+        replace: bool,
+        cancel: Option<&CancelToken>,
+    ) -> Result<usize> {
+        let mut seen = HashSet::new();
+        for (id, _, _) in &documents {
+            if !seen.insert(*id) {
+                return Err(Error::InvalidArgument(format!(
+                    "document {id:?} is repeated"
+                )));
+            }
+        }
+        if !replace {
+            self.check_new(&seen)?;
+        }
+
+        let texts: Vec<&str> = documents.iter().map(|(_, text, _)| *text).collect();
+        let progress = Progress::start(texts.len());
+        let embeddings = embed_in_steps(self.provider.as_ref(), &texts, &progress, cancel)?;
+        documents.truncate(embeddings.len());
+        let mut index = self.index.write().unwrap();
+        // Another call may have added one of the ids while this batch was embedded.
+        if !replace {
+            if let Some(id) = seen.iter().find(|id| index.slots.contains_key(**id)) {
+                return Err(duplicate(id));
+            }
+        }
+        let dim = match index.ids.is_empty() {
+            true => embeddings.first().map_or(0, Vec::len),
+            false => index.dim,
+        };
+        if let Some(embedding) = embeddings.iter().find(|e| e.len() != dim) {
+            return Err(Error::InvalidArgument(format!(
+                "{} returned a vector of length {} for a corpus of length {dim}",
+                self.provider.name(),
+                embedding.len()
+            )));
+        }
+
+        index.dim = dim;
+        let inserted = documents.len();
+        for ((id, text, metadata), mut embedding) in documents.into_iter().zip(embeddings) {
+            normalize(&mut embedding);
+            index.push(id.to_string(), metadata, &embedding, &term_counts(text));
+        }
+        compact_if_sparse(&mut index);
+        Ok(inserted)
+    }
+
+    /// The `k` documents most similar to `query`, best first.
+    pub fn search(&self, query: &str, k: usize) -> Result<Vec<SearchHit>> {
+        let query = self.provider.embed(query)?;
+        self.search_with(&query, k, None)
+    }
+
+    /// The `k` documents most similar to an embedding from the corpus's provider, best first.
+    pub fn search_vector(&self, query: &[f32], k: usize) -> Result<Vec<SearchHit>> {
+        self.search_with(query, k, None)
+    }
+
+    /// The `k` documents most similar to `query` among those whose metadata matches
+    /// `filter`, best first.
+    ///
+    /// The filter is applied during the search rather than to its results, so `k` matching
+    /// documents come back whenever there are that many.
+    pub fn search_filtered(
+        &self,
+        query: &str,
+        k: usize,
+        filter: &Filter,
+    ) -> Result<Vec<SearchHit>> {
+        let query = self.provider.embed(query)?;
+        self.search_with(&query, k, Some(filter))
+    }
+
+    /// [`Corpus::search_filtered`] with an embedding from the corpus's provider.
+    pub fn search_vector_filtered(
+        &self,
+        query: &[f32],
+        k: usize,
+        filter: &Filter,
+    ) -> Result<Vec<SearchHit>> {
+        self.search_with(query, k, Some(filter))
+    }
+
+    /// The `k` documents scoring highest for `query` by BM25 over their words, best first.
+    /// Documents sharing no word with the query are left out.
+    pub fn search_lexical(&self, query: &str, k: usize) -> Result<Vec<SearchHit>> {
+        let index = self.index.read().unwrap();
+        let scores = index.lexical.search(query, k, &|i| !index.deleted[i]);
+        Ok(index.hits(scores))
+    }
+
+    /// The `k` best documents for `query` by both embedding similarity and BM25, combined as
+    /// `fusion` says, best first.
+    pub fn search_hybrid(&self, query: &str, k: usize, fusion: Fusion) -> Result<Vec<SearchHit>> {
+        self.hybrid_search_with(query, k, fusion, None)
+    }
+
+    /// [`Corpus::search_hybrid`] among the documents whose metadata matches `filter`.
+    pub fn search_hybrid_filtered(
+        &self,
+        query: &str,
+        k: usize,
+        fusion: Fusion,
+        filter: &Filter,
+    ) -> Result<Vec<SearchHit>> {
+        self.hybrid_search_with(query, k, fusion, Some(filter))
+    }
+
+    fn hybrid_search_with(
+        &self,
+        query: &str,
+        k: usize,
+        fusion: Fusion,
+        filter: Option<&Filter>,
+    ) -> Result<Vec<SearchHit>> {
+        fusion.validate()?;
+        let embedding = self.provider.embed(query)?;
+        let index = self.index.read().unwrap();
+        if index.ids.is_empty() || k == 0 {
+            return Ok(Vec::new());
+        }
+        let depth = k.max(FUSION_DEPTH);
+        let dense = index.dense_search(&embedding, depth, filter)?;
+        let lexical = index
+            .lexical
+            .search(query, depth, &|i| index.accepts(i, filter));
+        let mut scores = fusion.fuse(&dense, &lexical);
+        scores.sort_unstable_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        scores.truncate(k);
+        Ok(index.hits(scores))
+    }
+
+    fn search_with(
+        &self,
+        query: &[f32],
+        k: usize,
+        filter: Option<&Filter>,
+    ) -> Result<Vec<SearchHit>> {
+        let index = self.index.read().unwrap();
+        if index.ids.is_empty() || k == 0 {
+            return Ok(Vec::new());
+        }
+        let scores = index.dense_search(query, k, filter)?;
+        Ok(index.hits(scores))
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.read().unwrap().slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write the corpus to `path` so it can be loaded without embedding its documents again.
+    ///
+    /// The file holds a format version, a fingerprint of the provider's model, the ids and
+    /// metadata, which of them are deleted, the vectors, the HNSW graph if there is one and
+    /// the words of every document for lexical search.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut fingerprint = self.provider.embed(FINGERPRINT_TEXT)?;
+        normalize(&mut fingerprint);
+        let index = self.index.read().unwrap();
+        if !index.ids.is_empty() && fingerprint.len() != index.dim {
+            return Err(Error::InvalidArgument(format!(
+                "{} returns vectors of length {} for a corpus of length {}",
+                self.provider.name(),
+                fingerprint.len(),
+                index.dim
+            )));
+        }
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&(fingerprint.len() as u32).to_le_bytes())?;
+        writer.write_all(&(index.ids.len() as u64).to_le_bytes())?;
+        write_f32s(&mut writer, &fingerprint)?;
+        for id in &index.ids {
+            write_bytes(&mut writer, id.as_bytes())?;
+        }
+        for metadata in &index.metadata {
+            write_bytes(&mut writer, &serde_json::to_vec(metadata)?)?;
+        }
+        let deleted: Vec<u8> = index.deleted.iter().map(|&deleted| deleted as u8).collect();
+        writer.write_all(&deleted)?;
+        write_f32s(&mut writer, &index.vectors)?;
+        match &index.hnsw {
+            Some(hnsw) => {
+                writer.write_all(&[1])?;
+                hnsw.write(&mut writer)?;
+            }
+            None => writer.write_all(&[0])?,
+        }
+        index.lexical.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Load a corpus written by [`Corpus::save`], to be searched and extended with `provider`.
+    ///
+    /// Fails if `provider` embeds differently from the one the corpus was built with, since
+    /// its queries would not be comparable with the stored vectors, and on files written in
+    /// any other format version than the current one; save those again to upgrade them.
+    pub fn load(path: impl AsRef<Path>, provider: Arc<dyn EmbeddingProvider>) -> Result<Self> {
+        let corrupt = |what: &str| Error::InvalidArgument(format!("not a saved corpus: {what}"));
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(corrupt("bad magic"));
+        }
+        let version = read_u32(&mut reader)?;
+        if version != FORMAT_VERSION {
+            return Err(Error::InvalidArgument(format!(
+                "unsupported corpus format version {version}, expected {FORMAT_VERSION}"
+            )));
+        }
+        let dim = read_u32(&mut reader)? as usize;
+        let count = read_u64(&mut reader)?;
+        // Refuse sizes the file can't hold before allocating for them
+        let floats = count.saturating_add(1).saturating_mul(dim as u64);
+        if dim == 0 || floats.saturating_mul(4) > file_len {
+            return Err(corrupt("bad vector count"));
+        }
+        let count = count as usize;
+
+        let stored = read_f32s(&mut reader, dim)?;
+        let mut fingerprint = provider.embed(FINGERPRINT_TEXT)?;
+        normalize(&mut fingerprint);
+        if fingerprint.len() != dim || dot(&fingerprint, &stored) < FINGERPRINT_MIN_SIMILARITY {
+            return Err(Error::InvalidArgument(format!(
+                "the corpus was built with a different model than {}",
+                provider.name()
+            )));
+        }
+
+        let ids = (0..count)
+            .map(|_| {
+                let bytes = read_bytes(&mut reader, file_len)?;
+                String::from_utf8(bytes).map_err(|_| corrupt("id is not UTF-8"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let metadata: Vec<Metadata> = (0..count)
+            .map(|_| Ok(serde_json::from_slice(&read_bytes(&mut reader, file_len)?)?))
+            .collect::<Result<_>>()?;
+        let mut deleted = vec![0; count];
+        reader.read_exact(&mut deleted)?;
+        let deleted: Vec<bool> = deleted.into_iter().map(|byte| byte != 0).collect();
+        let mut slots = HashMap::with_capacity(count);
+        for (slot, id) in ids.iter().enumerate().filter(|(slot, _)| !deleted[*slot]) {
+            if slots.insert(id.clone(), slot).is_some() {
+                return Err(corrupt("repeated id"));
+            }
+        }
+        let vectors = read_f32s(&mut reader, count * dim)?;
+
+        let mut flag = [0];
+        reader.read_exact(&mut flag)?;
+        let hnsw = match flag[0] {
+            0 => None,
+            1 => Some(Hnsw::read(&mut reader, count)?),
+            _ => return Err(corrupt("bad index kind")),
+        };
+        let lexical = Bm25::read(&mut reader, &deleted, file_len)?;
+        let index = Index {
+            ids,
+            metadata,
+            vectors,
+            dim,
+            hnsw,
+            lexical,
+            deleted,
+            slots,
+        };
+        Ok(Corpus {
+            provider,
+            index: RwLock::new(index),
+        })
+    }
+
+    // Fail before embedding anything if one of `ids` is already in the corpus
+    fn check_new(&self, ids: &HashSet<&str>) -> Result<()> {
+        let index = self.index.read().unwrap();
+        match ids.iter().find(|id| index.slots.contains_key(**id)) {
+            Some(id) => Err(duplicate(id)),
+            None => Ok(()),
+        }
+    }
+}
+
+// A length-prefixed byte string
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> Result<()> {
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+fn read_bytes(reader: &mut impl Read, file_len: u64) -> Result<Vec<u8>> {
+    let len = read_u32(reader)? as usize;
+    if len as u64 > file_len {
+        return Err(Error::InvalidArgument(
+            "not a saved corpus: bad string length".to_string(),
+        ));
+    }
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn write_f32s(writer: &mut impl Write, values: &[f32]) -> Result<()> {
+    for value in values {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_f32s(reader: &mut impl Read, len: usize) -> Result<Vec<f32>> {
+    let mut bytes = vec![0; len * 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
+
+fn document_parts(documents: &[Document]) -> Vec<(&str, &str, Metadata)> {
+    documents
+        .iter()
+        .map(|doc| (doc.id.as_str(), doc.text.as_str(), doc.metadata.clone()))
+        .collect()
+}
+
+// Tombstones cost every search; past half of the slots, dropping them is worth a rebuild
+fn compact_if_sparse(index: &mut Index) {
+    if index.tombstones() > 0 && index.tombstones() * 2 >= index.ids.len() {
+        index.compact();
+    }
+}
+
+fn duplicate(id: &str) -> Error {
+    Error::InvalidArgument(format!("document {id:?} is already in the corpus"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::MockProvider;
+
+    #[test]
+    fn test_corpus_search() {
+        let corpus = Corpus::new(Arc::new(MockProvider::new(16)));
+        assert!(corpus.search("anything", 3).unwrap().is_empty());
+
+        corpus
+            .add_batch(&[("a", "first"), ("b", "second"), ("c", "third")])
+            .unwrap();
+        corpus.add("d", "fourth").unwrap();
+        assert_eq!(4, corpus.len());
+        assert!(corpus.add("b", "again").is_err());
+        assert!(corpus.add_batch(&[("e", "x"), ("e", "y")]).is_err());
+        assert_eq!(4, corpus.len());
+
+        let hits = corpus.search("third", 2).unwrap();
+        assert_eq!(2, hits.len());
+        assert_eq!("c", hits[0].id);
+        assert!((hits[0].score - 1.0).abs() < 1e-5);
+        assert!(hits[0].score >= hits[1].score);
+        assert_eq!(4, corpus.search("third", 10).unwrap().len());
+        assert!(corpus.search_vector(&[1.0; 3], 1).is_err());
+    }
+
+    #[test]
+    fn test_cancelled_add() {
+        // Cancels as soon as the first step is being embedded
+        struct Cancelling(MockProvider, CancelToken);
+        impl EmbeddingProvider for Cancelling {
+            fn name(&self) -> &str {
+                "cancelling"
+            }
+            fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+                self.1.cancel();
+                self.0.embed_batch(texts)
+            }
+        }
+
+        let cancel = CancelToken::new();
+        let corpus = Corpus::new(Arc::new(Cancelling(MockProvider::new(8), cancel.clone())));
+        let documents: Vec<Document> = (0..70)
+            .map(|i| Document {
+                id: i.to_string(),
+                text: format!("document {i}"),
+                metadata: Metadata::new(),
+            })
+            .collect();
+        assert_eq!(
+            32,
+            corpus
+                .add_documents_cancellable(&documents, &cancel)
+                .unwrap()
+        );
+        assert_eq!(32, corpus.len());
+        assert!(corpus.metadata("31").is_some() && corpus.metadata("32").is_none());
+        assert_eq!(
+            0,
+            corpus
+                .add_documents_cancellable(&documents[32..], &cancel)
+                .unwrap()
+        );
+        assert!(corpus
+            .add_documents_cancellable(&documents[..1], &CancelToken::new())
+            .is_err());
+    }
+
+    #[test]
+    fn test_hnsw_corpus() {
+        let provider = Arc::new(MockProvider::new(16));
+        let config = HnswConfig {
+            m: 1,
+            ..HnswConfig::default()
+        };
+        assert!(Corpus::with_hnsw(provider.clone(), config).is_err());
+
+        let corpus = Corpus::with_hnsw(provider, HnswConfig::default()).unwrap();
+        let documents: Vec<(String, String)> = (0..300)
+            .map(|i| (i.to_string(), format!("document {i}")))
+            .collect();
+        corpus.add_batch(&documents).unwrap();
+        for i in [0, 123, 299] {
+            let hits = corpus.search(&format!("document {i}"), 5).unwrap();
+            assert_eq!(5, hits.len());
+            assert_eq!(i.to_string(), hits[0].id);
+            assert!(hits.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        }
+    }
+
+    #[test]
+    fn test_filtered_search() {
+        let documents: Vec<Document> = (0..200)
+            .map(|i| {
+                let serde_json::Value::Object(metadata) = serde_json::json!({
+                    "year": 2000 + i % 25,
+                    "tags": if i % 10 == 0 { vec!["rare"] } else { vec!["common"] },
+                }) else {
+                    unreachable!()
+                };
+                Document {
+                    id: i.to_string(),
+                    text: format!("document {i}"),
+                    metadata,
+                }
+            })
+            .collect();
+        let filter = Filter::from_json(&serde_json::json!({
+            "year": {"$gte": 2010},
+            "tags": {"$contains": "rare"}
+        }))
+        .unwrap();
+
+        let provider = Arc::new(MockProvider::new(16));
+        for corpus in [
+            Corpus::new(provider.clone()),
+            Corpus::with_hnsw(provider.clone(), HnswConfig::default()).unwrap(),
+        ] {
+            corpus.add_documents(&documents).unwrap();
+            assert_eq!(Some(&documents[7].metadata), corpus.metadata("7").as_ref());
+
+            // 12 of the 20 rare documents are from 2010 on
+            let hits = corpus.search_filtered("document 3", 20, &filter).unwrap();
+            assert_eq!(12, hits.len());
+            assert!(hits.iter().all(|hit| filter.matches(&hit.metadata)));
+            let hits = corpus.search_filtered("document 110", 1, &filter).unwrap();
+            assert_eq!("110", hits[0].id);
+        }
+    }
+
+    #[test]
+    fn test_updates_and_deletes() {
+        let provider = Arc::new(MockProvider::new(16));
+        for corpus in [
+            Corpus::new(provider.clone()),
+            Corpus::with_hnsw(provider.clone(), HnswConfig::default()).unwrap(),
+        ] {
+            let documents: Vec<(String, String)> = (0..10)
+                .map(|i| (i.to_string(), format!("document {i}")))
+                .collect();
+            corpus.add_batch(&documents).unwrap();
+
+            assert!(corpus.delete("3"));
+            assert!(!corpus.delete("3"));
+            assert_eq!(9, corpus.len());
+            assert!(corpus
+                .search("document 3", 10)
+                .unwrap()
+                .iter()
+                .all(|hit| hit.id != "3"));
+            corpus.add("3", "document 3 again").unwrap();
+
+            let metadata: Metadata = [("v".to_string(), 2.into())].into_iter().collect();
+            let update = Document {
+                id: "5".to_string(),
+                text: "an updated text".to_string(),
+                metadata: metadata.clone(),
+            };
+            corpus.upsert_documents(&[update]).unwrap();
+            assert_eq!(10, corpus.len());
+            assert_eq!(Some(metadata), corpus.metadata("5"));
+            let hits = corpus.search("an updated text", 10).unwrap();
+            assert_eq!("5", hits[0].id);
+            assert_eq!(1, hits.iter().filter(|hit| hit.id == "5").count());
+
+            // Deleting most documents compacts the index, leaving the rest searchable
+            for i in 0..8 {
+                corpus.delete(&i.to_string());
+            }
+            assert_eq!(2, corpus.len());
+            assert!(corpus.index.read().unwrap().ids.len() < 2 * corpus.len());
+            assert_eq!("9", corpus.search("document 9", 1).unwrap()[0].id);
+            corpus.delete("8");
+            corpus.compact();
+            assert_eq!(1, corpus.index.read().unwrap().ids.len());
+        }
+    }
+
+    #[test]
+    fn test_hybrid_search() {
+        let provider = Arc::new(MockProvider::new(16));
+        for corpus in [
+            Corpus::new(provider.clone()),
+            Corpus::with_hnsw(provider.clone(), HnswConfig::default()).unwrap(),
+        ] {
+            let mut documents: Vec<(String, String)> = (0..40)
+                .map(|i| (i.to_string(), format!("an ordinary document, number {i}")))
+                .collect();
+            documents[17].1 = "Invoice ZX-4411 is overdue".to_string();
+            corpus.add_batch(&documents).unwrap();
+
+            let hits = corpus.search_lexical("zx 4411", 5).unwrap();
+            assert_eq!(1, hits.len());
+            assert_eq!("17", hits[0].id);
+            assert_eq!(39, corpus.search_lexical("document", 50).unwrap().len());
+
+            // The mock provider's embeddings say nothing about meaning, yet fusion finds it
+            let hits = corpus
+                .search_hybrid("invoice zx-4411", 3, Fusion::default())
+                .unwrap();
+            assert_eq!(3, hits.len());
+            assert_eq!("17", hits[0].id);
+            let lexical_only = Fusion::Weighted { dense_weight: 0.0 };
+            let hits = corpus.search_hybrid("overdue", 1, lexical_only).unwrap();
+            assert_eq!("17", hits[0].id);
+            assert!((hits[0].score - 1.0).abs() < 1e-6);
+            let invalid = Fusion::Weighted { dense_weight: -1.0 };
+            assert!(corpus.search_hybrid("overdue", 1, invalid).is_err());
+
+            // Replaced and deleted documents leave the lexical index too
+            let update = Document {
+                id: "17".to_string(),
+                text: "paid in full".to_string(),
+                metadata: Metadata::new(),
+            };
+            corpus.upsert_documents(&[update]).unwrap();
+            assert!(corpus.search_lexical("overdue", 5).unwrap().is_empty());
+            assert_eq!("17", corpus.search_lexical("paid", 5).unwrap()[0].id);
+            corpus.delete("17");
+            assert!(corpus.search_lexical("paid", 5).unwrap().is_empty());
+            let filter = Filter::from_json(&serde_json::json!({"missing": 1})).unwrap();
+            let hits = corpus.search_hybrid_filtered("document", 5, Fusion::default(), &filter);
+            assert!(hits.unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("corpus-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let provider = Arc::new(MockProvider::new(16));
+        let documents: Vec<Document> = (0..50)
+            .map(|i| Document {
+                id: format!("doc-{i}"),
+                text: format!("text {i}"),
+                metadata: [("i".to_string(), i.into())].into_iter().collect(),
+            })
+            .collect();
+
+        for (name, corpus) in [
+            ("flat", Corpus::new(provider.clone())),
+            (
+                "hnsw",
+                Corpus::with_hnsw(provider.clone(), HnswConfig::default()).unwrap(),
+            ),
+        ] {
+            corpus.add_documents(&documents).unwrap();
+            corpus.delete("doc-0");
+            let path = dir.join(name);
+            corpus.save(&path).unwrap();
+
+            let loaded = Corpus::load(&path, provider.clone()).unwrap();
+            assert_eq!(49, loaded.len());
+            assert_eq!(None, loaded.metadata("doc-0"));
+            assert_eq!(
+                corpus.search("text 7", 5).unwrap(),
+                loaded.search("text 7", 5).unwrap()
+            );
+            assert_eq!(
+                corpus.search_lexical("text 7", 5).unwrap(),
+                loaded.search_lexical("text 7", 5).unwrap()
+            );
+            loaded.add("new", "a new document").unwrap();
+            assert_eq!("new", loaded.search("a new document", 1).unwrap()[0].id);
+
+            // Another model's queries can't search these vectors
+            assert!(Corpus::load(&path, Arc::new(MockProvider::new(8))).is_err());
+
+            // Files of an older format are refused rather than misread
+            let mut bytes = std::fs::read(&path).unwrap();
+            bytes[8..12].copy_from_slice(&3u32.to_le_bytes());
+            std::fs::write(&path, bytes).unwrap();
+            let Err(e) = Corpus::load(&path, provider.clone()) else {
+                panic!("loaded a corpus of format version 3");
+            };
+            assert!(e.to_string().contains("version 3"), "{e}");
+        }
+
+        std::fs::write(dir.join("bad"), b"RECORPUS\x07\0\0\0").unwrap();
+        assert!(Corpus::load(dir.join("bad"), provider.clone()).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,184 @@
+use crate::embedder::normalize;
+use crate::error::{Error, Result};
+use crate::hnsw::{Hnsw, HnswConfig, Vectors};
+use crate::provider::EmbeddingProvider;
+
+/// Settings of [`near_duplicates`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DedupConfig {
+    /// Cosine similarity from which two vectors are duplicates.
+    pub threshold: f32,
+    /// Nearest neighbors compared with each vector. Groups are closed transitively, so a
+    /// larger group than this is still found whole; raising it only helps when many
+    /// non-duplicates sit closer to a vector than its duplicates do.
+    pub candidates: usize,
+    pub hnsw: HnswConfig,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        DedupConfig {
+            threshold: 0.95,
+            candidates: 16,
+            hnsw: HnswConfig::default(),
+        }
+    }
+}
+
+/// Groups of near-duplicate vectors, as indices into `vectors`: every vector in a group has a
+/// cosine similarity of at least `config.threshold` to another one in it, directly or through
+/// a chain of such pairs. Vectors without a duplicate are left out; groups are sorted, and
+/// ordered by their first index.
+///
+/// Rather than comparing every pair, each vector is compared with its nearest neighbors among
+/// the ones before it in an HNSW graph, which keeps large inputs fast at the cost of
+/// occasionally missing a pair.
+pub fn near_duplicates<V: AsRef<[f32]>>(
+    vectors: &[V],
+    config: &DedupConfig,
+) -> Result<Vec<Vec<usize>>> {
+    if !(-1.0..=1.0).contains(&config.threshold) || config.candidates == 0 {
+        return Err(Error::InvalidArgument(
+            "dedup needs a threshold between -1 and 1 and at least 1 candidate".to_string(),
+        ));
+    }
+    config.hnsw.validate()?;
+    let dim = vectors.first().map_or(0, |v| v.as_ref().len());
+    let mut data = Vec::with_capacity(vectors.len() * dim);
+    for vector in vectors {
+        let vector = vector.as_ref();
+        if vector.len() != dim {
+            return Err(Error::InvalidArgument(format!(
+                "vectors of length {dim} and {} can't be compared",
+                vector.len()
+            )));
+        }
+        let start = data.len();
+        data.extend_from_slice(vector);
+        normalize(&mut data[start..]);
+    }
+
+    let mut hnsw = Hnsw::new(config.hnsw);
+    let mut groups = Groups::new(vectors.len());
+    for node in 0..vectors.len() {
+        let query = &data[node * dim..(node + 1) * dim];
+        let earlier = Vectors {
+            data: &data[..node * dim],
+            dim,
+        };
+        for (other, similarity) in hnsw.search(earlier, query, config.candidates, &|_| true) {
+            if similarity >= config.threshold {
+                groups.join(other as usize, node);
+            }
+        }
+        hnsw.insert(Vectors {
+            data: &data[..(node + 1) * dim],
+            dim,
+        });
+    }
+    Ok(groups.into_groups())
+}
+
+/// Embed `texts` with `provider` and find the [`near_duplicates`] among them.
+pub fn near_duplicate_texts<S: AsRef<str>>(
+    provider: &dyn EmbeddingProvider,
+    texts: &[S],
+    config: &DedupConfig,
+) -> Result<Vec<Vec<usize>>> {
+    let texts: Vec<&str> = texts.iter().map(AsRef::as_ref).collect();
+    near_duplicates(&provider.embed_batch(&texts)?, config)
+}
+
+// Union-find over the input indices; every root is the smallest index of its group.
+struct Groups {
+    parent: Vec<usize>,
+}
+
+impl Groups {
+    fn new(len: usize) -> Self {
+        Groups {
+            parent: (0..len).collect(),
+        }
+    }
+
+    fn root(&mut self, mut node: usize) -> usize {
+        while self.parent[node] != node {
+            self.parent[node] = self.parent[self.parent[node]];
+            node = self.parent[node];
+        }
+        node
+    }
+
+    fn join(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.root(a), self.root(b));
+        self.parent[a.max(b)] = a.min(b);
+    }
+
+    fn into_groups(mut self) -> Vec<Vec<usize>> {
+        let mut members: Vec<Vec<usize>> = vec![Vec::new(); self.parent.len()];
+        for node in 0..self.parent.len() {
+            let root = self.root(node);
+            members[root].push(node);
+        }
+        members.retain(|group| group.len() > 1);
+        members
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernels::dot;
+
+    #[test]
+    fn test_near_duplicates() {
+        let dim = 16;
+        let mut state = 11u32;
+        let mut random = || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as f32 / 65_536.0 - 0.5
+        };
+        let mut vectors: Vec<Vec<f32>> = (0..600)
+            .map(|_| (0..dim).map(|_| random()).collect())
+            .collect();
+        // Slightly perturbed and rescaled copies of every 25th vector, three of the first
+        for (copy, original) in (0..600).step_by(25).chain([0, 0]).enumerate() {
+            let noisy = vectors[original]
+                .iter()
+                .map(|x| (x + 0.01 * random()) * (copy + 1) as f32)
+                .collect();
+            vectors.push(noisy);
+        }
+
+        let config = DedupConfig {
+            threshold: 0.98,
+            ..Default::default()
+        };
+        let groups = near_duplicates(&vectors, &config).unwrap();
+        assert_eq!(24, groups.len());
+        assert_eq!(vec![0, 600, 624, 625], groups[0]);
+        assert!(groups.iter().skip(1).all(|group| group.len() == 2));
+
+        // Nothing a brute-force comparison finds is missing
+        let mut unit = vectors.clone();
+        unit.iter_mut().for_each(|v| normalize(v));
+        for (i, a) in unit.iter().enumerate() {
+            for (j, b) in unit.iter().enumerate().skip(i + 1) {
+                if dot(a, b) >= config.threshold {
+                    assert!(groups.iter().any(|g| g.contains(&i) && g.contains(&j)));
+                }
+            }
+        }
+
+        let loose = near_duplicates(&vectors, &DedupConfig::default()).unwrap();
+        assert!(loose.len() >= 24);
+        let none: [Vec<f32>; 0] = [];
+        assert!(near_duplicates(&none, &config).unwrap().is_empty());
+        assert!(near_duplicates(&[vec![1.0, 0.0], vec![1.0]], &config).is_err());
+        let invalid = DedupConfig {
+            threshold: 1.5,
+            ..config
+        };
+        assert!(near_duplicates(&vectors, &invalid).is_err());
+    }
+}
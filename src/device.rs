@@ -0,0 +1,315 @@
+use crate::embedder::Embedder;
+use crate::error::{Error, Result};
+use crate::options::{EmbedOptions, Embedding};
+use crate::progress::Progress;
+use candle::{Device, DeviceLocation};
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Where a model's weights live and its forward passes run.
+///
+/// GPUs need candle built with its `cuda` or `metal` feature, e.g. by depending on
+/// `candle-core` with that feature next to this crate; otherwise loading on them fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum DeviceKind {
+    #[default]
+    Cpu,
+    /// An NVIDIA GPU by ordinal.
+    Cuda(usize),
+    /// An Apple GPU by ordinal.
+    Metal(usize),
+    /// The first CUDA GPU if one can be opened, else the first Metal GPU, else the CPU, so one
+    /// configuration runs everywhere. [`Embedder::device`] tells which was picked.
+    Auto,
+}
+
+impl DeviceKind {
+    /// The device `self` stands for: [`DeviceKind::Auto`] resolved to what this machine and
+    /// build offer, any other kind as is.
+    pub fn resolve(self) -> DeviceKind {
+        match self {
+            DeviceKind::Auto => DeviceKind::of(&auto_device()),
+            kind => kind,
+        }
+    }
+
+    pub(crate) fn open(self) -> Result<Device> {
+        Ok(match self {
+            DeviceKind::Cpu => Device::Cpu,
+            DeviceKind::Cuda(ordinal) => Device::new_cuda(ordinal)?,
+            DeviceKind::Metal(ordinal) => Device::new_metal(ordinal)?,
+            DeviceKind::Auto => auto_device(),
+        })
+    }
+
+    // Total and available bytes of the device's memory where they can be read: the system
+    // memory on Linux and Android for the CPU.
+    pub(crate) fn memory(self) -> Option<(u64, u64)> {
+        match self {
+            DeviceKind::Cpu => system_memory(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn of(device: &Device) -> Self {
+        match device.location() {
+            DeviceLocation::Cpu => DeviceKind::Cpu,
+            DeviceLocation::Cuda { gpu_id } => DeviceKind::Cuda(gpu_id),
+            DeviceLocation::Metal { gpu_id } => DeviceKind::Metal(gpu_id),
+        }
+    }
+}
+
+fn auto_device() -> Device {
+    // The availability checks only say whether candle was built with the backend
+    let device = if let Some(device) = candle::utils::cuda_is_available()
+        .then(|| Device::new_cuda(0).ok())
+        .flatten()
+    {
+        device
+    } else if let Some(device) = candle::utils::metal_is_available()
+        .then(|| Device::new_metal(0).ok())
+        .flatten()
+    {
+        device
+    } else {
+        Device::Cpu
+    };
+    tracing::info!(device = %DeviceKind::of(&device), "picked a device");
+    device
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn system_memory() -> Option<(u64, u64)> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let field = |name: &str| -> Option<u64> {
+        let line = meminfo.lines().find_map(|line| line.strip_prefix(name))?;
+        let kb: u64 = line.trim().trim_end_matches("kB").trim().parse().ok()?;
+        Some(kb * 1024)
+    };
+    Some((field("MemTotal:")?, field("MemAvailable:")?))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn system_memory() -> Option<(u64, u64)> {
+    None
+}
+
+/// Parses `cpu`, `cuda`, `cuda:1`, `metal`, `metal:0` or `auto`; the ordinal defaults to 0.
+impl FromStr for DeviceKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "auto" {
+            return Ok(DeviceKind::Auto);
+        }
+        let (name, ordinal) = match s.split_once(':') {
+            Some((name, ordinal)) => (name, Some(ordinal)),
+            None => (s, None),
+        };
+        let ordinal = match ordinal.map(str::parse) {
+            Some(Ok(ordinal)) => ordinal,
+            Some(Err(_)) => return Err(Error::InvalidArgument(format!("invalid device {s:?}"))),
+            None => 0,
+        };
+        match name {
+            "cpu" if ordinal == 0 => Ok(DeviceKind::Cpu),
+            "cuda" => Ok(DeviceKind::Cuda(ordinal)),
+            "metal" => Ok(DeviceKind::Metal(ordinal)),
+            _ => Err(Error::InvalidArgument(format!("invalid device {s:?}"))),
+        }
+    }
+}
+
+impl Serialize for DeviceKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl fmt::Display for DeviceKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceKind::Cpu => write!(f, "cpu"),
+            DeviceKind::Cuda(ordinal) => write!(f, "cuda:{ordinal}"),
+            DeviceKind::Metal(ordinal) => write!(f, "metal:{ordinal}"),
+            DeviceKind::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+/// Replicas of one model, e.g. on several GPUs, for large offline jobs. A batch is split into
+/// shards of [`DevicePool::shard_size`] texts that are dealt to the replicas in turn and
+/// embedded concurrently, one thread per replica, so throughput grows with the number of
+/// devices.
+///
+/// Replicas on the CPU share their weights and its cores; they pay off when single forward
+/// passes leave cores idle, such as with short texts, by overlapping tokenization and pooling
+/// with the forward passes of other shards.
+pub struct DevicePool {
+    replicas: Vec<Embedder>,
+    shard_size: usize,
+}
+
+impl DevicePool {
+    /// A pool over already loaded replicas, which should be the same model with the same
+    /// settings.
+    pub fn new(replicas: Vec<Embedder>) -> Result<Self> {
+        if replicas.is_empty() {
+            return Err(Error::InvalidArgument(
+                "a device pool needs at least one replica".to_string(),
+            ));
+        }
+        Ok(DevicePool {
+            replicas,
+            shard_size: 64,
+        })
+    }
+
+    /// Load a replica of the model on each of `devices`, taking the files
+    /// [`Embedder::load`] takes. A device listed more than once gets further replicas sharing
+    /// the weights of its first, e.g. `[Cpu, Cpu, Cpu, Cpu]` for four CPU workers.
+    pub fn load(
+        config_path: impl AsRef<Path>,
+        tokenizer_path: impl AsRef<Path>,
+        weights_path: impl AsRef<Path>,
+        approximate_gelu: bool,
+        devices: &[DeviceKind],
+    ) -> Result<Self> {
+        let mut replicas: Vec<Embedder> = Vec::with_capacity(devices.len());
+        for device in devices.iter().map(|device| device.resolve()) {
+            let replica = match replicas.iter().find(|replica| replica.device() == device) {
+                Some(replica) => replica.clone(),
+                None => Embedder::load_on_device(
+                    config_path.as_ref(),
+                    tokenizer_path.as_ref(),
+                    weights_path.as_ref(),
+                    approximate_gelu,
+                    device,
+                )?,
+            };
+            replicas.push(replica);
+        }
+        DevicePool::new(replicas)
+    }
+
+    /// Texts per shard, 64 by default: large enough to keep a GPU busy, small enough to
+    /// spread a batch over every replica.
+    pub fn with_shard_size(mut self, shard_size: usize) -> Result<Self> {
+        if shard_size == 0 {
+            return Err(Error::InvalidArgument(
+                "shard size must be at least 1".to_string(),
+            ));
+        }
+        self.shard_size = shard_size;
+        Ok(self)
+    }
+
+    pub fn shard_size(&self) -> usize {
+        self.shard_size
+    }
+
+    pub fn replicas(&self) -> &[Embedder] {
+        &self.replicas
+    }
+
+    /// [`Embedder::embed_batch`] across the replicas, returning the embeddings in input order.
+    /// Shard `i` goes to replica `i % replicas`; if shards fail, the error of the first is
+    /// returned.
+    pub fn embed_batch<S: AsRef<str> + Sync>(
+        &self,
+        texts: &[S],
+        options: &EmbedOptions,
+    ) -> Result<Vec<Embedding>> {
+        if self.replicas.len() == 1 || texts.len() <= self.shard_size {
+            return self.replicas[0].embed_batch(texts, options);
+        }
+        let shards: Vec<&[S]> = texts.chunks(self.shard_size).collect();
+        let progress = Progress::start(texts.len());
+        let mut results: Vec<Option<Result<Vec<Embedding>>>> =
+            std::iter::repeat_with(|| None).take(shards.len()).collect();
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = self
+                .replicas
+                .iter()
+                .enumerate()
+                .map(|(worker, replica)| {
+                    let (shards, progress) = (&shards, &progress);
+                    scope.spawn(move || {
+                        progress.part(|| {
+                            (worker..shards.len())
+                                .step_by(self.replicas.len())
+                                .map(|i| {
+                                    let result = replica.embed_batch(shards[i], options);
+                                    if result.is_ok() {
+                                        progress.advance(shards[i].len());
+                                    }
+                                    (i, result)
+                                })
+                                .collect::<Vec<_>>()
+                        })
+                    })
+                })
+                .collect();
+            for worker in workers {
+                for (i, result) in worker.join().unwrap() {
+                    results[i] = Some(result);
+                }
+            }
+        });
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for result in results {
+            embeddings.extend(result.unwrap()?);
+        }
+        Ok(embeddings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_pool() {
+        assert_eq!(DeviceKind::Cuda(1), "cuda:1".parse().unwrap());
+        assert_eq!(DeviceKind::Metal(0), "metal".parse().unwrap());
+        assert_eq!("cuda:1", DeviceKind::Cuda(1).to_string());
+        assert!("cpu:1".parse::<DeviceKind>().is_err());
+        assert!("tpu".parse::<DeviceKind>().is_err());
+        assert_eq!(DeviceKind::Auto, "auto".parse().unwrap());
+        assert!("auto:0".parse::<DeviceKind>().is_err());
+        assert_ne!(DeviceKind::Auto, DeviceKind::Auto.resolve());
+
+        let devices = [DeviceKind::Cpu; 3];
+        let pool = DevicePool::load(
+            "models/gte-small/config.json",
+            "models/gte-small/tokenizer.json",
+            "models/gte-small/model.safetensors",
+            false,
+            &devices,
+        )
+        .unwrap()
+        .with_shard_size(2)
+        .unwrap();
+        assert_eq!(3, pool.replicas().len());
+        assert!(pool
+            .replicas()
+            .iter()
+            .all(|r| r.device() == DeviceKind::Cpu));
+
+        let texts: Vec<String> = (0..7).map(|i| format!("text number {i}")).collect();
+        let options = EmbedOptions::default();
+        let pooled = pool.embed_batch(&texts, &options).unwrap();
+        let single = pool.replicas()[0].embed_batch(&texts, &options).unwrap();
+        assert_eq!(7, pooled.len());
+        for (a, b) in pooled.iter().zip(&single) {
+            let (a, b) = (a.to_f32(), b.to_f32());
+            assert!(a.iter().zip(&b).all(|(x, y)| (x - y).abs() < 1e-4));
+        }
+
+        assert!(DevicePool::new(Vec::new()).is_err());
+        assert!(pool.with_shard_size(0).is_err());
+    }
+}
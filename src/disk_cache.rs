@@ -0,0 +1,197 @@
+use crate::cache::CacheKey;
+use crate::error::Result;
+use crate::kernels::{dot, squared_distance};
+use crate::options::{Embedding, OutputDtype};
+use rusqlite::{Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::Mutex;
+
+// How far, relative to its length, the probe embedding may move before the cache is considered
+// to belong to another model; rounding differences between CPUs stay well below it.
+const PROBE_TOLERANCE: f32 = 1e-3;
+
+/// A persistent cache of embeddings in a SQLite file, installed on a model with
+/// [`Embedder::set_disk_cache`](crate::Embedder::set_disk_cache), so re-running a large
+/// embedding job only computes the texts that changed.
+///
+/// Entries are keyed by the same hash of text and options as
+/// [`EmbeddingCache`](crate::EmbeddingCache). The file also records which model wrote them,
+/// identified by the embedding of a probe sentence and its task prefixes: once a different
+/// model (or the same one with settings that change its output) uses the file, every entry is
+/// deleted. Use one file per model.
+pub struct DiskCache {
+    connection: Mutex<Connection>,
+}
+
+impl DiskCache {
+    /// Open the cache at `path`, creating the file if needed.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS model (probe BLOB NOT NULL, settings BLOB NOT NULL);
+             CREATE TABLE IF NOT EXISTS embeddings (
+                 key BLOB PRIMARY KEY,
+                 vector BLOB NOT NULL
+             ) WITHOUT ROWID;",
+        )?;
+        Ok(DiskCache {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// The number of cached embeddings.
+    pub fn len(&self) -> Result<usize> {
+        let connection = self.connection.lock().unwrap();
+        let count: i64 =
+            connection.query_row("SELECT COUNT(*) FROM embeddings", [], |row| row.get(0))?;
+        Ok(count as usize)
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Delete every entry, e.g. after changing a preprocessor in a way the probe sentence
+    /// doesn't show.
+    pub fn clear(&self) -> Result<()> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute_batch("DELETE FROM embeddings; DELETE FROM model;")?;
+        Ok(())
+    }
+
+    // Claim the file for the model whose probe embedding and settings hash are given, deleting
+    // the entries of any other model. Returns whether entries were invalidated.
+    pub(crate) fn bind(&self, probe: &[f32], settings: &[u8]) -> Result<bool> {
+        let mut connection = self.connection.lock().unwrap();
+        let stored = connection
+            .query_row("SELECT probe, settings FROM model", [], |row| {
+                Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })
+            .optional()?;
+        if let Some((stored_probe, stored_settings)) = &stored {
+            let stored_probe = from_bytes(stored_probe);
+            if stored_settings == settings && same_probe(probe, &stored_probe) {
+                return Ok(false);
+            }
+        }
+        let transaction = connection.transaction()?;
+        transaction.execute_batch("DELETE FROM embeddings; DELETE FROM model;")?;
+        transaction.execute(
+            "INSERT INTO model (probe, settings) VALUES (?1, ?2)",
+            (to_bytes(probe), settings),
+        )?;
+        transaction.commit()?;
+        Ok(stored.is_some())
+    }
+
+    // The cached embeddings of `keys` in `dtype`, `None` for the ones not in the file.
+    pub(crate) fn get(
+        &self,
+        keys: &[CacheKey],
+        dtype: OutputDtype,
+    ) -> Result<Vec<Option<Embedding>>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement =
+            connection.prepare_cached("SELECT vector FROM embeddings WHERE key = ?1")?;
+        keys.iter()
+            .map(|key| {
+                let vector = statement
+                    .query_row([key.as_slice()], |row| row.get::<_, Vec<u8>>(0))
+                    .optional()?;
+                Ok(vector.map(|vector| Embedding::from_f32(from_bytes(&vector), dtype)))
+            })
+            .collect()
+    }
+
+    // Store `entries` in a single transaction. Embeddings are kept as f32, which every output
+    // type converts to and from without loss.
+    pub(crate) fn insert(&self, entries: &[(CacheKey, &Embedding)]) -> Result<()> {
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        {
+            let mut statement = transaction.prepare_cached(
+                "INSERT OR REPLACE INTO embeddings (key, vector) VALUES (?1, ?2)",
+            )?;
+            for (key, embedding) in entries {
+                statement.execute((key.as_slice(), to_bytes(&embedding.to_f32())))?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+fn same_probe(probe: &[f32], stored: &[f32]) -> bool {
+    probe.len() == stored.len()
+        && squared_distance(probe, stored).sqrt() <= PROBE_TOLERANCE * dot(stored, stored).sqrt()
+}
+
+fn to_bytes(values: &[f32]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn from_bytes(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::EmbedOptions;
+    use crate::Embedder;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_disk_cache() {
+        let path = std::env::temp_dir().join(format!("disk-cache-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut embedder = Embedder::load(
+            "models/gte-small/config.json",
+            "models/gte-small/tokenizer.json",
+            "models/gte-small/model.safetensors",
+            false,
+        )
+        .unwrap();
+        let cache = Arc::new(DiskCache::open(&path).unwrap());
+        embedder.set_disk_cache(Some(Arc::clone(&cache)));
+        let options = EmbedOptions::default();
+
+        let texts = ["first document", "second document"];
+        let embeddings = embedder.embed_batch(&texts, &options).unwrap();
+        assert_eq!(2, cache.len().unwrap());
+
+        // Reopening the file keeps the entries, which skip the forward pass
+        let reopened = Arc::new(DiskCache::open(&path).unwrap());
+        embedder.set_disk_cache(Some(Arc::clone(&reopened)));
+        let (embedding, timings) = embedder.embed_with_timings(texts[1], &options).unwrap();
+        assert_eq!(embeddings[1], embedding);
+        assert_eq!(Duration::ZERO, timings.forward);
+        let f16 = EmbedOptions {
+            dtype: OutputDtype::F16,
+            ..EmbedOptions::default()
+        };
+        let embedding = embedder.embed_with_options(texts[0], &f16).unwrap();
+        assert_eq!(
+            Embedding::from_f32(embeddings[0].to_f32(), OutputDtype::F16),
+            embedding
+        );
+        assert_eq!(3, reopened.len().unwrap());
+
+        // Different output, different model: the entries are invalidated
+        embedder.set_transforms(vec![crate::Transform::Normalize]);
+        embedder.embed(texts[0]).unwrap();
+        assert_eq!(1, reopened.len().unwrap());
+
+        reopened.clear().unwrap();
+        assert!(reopened.is_empty().unwrap());
+        drop((cache, reopened, embedder));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,2731 @@
+use crate::audit::AuditLog;
+use crate::bert::{Attention, BertModel, Config, HiddenAct, QuantizedWeights, DTYPE};
+use crate::cache::{cache_key, CacheKey, EmbeddingCache};
+use crate::cancel::CancelToken;
+use crate::cleanup::TextCleanup;
+use crate::device::DeviceKind;
+#[cfg(feature = "sqlite")]
+use crate::disk_cache::DiskCache;
+use crate::error::{Error, Result};
+use crate::kernels::dot;
+use crate::lora::LoraAdapter;
+#[cfg(feature = "ort")]
+use crate::onnx::OnnxModel;
+use crate::options::{
+    EmbedOptions, Embedding, Overflow, Pooling, TaskPrefixes, Timings, TruncationStrategy,
+};
+use crate::power;
+use crate::progress::{Progress, PROGRESS_STEP};
+use crate::quantize::{is_gguf, read_gguf};
+use crate::similarity::similarity_matrix_on;
+use crate::stats::{self, Phase};
+use crate::transform::{apply_all, Transform};
+use crate::Instant;
+use candle::{Device, Tensor};
+use candle_nn::VarBuilder;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokenizers::{
+    pad_encodings, truncate_encodings, Encoding, PostProcessor, Tokenizer, TruncationDirection,
+    TruncationParams,
+};
+
+/// Which encoder hidden states a sentence embedding is pooled from.
+///
+/// Hidden states are indexed like in transformers: `0` is the embedding layer output and
+/// `1..=num_hidden_layers` are the outputs of the encoder layers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LayerSelection {
+    /// The final encoder layer (the usual sentence embedding).
+    #[default]
+    Last,
+    /// A single hidden state by index.
+    Layer(usize),
+    /// The last `n` encoder layers concatenated along the hidden dimension, earliest first.
+    ConcatLast(usize),
+    /// The element-wise average of the last `n` encoder layers.
+    MeanLast(usize),
+}
+
+/// What [`Embedder::info`] reports about a loaded model.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ModelInfo {
+    /// `candle`, or `onnx` for [`Embedder::load_onnx`] models.
+    pub backend: &'static str,
+    /// Where the forward passes run; for a model loaded on [`DeviceKind::Auto`], the device
+    /// that was picked.
+    pub device: DeviceKind,
+    pub model_type: Option<String>,
+    /// Length of the embeddings with the default options, after any transforms.
+    pub embedding_dim: usize,
+    pub num_hidden_layers: usize,
+    pub max_position_embeddings: usize,
+    pub vocab_size: usize,
+}
+
+/// What [`Embedder::memory_usage`] reports, in bytes, so an embedder can be budgeted for
+/// within a memory-constrained host app.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MemoryUsage {
+    /// The model's weights as loaded, quantized weights at their quantized size. Clones of the
+    /// embedder share them. `None` for ONNX models, whose weights ONNX Runtime holds.
+    pub weight_bytes: Option<usize>,
+    /// The activations alive at once at the peak of the latest forward pass, estimated from
+    /// its batch size and padded length, 0 before the first. Grows with the batch size and
+    /// the square of the length; [`Attention::Chunked`] bounds the quadratic part. `None` for
+    /// ONNX models.
+    pub peak_activation_bytes: Option<usize>,
+    /// Entries of the in-memory cache and the approximate memory they hold, 0 without one.
+    pub cache_entries: usize,
+    pub cache_bytes: usize,
+    pub device: DeviceKind,
+    /// Total and available memory of the device where they can be read: the system memory
+    /// on Linux and Android for the CPU.
+    pub device_total_bytes: Option<u64>,
+    pub device_free_bytes: Option<u64>,
+}
+
+/// How much one token drove an embedding, from [`Embedder::explain`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TokenContribution {
+    pub token: String,
+    /// Byte range of the token in the text as it was tokenized: after the preprocessor, text
+    /// cleanup, instruction and prefix. Empty for special tokens.
+    pub start: usize,
+    pub end: usize,
+    /// The token's share of the embedding; the scores of a text's tokens sum to 1.
+    pub score: f32,
+    /// Whether the tokenizer added the token, such as `[CLS]` and `[SEP]`.
+    pub special: bool,
+}
+
+/// An embedding with the contribution of every token of its input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+    pub embedding: Embedding,
+    pub tokens: Vec<TokenContribution>,
+}
+
+/// A text transformation applied to every input before tokenization, e.g. to scrub PII.
+pub type Preprocessor = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+// The files of the `embedded-model` feature, from the directory chosen by the build script.
+#[cfg(feature = "embedded-model")]
+mod embedded {
+    pub(super) static CONFIG: &[u8] =
+        include_bytes!(concat!(env!("EMBEDDED_MODEL_DIR"), "/config.json"));
+    pub(super) static TOKENIZER: &[u8] =
+        include_bytes!(concat!(env!("EMBEDDED_MODEL_DIR"), "/tokenizer.json"));
+    pub(super) static WEIGHTS: &[u8] =
+        include_bytes!(concat!(env!("EMBEDDED_MODEL_DIR"), "/model.safetensors"));
+}
+
+// The network producing hidden states; tokenization, pooling and post-processing are shared.
+#[derive(Clone)]
+enum Model {
+    Candle(BertModel),
+    #[cfg(feature = "ort")]
+    Onnx(OnnxModel),
+}
+
+impl Model {
+    fn device(&self) -> &Device {
+        match self {
+            Model::Candle(model) => &model.device,
+            #[cfg(feature = "ort")]
+            Model::Onnx(model) => &model.device,
+        }
+    }
+}
+
+// The audit log installed on a model and the model name its entries carry.
+#[derive(Clone)]
+struct Audit {
+    log: Arc<AuditLog>,
+    model: String,
+}
+
+/// A loaded BERT model together with its tokenizer.
+///
+/// Embedding only needs `&self`, so one instance can be shared across threads (e.g. in an
+/// `Arc`) and run forward passes concurrently. Cloning is cheap: the weights are shared.
+#[derive(Clone)]
+pub struct Embedder {
+    model: Model,
+    tokenizer: Tokenizer,
+    // The tokenizer without any padding or truncation, for token counting and ids.
+    raw_tokenizer: Tokenizer,
+    config: Config,
+    cleanup: Option<TextCleanup>,
+    preprocessor: Option<Preprocessor>,
+    transforms: Vec<Transform>,
+    task_prefixes: TaskPrefixes,
+    instruction: Option<String>,
+    // The `set_truncation` length and strategy, used by calls that don't set their own.
+    truncation: Option<(usize, TruncationStrategy)>,
+    audit: Option<Audit>,
+    cache: Option<Arc<EmbeddingCache>>,
+    #[cfg(feature = "sqlite")]
+    disk_cache: Option<DiskBinding>,
+}
+
+// A disk cache and whether it was claimed for the current model and settings yet, which
+// happens on first use since it takes an embedding call.
+#[cfg(feature = "sqlite")]
+#[derive(Clone)]
+struct DiskBinding {
+    cache: Arc<DiskCache>,
+    bound: Arc<std::sync::atomic::AtomicBool>,
+}
+
+#[cfg(feature = "sqlite")]
+impl DiskBinding {
+    fn new(cache: Arc<DiskCache>) -> Self {
+        DiskBinding {
+            cache,
+            bound: Arc::default(),
+        }
+    }
+}
+
+// The files of a safetensors checkpoint: the shards listed by a `.index.json`, next to it, or
+// the single file given.
+pub(crate) fn safetensors_shards(weights_path: &Path) -> Result<Vec<PathBuf>> {
+    let is_index = weights_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.ends_with(".index.json"));
+    if !is_index {
+        return Ok(vec![weights_path.to_path_buf()]);
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Index {
+        weight_map: BTreeMap<String, String>,
+    }
+    let index: Index = serde_json::from_slice(&std::fs::read(weights_path)?)?;
+    let dir = weights_path.parent().unwrap_or(Path::new(""));
+    let shards: BTreeSet<&String> = index.weight_map.values().collect();
+    if shards.is_empty() {
+        return Err(Error::InvalidArgument(format!(
+            "{} lists no shards",
+            weights_path.display()
+        )));
+    }
+    Ok(shards.into_iter().map(|shard| dir.join(shard)).collect())
+}
+
+pub(crate) fn is_pytorch(weights_path: &Path) -> bool {
+    weights_path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| matches!(extension, "bin" | "pt" | "pth"))
+}
+
+// Every tensor of a PyTorch checkpoint, read up front since the zip archive isn't mapped.
+fn pytorch_weights(weights_path: &Path, device: &Device) -> Result<VarBuilder<'static>> {
+    let tensors = candle::pickle::read_all(weights_path)?
+        .into_iter()
+        .map(|(name, tensor)| (pytorch_name(name), tensor))
+        .collect();
+    Ok(VarBuilder::from_tensors(tensors, DTYPE, device))
+}
+
+// Safetensors weights, memory-mapped unless the `no-mmap` feature is on, a PyTorch checkpoint
+// or a quantized GGUF file, dequantized.
+pub(crate) fn load_weights(weights_path: &Path, device: &Device) -> Result<VarBuilder<'static>> {
+    if is_pytorch(weights_path) {
+        return pytorch_weights(weights_path, device);
+    }
+    if is_gguf(weights_path) {
+        let (tensors, _) = read_gguf(weights_path, device, true)?;
+        return Ok(VarBuilder::from_tensors(tensors, DTYPE, device));
+    }
+    let shards = safetensors_shards(weights_path)?;
+    #[cfg(not(feature = "no-mmap"))]
+    let vb = unsafe { VarBuilder::from_mmaped_safetensors(&shards, DTYPE, device)? };
+    #[cfg(feature = "no-mmap")]
+    let vb = match shards.as_slice() {
+        [path] => VarBuilder::from_buffered_safetensors(std::fs::read(path)?, DTYPE, device)?,
+        _ => {
+            let mut tensors = std::collections::HashMap::new();
+            for shard in &shards {
+                tensors.extend(candle::safetensors::load(shard, device)?);
+            }
+            VarBuilder::from_tensors(tensors, DTYPE, device)
+        }
+    };
+    Ok(vb)
+}
+
+// The oldest BERT checkpoints still name the LayerNorm parameters `gamma` and `beta`.
+pub(crate) fn pytorch_name(name: String) -> String {
+    if let Some(layer) = name.strip_suffix(".gamma") {
+        format!("{layer}.weight")
+    } else if let Some(layer) = name.strip_suffix(".beta") {
+        format!("{layer}.bias")
+    } else {
+        name
+    }
+}
+
+// The sentence whose embedding identifies a model to a disk cache.
+#[cfg(feature = "sqlite")]
+const PROBE_TEXT: &str = "The quick brown fox jumps over the lazy dog.";
+
+impl Embedder {
+    /// Load the model config, tokenizer and weights from local files.
+    ///
+    /// Safetensors weights are memory-mapped, or read into memory with the `no-mmap` feature
+    /// for platforms where mapping files is restricted, such as iOS app sandboxes. Files ending
+    /// in `.bin`, `.pt` or `.pth` are read as PyTorch checkpoints instead, as older
+    /// sentence-transformers models ship `pytorch_model.bin` only. Larger models split across
+    /// shards are loaded by passing their `model.safetensors.index.json`. A `.gguf` file written
+    /// by [`quantize_model`](crate::quantize_model) runs quantized on the CPU.
+    pub fn load(
+        config_path: impl AsRef<Path>,
+        tokenizer_path: impl AsRef<Path>,
+        weights_path: impl AsRef<Path>,
+        approximate_gelu: bool,
+    ) -> Result<Self> {
+        Embedder::load_on_device(
+            config_path,
+            tokenizer_path,
+            weights_path,
+            approximate_gelu,
+            DeviceKind::Cpu,
+        )
+    }
+
+    /// [`Embedder::load`] with the weights on `device` and the forward passes run there.
+    pub fn load_on_device(
+        config_path: impl AsRef<Path>,
+        tokenizer_path: impl AsRef<Path>,
+        weights_path: impl AsRef<Path>,
+        approximate_gelu: bool,
+        device: DeviceKind,
+    ) -> Result<Self> {
+        let start = Instant::now();
+        let config_contents = std::fs::read(config_path)?;
+        let weights_path = weights_path.as_ref();
+        let device = device.open()?;
+        // Quantized matmuls only run on the CPU; elsewhere the weights are dequantized
+        let (vb, quantized) = match is_gguf(weights_path) && device.is_cpu() {
+            true => {
+                let (tensors, quantized) = read_gguf(weights_path, &device, false)?;
+                (VarBuilder::from_tensors(tensors, DTYPE, &device), quantized)
+            }
+            false => (
+                load_weights(weights_path, &device)?,
+                QuantizedWeights::new(),
+            ),
+        };
+        let tokenizer = Tokenizer::from_file(tokenizer_path)?;
+        Embedder::with_weights(
+            vb,
+            quantized,
+            &config_contents,
+            tokenizer,
+            approximate_gelu,
+            None,
+            start,
+        )
+    }
+
+    /// [`Embedder::load`] with a LoRA adapter merged into the base weights as they load, so a
+    /// domain-adapted model ships as a few megabytes on top of a shared base checkpoint.
+    ///
+    /// `adapter_dir` holds the `adapter_config.json` and `adapter_model.safetensors` that PEFT
+    /// saves. The rank, `lora_alpha` and `use_rslora` settings are honored; every adapted weight
+    /// must exist in the base model.
+    pub fn load_with_adapter(
+        config_path: impl AsRef<Path>,
+        tokenizer_path: impl AsRef<Path>,
+        weights_path: impl AsRef<Path>,
+        adapter_dir: impl AsRef<Path>,
+        approximate_gelu: bool,
+    ) -> Result<Self> {
+        let start = Instant::now();
+        let config_contents = std::fs::read(config_path)?;
+        let vb = load_weights(weights_path.as_ref(), &Device::Cpu)?;
+        let adapter = LoraAdapter::load(adapter_dir)?;
+        let tokenizer = Tokenizer::from_file(tokenizer_path)?;
+        Embedder::with_weights(
+            vb,
+            QuantizedWeights::new(),
+            &config_contents,
+            tokenizer,
+            approximate_gelu,
+            Some(adapter),
+            start,
+        )
+    }
+
+    /// [`Embedder::load`] from the contents of the three files, e.g. fetched by a browser.
+    /// Nothing is read from disk or memory-mapped.
+    pub fn from_buffers(
+        config: &[u8],
+        tokenizer: &[u8],
+        weights: Vec<u8>,
+        approximate_gelu: bool,
+    ) -> Result<Self> {
+        let start = Instant::now();
+        let vb = VarBuilder::from_buffered_safetensors(weights, DTYPE, &Device::Cpu)?;
+        let tokenizer = Tokenizer::from_bytes(tokenizer)?;
+        Embedder::with_weights(
+            vb,
+            QuantizedWeights::new(),
+            config,
+            tokenizer,
+            approximate_gelu,
+            None,
+            start,
+        )
+    }
+
+    /// The model compiled into the library by the `embedded-model` feature, so nothing needs
+    /// to be shipped or found next to the binary.
+    #[cfg(feature = "embedded-model")]
+    pub fn embedded() -> Result<Self> {
+        Embedder::from_buffers(
+            embedded::CONFIG,
+            embedded::TOKENIZER,
+            embedded::WEIGHTS.to_vec(),
+            false,
+        )
+    }
+
+    fn with_weights(
+        vb: VarBuilder,
+        quantized: QuantizedWeights,
+        config_contents: &[u8],
+        tokenizer: Tokenizer,
+        approximate_gelu: bool,
+        adapter: Option<LoraAdapter>,
+        start: Instant,
+    ) -> Result<Self> {
+        let mut config: Config = serde_json::from_slice(config_contents)?;
+        if approximate_gelu {
+            config.hidden_act = HiddenAct::GeluApproximate;
+        }
+        let vb = match adapter {
+            Some(adapter) => adapter.apply(vb, &config)?,
+            None => vb,
+        };
+
+        let model = BertModel::load_quantized(vb, &config, &quantized)?;
+        let embedder =
+            Embedder::with_model(Model::Candle(model), config, config_contents, tokenizer)?;
+        tracing::info!(
+            load_ms = start.elapsed().as_millis() as u64,
+            backend = "candle",
+            "loaded model"
+        );
+        Ok(embedder)
+    }
+
+    /// Load an ONNX export of the model, run with ONNX Runtime instead of candle. The config
+    /// and tokenizer are the same files [`Embedder::load`] takes.
+    ///
+    /// Only the final hidden states are available, so other [`LayerSelection`]s are rejected.
+    /// ONNX Runtime manages its own threads, outside [`PowerMode`](crate::PowerMode).
+    #[cfg(feature = "ort")]
+    pub fn load_onnx(
+        config_path: impl AsRef<Path>,
+        tokenizer_path: impl AsRef<Path>,
+        model_path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let start = Instant::now();
+        let config_contents = std::fs::read(config_path)?;
+        let config: Config = serde_json::from_slice(&config_contents)?;
+        let model = OnnxModel::load(model_path)?;
+        let tokenizer = Tokenizer::from_file(tokenizer_path)?;
+        let embedder =
+            Embedder::with_model(Model::Onnx(model), config, &config_contents, tokenizer)?;
+        tracing::info!(
+            load_ms = start.elapsed().as_millis() as u64,
+            backend = "onnx",
+            "loaded model"
+        );
+        Ok(embedder)
+    }
+
+    fn with_model(
+        model: Model,
+        config: Config,
+        config_contents: &[u8],
+        tokenizer: Tokenizer,
+    ) -> Result<Self> {
+        stats::start();
+        let instruction = serde_json::from_slice::<serde_json::Value>(config_contents)?
+            .get("instruction")
+            .and_then(|instruction| instruction.as_str())
+            .map(str::to_string);
+
+        let mut raw_tokenizer = tokenizer.clone();
+        raw_tokenizer.with_padding(None);
+        raw_tokenizer.with_truncation(None)?;
+
+        Ok(Embedder {
+            model,
+            tokenizer,
+            raw_tokenizer,
+            config,
+            cleanup: None,
+            preprocessor: None,
+            transforms: Vec::new(),
+            task_prefixes: TaskPrefixes::default(),
+            instruction,
+            truncation: None,
+            audit: None,
+            cache: None,
+            #[cfg(feature = "sqlite")]
+            disk_cache: None,
+        })
+    }
+
+    /// The device the forward passes run on, never [`DeviceKind::Auto`].
+    pub fn device(&self) -> DeviceKind {
+        DeviceKind::of(self.model.device())
+    }
+
+    pub fn info(&self) -> ModelInfo {
+        ModelInfo {
+            backend: match self.model {
+                Model::Candle(_) => "candle",
+                #[cfg(feature = "ort")]
+                Model::Onnx(_) => "onnx",
+            },
+            device: self.device(),
+            model_type: self.config.model_type.clone(),
+            embedding_dim: self.embedding_dim(LayerSelection::Last),
+            num_hidden_layers: self.config.num_hidden_layers,
+            max_position_embeddings: self.config.max_position_embeddings,
+            vocab_size: self.config.vocab_size,
+        }
+    }
+
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let (weight_bytes, peak_activation_bytes) = match &self.model {
+            Model::Candle(model) => (
+                Some(model.weight_bytes()),
+                Some(model.peak_activation_bytes()),
+            ),
+            #[cfg(feature = "ort")]
+            Model::Onnx(_) => (None, None),
+        };
+        let cache = self
+            .cache
+            .as_ref()
+            .map(|cache| cache.stats())
+            .unwrap_or_default();
+        let device = self.device();
+        let memory = device.memory();
+        MemoryUsage {
+            weight_bytes,
+            peak_activation_bytes,
+            cache_entries: cache.entries,
+            cache_bytes: cache.bytes,
+            device,
+            device_total_bytes: memory.map(|(total, _)| total),
+            device_free_bytes: memory.map(|(_, free)| free),
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn tokenizer(&self) -> &Tokenizer {
+        &self.tokenizer
+    }
+
+    /// Install (or with `None`, remove) the hook run on every text before it is tokenized by
+    /// the embed, count and encode methods. [`TextSplitter`](crate::TextSplitter) is not
+    /// affected, since its chunks must be slices of the original text.
+    pub fn set_preprocessor(&mut self, preprocessor: Option<Preprocessor>) {
+        self.preprocessor = preprocessor;
+        self.settings_changed();
+    }
+
+    /// Set (or with `None`, remove) the cleanup run on every text before the preprocessor,
+    /// e.g. Unicode normalization and whitespace collapsing for scraped or OCR'd text.
+    pub fn set_text_cleanup(&mut self, cleanup: Option<TextCleanup>) {
+        self.cleanup = cleanup;
+        self.settings_changed();
+    }
+
+    pub fn text_cleanup(&self) -> Option<&TextCleanup> {
+        self.cleanup.as_ref()
+    }
+
+    fn preprocess<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let text = match &self.cleanup {
+            Some(cleanup) => cleanup.apply(text),
+            None => Cow::Borrowed(text),
+        };
+        match &self.preprocessor {
+            Some(preprocessor) => Cow::Owned(preprocessor(&text)),
+            None => text,
+        }
+    }
+
+    /// Record every embed call in `log` (or with `None`, stop recording) under the name
+    /// `model`. Several models can share one log.
+    pub fn set_audit_log(&mut self, log: Option<Arc<AuditLog>>, model: impl Into<String>) {
+        let model = model.into();
+        self.audit = log.map(|log| Audit { log, model });
+    }
+
+    // Record the outcome of an operation on `inputs` started at `start`, one entry per input.
+    // A call that succeeded but could not be recorded fails, so nothing is returned unaudited.
+    pub(crate) fn audited<'a, T>(
+        &self,
+        operation: &str,
+        inputs: impl IntoIterator<Item = &'a str>,
+        caller: Option<&str>,
+        start: Instant,
+        result: Result<T>,
+    ) -> Result<T> {
+        let Some(audit) = &self.audit else {
+            return result;
+        };
+        let latency = start.elapsed();
+        for input in inputs {
+            let recorded = audit.log.record(
+                operation,
+                &audit.model,
+                input,
+                latency,
+                caller,
+                result.is_ok(),
+            );
+            if result.is_ok() {
+                recorded?;
+            }
+        }
+        result
+    }
+
+    /// Replace the post-processing chain run, in order, on every embedding this model returns.
+    pub fn set_transforms(&mut self, transforms: Vec<Transform>) {
+        self.transforms = transforms;
+        self.settings_changed();
+    }
+
+    /// Select how the encoder computes attention, e.g. [`Attention::Chunked`] for long-context
+    /// models. ONNX models keep whatever their graph does.
+    pub fn set_attention(&mut self, attention: Attention) -> Result<()> {
+        if attention == (Attention::Chunked { block_size: 0 }) {
+            return Err(Error::InvalidArgument(
+                "attention block size must be at least 1".to_string(),
+            ));
+        }
+        match &mut self.model {
+            Model::Candle(model) => model.set_attention(attention),
+            #[cfg(feature = "ort")]
+            Model::Onnx(_) => {}
+        }
+        self.settings_changed();
+        Ok(())
+    }
+
+    /// Set the prefixes prepended for [`EmbedOptions::task`], e.g. [`TaskPrefixes::e5`].
+    pub fn set_task_prefixes(&mut self, prefixes: TaskPrefixes) {
+        self.task_prefixes = prefixes;
+        self.settings_changed();
+    }
+
+    pub fn task_prefixes(&self) -> &TaskPrefixes {
+        &self.task_prefixes
+    }
+
+    /// Set the instruction template of an instruction-tuned model, e.g.
+    /// `"Represent the document for retrieval: {text}"`. `{text}` marks where the input goes;
+    /// a template without it is prepended. Defaults to the `"instruction"` key of the model
+    /// config, if any, and can be overridden per call with [`EmbedOptions::instruction`].
+    pub fn set_instruction(&mut self, instruction: Option<String>) {
+        self.instruction = instruction;
+        self.settings_changed();
+    }
+
+    /// Truncate every input to `max_length` tokens, special tokens included, keeping the part
+    /// `strategy` selects, unless a call sets [`EmbedOptions::max_length`] itself. `None`
+    /// restores the truncation configured in the tokenizer file.
+    pub fn set_truncation(&mut self, max_length: Option<usize>, strategy: TruncationStrategy) {
+        self.truncation = max_length.map(|max_length| (max_length, strategy));
+        self.settings_changed();
+    }
+
+    pub fn truncation(&self) -> Option<(usize, TruncationStrategy)> {
+        self.truncation
+    }
+
+    /// Install (or with `None`, remove) a cache of this model's embeddings, so repeated
+    /// inputs skip inference. A cache must not be shared with other models, and is cleared
+    /// whenever a setting that changes the embeddings does.
+    pub fn set_cache(&mut self, cache: Option<Arc<EmbeddingCache>>) {
+        self.cache = cache;
+    }
+
+    pub fn cache(&self) -> Option<&Arc<EmbeddingCache>> {
+        self.cache.as_ref()
+    }
+
+    /// Install (or with `None`, remove) a persistent cache of this model's embeddings; see
+    /// [`DiskCache`] for how entries of other models are invalidated. Can be combined with
+    /// [`Embedder::set_cache`], which is checked first.
+    #[cfg(feature = "sqlite")]
+    pub fn set_disk_cache(&mut self, cache: Option<Arc<DiskCache>>) {
+        self.disk_cache = cache.map(DiskBinding::new);
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub fn disk_cache(&self) -> Option<&Arc<DiskCache>> {
+        self.disk_cache.as_ref().map(|binding| &binding.cache)
+    }
+
+    // Drop the cached embeddings, which a setting change made stale.
+    fn settings_changed(&mut self) {
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+        #[cfg(feature = "sqlite")]
+        if let Some(binding) = &self.disk_cache {
+            self.disk_cache = Some(DiskBinding::new(Arc::clone(&binding.cache)));
+        }
+    }
+
+    fn has_cache(&self) -> bool {
+        #[cfg(feature = "sqlite")]
+        if self.disk_cache.is_some() {
+            return true;
+        }
+        self.cache.is_some()
+    }
+
+    // The cached embeddings of `keys`, looked up in memory and then on disk.
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+    fn cached(&self, keys: &[CacheKey], options: &EmbedOptions) -> Result<Vec<Option<Embedding>>> {
+        let embeddings = match &self.cache {
+            Some(cache) => keys.iter().map(|key| cache.get(key)).collect(),
+            None => vec![None; keys.len()],
+        };
+        #[cfg(feature = "sqlite")]
+        let embeddings = self.disk_cached(keys, embeddings, options)?;
+        Ok(embeddings)
+    }
+
+    // Fill in the `embeddings` missing from memory from the disk cache, if any.
+    #[cfg(feature = "sqlite")]
+    fn disk_cached(
+        &self,
+        keys: &[CacheKey],
+        mut embeddings: Vec<Option<Embedding>>,
+        options: &EmbedOptions,
+    ) -> Result<Vec<Option<Embedding>>> {
+        let Some(binding) = &self.disk_cache else {
+            return Ok(embeddings);
+        };
+        self.bind_disk_cache(binding)?;
+        let missing: Vec<_> = (0..keys.len())
+            .filter(|&i| embeddings[i].is_none())
+            .collect();
+        let missing_keys: Vec<_> = missing.iter().map(|&i| keys[i]).collect();
+        let found = binding.cache.get(&missing_keys, options.dtype)?;
+        for (i, embedding) in missing.into_iter().zip(found) {
+            if let (Some(cache), Some(embedding)) = (&self.cache, &embedding) {
+                cache.insert(keys[i], embedding.clone());
+            }
+            embeddings[i] = embedding;
+        }
+        Ok(embeddings)
+    }
+
+    fn store(&self, entries: &[(CacheKey, &Embedding)]) -> Result<()> {
+        if let Some(cache) = &self.cache {
+            for (key, embedding) in entries {
+                cache.insert(*key, (*embedding).clone());
+            }
+        }
+        #[cfg(feature = "sqlite")]
+        if let Some(binding) = &self.disk_cache {
+            binding.cache.insert(entries)?;
+        }
+        Ok(())
+    }
+
+    // Claim the disk cache for this model, invalidating the entries of another one.
+    #[cfg(feature = "sqlite")]
+    fn bind_disk_cache(&self, binding: &DiskBinding) -> Result<()> {
+        use sha2::{Digest, Sha256};
+        use std::sync::atomic::Ordering;
+
+        if binding.bound.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        let (probe, _) = self.embed_uncached(PROBE_TEXT, &EmbedOptions::default())?;
+        let settings = Sha256::digest(format!("{:?}", self.task_prefixes));
+        if binding.cache.bind(&probe.to_f32(), &settings)? {
+            tracing::info!("the embedding cache was written by another model and was cleared");
+        }
+        binding.bound.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn instruction(&self) -> Option<&str> {
+        self.instruction.as_deref()
+    }
+
+    /// Number of tokens in `text`, without truncation.
+    pub fn count_tokens(&self, text: &str, add_special_tokens: bool) -> Result<usize> {
+        Ok(self.encode(text, add_special_tokens)?.len())
+    }
+
+    /// Token ids for `text`, without truncation or padding.
+    pub fn encode(&self, text: &str, add_special_tokens: bool) -> Result<Vec<u32>> {
+        let encoding = self
+            .raw_tokenizer
+            .encode(self.preprocess(text), add_special_tokens)?;
+        Ok(encoding.get_ids().to_vec())
+    }
+
+    /// Text for token ids produced by [`Embedder::encode`].
+    pub fn decode(&self, ids: &[u32], skip_special_tokens: bool) -> Result<String> {
+        Ok(self.raw_tokenizer.decode(ids, skip_special_tokens)?)
+    }
+
+    /// Length of the vectors produced for the given layer selection, after post-processing.
+    pub fn embedding_dim(&self, layers: LayerSelection) -> usize {
+        let projected = self.transforms.iter().rev().find_map(|t| match t {
+            Transform::Project { out_dim, .. } => Some(*out_dim),
+            Transform::Pca(pca) => Some(pca.output_dim()),
+            _ => None,
+        });
+        match (projected, layers) {
+            (Some(out_dim), _) => out_dim,
+            (None, LayerSelection::ConcatLast(n)) => n * self.config.hidden_size,
+            (None, _) => self.config.hidden_size,
+        }
+    }
+
+    /// Embed `text` by mean pooling the final encoder layer.
+    pub fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        self.embed_with_layers(text, LayerSelection::Last)
+    }
+
+    /// Embed `text` by mean pooling the selected hidden states.
+    pub fn embed_with_layers(&self, text: &str, layers: LayerSelection) -> Result<Vec<f32>> {
+        let options = EmbedOptions {
+            layers,
+            ..Default::default()
+        };
+        Ok(self.embed_with_options(text, &options)?.to_f32())
+    }
+
+    /// Embed `text` with per-call pooling, normalization, truncation, prefix and output type.
+    pub fn embed_with_options(&self, text: &str, options: &EmbedOptions) -> Result<Embedding> {
+        Ok(self.embed_with_timings(text, options)?.0)
+    }
+
+    /// [`Embedder::embed_with_options`], also returning how long each stage took.
+    pub fn embed_with_timings(
+        &self,
+        text: &str,
+        options: &EmbedOptions,
+    ) -> Result<(Embedding, Timings)> {
+        let start = Instant::now();
+        let result = self.embed_with_timings_unaudited(text, options);
+        self.audited("embed", [text], options.caller.as_deref(), start, result)
+    }
+
+    fn embed_with_timings_unaudited(
+        &self,
+        text: &str,
+        options: &EmbedOptions,
+    ) -> Result<(Embedding, Timings)> {
+        self.check_layers(options.layers)?;
+        if !self.has_cache() {
+            return self.embed_uncached(text, options);
+        }
+        let key = cache_key(text, options);
+        if let Some(embedding) = self.cached(&[key], options)?.pop().flatten() {
+            return Ok((embedding, Timings::default()));
+        }
+        let (embedding, timings) = self.embed_uncached(text, options)?;
+        self.store(&[(key, &embedding)])?;
+        Ok((embedding, timings))
+    }
+
+    fn embed_uncached(&self, text: &str, options: &EmbedOptions) -> Result<(Embedding, Timings)> {
+        let (embedding, timings, _) = self.embed_uncached_counted(text, options)?;
+        Ok((embedding, timings))
+    }
+
+    // `embed_uncached`, also returning the number of tokens in the text's windows
+    fn embed_uncached_counted(
+        &self,
+        text: &str,
+        options: &EmbedOptions,
+    ) -> Result<(Embedding, Timings, usize)> {
+        let deadline = deadline(options);
+        let mut timings = Timings::default();
+
+        let start = Instant::now();
+        let windows = self.encode_input(text, options, options.overflow)?;
+        timings.tokenize = start.elapsed();
+
+        let embed = |timings: &mut Timings| -> Result<Vec<f32>> {
+            if let [window] = windows.as_slice() {
+                return self.embed_ids(window.get_ids(), options.layers, options.pooling, timings);
+            }
+            let mut embeddings = Vec::with_capacity(windows.len());
+            for window in &windows {
+                check_deadline(deadline)?;
+                let mut window_timings = Timings::default();
+                let embedding = self.embed_ids(
+                    window.get_ids(),
+                    options.layers,
+                    options.pooling,
+                    &mut window_timings,
+                )?;
+                timings.forward += window_timings.forward;
+                timings.pool += window_timings.pool;
+                embeddings.push((embedding, num_tokens(window)));
+            }
+            Ok(weighted_average(embeddings))
+        };
+        let embedding = if options.deterministic {
+            power::serial(|| embed(&mut timings))?
+        } else {
+            embed(&mut timings)?
+        };
+
+        let start = Instant::now();
+        let embedding = self.postprocess(embedding, options)?;
+        timings.postprocess = start.elapsed();
+
+        let tokens = windows.iter().map(num_tokens).sum();
+        Ok((embedding, timings, tokens))
+    }
+
+    /// [`Embedder::embed_with_options`] with how much each token contributed to the embedding,
+    /// so search results can highlight the words that drove a match.
+    ///
+    /// The scores come from attention rollout: every layer's attention, averaged over its
+    /// heads and mixed half and half with the residual connection, is multiplied through the
+    /// layers to trace how much of each final hidden state comes from each input token. Those
+    /// shares are pooled like the embedding: the first position's for [`Pooling::Cls`], the
+    /// average over positions otherwise. Padding is left out. Special tokens such as `[CLS]`
+    /// usually take most of the share, so highlighting compares the scores of the others.
+    ///
+    /// The input must fit one window, so [`Overflow::Average`] and layer selections other than
+    /// the last are rejected, as are ONNX models. Explanations are never cached.
+    pub fn explain(&self, text: &str, options: &EmbedOptions) -> Result<Explanation> {
+        let start = Instant::now();
+        let result = self.explain_unaudited(text, options);
+        self.audited("explain", [text], options.caller.as_deref(), start, result)
+    }
+
+    #[cfg_attr(not(feature = "ort"), allow(clippy::infallible_destructuring_match))]
+    fn explain_unaudited(&self, text: &str, options: &EmbedOptions) -> Result<Explanation> {
+        if options.layers != LayerSelection::Last || options.overflow == Overflow::Average {
+            return Err(Error::InvalidArgument(
+                "explanations need the last layer and a truncated input".to_string(),
+            ));
+        }
+        let model = match &self.model {
+            Model::Candle(model) => model,
+            #[cfg(feature = "ort")]
+            Model::Onnx(_) => {
+                return Err(Error::InvalidArgument(
+                    "ONNX models don't expose their attention".to_string(),
+                ))
+            }
+        };
+        let encoding = self
+            .encode_input(text, options, Overflow::Truncate)?
+            .swap_remove(0);
+
+        let start = Instant::now();
+        let token_ids = Tensor::new(encoding.get_ids(), &model.device)?.unsqueeze(0)?;
+        let token_type_ids = token_ids.zeros_like()?;
+        let forward =
+            || power::install(|| model.forward_attentions(&token_ids, &token_type_ids, None));
+        let (hidden, attentions) = if options.deterministic {
+            power::serial(forward)?
+        } else {
+            forward()?
+        };
+        stats::record(Phase::Forward, start.elapsed());
+
+        let scores = attention_rollout(&attentions, hidden.dim(1)?, options.pooling)?;
+        let embedding = self.postprocess(pool(&hidden, options.pooling)?, options)?;
+        let mask = encoding.get_attention_mask();
+        let total: f32 = scores
+            .iter()
+            .zip(mask)
+            .filter(|(_, &mask)| mask == 1)
+            .map(|(score, _)| score)
+            .sum();
+        let tokens = (0..encoding.len())
+            .filter(|&i| mask[i] == 1)
+            .map(|i| {
+                let (start, end) = encoding.get_offsets()[i];
+                TokenContribution {
+                    token: encoding.get_tokens()[i].clone(),
+                    start,
+                    end,
+                    score: scores[i] / total,
+                    special: encoding.get_special_tokens_mask()[i] == 1,
+                }
+            })
+            .collect();
+        Ok(Explanation { embedding, tokens })
+    }
+
+    /// Embed two segments as one input, `[CLS] a [SEP] b [SEP]` for BERT, with the tokens of
+    /// `b` marked as the second segment, as NLI-style and cross-encoder models expect.
+    pub fn embed_pair(&self, a: &str, b: &str) -> Result<Vec<f32>> {
+        Ok(self
+            .embed_pair_with_options(a, b, &EmbedOptions::default())?
+            .to_f32())
+    }
+
+    /// [`Embedder::embed_pair`] with per-call options. Prefixes, instructions and
+    /// [`EmbedOptions::overflow`] don't apply: an over-long pair is truncated, the longer
+    /// segment first, and pairs are never cached. ONNX models without a `token_type_ids`
+    /// input see both segments as one.
+    pub fn embed_pair_with_options(
+        &self,
+        a: &str,
+        b: &str,
+        options: &EmbedOptions,
+    ) -> Result<Embedding> {
+        let start = Instant::now();
+        let embed = || {
+            self.check_layers(options.layers)?;
+            let encoding = self.encode_pair(a, b, options)?;
+            let embed = || {
+                self.embed_segments(
+                    encoding.get_ids(),
+                    Some(encoding.get_type_ids()),
+                    options.layers,
+                    options.pooling,
+                    &mut Timings::default(),
+                )
+            };
+            let embedding = if options.deterministic {
+                power::serial(embed)?
+            } else {
+                embed()?
+            };
+            self.postprocess(embedding, options)
+        };
+        let result = embed();
+        self.audited(
+            "embed_pair",
+            [a, b],
+            options.caller.as_deref(),
+            start,
+            result,
+        )
+    }
+
+    // Preprocess and tokenize two segments as a pair, truncated as configured by `options`.
+    fn encode_pair(&self, a: &str, b: &str, options: &EmbedOptions) -> Result<Encoding> {
+        let start = Instant::now();
+        let (a, b) = (self.preprocess(a), self.preprocess(b));
+        let truncation = match options.max_length {
+            Some(max_length) => Some((max_length, options.truncation)),
+            None => self.truncation,
+        };
+        let encoding = match truncation {
+            None => self.tokenizer.encode((a.as_ref(), b.as_ref()), true)?,
+            Some((max_length, strategy)) => {
+                let direction = match strategy {
+                    TruncationStrategy::Head => TruncationDirection::Right,
+                    TruncationStrategy::Tail => TruncationDirection::Left,
+                    TruncationStrategy::HeadTail => {
+                        return Err(Error::InvalidArgument(
+                            "head+tail truncation is not supported for pairs".to_string(),
+                        ))
+                    }
+                };
+                let num_special = self
+                    .raw_tokenizer
+                    .get_post_processor()
+                    .map_or(0, |processor| processor.added_tokens(true));
+                let params = TruncationParams {
+                    max_length: max_length.saturating_sub(num_special),
+                    direction,
+                    ..Default::default()
+                };
+                let (a, b) = truncate_encodings(
+                    self.raw_tokenizer.encode(a.as_ref(), false)?,
+                    Some(self.raw_tokenizer.encode(b.as_ref(), false)?),
+                    &params,
+                )?;
+                let mut encoding = self.raw_tokenizer.post_process(a, b, true)?;
+                if let Some(padding) = self.tokenizer.get_padding() {
+                    pad_encodings(std::slice::from_mut(&mut encoding), padding)?;
+                }
+                encoding
+            }
+        };
+        // Both segments must keep some of their own tokens
+        let sequence_ids = encoding.get_sequence_ids();
+        let empty = [0, 1].iter().any(|&i| !sequence_ids.contains(&Some(i)));
+        if empty && !options.allow_empty {
+            return Err(Error::EmptyInput);
+        }
+        stats::record(Phase::Tokenize, start.elapsed());
+        Ok(encoding)
+    }
+
+    // Preprocess, template, prefix and tokenize `text` as configured by `options`.
+    pub(crate) fn tokenize(&self, text: &str, options: &EmbedOptions) -> Result<Vec<u32>> {
+        let mut encodings = self.encode_input(text, options, Overflow::Truncate)?;
+        Ok(encodings.swap_remove(0).get_ids().to_vec())
+    }
+
+    // `tokenize`, but with `Overflow::Average` into as many windows as it takes to hold every
+    // token, each padded like a truncated text would be.
+    fn encode_input(
+        &self,
+        text: &str,
+        options: &EmbedOptions,
+        overflow: Overflow,
+    ) -> Result<Vec<Encoding>> {
+        let start = Instant::now();
+        let bare = self.preprocess(text);
+        let text = match options
+            .instruction
+            .as_deref()
+            .or(self.instruction.as_deref())
+        {
+            Some(template) => Cow::Owned(render_instruction(template, &bare)),
+            None => Cow::Borrowed(bare.as_ref()),
+        };
+        let prefix = options
+            .prefix
+            .as_deref()
+            .or_else(|| options.task.and_then(|task| self.task_prefixes.get(task)));
+        let text = match prefix {
+            Some(prefix) => Cow::Owned(format!("{prefix}{text}")),
+            None => text,
+        };
+
+        let decorated = matches!(text, Cow::Owned(_));
+
+        let truncation = match options.max_length {
+            Some(max_length) => Some((max_length, options.truncation)),
+            None => self.truncation,
+        };
+        let mut encodings = match (truncation, overflow) {
+            (None, Overflow::Truncate) => vec![self.tokenizer.encode(text, true)?],
+            (Some((max_length, strategy)), Overflow::Truncate) => vec![match strategy {
+                TruncationStrategy::Head => {
+                    self.encode_truncated(&text, max_length, 0, TruncationDirection::Right)?
+                }
+                TruncationStrategy::Tail => {
+                    self.encode_truncated(&text, max_length, 0, TruncationDirection::Left)?
+                }
+                TruncationStrategy::HeadTail => self.encode_head_tail(&text, max_length)?,
+            }],
+            (truncation, Overflow::Average) => {
+                let max_length = match truncation {
+                    Some((max_length, _)) => max_length,
+                    None => self
+                        .tokenizer
+                        .get_truncation()
+                        .map_or(self.config.max_position_embeddings, |t| t.max_length),
+                };
+                let mut encoding =
+                    self.encode_truncated(&text, max_length, 0, TruncationDirection::Right)?;
+                let overflowing = encoding.take_overflowing();
+                std::iter::once(encoding).chain(overflowing).collect()
+            }
+        };
+        if !options.allow_empty {
+            // With a template or prefix around it, only the text itself tells
+            let empty = if decorated {
+                bare.trim().is_empty()
+                    || self.raw_tokenizer.encode(bare.as_ref(), false)?.is_empty()
+            } else {
+                !has_content(&encodings[0])
+            };
+            if empty {
+                return Err(Error::EmptyInput);
+            }
+        }
+        // Padded like the tokenizer file says, as the default path is
+        if truncation.is_some() || overflow == Overflow::Average {
+            if let Some(padding) = self.tokenizer.get_padding() {
+                for encoding in &mut encodings {
+                    pad_encodings(std::slice::from_mut(encoding), padding)?;
+                }
+            }
+        }
+        stats::record(Phase::Tokenize, start.elapsed());
+        Ok(encodings)
+    }
+
+    // Encode with truncation to `max_length` tokens, special tokens included, and `stride`
+    // tokens of overlap between the overflowing windows. This is what a tokenizer configured
+    // with `TruncationParams` does, done on the unconfigured one so no call needs its own copy.
+    fn encode_truncated(
+        &self,
+        text: &str,
+        max_length: usize,
+        stride: usize,
+        direction: TruncationDirection,
+    ) -> Result<Encoding> {
+        let num_special = self
+            .raw_tokenizer
+            .get_post_processor()
+            .map_or(0, |processor| processor.added_tokens(false));
+        let max_tokens = max_length.saturating_sub(num_special);
+        if stride > 0 && stride >= max_tokens {
+            return Err(Error::InvalidArgument(format!(
+                "overlap of {stride} tokens leaves no room in windows of {max_tokens} tokens"
+            )));
+        }
+        let mut encoding = self.raw_tokenizer.encode(text, false)?;
+        encoding.truncate(max_tokens, stride, direction);
+        Ok(self.raw_tokenizer.post_process(encoding, None, true)?)
+    }
+
+    // Encode keeping the first and last tokens of an over-long text, the beginning getting the
+    // odd one out, so `max_length` tokens are left with the special tokens.
+    fn encode_head_tail(&self, text: &str, max_length: usize) -> Result<Encoding> {
+        let num_special = self
+            .raw_tokenizer
+            .get_post_processor()
+            .map_or(0, |processor| processor.added_tokens(false));
+        let max_tokens = max_length.saturating_sub(num_special);
+        let mut head = self.raw_tokenizer.encode(text, false)?;
+        if head.len() > max_tokens {
+            let mut tail = head.clone();
+            head.truncate(max_tokens.div_ceil(2), 0, TruncationDirection::Right);
+            tail.truncate(max_tokens / 2, 0, TruncationDirection::Left);
+            head.take_overflowing();
+            tail.take_overflowing();
+            head.merge_with(tail, false);
+        }
+        Ok(self.raw_tokenizer.post_process(head, None, true)?)
+    }
+
+    // Normalization, the model's transforms and the output type conversion.
+    pub(crate) fn postprocess(
+        &self,
+        mut embedding: Vec<f32>,
+        options: &EmbedOptions,
+    ) -> Result<Embedding> {
+        if options.normalize {
+            normalize(&mut embedding);
+        }
+        apply_all(&self.transforms, &mut embedding)?;
+        Ok(Embedding::from_f32(embedding, options.dtype))
+    }
+
+    /// Embed every text with the same options, returning the embeddings in input order.
+    ///
+    /// The texts go through the encoder together, padded to the longest one and masked, so
+    /// each result matches [`Embedder::embed_with_options`] on its own.
+    pub fn embed_batch<S: AsRef<str>>(
+        &self,
+        texts: &[S],
+        options: &EmbedOptions,
+    ) -> Result<Vec<Embedding>> {
+        Ok(self.embed_batch_with_usage(texts, options)?.0)
+    }
+
+    /// [`Embedder::embed_batch`], also returning how many tokens went through the encoder,
+    /// special tokens included and padding left out. Texts answered from a cache count none.
+    pub fn embed_batch_with_usage<S: AsRef<str>>(
+        &self,
+        texts: &[S],
+        options: &EmbedOptions,
+    ) -> Result<(Vec<Embedding>, usize)> {
+        let start = Instant::now();
+        let embed = || {
+            self.check_layers(options.layers)?;
+            if !self.has_cache() {
+                return self.embed_batch_uncached(texts, options);
+            }
+            // Only the texts missing from the caches go through the encoder
+            let keys: Vec<_> = texts
+                .iter()
+                .map(|text| cache_key(text.as_ref(), options))
+                .collect();
+            let mut embeddings = self.cached(&keys, options)?;
+            let missing: Vec<_> = (0..texts.len())
+                .filter(|&i| embeddings[i].is_none())
+                .collect();
+            let missing_texts: Vec<_> = missing.iter().map(|&i| texts[i].as_ref()).collect();
+            let (computed, tokens) = self.embed_batch_uncached(&missing_texts, options)?;
+            let entries: Vec<_> = missing.iter().map(|&i| keys[i]).zip(&computed).collect();
+            self.store(&entries)?;
+            for (i, embedding) in missing.into_iter().zip(computed) {
+                embeddings[i] = Some(embedding);
+            }
+            Ok((embeddings.into_iter().flatten().collect(), tokens))
+        };
+        let result = embed();
+        let inputs = texts.iter().map(AsRef::as_ref);
+        self.audited(
+            "embed_batch",
+            inputs,
+            options.caller.as_deref(),
+            start,
+            result,
+        )
+    }
+
+    // The embeddings of `texts` and the number of tokens they came to
+    fn embed_batch_uncached<S: AsRef<str>>(
+        &self,
+        texts: &[S],
+        options: &EmbedOptions,
+    ) -> Result<(Vec<Embedding>, usize)> {
+        let deadline = deadline(options);
+        let progress = Progress::start(texts.len());
+        if !progress.is_active() && deadline.is_none() {
+            return self.embed_batch_step(texts, options);
+        }
+        // Forward passes of a few texts each, so there is progress to report and a deadline to
+        // check between them
+        let mut embeddings = Vec::with_capacity(texts.len());
+        let mut tokens = 0;
+        for step in texts.chunks(PROGRESS_STEP) {
+            check_deadline(deadline)?;
+            let (step_embeddings, step_tokens) = self.embed_batch_step(step, options)?;
+            embeddings.extend(step_embeddings);
+            tokens += step_tokens;
+            progress.advance(step.len());
+        }
+        Ok((embeddings, tokens))
+    }
+
+    fn embed_batch_step<S: AsRef<str>>(
+        &self,
+        texts: &[S],
+        options: &EmbedOptions,
+    ) -> Result<(Vec<Embedding>, usize)> {
+        if options.deterministic {
+            // Padding to a common length changes the shapes, and with them the summation order
+            let mut tokens = 0;
+            let embeddings = texts
+                .iter()
+                .map(|text| {
+                    let (embedding, _, text_tokens) =
+                        self.embed_uncached_counted(text.as_ref(), options)?;
+                    tokens += text_tokens;
+                    Ok(embedding)
+                })
+                .collect::<Result<_>>()?;
+            return Ok((embeddings, tokens));
+        }
+        let inputs = texts
+            .iter()
+            .map(|text| self.encode_input(text.as_ref(), options, options.overflow))
+            .collect::<Result<Vec<_>>>()?;
+        let tokens = inputs.iter().flatten().map(num_tokens).sum();
+        // The windows of over-long texts go through the encoder with everything else
+        let ids: Vec<_> = inputs
+            .iter()
+            .flatten()
+            .map(|window| window.get_ids().to_vec())
+            .collect();
+        let mut embeddings = self
+            .embed_ids_batch(&ids, options.layers, options.pooling)?
+            .into_iter();
+        inputs
+            .iter()
+            .map(|windows| {
+                let embedding = match windows.as_slice() {
+                    [_] => embeddings.next().unwrap(),
+                    windows => weighted_average(
+                        windows
+                            .iter()
+                            .map(|window| (embeddings.next().unwrap(), num_tokens(window))),
+                    ),
+                };
+                self.postprocess(embedding, options)
+            })
+            .collect::<Result<_>>()
+            .map(|embeddings| (embeddings, tokens))
+    }
+
+    /// [`Embedder::embed_batch`] in steps of a few texts, stopping once `cancel` is cancelled.
+    /// Returns the embeddings of the texts embedded by then, the first ones in input order;
+    /// fewer than `texts` only if it was cancelled. A token that ran past its timeout (see
+    /// [`CancelToken::with_timeout`]) fails the call with [`Error::Timeout`] instead.
+    pub fn embed_batch_cancellable<S: AsRef<str>>(
+        &self,
+        texts: &[S],
+        options: &EmbedOptions,
+        cancel: &CancelToken,
+    ) -> Result<Vec<Embedding>> {
+        let progress = Progress::start(texts.len());
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for step in texts.chunks(PROGRESS_STEP) {
+            // Timing out never reverts, so a cancelled token that has timed out is one
+            if cancel.is_cancelled() {
+                if cancel.is_timed_out() {
+                    return Err(Error::Timeout);
+                }
+                break;
+            }
+            embeddings.extend(self.embed_batch(step, options)?);
+            progress.advance(step.len());
+        }
+        Ok(embeddings)
+    }
+
+    /// [`Embedder::embed_batch`] with the texts spread over `parallelism` worker threads
+    /// (`0` uses rayon's global pool, one thread per core). Each worker tokenizes and runs
+    /// its own forward passes; idle workers steal pending texts, so uneven lengths balance
+    /// out. The results are in input order. While low power mode is in effect (see
+    /// [`PowerMode`](crate::PowerMode)) the batch runs on its capped pool instead.
+    pub fn embed_batch_parallel<S: AsRef<str> + Sync>(
+        &self,
+        texts: &[S],
+        options: &EmbedOptions,
+        parallelism: usize,
+    ) -> Result<Vec<Embedding>> {
+        let deadline = deadline(options);
+        let progress = Progress::start(texts.len());
+        let embed = || {
+            texts
+                .par_iter()
+                .map(|text| {
+                    check_deadline(deadline)?;
+                    let embedding = self.embed_with_options(text.as_ref(), options);
+                    progress.advance(1);
+                    embedding
+                })
+                .collect()
+        };
+        match power::thread_pool() {
+            Some(pool) if parallelism == 0 || parallelism >= pool.current_num_threads() => {
+                return pool.install(embed)
+            }
+            None if parallelism == 0 => return embed(),
+            _ => {}
+        }
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(parallelism)
+            .build()
+            .map_err(|e| Error::InvalidArgument(e.to_string()))?
+            .install(embed)
+    }
+
+    /// [`Embedder::embed_with_options`] on tokio's blocking thread pool, so async callers
+    /// don't stall their runtime threads for the duration of the forward pass.
+    #[cfg(feature = "async")]
+    pub async fn embed_async(
+        self: &Arc<Self>,
+        text: String,
+        options: EmbedOptions,
+    ) -> Result<Embedding> {
+        let embedder = Arc::clone(self);
+        run_blocking(move || embedder.embed_with_options(&text, &options)).await
+    }
+
+    /// [`Embedder::embed_batch`] on tokio's blocking thread pool.
+    #[cfg(feature = "async")]
+    pub async fn embed_batch_async(
+        self: &Arc<Self>,
+        texts: Vec<String>,
+        options: EmbedOptions,
+    ) -> Result<Vec<Embedding>> {
+        let embedder = Arc::clone(self);
+        run_blocking(move || embedder.embed_batch(&texts, &options)).await
+    }
+
+    /// Cosine similarities of every embedding in `a` to every embedding in `b`, computed as one
+    /// matrix product on the model's device; see
+    /// [`similarity_matrix`](crate::similarity::similarity_matrix).
+    pub fn similarity_matrix<A: AsRef<[f32]>, B: AsRef<[f32]>>(
+        &self,
+        a: &[A],
+        b: &[B],
+    ) -> Result<Vec<Vec<f32>>> {
+        similarity_matrix_on(self.model.device(), a, b)
+    }
+
+    /// Embed both lists of texts as batches and return their similarity matrix, row `i`
+    /// holding the similarities of `a[i]` to every text in `b`.
+    pub fn text_similarity_matrix<S: AsRef<str>, T: AsRef<str>>(
+        &self,
+        a: &[S],
+        b: &[T],
+        options: &EmbedOptions,
+    ) -> Result<Vec<Vec<f32>>> {
+        let a = self.embed_batch(a, options)?;
+        let b = self.embed_batch(b, options)?;
+        let a: Vec<Vec<f32>> = a.iter().map(Embedding::to_f32).collect();
+        let b: Vec<Vec<f32>> = b.iter().map(Embedding::to_f32).collect();
+        self.similarity_matrix(&a, &b)
+    }
+
+    /// Run forward passes at the extremes of the shapes requests will have, from one short
+    /// input to `max_batch` inputs of `max_tokens` tokens (at most `max_position_embeddings`),
+    /// so the first real request doesn't pay for paging in the weights, growing the allocator's
+    /// pools and starting threads. Call it after loading, before taking traffic.
+    ///
+    /// The passes don't touch the caches, the audit log or [`Stats`](crate::Stats).
+    pub fn warmup(&self, max_batch: usize, max_tokens: usize) -> Result<()> {
+        let start = Instant::now();
+        let max_batch = max_batch.max(1);
+        let max_tokens = max_tokens.clamp(1, self.config.max_position_embeddings);
+        let short = max_tokens.min(16);
+        let mut shapes = vec![
+            (1, short),
+            (1, max_tokens),
+            (max_batch, short),
+            (max_batch, max_tokens),
+        ];
+        shapes.sort_unstable();
+        shapes.dedup();
+        let pad_id = self.config.pad_token_id as u32;
+        for &(batch, tokens) in &shapes {
+            let token_ids = Tensor::from_vec(
+                vec![pad_id; batch * tokens],
+                (batch, tokens),
+                self.model.device(),
+            )?;
+            self.forward(&token_ids, None, None, LayerSelection::Last)?;
+        }
+        tracing::info!(
+            warmup_ms = start.elapsed().as_millis() as u64,
+            max_batch,
+            max_tokens,
+            "warmed up"
+        );
+        Ok(())
+    }
+
+    /// Embed a text that may exceed the model's maximum sequence length.
+    ///
+    /// The text is split into windows of at most `max_position_embeddings` tokens, each
+    /// sharing `overlap` tokens with the previous one, and every window is embedded on its
+    /// own. Returns one vector per window, in order, or [`Error::EmptyInput`] for a text without
+    /// tokens.
+    pub fn embed_document_chunks(&self, text: &str, overlap: usize) -> Result<Vec<Vec<f32>>> {
+        let start = Instant::now();
+        let result = self.embed_windows(text, overlap).and_then(|windows| {
+            windows
+                .into_iter()
+                .map(|(mut embedding, _)| {
+                    apply_all(&self.transforms, &mut embedding)?;
+                    Ok(embedding)
+                })
+                .collect()
+        });
+        self.audited("embed_document", [text], None, start, result)
+    }
+
+    /// Embed a text that may exceed the model's maximum sequence length as a single vector:
+    /// the average of its window embeddings (see [`Embedder::embed_document_chunks`]),
+    /// weighted by how many tokens each window holds.
+    pub fn embed_document(&self, text: &str, overlap: usize) -> Result<Vec<f32>> {
+        let start = Instant::now();
+        let result = self.embed_document_unaudited(text, overlap);
+        self.audited("embed_document", [text], None, start, result)
+    }
+
+    fn embed_document_unaudited(&self, text: &str, overlap: usize) -> Result<Vec<f32>> {
+        let mut document = weighted_average(self.embed_windows(text, overlap)?);
+        apply_all(&self.transforms, &mut document)?;
+        Ok(document)
+    }
+
+    fn embed_windows(&self, text: &str, overlap: usize) -> Result<Vec<(Vec<f32>, usize)>> {
+        // Truncation with a stride emits the overflowing windows, each wrapped in the model's
+        // special tokens.
+        let mut encoding = self.encode_truncated(
+            &self.preprocess(text),
+            self.config.max_position_embeddings,
+            overlap,
+            TruncationDirection::Right,
+        )?;
+        if !has_content(&encoding) {
+            return Err(Error::EmptyInput);
+        }
+        let overflowing = encoding.take_overflowing();
+
+        std::iter::once(encoding)
+            .chain(overflowing)
+            .map(|window| {
+                let ids = window.get_ids();
+                Ok((
+                    self.embed_ids(
+                        ids,
+                        LayerSelection::Last,
+                        Pooling::Mean,
+                        &mut Timings::default(),
+                    )?,
+                    ids.len(),
+                ))
+            })
+            .collect()
+    }
+
+    fn check_layers(&self, layers: LayerSelection) -> Result<()> {
+        #[cfg(feature = "ort")]
+        if matches!(self.model, Model::Onnx(_)) && layers != LayerSelection::Last {
+            return Err(Error::InvalidLayer(
+                "an ONNX model only provides the final layer".to_string(),
+            ));
+        }
+        let num_layers = self.config.num_hidden_layers;
+        match layers {
+            LayerSelection::Layer(index) if index > num_layers => Err(Error::InvalidLayer(
+                format!("layer {index} requested but the model has {num_layers} layers"),
+            )),
+            LayerSelection::ConcatLast(n) | LayerSelection::MeanLast(n)
+                if n == 0 || n > num_layers =>
+            {
+                Err(Error::InvalidLayer(format!(
+                    "last {n} layers requested but the model has {num_layers} layers"
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn embed_ids(
+        &self,
+        ids: &[u32],
+        layers: LayerSelection,
+        pooling: Pooling,
+        timings: &mut Timings,
+    ) -> Result<Vec<f32>> {
+        self.embed_segments(ids, None, layers, pooling, timings)
+    }
+
+    // `embed_ids` with the segment (token type) of every token, all 0 if `None`.
+    fn embed_segments(
+        &self,
+        ids: &[u32],
+        type_ids: Option<&[u32]>,
+        layers: LayerSelection,
+        pooling: Pooling,
+        timings: &mut Timings,
+    ) -> Result<Vec<f32>> {
+        let start = Instant::now();
+        let device = self.model.device();
+        let token_ids = Tensor::new(ids, device)?.unsqueeze(0)?;
+        let type_ids = match type_ids {
+            Some(type_ids) => Some(Tensor::new(type_ids, device)?.unsqueeze(0)?),
+            None => None,
+        };
+        let hidden = self.hidden_states(&token_ids, type_ids.as_ref(), None, layers)?;
+        timings.forward = start.elapsed();
+        tracing::debug!(
+            tokens = ids.len(),
+            forward_us = timings.forward.as_micros() as u64,
+            "forward pass"
+        );
+
+        let start = Instant::now();
+        let embedding = pool(&hidden, pooling)?;
+        timings.pool = start.elapsed();
+        Ok(embedding)
+    }
+
+    // Run several token sequences through the encoder at once. Shorter sequences are padded
+    // and masked out of attention, and every sequence is pooled over its own tokens only.
+    pub(crate) fn embed_ids_batch(
+        &self,
+        ids: &[Vec<u32>],
+        layers: LayerSelection,
+        pooling: Pooling,
+    ) -> Result<Vec<Vec<f32>>> {
+        let Some(max_len) = ids.iter().map(Vec::len).max() else {
+            return Ok(Vec::new());
+        };
+        let pad_id = self.config.pad_token_id as u32;
+        let padded: Vec<u32> = ids
+            .iter()
+            .flat_map(|seq| {
+                seq.iter()
+                    .copied()
+                    .chain(std::iter::repeat_n(pad_id, max_len - seq.len()))
+            })
+            .collect();
+        let token_ids = Tensor::from_vec(padded, (ids.len(), max_len), self.model.device())?;
+
+        let attention_mask = if ids.iter().all(|seq| seq.len() == max_len) {
+            None
+        } else {
+            let mask: Vec<u32> = ids
+                .iter()
+                .flat_map(|seq| (0..max_len).map(|i| u32::from(i < seq.len())))
+                .collect();
+            Some(Tensor::from_vec(
+                mask,
+                (ids.len(), max_len),
+                self.model.device(),
+            )?)
+        };
+
+        let start = Instant::now();
+        let hidden = self.hidden_states(&token_ids, None, attention_mask.as_ref(), layers)?;
+        tracing::debug!(
+            batch_size = ids.len(),
+            tokens = ids.iter().map(Vec::len).sum::<usize>(),
+            padded_len = max_len,
+            forward_us = start.elapsed().as_micros() as u64,
+            "batched forward pass"
+        );
+        ids.iter()
+            .enumerate()
+            .map(|(i, seq)| pool(&hidden.narrow(0, i, 1)?.narrow(1, 0, seq.len())?, pooling))
+            .collect()
+    }
+
+    // The selected hidden states, `(batch, seq_len, dim)`.
+    fn hidden_states(
+        &self,
+        token_ids: &Tensor,
+        token_type_ids: Option<&Tensor>,
+        attention_mask: Option<&Tensor>,
+        layers: LayerSelection,
+    ) -> Result<Tensor> {
+        let start = Instant::now();
+        let hidden = self.forward(token_ids, token_type_ids, attention_mask, layers)?;
+        stats::record(Phase::Forward, start.elapsed());
+        Ok(hidden)
+    }
+
+    fn forward(
+        &self,
+        token_ids: &Tensor,
+        token_type_ids: Option<&Tensor>,
+        attention_mask: Option<&Tensor>,
+        layers: LayerSelection,
+    ) -> Result<Tensor> {
+        let token_type_ids = match token_type_ids {
+            Some(token_type_ids) => token_type_ids.clone(),
+            None => token_ids.zeros_like()?,
+        };
+        match &self.model {
+            Model::Candle(model) => {
+                candle_hidden_states(model, token_ids, &token_type_ids, attention_mask, layers)
+            }
+            #[cfg(feature = "ort")]
+            Model::Onnx(model) => model.forward(token_ids, &token_type_ids, attention_mask),
+        }
+    }
+}
+
+fn candle_hidden_states(
+    model: &BertModel,
+    token_ids: &Tensor,
+    token_type_ids: &Tensor,
+    attention_mask: Option<&Tensor>,
+    layers: LayerSelection,
+) -> Result<Tensor> {
+    let num_layers = model.num_hidden_layers();
+
+    power::install(|| -> Result<Tensor> {
+        Ok(match layers {
+            LayerSelection::Last => model.forward(token_ids, token_type_ids, attention_mask)?,
+            _ => {
+                let hidden_states =
+                    model.forward_hidden_states(token_ids, token_type_ids, attention_mask)?;
+                match layers {
+                    LayerSelection::Layer(index) => hidden_states[index].clone(),
+                    LayerSelection::ConcatLast(n) => {
+                        Tensor::cat(&hidden_states[num_layers + 1 - n..], 2)?
+                    }
+                    LayerSelection::MeanLast(n) => {
+                        (Tensor::stack(&hidden_states[num_layers + 1 - n..], 0)?.sum(0)?
+                            / n as f64)?
+                    }
+                    LayerSelection::Last => unreachable!(),
+                }
+            }
+        })
+    })
+}
+
+// When a call with these options must stop, if they set a timeout.
+fn deadline(options: &EmbedOptions) -> Option<Instant> {
+    options
+        .timeout_ms
+        .map(|ms| Instant::now() + Duration::from_millis(ms))
+}
+
+fn check_deadline(deadline: Option<Instant>) -> Result<()> {
+    match deadline {
+        Some(deadline) if Instant::now() >= deadline => Err(Error::Timeout),
+        _ => Ok(()),
+    }
+}
+
+// The average of window embeddings, weighted by how many tokens each window holds.
+fn weighted_average(windows: impl IntoIterator<Item = (Vec<f32>, usize)>) -> Vec<f32> {
+    let windows: Vec<_> = windows.into_iter().collect();
+    let total_tokens: usize = windows.iter().map(|(_, n_tokens)| n_tokens).sum();
+    let dim = windows.first().map_or(0, |(embedding, _)| embedding.len());
+
+    let mut average = vec![0f32; dim];
+    for (embedding, n_tokens) in &windows {
+        let weight = *n_tokens as f32 / total_tokens as f32;
+        for (acc, value) in average.iter_mut().zip(embedding) {
+            *acc += weight * value;
+        }
+    }
+    average
+}
+
+// Whether an encoding holds any token besides the special ones and padding.
+fn has_content(encoding: &Encoding) -> bool {
+    encoding.get_special_tokens_mask().contains(&0)
+}
+
+// The tokens of an encoding that aren't padding.
+fn num_tokens(encoding: &Encoding) -> usize {
+    encoding
+        .get_attention_mask()
+        .iter()
+        .filter(|&&mask| mask != 0)
+        .count()
+}
+
+// Pool the `(1, n_tokens, dim)` hidden states of one sequence over the token dimension.
+fn pool(hidden: &Tensor, pooling: Pooling) -> Result<Vec<f32>> {
+    let start = Instant::now();
+    let (_n_sentence, n_tokens, _hidden_size) = hidden.dims3()?;
+    let embeddings = match pooling {
+        Pooling::Mean => (hidden.sum(1)? / (n_tokens as f64))?,
+        Pooling::Cls => hidden.narrow(1, 0, 1)?,
+        Pooling::Max => hidden.max(1)?,
+    };
+    let embedding = embeddings.flatten_all()?.to_vec1::<f32>()?;
+    stats::record(Phase::Pool, start.elapsed());
+    Ok(embedding)
+}
+
+// How much each input position contributes to the pooled output, by attention rollout over
+// the head-averaged `attentions` of every layer.
+fn attention_rollout(attentions: &[Tensor], seq_len: usize, pooling: Pooling) -> Result<Vec<f32>> {
+    let identity = Tensor::eye(seq_len, DTYPE, &Device::Cpu)?;
+    let mut rollout = identity.clone();
+    for attention in attentions {
+        // The residual connection carries each token's own state past the attention
+        let attention = ((attention.squeeze(0)?.to_device(&Device::Cpu)? + &identity)? * 0.5)?;
+        rollout = attention.matmul(&rollout)?;
+    }
+    let pooled = match pooling {
+        Pooling::Cls => rollout.get(0)?,
+        Pooling::Mean | Pooling::Max => rollout.mean(0)?,
+    };
+    Ok(pooled.to_vec1::<f32>()?)
+}
+
+#[cfg(feature = "async")]
+async fn run_blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => value,
+        // Blocking tasks cannot be cancelled, so the only failure is a panic inside `f`
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+fn render_instruction(template: &str, text: &str) -> String {
+    if template.contains("{text}") {
+        template.replace("{text}", text)
+    } else {
+        format!("{template}{text}")
+    }
+}
+
+/// Scale `embedding` to unit L2 norm in place (zero vectors are left untouched).
+pub(crate) fn normalize(embedding: &mut [f32]) {
+    let norm = dot(embedding, embedding).sqrt();
+    if norm > 0.0 {
+        embedding.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::OutputDtype;
+    use std::collections::HashMap;
+
+    fn test_embedder() -> Embedder {
+        Embedder::load(
+            "models/gte-small/config.json",
+            "models/gte-small/tokenizer.json",
+            "models/gte-small/model.safetensors",
+            false,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_layer_selection_dims() {
+        let embedder = test_embedder();
+        let text = "Test sentence for embeddings.";
+
+        let last = embedder.embed(text).unwrap();
+        let layer_12 = embedder
+            .embed_with_layers(text, LayerSelection::Layer(12))
+            .unwrap();
+        assert_eq!(last, layer_12);
+
+        let concat = embedder
+            .embed_with_layers(text, LayerSelection::ConcatLast(4))
+            .unwrap();
+        assert_eq!(4 * 384, concat.len());
+        assert_eq!(&last[..], &concat[3 * 384..]);
+
+        let mean = embedder
+            .embed_with_layers(text, LayerSelection::MeanLast(4))
+            .unwrap();
+        assert_eq!(384, mean.len());
+
+        assert!(embedder
+            .embed_with_layers(text, LayerSelection::Layer(13))
+            .is_err());
+        assert!(embedder
+            .embed_with_layers(text, LayerSelection::ConcatLast(0))
+            .is_err());
+    }
+
+    #[test]
+    fn test_embed_long_document() {
+        let embedder = test_embedder();
+
+        let short = "A short document.";
+        assert_eq!(1, embedder.embed_document_chunks(short, 32).unwrap().len());
+
+        let long = "The quick brown fox jumps over the lazy dog. ".repeat(120);
+        let chunks = embedder.embed_document_chunks(&long, 32).unwrap();
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|chunk| chunk.len() == 384));
+
+        let document = embedder.embed_document(&long, 32).unwrap();
+        assert_eq!(384, document.len());
+    }
+
+    #[test]
+    fn test_chunked_attention() {
+        let full = test_embedder();
+        let mut chunked = full.clone();
+        chunked
+            .set_attention(Attention::Chunked { block_size: 48 })
+            .unwrap();
+        assert!(chunked
+            .set_attention(Attention::Chunked { block_size: 0 })
+            .is_err());
+
+        let texts = [
+            "The quick brown fox jumps over the lazy dog. ".repeat(20),
+            "short".to_string(),
+        ];
+        let options = EmbedOptions::default();
+        let expected = full.embed_batch(&texts, &options).unwrap();
+        for (a, b) in expected
+            .iter()
+            .zip(chunked.embed_batch(&texts, &options).unwrap())
+        {
+            assert!(a
+                .to_f32()
+                .iter()
+                .zip(b.to_f32())
+                .all(|(a, b)| (a - b).abs() < 1e-4));
+        }
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let mut embedder = test_embedder();
+        let usage = embedder.memory_usage();
+        // The f16 checkpoint loads as f32
+        let file_bytes = std::fs::metadata("models/gte-small/model.safetensors")
+            .unwrap()
+            .len();
+        let weight_bytes = usage.weight_bytes.unwrap() as f64;
+        assert!((weight_bytes / (2 * file_bytes) as f64 - 1.0).abs() < 0.01);
+        assert_eq!(Some(0), usage.peak_activation_bytes);
+        assert_eq!((0, DeviceKind::Cpu), (usage.cache_bytes, usage.device));
+        #[cfg(target_os = "linux")]
+        assert!(usage.device_free_bytes.unwrap() <= usage.device_total_bytes.unwrap());
+
+        embedder.set_cache(Some(Arc::new(EmbeddingCache::new(8))));
+        embedder.embed("memory usage").unwrap();
+        let usage = embedder.memory_usage();
+        let full = usage.peak_activation_bytes.unwrap();
+        assert!(full > 0);
+        assert_eq!(1, usage.cache_entries);
+        assert!(usage.cache_bytes > 384 * 4);
+
+        embedder
+            .set_attention(Attention::Chunked { block_size: 16 })
+            .unwrap();
+        embedder.embed("chunked attention").unwrap();
+        assert!(embedder.memory_usage().peak_activation_bytes.unwrap() < full);
+    }
+
+    #[test]
+    fn test_explain() {
+        let embedder = test_embedder();
+        let text = "The cat sat on the mat.";
+        let options = EmbedOptions::default();
+        let explanation = embedder.explain(text, &options).unwrap();
+        let expected = embedder.embed_with_options(text, &options).unwrap();
+        assert!(expected
+            .to_f32()
+            .iter()
+            .zip(explanation.embedding.to_f32())
+            .all(|(a, b)| (a - b).abs() < 1e-5));
+
+        let tokens = &explanation.tokens;
+        assert_eq!(embedder.count_tokens(text, true).unwrap(), tokens.len());
+        assert_eq!(
+            ("[CLS]", true),
+            (tokens[0].token.as_str(), tokens[0].special)
+        );
+        assert_eq!("cat", &text[tokens[2].start..tokens[2].end]);
+        let total: f32 = tokens.iter().map(|token| token.score).sum();
+        assert!((total - 1.0).abs() < 1e-4);
+        assert!(tokens.iter().all(|token| token.score > 0.0));
+
+        // Chunked attention rolls out the same attention
+        let mut chunked = embedder.clone();
+        chunked
+            .set_attention(Attention::Chunked { block_size: 16 })
+            .unwrap();
+        let cls = EmbedOptions {
+            pooling: Pooling::Cls,
+            ..EmbedOptions::default()
+        };
+        let full = embedder.explain(text, &cls).unwrap();
+        for (a, b) in full
+            .tokens
+            .iter()
+            .zip(chunked.explain(text, &cls).unwrap().tokens)
+        {
+            assert!((a.score - b.score).abs() < 1e-4);
+        }
+
+        let average = EmbedOptions {
+            overflow: Overflow::Average,
+            ..EmbedOptions::default()
+        };
+        assert!(embedder.explain(text, &average).is_err());
+    }
+
+    #[test]
+    fn test_from_buffers() {
+        let read = |name: &str| std::fs::read(format!("models/gte-small/{name}")).unwrap();
+        let embedder = Embedder::from_buffers(
+            &read("config.json"),
+            &read("tokenizer.json"),
+            read("model.safetensors"),
+            false,
+        )
+        .unwrap();
+        let text = "Loaded from memory.";
+        assert_eq!(
+            test_embedder().embed(text).unwrap(),
+            embedder.embed(text).unwrap()
+        );
+        assert!(Embedder::from_buffers(b"{}", b"{}", Vec::new(), false).is_err());
+    }
+
+    #[test]
+    fn test_sharded_safetensors() {
+        let dir = std::env::temp_dir().join(format!("shards-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tensors =
+            candle::safetensors::load("models/gte-small/model.safetensors", &Device::Cpu).unwrap();
+        let mut shards = [HashMap::new(), HashMap::new()];
+        let mut weight_map = serde_json::Map::new();
+        for (name, tensor) in tensors {
+            let shard = usize::from(name.starts_with("encoder.layer.1"));
+            let file = format!("model-0000{}-of-00002.safetensors", shard + 1);
+            weight_map.insert(name.clone(), file.into());
+            shards[shard].insert(name, tensor);
+        }
+        for (i, shard) in shards.iter().enumerate() {
+            let path = dir.join(format!("model-0000{}-of-00002.safetensors", i + 1));
+            candle::safetensors::save(shard, path).unwrap();
+        }
+        let index = dir.join("model.safetensors.index.json");
+        let contents = serde_json::json!({"metadata": {}, "weight_map": weight_map});
+        std::fs::write(&index, contents.to_string()).unwrap();
+
+        let sharded = Embedder::load(
+            "models/gte-small/config.json",
+            "models/gte-small/tokenizer.json",
+            &index,
+            false,
+        )
+        .unwrap();
+        let text = "Split across two files.";
+        assert_eq!(
+            test_embedder().embed(text).unwrap(),
+            sharded.embed(text).unwrap()
+        );
+        drop(sharded);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_lora_adapter() {
+        let dir = std::env::temp_dir().join(format!("lora-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ramp = |rows: usize, cols: usize, step: f32| {
+            let values = (0..rows * cols).map(|i| ((i % 7) as f32 - 3.0) * step);
+            Tensor::from_iter(values, &Device::Cpu)
+                .unwrap()
+                .reshape((rows, cols))
+                .unwrap()
+        };
+        let query = "base_model.model.encoder.layer.0.attention.self.query";
+        let words = "base_model.model.embeddings.word_embeddings";
+        let adapter: HashMap<String, Tensor> = [
+            (format!("{query}.lora_A.weight"), ramp(2, 384, 0.01)),
+            (format!("{query}.lora_B.weight"), ramp(384, 2, 0.02)),
+            (format!("{words}.lora_embedding_A"), ramp(2, 30522, 0.01)),
+            (format!("{words}.lora_embedding_B"), ramp(384, 2, 0.01)),
+        ]
+        .into_iter()
+        .collect();
+        candle::safetensors::save(&adapter, dir.join("adapter_model.safetensors")).unwrap();
+        let config = r#"{"r": 2, "lora_alpha": 4, "target_modules": ["query"]}"#;
+        std::fs::write(dir.join("adapter_config.json"), config).unwrap();
+
+        // The same weights merged by hand: W + alpha / r * B A
+        let mut merged =
+            candle::safetensors::load("models/gte-small/model.safetensors", &Device::Cpu).unwrap();
+        let delta = (adapter[&format!("{query}.lora_B.weight")]
+            .matmul(&adapter[&format!("{query}.lora_A.weight")])
+            .unwrap()
+            * 2.0)
+            .unwrap();
+        let weight = "encoder.layer.0.attention.self.query.weight".to_string();
+        merged.insert(
+            weight.clone(),
+            (merged[&weight].to_dtype(candle::DType::F32).unwrap() + delta).unwrap(),
+        );
+        let delta = (adapter[&format!("{words}.lora_embedding_B")]
+            .matmul(&adapter[&format!("{words}.lora_embedding_A")])
+            .unwrap()
+            .t()
+            .unwrap()
+            * 2.0)
+            .unwrap();
+        let weight = "embeddings.word_embeddings.weight".to_string();
+        merged.insert(
+            weight.clone(),
+            (merged[&weight].to_dtype(candle::DType::F32).unwrap() + delta).unwrap(),
+        );
+        candle::safetensors::save(&merged, dir.join("merged.safetensors")).unwrap();
+
+        let load = |weights: &Path| {
+            Embedder::load(
+                "models/gte-small/config.json",
+                "models/gte-small/tokenizer.json",
+                weights,
+                false,
+            )
+        };
+        let adapted = Embedder::load_with_adapter(
+            "models/gte-small/config.json",
+            "models/gte-small/tokenizer.json",
+            "models/gte-small/model.safetensors",
+            &dir,
+            false,
+        )
+        .unwrap();
+        let text = "A domain-adapted embedding.";
+        let expected = load(&dir.join("merged.safetensors"))
+            .unwrap()
+            .embed(text)
+            .unwrap();
+        let embedding = adapted.embed(text).unwrap();
+        assert!(expected
+            .iter()
+            .zip(&embedding)
+            .all(|(a, b)| (a - b).abs() < 1e-5));
+        assert_ne!(test_embedder().embed(text).unwrap(), embedding);
+
+        // Adapters for weights the model doesn't have are rejected
+        let renamed: HashMap<String, Tensor> = adapter
+            .into_iter()
+            .map(|(name, tensor)| (name.replace("layer.0.", "layer.40."), tensor))
+            .collect();
+        candle::safetensors::save(&renamed, dir.join("adapter_model.safetensors")).unwrap();
+        assert!(Embedder::load_with_adapter(
+            "models/gte-small/config.json",
+            "models/gte-small/tokenizer.json",
+            "models/gte-small/model.safetensors",
+            &dir,
+            false,
+        )
+        .is_err());
+        drop(adapted);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_warmup() {
+        let embedder = test_embedder();
+        embedder.warmup(4, 64).unwrap();
+        // Longer than the model takes and an empty batch are clamped, not errors
+        embedder.warmup(0, 100_000).unwrap();
+        let text = "After warming up.";
+        assert_eq!(
+            test_embedder().embed(text).unwrap(),
+            embedder.embed(text).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_pytorch_weights() {
+        assert!(is_pytorch(Path::new("models/old/pytorch_model.bin")));
+        assert!(is_pytorch(Path::new("checkpoint.pth")));
+        assert!(!is_pytorch(Path::new("models/gte-small/model.safetensors")));
+
+        // A .bin file is read as a PyTorch checkpoint even when it holds safetensors
+        let path = std::env::temp_dir().join(format!("weights-{}.bin", std::process::id()));
+        std::fs::copy("models/gte-small/model.safetensors", &path).unwrap();
+        let loaded = Embedder::load(
+            "models/gte-small/config.json",
+            "models/gte-small/tokenizer.json",
+            &path,
+            false,
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.is_err());
+    }
+
+    #[cfg(feature = "embedded-model")]
+    #[test]
+    fn test_embedded_model() {
+        let embedded = Embedder::embedded().unwrap();
+        let text = "compiled into the binary";
+        assert_eq!(
+            test_embedder().embed(text).unwrap(),
+            embedded.embed(text).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_tokenizer_utilities() {
+        let embedder = test_embedder();
+        let text = "Counting tokens is cheap.";
+
+        let ids = embedder.encode(text, true).unwrap();
+        assert_eq!(ids.len(), embedder.count_tokens(text, true).unwrap());
+        assert_eq!(ids.len() - 2, embedder.count_tokens(text, false).unwrap());
+        assert_eq!(
+            "counting tokens is cheap.",
+            embedder.decode(&ids, true).unwrap()
+        );
+
+        // The tokenizer file pads and truncates to 128 tokens; counts must not.
+        let long = "word ".repeat(300);
+        assert_eq!(302, embedder.count_tokens(&long, true).unwrap());
+    }
+
+    #[test]
+    fn test_truncation() {
+        let mut embedder = test_embedder();
+        let text = "one two three four five six seven eight";
+        let words = embedder.encode(text, false).unwrap();
+        let head_tail = EmbedOptions {
+            max_length: Some(7),
+            truncation: TruncationStrategy::HeadTail,
+            ..Default::default()
+        };
+        let mut ids = embedder.tokenize(text, &head_tail).unwrap();
+        ids.retain(|&id| id != embedder.config.pad_token_id as u32);
+        assert_eq!(7, ids.len());
+        assert_eq!(&words[..3], &ids[1..4]);
+        assert_eq!(&words[words.len() - 2..], &ids[4..6]);
+
+        // The model's truncation applies to calls that don't set their own
+        let expected = embedder.embed_with_options(text, &head_tail).unwrap();
+        embedder.set_truncation(Some(7), TruncationStrategy::HeadTail);
+        assert_eq!(expected.to_f32(), embedder.embed(text).unwrap());
+        let untruncated = EmbedOptions {
+            max_length: Some(512),
+            ..Default::default()
+        };
+        assert_ne!(
+            expected,
+            embedder.embed_with_options(text, &untruncated).unwrap()
+        );
+        embedder.set_truncation(None, TruncationStrategy::Head);
+        assert_eq!(
+            test_embedder().embed(text).unwrap(),
+            embedder.embed(text).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_overflow_average() {
+        let embedder = test_embedder();
+        let average = EmbedOptions {
+            overflow: Overflow::Average,
+            ..Default::default()
+        };
+        // Texts within the tokenizer's 128 tokens are unaffected
+        let short = "fits in one window";
+        assert_eq!(
+            embedder.embed(short).unwrap(),
+            embedder
+                .embed_with_options(short, &average)
+                .unwrap()
+                .to_f32()
+        );
+
+        // 300 words make windows of 126, 126 and 48 words between the special tokens
+        let long = "word ".repeat(300);
+        let windows: Vec<_> = [126, 126, 48]
+            .into_iter()
+            .map(|n| (embedder.embed(&"word ".repeat(n)).unwrap(), n + 2))
+            .collect();
+        let expected = weighted_average(windows);
+        let single = embedder
+            .embed_with_options(&long, &average)
+            .unwrap()
+            .to_f32();
+        assert_ne!(embedder.embed(&long).unwrap(), single);
+        let batch = embedder.embed_batch(&[short, &long], &average).unwrap();
+        for embedding in [single, batch[1].to_f32()] {
+            for (a, b) in expected.iter().zip(&embedding) {
+                assert!((a - b).abs() < 1e-5, "{a} != {b}");
+            }
+        }
+        assert_eq!(embedder.embed(short).unwrap(), batch[0].to_f32());
+    }
+
+    #[test]
+    fn test_empty_input() {
+        let embedder = test_embedder();
+        let prefixed = EmbedOptions {
+            prefix: Some("query: ".to_string()),
+            ..Default::default()
+        };
+        for text in ["", " \n\t", "\u{200b}"] {
+            assert!(matches!(embedder.embed(text), Err(Error::EmptyInput)));
+            assert!(matches!(
+                embedder.embed_with_options(text, &prefixed),
+                Err(Error::EmptyInput)
+            ));
+        }
+        assert!(matches!(
+            embedder.embed_batch(&["text", ""], &EmbedOptions::default()),
+            Err(Error::EmptyInput)
+        ));
+        assert!(matches!(
+            embedder.embed_document("", 0),
+            Err(Error::EmptyInput)
+        ));
+
+        let allow_empty = EmbedOptions {
+            allow_empty: true,
+            ..Default::default()
+        };
+        let embedding = embedder.embed_with_options("", &allow_empty).unwrap();
+        assert_eq!(384, embedding.len());
+    }
+
+    #[test]
+    fn test_embed_pair() {
+        let embedder = test_embedder();
+        let (a, b) = ("A man is playing a guitar.", "Someone plays music.");
+        let pair = embedder.embed_pair(a, b).unwrap();
+        assert_eq!(384, pair.len());
+
+        // The second segment is marked as such
+        let encoding = embedder
+            .encode_pair(a, b, &EmbedOptions::default())
+            .unwrap();
+        assert!(encoding.get_type_ids().contains(&1));
+        let unmarked = embedder
+            .embed_ids(
+                encoding.get_ids(),
+                LayerSelection::Last,
+                Pooling::Mean,
+                &mut Timings::default(),
+            )
+            .unwrap();
+        assert_ne!(pair, unmarked);
+
+        let truncated = EmbedOptions {
+            max_length: Some(8),
+            ..Default::default()
+        };
+        let encoding = embedder.encode_pair(a, b, &truncated).unwrap();
+        assert_eq!(8, encoding.get_attention_mask().iter().sum::<u32>());
+        assert!(embedder.embed_pair_with_options(a, b, &truncated).is_ok());
+        let head_tail = EmbedOptions {
+            truncation: TruncationStrategy::HeadTail,
+            ..truncated
+        };
+        assert!(embedder.embed_pair_with_options(a, b, &head_tail).is_err());
+        assert!(matches!(
+            embedder.embed_pair(a, " "),
+            Err(Error::EmptyInput)
+        ));
+    }
+
+    #[test]
+    fn test_preprocessor() {
+        let mut embedder = test_embedder();
+        let redacted = embedder.embed("call me at ###-####").unwrap();
+
+        embedder.set_preprocessor(Some(Arc::new(|text: &str| {
+            text.replace(|c: char| c.is_ascii_digit(), "#")
+        })));
+        assert_eq!(redacted, embedder.embed("call me at 555-1234").unwrap());
+        assert_eq!(
+            embedder.count_tokens("call me at ###-####", true).unwrap(),
+            embedder.count_tokens("call me at 555-1234", true).unwrap()
+        );
+
+        embedder.set_preprocessor(None);
+        assert_ne!(redacted, embedder.embed("call me at 555-1234").unwrap());
+
+        // The cleanup runs first, so the preprocessor sees the cleaned text
+        let messy = "\u{FB01}ne wi\u{00AD}ne";
+        let clean = embedder.embed("fine wine").unwrap();
+        assert_ne!(clean, embedder.embed(messy).unwrap());
+        embedder.set_text_cleanup(Some(TextCleanup {
+            strip_control: true,
+            normalization: Some(crate::UnicodeForm::Nfkc),
+            ..Default::default()
+        }));
+        assert_eq!(clean, embedder.embed(messy).unwrap());
+        embedder.set_preprocessor(Some(Arc::new(|text: &str| text.replace("fine", "red"))));
+        assert_eq!(
+            embedder.embed("red wine").unwrap(),
+            embedder.embed(messy).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_embed_with_options() {
+        let mut embedder = test_embedder();
+        let text = "Options change how a text is embedded.";
+
+        let default = embedder
+            .embed_with_options(text, &EmbedOptions::default())
+            .unwrap();
+        assert_eq!(Embedding::F32(embedder.embed(text).unwrap()), default);
+        let (timed, timings) = embedder
+            .embed_with_timings(text, &EmbedOptions::default())
+            .unwrap();
+        assert_eq!(default, timed);
+        assert!(timings.forward > std::time::Duration::ZERO);
+
+        let options = EmbedOptions {
+            pooling: Pooling::Cls,
+            normalize: true,
+            dtype: OutputDtype::F64,
+            ..Default::default()
+        };
+        let Embedding::F64(cls) = embedder.embed_with_options(text, &options).unwrap() else {
+            panic!("expected f64 output");
+        };
+        assert_eq!(384, cls.len());
+        let norm = cls.iter().map(|x| x * x).sum::<f64>().sqrt();
+        assert!((norm - 1.0).abs() < 1e-4);
+
+        // Keeping the head or the tail of a truncated text gives different vectors
+        let head = EmbedOptions {
+            max_length: Some(6),
+            ..Default::default()
+        };
+        let tail = EmbedOptions {
+            truncation: TruncationStrategy::Tail,
+            ..head.clone()
+        };
+        assert_ne!(
+            embedder.embed_with_options(text, &head).unwrap(),
+            embedder.embed_with_options(text, &tail).unwrap()
+        );
+
+        let prefixed = EmbedOptions {
+            prefix: Some("query: ".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            embedder.embed("query: hello").unwrap(),
+            embedder
+                .embed_with_options("hello", &prefixed)
+                .unwrap()
+                .to_f32()
+        );
+
+        // The instruction template wraps the text unless disabled per call
+        embedder.set_instruction(Some("Represent the title: {text}".to_string()));
+        let disabled = EmbedOptions {
+            instruction: Some(String::new()),
+            ..Default::default()
+        };
+        assert_eq!(
+            embedder
+                .embed_with_options("Represent the title: hello", &disabled)
+                .unwrap(),
+            embedder
+                .embed_with_options("hello", &EmbedOptions::default())
+                .unwrap()
+        );
+        embedder.set_instruction(None);
+
+        // Task prefixes come from the model; an explicit prefix still wins
+        embedder.set_task_prefixes(crate::options::TaskPrefixes::e5());
+        let passage = EmbedOptions {
+            task: Some(crate::options::Task::Passage),
+            ..Default::default()
+        };
+        assert_eq!(
+            embedder.embed("passage: hello").unwrap(),
+            embedder
+                .embed_with_options("hello", &passage)
+                .unwrap()
+                .to_f32()
+        );
+        assert_eq!(
+            embedder.embed("query: hello").unwrap(),
+            embedder
+                .embed_with_options(
+                    "hello",
+                    &EmbedOptions {
+                        task: Some(crate::options::Task::Passage),
+                        ..prefixed
+                    }
+                )
+                .unwrap()
+                .to_f32()
+        );
+    }
+
+    #[test]
+    fn test_concurrent_embedding() {
+        let embedder = test_embedder();
+        let expected = embedder.embed("shared across threads").unwrap();
+
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| embedder.embed("shared across threads").unwrap()))
+                .collect();
+            for handle in handles {
+                assert_eq!(expected, handle.join().unwrap());
+            }
+        });
+    }
+
+    #[test]
+    fn test_embed_batch_parallel() {
+        let embedder = test_embedder();
+        let texts = ["first text", "a somewhat longer second text", "third"];
+        let options = EmbedOptions::default();
+
+        let sequential = embedder.embed_batch(&texts, &options).unwrap();
+        assert_eq!(
+            Embedding::F32(embedder.embed("third").unwrap()),
+            sequential[2]
+        );
+        for parallelism in [0, 2] {
+            assert_eq!(
+                sequential,
+                embedder
+                    .embed_batch_parallel(&texts, &options, parallelism)
+                    .unwrap()
+            );
+        }
+
+        // A timeout holds back every forward pass once it has passed
+        let expired = EmbedOptions {
+            timeout_ms: Some(0),
+            ..Default::default()
+        };
+        assert!(matches!(
+            embedder.embed_batch(&texts, &expired),
+            Err(Error::Timeout)
+        ));
+        assert!(matches!(
+            embedder.embed_batch_parallel(&texts, &expired, 2),
+            Err(Error::Timeout)
+        ));
+        assert!(matches!(
+            embedder.embed_batch_cancellable(
+                &texts,
+                &options,
+                &CancelToken::with_timeout(Duration::ZERO)
+            ),
+            Err(Error::Timeout)
+        ));
+        let generous = EmbedOptions {
+            timeout_ms: Some(60_000),
+            ..Default::default()
+        };
+        assert_eq!(sequential, embedder.embed_batch(&texts, &generous).unwrap());
+
+        let (embeddings, tokens) = embedder.embed_batch_with_usage(&texts, &options).unwrap();
+        assert_eq!(sequential, embeddings);
+        let expected: usize = texts
+            .iter()
+            .map(|text| embedder.count_tokens(text, true).unwrap())
+            .sum();
+        assert_eq!(expected, tokens);
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let embedder = test_embedder();
+        let texts = ["first text", "a somewhat longer second text", "third"];
+        let options = EmbedOptions {
+            deterministic: true,
+            dtype: OutputDtype::F64,
+            ..EmbedOptions::default()
+        };
+
+        let batch = embedder.embed_batch(&texts, &options).unwrap();
+        for (text, embedding) in texts.iter().zip(&batch) {
+            let Embedding::F64(embedding) = embedding else {
+                panic!("expected f64 output");
+            };
+            let Embedding::F64(single) = embedder.embed_with_options(text, &options).unwrap()
+            else {
+                panic!("expected f64 output");
+            };
+            let bits = |v: &[f64]| v.iter().map(|x| x.to_bits()).collect::<Vec<_>>();
+            assert_eq!(bits(&single), bits(embedding));
+        }
+        assert_eq!(
+            batch,
+            embedder.embed_batch_parallel(&texts, &options, 2).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_embedding_cache() {
+        let mut embedder = test_embedder();
+        let cache = Arc::new(EmbeddingCache::new(8));
+        embedder.set_cache(Some(Arc::clone(&cache)));
+        let options = EmbedOptions::default();
+
+        let first = embedder.embed("cached text").unwrap();
+        assert_eq!(first, embedder.embed("cached text").unwrap());
+        let batch = embedder
+            .embed_batch(&["new text", "cached text"], &options)
+            .unwrap();
+        assert_eq!(Embedding::F32(first), batch[1]);
+        let stats = cache.stats();
+        assert_eq!((2, 2, 2), (stats.hits, stats.misses, stats.entries));
+
+        embedder.set_transforms(vec![Transform::Normalize]);
+        assert_eq!(0, cache.stats().entries);
+    }
+
+    #[test]
+    fn test_text_similarity_matrix() {
+        let embedder = test_embedder();
+        let queries = ["a cat sat on the mat", "stock prices fell"];
+        let documents = ["a kitten on a rug", "markets dropped today", "cats"];
+
+        let matrix = embedder
+            .text_similarity_matrix(&queries, &documents, &EmbedOptions::default())
+            .unwrap();
+        assert_eq!((2, 3), (matrix.len(), matrix[0].len()));
+        let query = embedder.embed(queries[1]).unwrap();
+        let document = embedder.embed(documents[1]).unwrap();
+        let expected = crate::similarity::cosine_similarity(&query, &document).unwrap();
+        assert!((matrix[1][1] - expected).abs() < 1e-4);
+        assert!(matrix[0][0] > matrix[0][1]);
+    }
+
+    #[test]
+    fn test_embed_ids_batch_masks_padding() {
+        let embedder = test_embedder();
+        let short = embedder.encode("short", true).unwrap();
+        let long = embedder
+            .encode("a much longer sequence than the first one", true)
+            .unwrap();
+
+        let batch = embedder
+            .embed_ids_batch(
+                &[short.clone(), long.clone()],
+                LayerSelection::Last,
+                Pooling::Mean,
+            )
+            .unwrap();
+        for (ids, batched) in [short, long].iter().zip(&batch) {
+            let single = embedder
+                .embed_ids(
+                    ids,
+                    LayerSelection::Last,
+                    Pooling::Mean,
+                    &mut Timings::default(),
+                )
+                .unwrap();
+            assert!(single
+                .iter()
+                .zip(batched)
+                .all(|(a, b)| (a - b).abs() < 1e-4));
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_embed_async() {
+        let embedder = Arc::new(test_embedder());
+        let expected = embedder.embed("async text").unwrap();
+
+        let embedding = embedder
+            .embed_async("async text".to_string(), EmbedOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(Embedding::F32(expected.clone()), embedding);
+
+        let batch = embedder
+            .embed_batch_async(vec!["async text".to_string()], EmbedOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(vec![Embedding::F32(expected)], batch);
+    }
+
+    #[test]
+    fn test_transforms() {
+        let mut embedder = test_embedder();
+        let text = "Post-processing runs last.";
+        let mut expected = embedder.embed(text).unwrap();
+        normalize(&mut expected);
+
+        embedder.set_transforms(vec![
+            Transform::Normalize,
+            Transform::Custom(Arc::new(|embedding: &mut [f32]| embedding[0] = 42.0)),
+        ]);
+        let embedding = embedder.embed(text).unwrap();
+        assert_eq!(42.0, embedding[0]);
+        assert_eq!(&expected[1..], &embedding[1..]);
+
+        embedder.set_transforms(vec![Transform::Project {
+            matrix: vec![1.0; 2 * 384],
+            out_dim: 2,
+        }]);
+        assert_eq!(2, embedder.embedding_dim(LayerSelection::Last));
+        assert_eq!(2, embedder.embed(text).unwrap().len());
+    }
+}
@@ -0,0 +1,127 @@
+use std::fmt;
+
+/// Errors produced while loading a model or generating embeddings.
+#[derive(Debug)]
+pub enum Error {
+    Candle(candle::Error),
+    Tokenizer(tokenizers::Error),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Csv(csv::Error),
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+    #[cfg(feature = "ort")]
+    Onnx(ort::Error),
+    #[cfg(feature = "arrow")]
+    Arrow(arrow_schema::ArrowError),
+    #[cfg(feature = "arrow")]
+    Parquet(parquet::errors::ParquetError),
+    /// A remote embedding service failed or answered with something unexpected.
+    #[cfg(feature = "remote")]
+    Remote(String),
+    /// A model file could not be fetched from the Hub, or failed its size or checksum check.
+    #[cfg(feature = "hub")]
+    Download(String),
+    /// The requested encoder layer(s) do not exist in the loaded model.
+    InvalidLayer(String),
+    InvalidArgument(String),
+    /// The text to embed is empty, whitespace only or has no tokens the model knows, so its
+    /// embedding would only reflect the special tokens (see
+    /// [`EmbedOptions::allow_empty`](crate::EmbedOptions::allow_empty)).
+    EmptyInput,
+    /// A request queued on a [`MicroBatcher`](crate::MicroBatcher) could not be served.
+    Batch(String),
+    /// The call ran past its [`EmbedOptions::timeout_ms`](crate::EmbedOptions::timeout_ms).
+    Timeout,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Candle(e) => write!(f, "{e}"),
+            Error::Tokenizer(e) => write!(f, "{e}"),
+            Error::Io(e) => write!(f, "{e}"),
+            Error::Json(e) => write!(f, "{e}"),
+            Error::Csv(e) => write!(f, "{e}"),
+            #[cfg(feature = "sqlite")]
+            Error::Sqlite(e) => write!(f, "{e}"),
+            #[cfg(feature = "ort")]
+            Error::Onnx(e) => write!(f, "{e}"),
+            #[cfg(feature = "arrow")]
+            Error::Arrow(e) => write!(f, "{e}"),
+            #[cfg(feature = "arrow")]
+            Error::Parquet(e) => write!(f, "{e}"),
+            #[cfg(feature = "remote")]
+            Error::Remote(msg) => write!(f, "{msg}"),
+            #[cfg(feature = "hub")]
+            Error::Download(msg) => write!(f, "download failed: {msg}"),
+            Error::InvalidLayer(msg) => write!(f, "invalid layer selection: {msg}"),
+            Error::InvalidArgument(msg) => write!(f, "invalid argument: {msg}"),
+            Error::EmptyInput => write!(f, "the input has no tokens to embed"),
+            Error::Batch(msg) => write!(f, "{msg}"),
+            Error::Timeout => write!(f, "the call timed out"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<candle::Error> for Error {
+    fn from(e: candle::Error) -> Self {
+        Error::Candle(e)
+    }
+}
+
+impl From<tokenizers::Error> for Error {
+    fn from(e: tokenizers::Error) -> Self {
+        Error::Tokenizer(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(e: csv::Error) -> Self {
+        Error::Csv(e)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Sqlite(e)
+    }
+}
+
+#[cfg(feature = "ort")]
+impl From<ort::Error> for Error {
+    fn from(e: ort::Error) -> Self {
+        Error::Onnx(e)
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl From<arrow_schema::ArrowError> for Error {
+    fn from(e: arrow_schema::ArrowError) -> Self {
+        Error::Arrow(e)
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl From<parquet::errors::ParquetError> for Error {
+    fn from(e: parquet::errors::ParquetError) -> Self {
+        Error::Parquet(e)
+    }
+}
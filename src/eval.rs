@@ -0,0 +1,320 @@
+use crate::embedder::{normalize, Embedder};
+use crate::error::{Error, Result};
+use crate::kernels::dot;
+use crate::options::{EmbedOptions, Task};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// A retrieval test set: queries, the documents to search and how relevant each document is
+/// to each query.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RetrievalDataset {
+    /// Query texts by id.
+    pub queries: BTreeMap<String, String>,
+    /// Document texts by id.
+    pub documents: BTreeMap<String, String>,
+    /// The graded relevance of documents by query id, then document id. Documents not listed
+    /// for a query are irrelevant to it.
+    pub qrels: HashMap<String, HashMap<String, u32>>,
+}
+
+impl RetrievalDataset {
+    /// Load a dataset in the BEIR layout: `queries.jsonl` and `corpus.jsonl` with `_id` and
+    /// `text` fields (documents may add a `title`, prepended to the text), and
+    /// `qrels/test.tsv` with a header row and `query-id`, `corpus-id`, `score` columns.
+    pub fn load_beir(dir: impl AsRef<Path>) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Entry {
+            #[serde(rename = "_id")]
+            id: String,
+            text: String,
+            #[serde(default)]
+            title: String,
+        }
+        let read_entries = |name: &str| -> Result<BTreeMap<String, String>> {
+            let file = std::fs::File::open(dir.as_ref().join(name))?;
+            let mut entries = BTreeMap::new();
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let entry: Entry = serde_json::from_str(&line)?;
+                let text = match entry.title.is_empty() {
+                    true => entry.text,
+                    false => format!("{} {}", entry.title, entry.text),
+                };
+                entries.insert(entry.id, text);
+            }
+            Ok(entries)
+        };
+
+        let mut qrels: HashMap<String, HashMap<String, u32>> = HashMap::new();
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(b'\t')
+            .from_path(dir.as_ref().join("qrels").join("test.tsv"))?;
+        for row in reader.records() {
+            let row = row?;
+            let (Some(query), Some(document), Some(score)) = (row.get(0), row.get(1), row.get(2))
+            else {
+                return Err(Error::InvalidArgument(
+                    "qrels rows need a query id, corpus id and score".to_string(),
+                ));
+            };
+            let score = score
+                .trim()
+                .parse()
+                .map_err(|_| Error::InvalidArgument(format!("invalid qrels score {score:?}")))?;
+            qrels
+                .entry(query.to_string())
+                .or_default()
+                .insert(document.to_string(), score);
+        }
+        Ok(RetrievalDataset {
+            queries: read_entries("queries.jsonl")?,
+            documents: read_entries("corpus.jsonl")?,
+            qrels,
+        })
+    }
+}
+
+/// How [`evaluate`] embeds and ranks. Serialized as JSON, e.g.
+/// `{"k": [10, 100], "query": {"pooling": "cls", "task": "query"}}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EvalConfig {
+    /// The cutoffs to report the metrics at.
+    pub k: Vec<usize>,
+    /// Options for the queries, [`Task::Query`] by default.
+    pub query: EmbedOptions,
+    /// Options for the documents, [`Task::Passage`] by default.
+    pub document: EmbedOptions,
+    /// Texts embedded per batch.
+    pub batch_size: usize,
+}
+
+impl Default for EvalConfig {
+    fn default() -> Self {
+        EvalConfig {
+            k: vec![1, 5, 10],
+            query: EmbedOptions {
+                task: Some(Task::Query),
+                ..EmbedOptions::default()
+            },
+            document: EmbedOptions {
+                task: Some(Task::Passage),
+                ..EmbedOptions::default()
+            },
+            batch_size: 32,
+        }
+    }
+}
+
+impl EvalConfig {
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+/// The metrics at one cutoff, averaged over the queries.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct RetrievalMetrics {
+    pub k: usize,
+    /// Normalized discounted cumulative gain with the graded relevance as the gain.
+    pub ndcg: f64,
+    /// Mean reciprocal rank of the first relevant document, 0 if none is in the top `k`.
+    pub mrr: f64,
+    /// Share of the relevant documents in the top `k`.
+    pub recall: f64,
+}
+
+/// What [`evaluate`] reports.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EvalReport {
+    /// Queries with at least one relevant document, which the metrics average over.
+    pub queries: usize,
+    pub documents: usize,
+    pub metrics: Vec<RetrievalMetrics>,
+}
+
+/// Embed the queries and documents of `dataset` with `embedder`, rank every document for every
+/// query by cosine similarity and score the rankings against the relevance labels, to compare
+/// models and pooling settings on the same data.
+pub fn evaluate(
+    embedder: &Embedder,
+    dataset: &RetrievalDataset,
+    config: &EvalConfig,
+) -> Result<EvalReport> {
+    if config.k.is_empty() || config.k.contains(&0) || config.batch_size == 0 {
+        return Err(Error::InvalidArgument(
+            "evaluation needs cutoffs and a batch size of at least 1".to_string(),
+        ));
+    }
+    let queries: Vec<(&String, &HashMap<String, u32>)> = dataset
+        .qrels
+        .iter()
+        .filter(|(_, relevance)| relevance.values().any(|&score| score > 0))
+        .collect();
+    if let Some((id, _)) = queries
+        .iter()
+        .find(|(id, _)| !dataset.queries.contains_key(*id))
+    {
+        return Err(Error::InvalidArgument(format!(
+            "qrels name query {id:?}, which the dataset does not have"
+        )));
+    }
+
+    let document_ids: Vec<&String> = dataset.documents.keys().collect();
+    let document_texts: Vec<&str> = dataset.documents.values().map(String::as_str).collect();
+    let documents = embed_unit(
+        embedder,
+        &document_texts,
+        &config.document,
+        config.batch_size,
+    )?;
+    let query_texts: Vec<&str> = queries
+        .iter()
+        .map(|(id, _)| dataset.queries[*id].as_str())
+        .collect();
+    let query_embeddings = embed_unit(embedder, &query_texts, &config.query, config.batch_size)?;
+
+    let max_k = config.k.iter().copied().max().unwrap_or(0);
+    let mut sums = vec![(0.0, 0.0, 0.0); config.k.len()];
+    for ((_, relevance), query) in queries.iter().zip(&query_embeddings) {
+        let mut scores: Vec<(usize, f32)> = documents
+            .iter()
+            .map(|document| dot(query, document))
+            .enumerate()
+            .collect();
+        scores.sort_unstable_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        scores.truncate(max_k);
+        let gains: Vec<u32> = scores
+            .iter()
+            .map(|&(i, _)| relevance.get(document_ids[i]).copied().unwrap_or(0))
+            .collect();
+        let mut ideal: Vec<u32> = relevance.values().copied().filter(|&s| s > 0).collect();
+        ideal.sort_unstable_by(|a, b| b.cmp(a));
+
+        for (sum, &k) in sums.iter_mut().zip(&config.k) {
+            let top = &gains[..k.min(gains.len())];
+            let ideal_dcg = dcg(&ideal[..k.min(ideal.len())]);
+            sum.0 += dcg(top) / ideal_dcg;
+            sum.1 += top
+                .iter()
+                .position(|&gain| gain > 0)
+                .map_or(0.0, |rank| 1.0 / (rank + 1) as f64);
+            sum.2 += top.iter().filter(|&&gain| gain > 0).count() as f64 / ideal.len() as f64;
+        }
+    }
+
+    let count = queries.len().max(1) as f64;
+    Ok(EvalReport {
+        queries: queries.len(),
+        documents: documents.len(),
+        metrics: config
+            .k
+            .iter()
+            .zip(sums)
+            .map(|(&k, (ndcg, mrr, recall))| RetrievalMetrics {
+                k,
+                ndcg: ndcg / count,
+                mrr: mrr / count,
+                recall: recall / count,
+            })
+            .collect(),
+    })
+}
+
+// Unit-length embeddings of `texts`, so dot products are cosine similarities.
+fn embed_unit(
+    embedder: &Embedder,
+    texts: &[&str],
+    options: &EmbedOptions,
+    batch_size: usize,
+) -> Result<Vec<Vec<f32>>> {
+    let mut embeddings = Vec::with_capacity(texts.len());
+    for batch in texts.chunks(batch_size) {
+        for embedding in embedder.embed_batch(batch, options)? {
+            let mut embedding = embedding.to_f32();
+            normalize(&mut embedding);
+            embeddings.push(embedding);
+        }
+    }
+    Ok(embeddings)
+}
+
+// Discounted cumulative gain of gains in rank order.
+fn dcg(gains: &[u32]) -> f64 {
+    gains
+        .iter()
+        .enumerate()
+        .map(|(rank, &gain)| gain as f64 / (rank as f64 + 2.0).log2())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() {
+        let dir = std::env::temp_dir().join(format!("beir-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("qrels")).unwrap();
+        std::fs::write(
+            dir.join("corpus.jsonl"),
+            r#"{"_id": "d1", "title": "Cats", "text": "Cats are small furry pets that purr."}
+{"_id": "d2", "text": "The stock market fell sharply on Monday."}
+{"_id": "d3", "text": "Bread is baked from flour, water and yeast."}
+"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("queries.jsonl"),
+            r#"{"_id": "q1", "text": "furry cats purr"}
+{"_id": "q2", "text": "baking bread from flour and yeast"}
+{"_id": "q3", "text": "unjudged query"}
+"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("qrels").join("test.tsv"),
+            "query-id\tcorpus-id\tscore\nq1\td1\t1\nq2\td3\t2\nq2\td2\t0\n",
+        )
+        .unwrap();
+        let dataset = RetrievalDataset::load_beir(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(3, dataset.queries.len());
+        assert_eq!(
+            "Cats Cats are small furry pets that purr.",
+            dataset.documents["d1"]
+        );
+        assert_eq!(Some(&2), dataset.qrels["q2"].get("d3"));
+
+        let embedder = Embedder::load(
+            "models/gte-small/config.json",
+            "models/gte-small/tokenizer.json",
+            "models/gte-small/model.safetensors",
+            false,
+        )
+        .unwrap();
+        let config = EvalConfig::from_json(r#"{"k": [1, 3]}"#).unwrap();
+        let report = evaluate(&embedder, &dataset, &config).unwrap();
+        assert_eq!((2, 3), (report.queries, report.documents));
+        // Each query's one relevant document ranks first
+        for metrics in &report.metrics {
+            assert!((metrics.ndcg - 1.0).abs() < 1e-9, "{metrics:?}");
+            assert_eq!((1.0, 1.0), (metrics.mrr, metrics.recall));
+        }
+
+        // The relevant document ranked second of two
+        assert!((dcg(&[0, 2]) / dcg(&[2]) - 1.0 / 3f64.log2()).abs() < 1e-12);
+        let mut missing = dataset.clone();
+        missing.queries.remove("q1");
+        assert!(evaluate(&embedder, &missing, &config).is_err());
+        assert!(EvalConfig::from_json(r#"{"k": [0]}"#)
+            .and_then(|config| evaluate(&embedder, &dataset, &config))
+            .is_err());
+    }
+}
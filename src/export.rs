@@ -0,0 +1,717 @@
+use crate::error::{Error, Result};
+use crate::pipeline::{PipelineRecord, Sink};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "arrow")]
+use std::sync::Arc;
+
+/// Distance a FAISS flat index ranks by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FaissMetric {
+    /// `IndexFlatIP`; cosine similarity for normalized vectors.
+    #[default]
+    InnerProduct,
+    /// `IndexFlatL2`.
+    L2,
+}
+
+/// Write `vectors` (row-major, `dim` values each) as a FAISS `IndexFlatIP`/`IndexFlatL2`
+/// file, readable with `faiss.read_index`.
+pub fn write_faiss_flat(
+    writer: &mut impl Write,
+    dim: usize,
+    vectors: &[f32],
+    metric: FaissMetric,
+) -> Result<()> {
+    if dim == 0 || !vectors.len().is_multiple_of(dim) {
+        return Err(Error::InvalidArgument(format!(
+            "{} values do not form vectors of dimension {dim}",
+            vectors.len()
+        )));
+    }
+    let (fourcc, metric_type): (&[u8; 4], i32) = match metric {
+        FaissMetric::InnerProduct => (b"IxFI", 0),
+        FaissMetric::L2 => (b"IxF2", 1),
+    };
+
+    // Layout of faiss/impl/index_write.cpp: fourcc, index header, then the codes vector.
+    writer.write_all(fourcc)?;
+    writer.write_all(&(dim as i32).to_le_bytes())?;
+    writer.write_all(&((vectors.len() / dim) as i64).to_le_bytes())?;
+    writer.write_all(&(1i64 << 20).to_le_bytes())?;
+    writer.write_all(&(1i64 << 20).to_le_bytes())?;
+    writer.write_all(&[1u8])?; // is_trained
+    writer.write_all(&metric_type.to_le_bytes())?;
+    writer.write_all(&(vectors.len() as u64).to_le_bytes())?;
+    for value in vectors {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Write `vectors` (row-major, `dim` values each) as a NumPy `.npy` file of `float32`, shape
+/// `(rows, dim)`, readable with `numpy.load`.
+pub fn write_npy(writer: &mut impl Write, dim: usize, vectors: &[f32]) -> Result<()> {
+    let rows = rows(dim, vectors)?;
+    write_npy_header(writer, "<f4", &format!("({rows}, {dim})"))?;
+    for value in vectors {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Write `vectors` (row-major, `dim` values each) as a NumPy `.npz` archive holding the array
+/// `embeddings` and, if given, the matching `ids` as a unicode array, so
+/// `numpy.load(path)["ids"]` works without pickle. The archive is not compressed.
+pub fn write_npz<S: AsRef<str>>(
+    writer: &mut impl Write,
+    dim: usize,
+    vectors: &[f32],
+    ids: Option<&[S]>,
+) -> Result<()> {
+    let rows = rows(dim, vectors)?;
+    let mut members = Vec::new();
+    let mut embeddings = Vec::new();
+    write_npy(&mut embeddings, dim, vectors)?;
+    members.push(("embeddings.npy", embeddings));
+    if let Some(ids) = ids {
+        if ids.len() != rows {
+            return Err(Error::InvalidArgument(format!(
+                "{} ids for {rows} vectors",
+                ids.len()
+            )));
+        }
+        members.push(("ids.npy", npy_strings(ids)?));
+    }
+    write_zip(writer, &members)
+}
+
+// The number of `dim`-long rows in `vectors`.
+fn rows(dim: usize, vectors: &[f32]) -> Result<usize> {
+    if !vectors.len().is_multiple_of(dim) {
+        return Err(Error::InvalidArgument(format!(
+            "{} values do not form vectors of dimension {dim}",
+            vectors.len()
+        )));
+    }
+    Ok(vectors.len().checked_div(dim).unwrap_or(0))
+}
+
+// Version 1.0 of the format: magic, header length, then a Python dict literal padded with
+// spaces so the data starts at a multiple of 64 bytes.
+fn write_npy_header(writer: &mut impl Write, descr: &str, shape: &str) -> Result<()> {
+    let mut header = format!("{{'descr': '{descr}', 'fortran_order': False, 'shape': {shape}, }}");
+    let unpadded = 10 + header.len() + 1;
+    header.push_str(&" ".repeat(unpadded.next_multiple_of(64) - unpadded));
+    header.push('\n');
+    writer.write_all(b"\x93NUMPY\x01\x00")?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    Ok(writer.write_all(header.as_bytes())?)
+}
+
+// A one-dimensional `.npy` array of fixed-width UTF-32 strings, NumPy's `<U` type.
+fn npy_strings<S: AsRef<str>>(strings: &[S]) -> Result<Vec<u8>> {
+    let width = strings
+        .iter()
+        .map(|s| s.as_ref().chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let mut bytes = Vec::new();
+    write_npy_header(
+        &mut bytes,
+        &format!("<U{width}"),
+        &format!("({},)", strings.len()),
+    )?;
+    for string in strings {
+        let string = string.as_ref();
+        for c in string.chars() {
+            bytes.extend_from_slice(&(c as u32).to_le_bytes());
+        }
+        bytes.resize(bytes.len() + 4 * (width - string.chars().count()), 0);
+    }
+    Ok(bytes)
+}
+
+// A zip archive of uncompressed `members`, which is all `numpy.load` needs.
+fn write_zip(writer: &mut impl Write, members: &[(&str, Vec<u8>)]) -> Result<()> {
+    let too_large = || Error::InvalidArgument("the archive exceeds 4 GiB".to_string());
+    let mut offset = 0u32;
+    let mut central = Vec::new();
+    for (name, data) in members {
+        let size = u32::try_from(data.len()).map_err(|_| too_large())?;
+        // Version 2.0, no flags, stored, a zero DOS time and date
+        let mut header = Vec::new();
+        header.extend_from_slice(&20u16.to_le_bytes());
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&crc32(data).to_le_bytes());
+        header.extend_from_slice(&size.to_le_bytes());
+        header.extend_from_slice(&size.to_le_bytes());
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+
+        writer.write_all(b"PK\x03\x04")?;
+        writer.write_all(&header)?;
+        writer.write_all(name.as_bytes())?;
+        writer.write_all(data)?;
+
+        central.extend_from_slice(b"PK\x01\x02");
+        central.extend_from_slice(&20u16.to_le_bytes());
+        central.extend_from_slice(&header);
+        // No comment, disk 0, no attributes
+        central.extend_from_slice(&[0; 10]);
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+
+        let length = 30 + name.len() + data.len();
+        offset = u32::try_from(length)
+            .ok()
+            .and_then(|length| offset.checked_add(length))
+            .ok_or_else(too_large)?;
+    }
+    writer.write_all(&central)?;
+    writer.write_all(b"PK\x05\x06")?;
+    writer.write_all(&[0; 4])?;
+    writer.write_all(&(members.len() as u16).to_le_bytes())?;
+    writer.write_all(&(members.len() as u16).to_le_bytes())?;
+    writer.write_all(&(central.len() as u32).to_le_bytes())?;
+    writer.write_all(&offset.to_le_bytes())?;
+    Ok(writer.write_all(&0u16.to_le_bytes())?)
+}
+
+// CRC-32 as used by zip (IEEE polynomial, reflected).
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// The Arrow schema of [`embedding_record_batch`]: `id` and `text` strings, and `embedding`
+/// a fixed-size list of `dim` float32 values, which DuckDB, LanceDB and Spark read as a
+/// vector column.
+#[cfg(feature = "arrow")]
+pub fn embedding_schema(dim: usize) -> Result<arrow_schema::SchemaRef> {
+    use arrow_schema::{DataType, Field, Schema};
+
+    let dim = i32::try_from(dim)
+        .map_err(|_| Error::InvalidArgument(format!("dimension {dim} is too large for Arrow")))?;
+    let item = Arc::new(Field::new_list_field(DataType::Float32, false));
+    Ok(Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Utf8, false),
+        Field::new("text", DataType::Utf8, false),
+        Field::new("embedding", DataType::FixedSizeList(item, dim), false),
+    ])))
+}
+
+/// An Arrow record batch of embeddings with their ids and texts, one row per text; `vectors`
+/// are row-major, `dim` values each.
+#[cfg(feature = "arrow")]
+pub fn embedding_record_batch<I: AsRef<str>, T: AsRef<str>>(
+    ids: &[I],
+    texts: &[T],
+    dim: usize,
+    vectors: &[f32],
+) -> Result<arrow_array::RecordBatch> {
+    use arrow_array::{FixedSizeListArray, Float32Array, RecordBatch, StringArray};
+
+    let rows = rows(dim, vectors)?;
+    if ids.len() != rows || texts.len() != rows {
+        return Err(Error::InvalidArgument(format!(
+            "{} ids and {} texts for {rows} vectors",
+            ids.len(),
+            texts.len()
+        )));
+    }
+    let schema = embedding_schema(dim)?;
+    let item = Arc::new(arrow_schema::Field::new_list_field(
+        arrow_schema::DataType::Float32,
+        false,
+    ));
+    let values = Arc::new(Float32Array::from(vectors.to_vec()));
+    let embeddings = FixedSizeListArray::try_new(item, dim as i32, values, None)?;
+    let ids = StringArray::from_iter_values(ids.iter().map(AsRef::as_ref));
+    let texts = StringArray::from_iter_values(texts.iter().map(AsRef::as_ref));
+    Ok(RecordBatch::try_new(
+        schema,
+        vec![Arc::new(ids), Arc::new(texts), Arc::new(embeddings)],
+    )?)
+}
+
+/// Writes [`embedding_record_batch`]es to a Parquet file, one row group per batch (so a batch
+/// of a few thousand rows or more reads best), Snappy-compressed.
+#[cfg(feature = "arrow")]
+pub struct ParquetWriter<W: Write + Send> {
+    writer: parquet::arrow::ArrowWriter<W>,
+}
+
+#[cfg(feature = "arrow")]
+impl<W: Write + Send> ParquetWriter<W> {
+    pub fn new(writer: W, dim: usize) -> Result<Self> {
+        use parquet::basic::Compression;
+        use parquet::file::properties::WriterProperties;
+
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let writer =
+            parquet::arrow::ArrowWriter::try_new(writer, embedding_schema(dim)?, Some(properties))?;
+        Ok(ParquetWriter { writer })
+    }
+
+    pub fn write(&mut self, batch: &arrow_array::RecordBatch) -> Result<()> {
+        self.writer.write(batch)?;
+        Ok(self.writer.flush()?)
+    }
+
+    /// Write the file footer, returning the underlying writer.
+    pub fn finish(self) -> Result<W> {
+        Ok(self.writer.into_inner()?)
+    }
+}
+
+#[derive(Serialize)]
+struct FaissId<'a> {
+    document_id: &'a str,
+    chunk_index: usize,
+}
+
+/// A pipeline sink building a FAISS flat index plus an id map.
+///
+/// On [`Sink::finish`] the index goes to `index_path` and the id map to `ids_path`, one JSON
+/// line `{"document_id", "chunk_index"}` per index row, in row order.
+pub struct FaissSink {
+    index_path: PathBuf,
+    ids_path: PathBuf,
+    metric: FaissMetric,
+    dim: Option<usize>,
+    vectors: Vec<f32>,
+    ids: Vec<(String, usize)>,
+}
+
+impl FaissSink {
+    pub fn new(
+        index_path: impl AsRef<Path>,
+        ids_path: impl AsRef<Path>,
+        metric: FaissMetric,
+    ) -> Self {
+        FaissSink {
+            index_path: index_path.as_ref().to_path_buf(),
+            ids_path: ids_path.as_ref().to_path_buf(),
+            metric,
+            dim: None,
+            vectors: Vec::new(),
+            ids: Vec::new(),
+        }
+    }
+}
+
+impl Sink for FaissSink {
+    fn write(&mut self, record: PipelineRecord) -> Result<()> {
+        let dim = *self.dim.get_or_insert(record.embedding.len());
+        if record.embedding.len() != dim {
+            return Err(Error::InvalidArgument(format!(
+                "embedding of dimension {} in an index of dimension {dim}",
+                record.embedding.len()
+            )));
+        }
+        self.vectors.extend_from_slice(&record.embedding);
+        self.ids.push((record.document_id, record.chunk_index));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let Some(dim) = self.dim else {
+            return Err(Error::InvalidArgument(
+                "no records to write to the FAISS index".to_string(),
+            ));
+        };
+        let mut index = BufWriter::new(File::create(&self.index_path)?);
+        write_faiss_flat(&mut index, dim, &self.vectors, self.metric)?;
+        index.flush()?;
+
+        let mut ids = BufWriter::new(File::create(&self.ids_path)?);
+        for (document_id, chunk_index) in self.ids.iter() {
+            let id = FaissId {
+                document_id,
+                chunk_index: *chunk_index,
+            };
+            serde_json::to_writer(&mut ids, &id)?;
+            ids.write_all(b"\n")?;
+        }
+        Ok(ids.flush()?)
+    }
+}
+
+/// A pipeline sink writing a psql script that loads the records into a pgvector table with
+/// `COPY ... FROM STDIN`, for `psql -f`.
+///
+/// `table` is inserted verbatim (it may be schema-qualified) and needs the columns
+/// `document_id text, chunk_index integer, content text, embedding vector(dim)`.
+pub struct PgvectorSink<W: Write> {
+    writer: W,
+    table: String,
+    started: bool,
+}
+
+impl<W: Write> PgvectorSink<W> {
+    pub fn new(writer: W, table: impl Into<String>) -> Self {
+        PgvectorSink {
+            writer,
+            table: table.into(),
+            started: false,
+        }
+    }
+}
+
+impl<W: Write> Sink for PgvectorSink<W> {
+    fn write(&mut self, record: PipelineRecord) -> Result<()> {
+        if !self.started {
+            writeln!(
+                self.writer,
+                "COPY {} (document_id, chunk_index, content, embedding) FROM STDIN;",
+                self.table
+            )?;
+            self.started = true;
+        }
+        let embedding: Vec<String> = record.embedding.iter().map(f32::to_string).collect();
+        writeln!(
+            self.writer,
+            "{}\t{}\t{}\t[{}]",
+            copy_escape(&record.document_id),
+            record.chunk_index,
+            copy_escape(&record.text),
+            embedding.join(",")
+        )?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.started {
+            writeln!(self.writer, "\\.")?;
+        }
+        Ok(self.writer.flush()?)
+    }
+}
+
+// Escape a value for COPY's text format.
+fn copy_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A pipeline sink writing every record as a Redis `HSET` in the wire protocol, for Redis
+/// mass insertion: `redis-cli --pipe < output`, which sends the commands pipelined.
+///
+/// Each record becomes the hash `{key_prefix}{document_id}:{chunk_index}` with the fields
+/// `document_id`, `chunk_index`, `content` and `embedding`, the last one as little-endian
+/// f32 bytes so a search index can declare it `VECTOR FLAT 6 TYPE FLOAT32 DIM <dim>
+/// DISTANCE_METRIC COSINE`.
+pub struct RedisSink<W: Write> {
+    writer: W,
+    key_prefix: String,
+}
+
+impl<W: Write> RedisSink<W> {
+    pub fn new(writer: W, key_prefix: impl Into<String>) -> Self {
+        RedisSink {
+            writer,
+            key_prefix: key_prefix.into(),
+        }
+    }
+}
+
+impl<W: Write> Sink for RedisSink<W> {
+    fn write(&mut self, record: PipelineRecord) -> Result<()> {
+        let key = format!(
+            "{}{}:{}",
+            self.key_prefix, record.document_id, record.chunk_index
+        );
+        let chunk_index = record.chunk_index.to_string();
+        let embedding: Vec<u8> = record
+            .embedding
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        let args: [&[u8]; 10] = [
+            b"HSET",
+            key.as_bytes(),
+            b"document_id",
+            record.document_id.as_bytes(),
+            b"chunk_index",
+            chunk_index.as_bytes(),
+            b"content",
+            record.text.as_bytes(),
+            b"embedding",
+            &embedding,
+        ];
+
+        write!(self.writer, "*{}\r\n", args.len())?;
+        for arg in args {
+            write!(self.writer, "${}\r\n", arg.len())?;
+            self.writer.write_all(arg)?;
+            self.writer.write_all(b"\r\n")?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+#[derive(Serialize)]
+struct BulkAction<'a> {
+    index: BulkTarget<'a>,
+}
+
+#[derive(Serialize)]
+struct BulkTarget<'a> {
+    #[serde(rename = "_index")]
+    index: &'a str,
+    #[serde(rename = "_id")]
+    id: String,
+}
+
+#[derive(Serialize)]
+struct BulkDocument<'a> {
+    document_id: &'a str,
+    chunk_index: usize,
+    content: &'a str,
+    embedding: &'a [f32],
+}
+
+/// A pipeline sink writing the NDJSON body of an Elasticsearch/OpenSearch `_bulk` request:
+/// an `index` action with the id `{document_id}:{chunk_index}` per record, followed by the
+/// document `{"document_id", "chunk_index", "content", "embedding"}`.
+///
+/// Map `embedding` as a `dense_vector` (`knn_vector` on OpenSearch) of the model's dimension
+/// before loading, e.g. `curl -H 'Content-Type: application/x-ndjson' --data-binary @output
+/// http://localhost:9200/_bulk`. Large exports should be split into requests of a few MB.
+pub struct ElasticsearchSink<W: Write> {
+    writer: W,
+    index: String,
+}
+
+impl<W: Write> ElasticsearchSink<W> {
+    pub fn new(writer: W, index: impl Into<String>) -> Self {
+        ElasticsearchSink {
+            writer,
+            index: index.into(),
+        }
+    }
+}
+
+impl<W: Write> Sink for ElasticsearchSink<W> {
+    fn write(&mut self, record: PipelineRecord) -> Result<()> {
+        let action = BulkAction {
+            index: BulkTarget {
+                index: &self.index,
+                id: format!("{}:{}", record.document_id, record.chunk_index),
+            },
+        };
+        serde_json::to_writer(&mut self.writer, &action)?;
+        self.writer.write_all(b"\n")?;
+        let document = BulkDocument {
+            document_id: &record.document_id,
+            chunk_index: record.chunk_index,
+            content: &record.text,
+            embedding: &record.embedding,
+        };
+        serde_json::to_writer(&mut self.writer, &document)?;
+        Ok(self.writer.write_all(b"\n")?)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_faiss_flat() {
+        let mut bytes = Vec::new();
+        write_faiss_flat(&mut bytes, 2, &[1.0, 0.0, 0.5, 0.5], FaissMetric::L2).unwrap();
+        assert_eq!(b"IxF2", &bytes[..4]);
+        assert_eq!(2, i32::from_le_bytes(bytes[4..8].try_into().unwrap()));
+        assert_eq!(2, i64::from_le_bytes(bytes[8..16].try_into().unwrap()));
+        assert_eq!(1, i32::from_le_bytes(bytes[33..37].try_into().unwrap()));
+        assert_eq!(4, u64::from_le_bytes(bytes[37..45].try_into().unwrap()));
+        assert_eq!(45 + 4 * 4, bytes.len());
+        assert_eq!(0.5, f32::from_le_bytes(bytes[53..57].try_into().unwrap()));
+
+        assert!(write_faiss_flat(&mut Vec::new(), 3, &[1.0; 4], FaissMetric::L2).is_err());
+    }
+
+    #[test]
+    fn test_write_npy_and_npz() {
+        let mut bytes = Vec::new();
+        write_npy(&mut bytes, 2, &[1.0, 0.0, 0.5, 0.5]).unwrap();
+        assert_eq!(b"\x93NUMPY\x01\x00", &bytes[..8]);
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!(0, (10 + header_len) % 64);
+        let header = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap();
+        assert!(header.contains("'descr': '<f4'") && header.contains("'shape': (2, 2)"));
+        assert!(header.ends_with('\n'));
+        assert_eq!(10 + header_len + 4 * 4, bytes.len());
+        assert_eq!(
+            0.5,
+            f32::from_le_bytes(bytes[bytes.len() - 4..].try_into().unwrap())
+        );
+        assert!(write_npy(&mut Vec::new(), 3, &[1.0; 4]).is_err());
+
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+        let ids = npy_strings(&["a", "éé"]).unwrap();
+        let start = 10 + u16::from_le_bytes([ids[8], ids[9]]) as usize;
+        assert!(std::str::from_utf8(&ids[10..start])
+            .unwrap()
+            .contains("'<U2'"));
+        assert_eq!(start + 2 * 2 * 4, ids.len());
+        assert_eq!(
+            &[b'a', 0, 0, 0, 0, 0, 0, 0, 0xE9, 0, 0, 0],
+            &ids[start..start + 12]
+        );
+
+        let mut archive = Vec::new();
+        write_npz(&mut archive, 2, &[1.0, 0.0, 0.5, 0.5], Some(&["a", "b"])).unwrap();
+        assert_eq!(b"PK\x03\x04", &archive[..4]);
+        let end = archive.len() - 22;
+        assert_eq!(b"PK\x05\x06", &archive[end..end + 4]);
+        assert_eq!(
+            2,
+            u16::from_le_bytes([archive[end + 10], archive[end + 11]])
+        );
+        let npy = &archive[30 + "embeddings.npy".len()..][..bytes.len()];
+        assert_eq!(bytes, npy);
+        assert!(write_npz(&mut Vec::new(), 2, &[1.0; 4], Some(&["a"])).is_err());
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn test_parquet_writer() {
+        use arrow_array::{Array, FixedSizeListArray, Float32Array, StringArray};
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let batch =
+            embedding_record_batch(&["a", "b"], &["first", "second"], 2, &[1.0, 0.0, 0.5, 0.5])
+                .unwrap();
+        assert_eq!(2, batch.num_rows());
+        assert!(embedding_record_batch(&["a"], &["first"], 2, &[1.0; 4]).is_err());
+
+        let path = std::env::temp_dir().join(format!("embeddings-{}.parquet", std::process::id()));
+        let mut writer = ParquetWriter::new(File::create(&path).unwrap(), 2).unwrap();
+        writer.write(&batch).unwrap();
+        writer.write(&batch).unwrap();
+        writer.finish().unwrap();
+
+        let batches: Vec<_> = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        assert_eq!(4, batches.iter().map(|b| b.num_rows()).sum::<usize>());
+        let read = &batches[0];
+        assert_eq!(batch.schema(), read.schema());
+        let texts = read
+            .column(1)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!("second", texts.value(1));
+        let embeddings = read
+            .column(2)
+            .as_any()
+            .downcast_ref::<FixedSizeListArray>()
+            .unwrap();
+        let second = embeddings.value(1);
+        let second = second.as_any().downcast_ref::<Float32Array>().unwrap();
+        assert_eq!(&[0.5, 0.5], second.values().as_ref());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_pgvector_sink() {
+        let mut sink = PgvectorSink::new(Vec::new(), "public.chunks");
+        sink.write(PipelineRecord {
+            document_id: "doc\t1".to_string(),
+            chunk_id: String::new(),
+            chunk_index: 0,
+            range: 0..11,
+            char_range: 0..11,
+            text: "two\nlines\\".to_string(),
+            embedding: vec![0.5, -1.0],
+            metadata: Default::default(),
+        })
+        .unwrap();
+        sink.finish().unwrap();
+        assert_eq!(
+            "COPY public.chunks (document_id, chunk_index, content, embedding) FROM STDIN;\n\
+             doc\\t1\t0\ttwo\\nlines\\\\\t[0.5,-1]\n\\.\n",
+            String::from_utf8(sink.writer).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_redis_sink() {
+        let mut sink = RedisSink::new(Vec::new(), "doc:");
+        sink.write(PipelineRecord {
+            document_id: "a".to_string(),
+            chunk_id: String::new(),
+            chunk_index: 2,
+            range: 0..2,
+            char_range: 0..2,
+            text: "hi".to_string(),
+            embedding: vec![1.0],
+            metadata: Default::default(),
+        })
+        .unwrap();
+        let mut expected =
+            b"*10\r\n$4\r\nHSET\r\n$7\r\ndoc:a:2\r\n$11\r\ndocument_id\r\n$1\r\na\r\n\
+              $11\r\nchunk_index\r\n$1\r\n2\r\n$7\r\ncontent\r\n$2\r\nhi\r\n\
+              $9\r\nembedding\r\n$4\r\n"
+                .to_vec();
+        expected.extend_from_slice(&1f32.to_le_bytes());
+        expected.extend_from_slice(b"\r\n");
+        assert_eq!(expected, sink.writer);
+    }
+
+    #[test]
+    fn test_elasticsearch_sink() {
+        let mut sink = ElasticsearchSink::new(Vec::new(), "chunks");
+        sink.write(PipelineRecord {
+            document_id: "a".to_string(),
+            chunk_id: String::new(),
+            chunk_index: 1,
+            range: 0..2,
+            char_range: 0..2,
+            text: "hi".to_string(),
+            embedding: vec![0.5, 1.0],
+            metadata: Default::default(),
+        })
+        .unwrap();
+        assert_eq!(
+            "{\"index\":{\"_index\":\"chunks\",\"_id\":\"a:1\"}}\n\
+             {\"document_id\":\"a\",\"chunk_index\":1,\"content\":\"hi\",\"embedding\":[0.5,1.0]}\n",
+            String::from_utf8(sink.writer).unwrap()
+        );
+    }
+}
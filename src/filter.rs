@@ -0,0 +1,188 @@
+use crate::error::{Error, Result};
+use crate::pipeline::Metadata;
+use serde_json::Value;
+
+/// A condition on document metadata, see
+/// [`Corpus::search_filtered`](crate::Corpus::search_filtered).
+///
+/// Fields are top-level metadata keys, or paths into nested objects separated by dots
+/// (`"source.lang"`). A condition on a missing field is false, except [`Filter::Ne`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Eq(String, Value),
+    Ne(String, Value),
+    /// Numeric comparisons; false if the field is not a number.
+    Gt(String, f64),
+    Gte(String, f64),
+    Lt(String, f64),
+    Lte(String, f64),
+    /// The field equals one of the values.
+    In(String, Vec<Value>),
+    /// The field is an array (e.g. of tags) containing the value.
+    Contains(String, Value),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    /// Parse a filter in the MongoDB-style syntax common to vector databases:
+    ///
+    /// ```json
+    /// {"lang": "en", "year": {"$gte": 2020, "$lt": 2024}, "tags": {"$contains": "rust"}}
+    /// ```
+    ///
+    /// A plain value tests equality; an object of operators (`$eq`, `$ne`, `$gt`, `$gte`,
+    /// `$lt`, `$lte`, `$in`, `$contains`) applies each of them; `$and`, `$or` and `$not`
+    /// combine filters. Several keys must all match.
+    pub fn from_json(value: &Value) -> Result<Self> {
+        let Value::Object(conditions) = value else {
+            return Err(invalid(format!("a filter must be an object, not {value}")));
+        };
+        let mut filters = conditions
+            .iter()
+            .map(|(key, value)| match key.as_str() {
+                "$and" => Ok(Filter::And(Filter::list(key, value)?)),
+                "$or" => Ok(Filter::Or(Filter::list(key, value)?)),
+                "$not" => Ok(Filter::Not(Box::new(Filter::from_json(value)?))),
+                _ if key.starts_with('$') => Err(invalid(format!("unknown operator {key}"))),
+                field => Filter::field(field, value),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(match filters.len() {
+            1 => filters.pop().unwrap(),
+            _ => Filter::And(filters),
+        })
+    }
+
+    /// Whether a document with `metadata` passes the filter.
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        let field = |path: &str| lookup(metadata, path);
+        let number = |path: &str| field(path).and_then(Value::as_f64);
+        match self {
+            Filter::Eq(path, value) => field(path).is_some_and(|v| equal(v, value)),
+            Filter::Ne(path, value) => !field(path).is_some_and(|v| equal(v, value)),
+            Filter::Gt(path, bound) => number(path).is_some_and(|n| n > *bound),
+            Filter::Gte(path, bound) => number(path).is_some_and(|n| n >= *bound),
+            Filter::Lt(path, bound) => number(path).is_some_and(|n| n < *bound),
+            Filter::Lte(path, bound) => number(path).is_some_and(|n| n <= *bound),
+            Filter::In(path, values) => {
+                field(path).is_some_and(|v| values.iter().any(|value| equal(v, value)))
+            }
+            Filter::Contains(path, value) => field(path)
+                .and_then(Value::as_array)
+                .is_some_and(|items| items.iter().any(|item| equal(item, value))),
+            Filter::And(filters) => filters.iter().all(|f| f.matches(metadata)),
+            Filter::Or(filters) => filters.iter().any(|f| f.matches(metadata)),
+            Filter::Not(filter) => !filter.matches(metadata),
+        }
+    }
+
+    fn list(operator: &str, value: &Value) -> Result<Vec<Filter>> {
+        value
+            .as_array()
+            .ok_or_else(|| invalid(format!("{operator} takes an array of filters")))?
+            .iter()
+            .map(Filter::from_json)
+            .collect()
+    }
+
+    fn field(field: &str, value: &Value) -> Result<Filter> {
+        let operators = match value {
+            Value::Object(operators)
+                if !operators.is_empty() && operators.keys().all(|k| k.starts_with('$')) =>
+            {
+                operators
+            }
+            _ => return Ok(Filter::Eq(field.to_string(), value.clone())),
+        };
+        let bound = |operator: &str, value: &Value| {
+            value
+                .as_f64()
+                .ok_or_else(|| invalid(format!("{operator} on {field} takes a number")))
+        };
+        let mut filters = operators
+            .iter()
+            .map(|(operator, value)| {
+                let field = field.to_string();
+                Ok(match operator.as_str() {
+                    "$eq" => Filter::Eq(field, value.clone()),
+                    "$ne" => Filter::Ne(field, value.clone()),
+                    "$gt" => Filter::Gt(field, bound(operator, value)?),
+                    "$gte" => Filter::Gte(field, bound(operator, value)?),
+                    "$lt" => Filter::Lt(field, bound(operator, value)?),
+                    "$lte" => Filter::Lte(field, bound(operator, value)?),
+                    "$in" => match value {
+                        Value::Array(values) => Filter::In(field, values.clone()),
+                        _ => return Err(invalid(format!("$in on {field} takes an array"))),
+                    },
+                    "$contains" => Filter::Contains(field, value.clone()),
+                    _ => return Err(invalid(format!("unknown operator {operator} on {field}"))),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(match filters.len() {
+            1 => filters.pop().unwrap(),
+            _ => Filter::And(filters),
+        })
+    }
+}
+
+fn lookup<'a>(metadata: &'a Metadata, path: &str) -> Option<&'a Value> {
+    if let Some(value) = metadata.get(path) {
+        return Some(value);
+    }
+    let mut parts = path.split('.');
+    let mut value = metadata.get(parts.next()?)?;
+    for part in parts {
+        value = value.as_object()?.get(part)?;
+    }
+    Some(value)
+}
+
+// Numbers compare by value, so 2020 equals 2020.0
+fn equal(a: &Value, b: &Value) -> bool {
+    match (a.as_f64(), b.as_f64()) {
+        (Some(a), Some(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::InvalidArgument(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_filters() {
+        let Value::Object(metadata) = json!({
+            "lang": "en",
+            "year": 2021,
+            "tags": ["rust", "ffi"],
+            "source": {"site": "docs"}
+        }) else {
+            unreachable!()
+        };
+        let matches = |filter: Value| Filter::from_json(&filter).unwrap().matches(&metadata);
+
+        assert!(matches(json!({"lang": "en", "year": 2021.0})));
+        assert!(!matches(json!({"lang": "de"})));
+        assert!(matches(json!({"year": {"$gte": 2020, "$lt": 2022}})));
+        assert!(!matches(json!({"year": {"$gt": 2021}})));
+        assert!(matches(json!({"tags": {"$contains": "rust"}})));
+        assert!(!matches(json!({"lang": {"$contains": "e"}})));
+        assert!(matches(json!({"source.site": {"$in": ["blog", "docs"]}})));
+        assert!(matches(json!({"missing": {"$ne": 1}})));
+        assert!(matches(
+            json!({"$or": [{"lang": "de"}, {"$not": {"year": 1999}}]})
+        ));
+
+        assert!(Filter::from_json(&json!({"year": {"$gte": "2020"}})).is_err());
+        assert!(Filter::from_json(&json!({"$nor": []})).is_err());
+        assert!(Filter::from_json(&json!([])).is_err());
+    }
+}
@@ -0,0 +1,421 @@
+use crate::error::{Error, Result};
+use crate::kernels::dot;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+use std::io::{Read, Write};
+
+/// Parameters of the HNSW graph behind a [`Corpus`](crate::Corpus) built with
+/// [`Corpus::with_hnsw`](crate::Corpus::with_hnsw).
+///
+/// Larger values raise recall at the cost of memory and time: `m` sets the graph's degree,
+/// `ef_construction` and `ef_search` how many candidates are followed while inserting and
+/// searching.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HnswConfig {
+    /// Links per node on the upper layers; the bottom layer keeps twice as many.
+    pub m: usize,
+    pub ef_construction: usize,
+    /// Raised to `k` for searches asking for more results.
+    pub ef_search: usize,
+}
+
+impl Default for HnswConfig {
+    fn default() -> Self {
+        HnswConfig {
+            m: 16,
+            ef_construction: 200,
+            ef_search: 64,
+        }
+    }
+}
+
+impl HnswConfig {
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.m < 2 || self.ef_construction == 0 || self.ef_search == 0 {
+            return Err(Error::InvalidArgument(
+                "HNSW needs m of at least 2 and non-zero ef values".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Normalized vectors stored one after another; a node is the index of its vector.
+#[derive(Clone, Copy)]
+pub(crate) struct Vectors<'a> {
+    pub(crate) data: &'a [f32],
+    pub(crate) dim: usize,
+}
+
+impl<'a> Vectors<'a> {
+    fn get(&self, node: u32) -> &'a [f32] {
+        let start = node as usize * self.dim;
+        &self.data[start..start + self.dim]
+    }
+}
+
+// A node with its similarity to the current query, ordered by similarity.
+#[derive(Clone, Copy, PartialEq)]
+struct Scored(f32, u32);
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(other.1.cmp(&self.1))
+    }
+}
+
+/// A hierarchical navigable small world graph (Malkov & Yashunin) over cosine similarity.
+///
+/// Every node is on the bottom layer and on each layer above with a probability of `1 / m`;
+/// a search descends greedily from the single entry point at the top and widens to
+/// `ef_search` candidates on the bottom layer.
+pub(crate) struct Hnsw {
+    config: HnswConfig,
+    // `links[node][layer]`, for the layers from 0 up to the node's level.
+    links: Vec<Vec<Vec<u32>>>,
+    entry: Option<u32>,
+    rng: u64,
+}
+
+impl Hnsw {
+    pub(crate) fn new(config: HnswConfig) -> Self {
+        Hnsw {
+            config,
+            links: Vec::new(),
+            entry: None,
+            rng: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+
+    pub(crate) fn config(&self) -> HnswConfig {
+        self.config
+    }
+
+    /// Link the next node, whose vector must be the last one in `vectors`.
+    pub(crate) fn insert(&mut self, vectors: Vectors) {
+        let node = self.links.len() as u32;
+        let level = self.random_level();
+        self.links.push(vec![Vec::new(); level + 1]);
+        let Some(entry) = self.entry else {
+            self.entry = Some(node);
+            return;
+        };
+
+        let query = vectors.get(node);
+        let top = self.level(entry);
+        let mut nearest = vec![Scored(dot(vectors.get(entry), query), entry)];
+        for layer in (level + 1..=top).rev() {
+            nearest = self.search_layer(vectors, query, nearest, 1, layer, &|_| true);
+        }
+        for layer in (0..=level.min(top)).rev() {
+            nearest = self.search_layer(
+                vectors,
+                query,
+                nearest,
+                self.config.ef_construction,
+                layer,
+                &|_| true,
+            );
+            let neighbors = self.select(vectors, &nearest, self.max_links(layer));
+            for &neighbor in &neighbors {
+                self.link(vectors, neighbor, node, layer);
+            }
+            self.links[node as usize][layer] = neighbors;
+        }
+        if level > top {
+            self.entry = Some(node);
+        }
+    }
+
+    /// Up to `k` nodes most similar to the normalized `query` for which `accept` holds, best
+    /// first. Rejected nodes are still followed through the graph, so a selective `accept`
+    /// costs time rather than recall.
+    pub(crate) fn search(
+        &self,
+        vectors: Vectors,
+        query: &[f32],
+        k: usize,
+        accept: &dyn Fn(u32) -> bool,
+    ) -> Vec<(u32, f32)> {
+        let Some(entry) = self.entry else {
+            return Vec::new();
+        };
+        let mut nearest = vec![Scored(dot(vectors.get(entry), query), entry)];
+        for layer in (1..=self.level(entry)).rev() {
+            nearest = self.search_layer(vectors, query, nearest, 1, layer, &|_| true);
+        }
+        let ef = self.config.ef_search.max(k);
+        let mut nearest = self.search_layer(vectors, query, nearest, ef, 0, accept);
+        nearest.truncate(k);
+        nearest
+            .into_iter()
+            .map(|Scored(s, node)| (node, s))
+            .collect()
+    }
+
+    /// Write the graph for [`Hnsw::read`]: the config, the level generator's state, the entry
+    /// point (`u32::MAX` if empty) and each node's links per layer, all little-endian.
+    pub(crate) fn write(&self, writer: &mut impl Write) -> Result<()> {
+        let config = [
+            self.config.m,
+            self.config.ef_construction,
+            self.config.ef_search,
+        ];
+        for value in config {
+            writer.write_all(&(value as u64).to_le_bytes())?;
+        }
+        writer.write_all(&self.rng.to_le_bytes())?;
+        writer.write_all(&self.entry.unwrap_or(u32::MAX).to_le_bytes())?;
+        for layers in &self.links {
+            writer.write_all(&(layers.len() as u32).to_le_bytes())?;
+            for links in layers {
+                writer.write_all(&(links.len() as u32).to_le_bytes())?;
+                for link in links {
+                    writer.write_all(&link.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a graph of `nodes` nodes written by [`Hnsw::write`].
+    pub(crate) fn read(reader: &mut impl Read, nodes: usize) -> Result<Self> {
+        let corrupt = || Error::InvalidArgument("corrupt HNSW graph".to_string());
+        let config = HnswConfig {
+            m: read_u64(reader)? as usize,
+            ef_construction: read_u64(reader)? as usize,
+            ef_search: read_u64(reader)? as usize,
+        };
+        config.validate()?;
+        let rng = read_u64(reader)?;
+        let entry = match read_u32(reader)? {
+            u32::MAX if nodes == 0 => None,
+            entry if (entry as usize) < nodes => Some(entry),
+            _ => return Err(corrupt()),
+        };
+
+        let mut links = Vec::with_capacity(nodes);
+        for _ in 0..nodes {
+            let levels = read_u32(reader)? as usize;
+            // A level above 64 has a probability of at most 2^-64
+            if levels == 0 || levels > 64 {
+                return Err(corrupt());
+            }
+            let mut layers = Vec::with_capacity(levels);
+            for _ in 0..levels {
+                let len = read_u32(reader)? as usize;
+                if len > 2 * config.m {
+                    return Err(corrupt());
+                }
+                let mut layer_links = Vec::with_capacity(len);
+                for _ in 0..len {
+                    layer_links.push(read_u32(reader)?);
+                }
+                layers.push(layer_links);
+            }
+            links.push(layers);
+        }
+        // Every link must point at a node present on its layer
+        for layers in &links {
+            for (layer, layer_links) in layers.iter().enumerate() {
+                let valid = |&link: &u32| links.get(link as usize).is_some_and(|l| l.len() > layer);
+                if !layer_links.iter().all(valid) {
+                    return Err(corrupt());
+                }
+            }
+        }
+        Ok(Hnsw {
+            config,
+            links,
+            entry,
+            rng,
+        })
+    }
+
+    fn level(&self, node: u32) -> usize {
+        self.links[node as usize].len() - 1
+    }
+
+    fn max_links(&self, layer: usize) -> usize {
+        match layer {
+            0 => 2 * self.config.m,
+            _ => self.config.m,
+        }
+    }
+
+    // Levels are geometrically distributed with ratio 1 / m; xorshift keeps builds reproducible
+    fn random_level(&mut self) -> usize {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        let uniform = (self.rng >> 11) as f64 / (1u64 << 53) as f64;
+        let level = -(1.0 - uniform).ln() / (self.config.m as f64).ln();
+        level as usize
+    }
+
+    // The `ef` accepted nodes of `layer` most similar to `query` found from `entries`, best
+    // first
+    fn search_layer(
+        &self,
+        vectors: Vectors,
+        query: &[f32],
+        entries: Vec<Scored>,
+        ef: usize,
+        layer: usize,
+        accept: &dyn Fn(u32) -> bool,
+    ) -> Vec<Scored> {
+        let mut visited: HashSet<u32> = entries.iter().map(|s| s.1).collect();
+        let mut candidates: BinaryHeap<Scored> = entries.iter().copied().collect();
+        let mut found: BinaryHeap<Reverse<Scored>> = entries
+            .into_iter()
+            .filter(|s| accept(s.1))
+            .map(Reverse)
+            .collect();
+        while found.len() > ef {
+            found.pop();
+        }
+
+        while let Some(candidate) = candidates.pop() {
+            let worst = found.peek().map_or(f32::NEG_INFINITY, |Reverse(s)| s.0);
+            if candidate.0 < worst && found.len() >= ef {
+                break;
+            }
+            for &neighbor in &self.links[candidate.1 as usize][layer] {
+                if !visited.insert(neighbor) {
+                    continue;
+                }
+                let scored = Scored(dot(vectors.get(neighbor), query), neighbor);
+                let worst = found.peek().map_or(f32::NEG_INFINITY, |Reverse(s)| s.0);
+                if found.len() < ef || scored.0 > worst {
+                    candidates.push(scored);
+                    if accept(neighbor) {
+                        found.push(Reverse(scored));
+                    }
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+        let mut found: Vec<Scored> = found.into_iter().map(|Reverse(s)| s).collect();
+        found.sort_unstable_by(|a, b| b.cmp(a));
+        found
+    }
+
+    // Pick up to `max` neighbors from `candidates` (best first), preferring ones that are more
+    // similar to the query than to any neighbor already picked so links spread in every
+    // direction, then topping up with the best of the rest.
+    fn select(&self, vectors: Vectors, candidates: &[Scored], max: usize) -> Vec<u32> {
+        let mut picked: Vec<u32> = Vec::with_capacity(max);
+        let mut skipped = Vec::new();
+        for &Scored(similarity, node) in candidates {
+            if picked.len() == max {
+                break;
+            }
+            let diverse = picked
+                .iter()
+                .all(|&other| dot(vectors.get(node), vectors.get(other)) < similarity);
+            match diverse {
+                true => picked.push(node),
+                false => skipped.push(node),
+            }
+        }
+        let room = max - picked.len();
+        picked.extend(skipped.into_iter().take(room));
+        picked
+    }
+
+    fn link(&mut self, vectors: Vectors, from: u32, to: u32, layer: usize) {
+        let max = self.max_links(layer);
+        let links = &mut self.links[from as usize][layer];
+        links.push(to);
+        if links.len() <= max {
+            return;
+        }
+        let base = vectors.get(from);
+        let mut candidates: Vec<Scored> = links
+            .iter()
+            .map(|&node| Scored(dot(vectors.get(node), base), node))
+            .collect();
+        candidates.sort_unstable_by(|a, b| b.cmp(a));
+        self.links[from as usize][layer] = self.select(vectors, &candidates, max);
+    }
+}
+
+pub(crate) fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embedder::normalize;
+
+    #[test]
+    fn test_hnsw_recall() {
+        let dim = 16;
+        let mut state = 7u32;
+        let mut data: Vec<f32> = (0..2000 * dim)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as f32 / 65_536.0 - 0.5
+            })
+            .collect();
+        data.chunks_exact_mut(dim).for_each(normalize);
+
+        let mut hnsw = Hnsw::new(HnswConfig {
+            m: 8,
+            ef_construction: 64,
+            ef_search: 32,
+        });
+        for n in 1..=2000 {
+            hnsw.insert(Vectors {
+                data: &data[..n * dim],
+                dim,
+            });
+        }
+        let vectors = Vectors { data: &data, dim };
+
+        let mut hits = 0;
+        for query in data.chunks_exact(dim).step_by(40) {
+            let mut exact: Vec<(u32, f32)> = (0..2000)
+                .map(|node| (node, dot(vectors.get(node), query)))
+                .collect();
+            exact.sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
+            let found = hnsw.search(vectors, query, 10, &|_| true);
+            assert_eq!(exact[0].0, found[0].0);
+            hits += found
+                .iter()
+                .filter(|(node, _)| exact[..10].iter().any(|(n, _)| n == node))
+                .count();
+        }
+        assert!(hits >= 450, "recall@10 of {hits} / 500");
+
+        // Filtered searches only return accepted nodes, still finding the best of them
+        let query = vectors.get(3);
+        let even = |node: u32| node.is_multiple_of(2);
+        let found = hnsw.search(vectors, query, 10, &even);
+        assert_eq!(10, found.len());
+        assert!(found.iter().all(|&(node, _)| even(node)));
+        let best = (0..2000)
+            .filter(|&node| even(node))
+            .max_by(|&a, &b| dot(vectors.get(a), query).total_cmp(&dot(vectors.get(b), query)));
+        assert_eq!(best, Some(found[0].0));
+    }
+}
@@ -0,0 +1,403 @@
+use crate::embedder::Embedder;
+use crate::error::{Error, Result};
+use crate::options::{EmbedOptions, Embedding};
+use crate::provider::EmbeddingProvider;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::{Arc, Mutex};
+
+// Larger frames are refused before anything is allocated for them.
+const MAX_FRAME: usize = 64 << 20;
+
+/// Status byte of a successful response.
+pub const IPC_OK: u8 = 0;
+/// Status byte of a failed response, followed by the error kind byte and the UTF-8 message.
+pub const IPC_ERROR: u8 = 1;
+
+// Error kinds, so the client can hand back the error the server hit
+const KIND_MODEL: u8 = 0;
+const KIND_INVALID_ARGUMENT: u8 = 1;
+const KIND_INVALID_LAYER: u8 = 2;
+const KIND_EMPTY_INPUT: u8 = 3;
+const KIND_TIMEOUT: u8 = 4;
+const KIND_IO: u8 = 5;
+
+/// A request to an [`IpcServer`].
+///
+/// On the wire every message is a frame: a little-endian `u32` byte length followed by the
+/// body. A request body is this struct as JSON, e.g. `{"texts": ["a", "b"]}`. A response body
+/// is the status byte [`IPC_OK`], the little-endian `u32` count and dimension and then
+/// `count * dim` little-endian `f32`s, or [`IPC_ERROR`], a byte for the kind of error and a
+/// message. Requests on one connection are answered in order.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IpcRequest {
+    pub texts: Vec<String>,
+    /// A model added to the server; the first one added if `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options: Option<serde_json::Value>,
+}
+
+fn read_frame(reader: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME {
+        return Err(Error::InvalidArgument(format!(
+            "frame of {len} bytes exceeds the limit of {MAX_FRAME}"
+        )));
+    }
+    let mut body = vec![0u8; len];
+    reader.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+fn write_frame(writer: &mut impl Write, body: &[u8]) -> Result<()> {
+    let len = u32::try_from(body.len())
+        .ok()
+        .filter(|&len| len as usize <= MAX_FRAME)
+        .ok_or_else(|| {
+            Error::InvalidArgument(format!("frame of {} bytes is too large", body.len()))
+        })?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(body)?;
+    Ok(writer.flush()?)
+}
+
+/// Serves loaded models to other processes over a Unix domain socket, or a named pipe on
+/// Windows, with the framing described on [`IpcRequest`]. Every connection gets a thread.
+#[derive(Default)]
+pub struct IpcServer {
+    models: HashMap<String, Arc<Embedder>>,
+    default_model: Option<String>,
+}
+
+impl IpcServer {
+    pub fn new() -> Self {
+        IpcServer::default()
+    }
+
+    /// Serve `embedder` as `name`, replacing a model added under the same name.
+    pub fn add_model(&mut self, name: impl Into<String>, embedder: Arc<Embedder>) {
+        let name = name.into();
+        self.default_model.get_or_insert_with(|| name.clone());
+        self.models.insert(name, embedder);
+    }
+
+    /// Accept connections on `path` until accepting fails. A stale socket left by a previous
+    /// run is replaced; any other file at `path` is an error and is left alone.
+    #[cfg(unix)]
+    pub fn serve_unix(self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        use std::os::unix::fs::FileTypeExt;
+        use std::os::unix::net::{UnixListener, UnixStream};
+
+        let path = path.as_ref();
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(Error::InvalidArgument(format!(
+                    "{} exists and is not a socket",
+                    path.display()
+                )));
+            }
+            if UnixStream::connect(path).is_err() {
+                std::fs::remove_file(path)?;
+            }
+        }
+        let listener = UnixListener::bind(path)?;
+        self.serve_listener(listener)
+    }
+
+    #[cfg(unix)]
+    fn serve_listener(self, listener: std::os::unix::net::UnixListener) -> Result<()> {
+        let server = Arc::new(self);
+        loop {
+            let (stream, _) = listener.accept()?;
+            let server = Arc::clone(&server);
+            std::thread::spawn(move || server.serve_connection(stream));
+        }
+    }
+
+    /// Accept connections on the named pipe `name`, e.g. `\\.\pipe\rust-embed`, until
+    /// creating a pipe instance fails.
+    #[cfg(windows)]
+    pub fn serve_pipe(self, name: &str) -> Result<()> {
+        use std::fs::File;
+        use std::os::windows::io::FromRawHandle;
+        use windows_sys::Win32::Foundation::{
+            GetLastError, ERROR_PIPE_CONNECTED, INVALID_HANDLE_VALUE,
+        };
+        use windows_sys::Win32::Storage::FileSystem::PIPE_ACCESS_DUPLEX;
+        use windows_sys::Win32::System::Pipes::{
+            ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_TYPE_BYTE,
+            PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+        };
+
+        let wide: Vec<u16> = name.encode_utf16().chain([0]).collect();
+        let server = Arc::new(self);
+        loop {
+            let handle = unsafe {
+                CreateNamedPipeW(
+                    wide.as_ptr(),
+                    PIPE_ACCESS_DUPLEX,
+                    PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT,
+                    PIPE_UNLIMITED_INSTANCES,
+                    1 << 16,
+                    1 << 16,
+                    0,
+                    std::ptr::null(),
+                )
+            };
+            if handle == INVALID_HANDLE_VALUE {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            // Owning the handle closes it on every path below
+            let pipe = unsafe { File::from_raw_handle(handle as _) };
+            let connected = unsafe { ConnectNamedPipe(handle, std::ptr::null_mut()) } != 0
+                || unsafe { GetLastError() } == ERROR_PIPE_CONNECTED;
+            if connected {
+                let server = Arc::clone(&server);
+                std::thread::spawn(move || server.serve_connection(pipe));
+            }
+        }
+    }
+
+    fn serve_connection(&self, mut stream: impl Read + Write) {
+        loop {
+            let body = match read_frame(&mut stream) {
+                Ok(Some(body)) => body,
+                Ok(None) => return,
+                Err(e) => {
+                    // The stream may be out of sync, so report and hang up
+                    let _ = write_frame(&mut stream, &error_body(&e));
+                    return;
+                }
+            };
+            let response = match self.handle(&body) {
+                Ok(response) => response,
+                Err(e) => error_body(&e),
+            };
+            if write_frame(&mut stream, &response).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn handle(&self, body: &[u8]) -> Result<Vec<u8>> {
+        let request: IpcRequest = serde_json::from_slice(body)?;
+        let name = request
+            .model
+            .as_deref()
+            .or(self.default_model.as_deref())
+            .unwrap_or_default();
+        let embedder = self
+            .models
+            .get(name)
+            .ok_or_else(|| Error::InvalidArgument(format!("no model named {name:?}")))?;
+        let options = match request.options {
+            Some(options) => serde_json::from_value(options)?,
+            None => EmbedOptions::default(),
+        };
+        let embeddings = embedder.embed_batch(&request.texts, &options)?;
+
+        let dim = embeddings.first().map_or(0, |e| e.to_f32().len());
+        // Answered with an error rather than a frame the client would refuse
+        let size = 9 + embeddings.len() * dim * 4;
+        if size > MAX_FRAME {
+            return Err(Error::InvalidArgument(format!(
+                "a response of {size} bytes exceeds the frame limit of {MAX_FRAME}; send fewer texts"
+            )));
+        }
+        let mut response = Vec::with_capacity(size);
+        response.push(IPC_OK);
+        response.extend_from_slice(&(embeddings.len() as u32).to_le_bytes());
+        response.extend_from_slice(&(dim as u32).to_le_bytes());
+        for embedding in embeddings.iter().map(Embedding::to_f32) {
+            for x in embedding {
+                response.extend_from_slice(&x.to_le_bytes());
+            }
+        }
+        Ok(response)
+    }
+}
+
+fn error_body(e: &Error) -> Vec<u8> {
+    let (kind, message) = match e {
+        Error::InvalidArgument(message) => (KIND_INVALID_ARGUMENT, message.clone()),
+        Error::Json(_) | Error::Csv(_) => (KIND_INVALID_ARGUMENT, e.to_string()),
+        Error::InvalidLayer(message) => (KIND_INVALID_LAYER, message.clone()),
+        Error::EmptyInput => (KIND_EMPTY_INPUT, String::new()),
+        Error::Timeout => (KIND_TIMEOUT, String::new()),
+        Error::Io(_) => (KIND_IO, e.to_string()),
+        _ => (KIND_MODEL, e.to_string()),
+    };
+    let mut body = vec![IPC_ERROR, kind];
+    body.extend_from_slice(message.as_bytes());
+    body
+}
+
+// The error a server reported, as the variant it started out as where the kind says which
+fn response_error(kind: u8, message: &[u8]) -> Error {
+    let message = String::from_utf8_lossy(message).into_owned();
+    match kind {
+        KIND_INVALID_ARGUMENT => Error::InvalidArgument(message),
+        KIND_INVALID_LAYER => Error::InvalidLayer(message),
+        KIND_EMPTY_INPUT => Error::EmptyInput,
+        KIND_TIMEOUT => Error::Timeout,
+        KIND_IO => Error::Io(std::io::Error::other(message)),
+        _ => Error::Candle(candle::Error::Msg(message)),
+    }
+}
+
+trait Stream: Read + Write + Send {}
+
+impl<S: Read + Write + Send> Stream for S {}
+
+/// A connection to an [`IpcServer`], usable anywhere an [`EmbeddingProvider`] is. Calls from
+/// several threads take turns on the one connection.
+pub struct IpcClient {
+    name: String,
+    model: Option<String>,
+    stream: Mutex<Box<dyn Stream>>,
+}
+
+impl IpcClient {
+    /// Connect to a server's Unix socket, or its named pipe on Windows.
+    pub fn connect(path: &str) -> Result<Self> {
+        #[cfg(unix)]
+        let stream = std::os::unix::net::UnixStream::connect(path)?;
+        #[cfg(windows)]
+        let stream = std::fs::File::options().read(true).write(true).open(path)?;
+        Ok(IpcClient {
+            name: format!("ipc:{path}"),
+            model: None,
+            stream: Mutex::new(Box::new(stream)),
+        })
+    }
+
+    /// Ask for a model other than the server's first.
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    /// Send `request` and wait for its embeddings.
+    pub fn request(&self, request: &IpcRequest) -> Result<Vec<Vec<f32>>> {
+        let body = serde_json::to_vec(request)?;
+        let mut stream = self.stream.lock().unwrap();
+        write_frame(&mut *stream, &body)?;
+        let response = read_frame(&mut *stream)?.ok_or_else(|| {
+            Error::Io(std::io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "the server closed the connection",
+            ))
+        })?;
+        drop(stream);
+        parse_response(&response)
+    }
+}
+
+fn parse_response(response: &[u8]) -> Result<Vec<Vec<f32>>> {
+    let malformed = || Error::InvalidArgument("malformed IPC response".to_string());
+    match response.split_first() {
+        Some((&IPC_OK, rest)) if rest.len() >= 8 => {
+            let count = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+            let dim = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+            let values = &rest[8..];
+            if count.checked_mul(dim).and_then(|n| n.checked_mul(4)) != Some(values.len()) {
+                return Err(malformed());
+            }
+            let floats: Vec<f32> = values
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+                .collect();
+            Ok(if dim == 0 {
+                vec![Vec::new(); count]
+            } else {
+                floats.chunks(dim).map(<[f32]>::to_vec).collect()
+            })
+        }
+        Some((&IPC_ERROR, [kind, message @ ..])) => Err(response_error(*kind, message)),
+        _ => Err(malformed()),
+    }
+}
+
+impl EmbeddingProvider for IpcClient {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        self.request(&IpcRequest {
+            texts: texts.iter().map(|text| text.to_string()).collect(),
+            model: self.model.clone(),
+            options: None,
+        })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ipc_round_trip() {
+        let embedder = Embedder::load(
+            "models/gte-small/config.json",
+            "models/gte-small/tokenizer.json",
+            "models/gte-small/model.safetensors",
+            false,
+        )
+        .unwrap();
+        let expected = embedder.embed("hello").unwrap();
+        let mut server = IpcServer::new();
+        server.add_model("gte-small", Arc::new(embedder));
+
+        let path = std::env::temp_dir().join(format!("ipc-{}.sock", std::process::id()));
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        std::thread::spawn(move || server.serve_listener(listener));
+
+        let client = IpcClient::connect(path.to_str().unwrap()).unwrap();
+        let embeddings = client.embed_batch(&["hello", "world"]).unwrap();
+        assert_eq!(2, embeddings.len());
+        assert_eq!(expected, embeddings[0]);
+        assert!(client.embed_batch(&[]).unwrap().is_empty());
+
+        let normalized = client
+            .request(&IpcRequest {
+                texts: vec!["hello".to_string()],
+                model: Some("gte-small".to_string()),
+                options: Some(serde_json::json!({"normalize": true})),
+            })
+            .unwrap();
+        let norm: f32 = normalized[0].iter().map(|x| x * x).sum();
+        assert!((norm - 1.0).abs() < 1e-4);
+
+        assert!(matches!(client.embed(""), Err(Error::EmptyInput)));
+
+        let unknown = IpcClient::connect(path.to_str().unwrap())
+            .unwrap()
+            .with_model("nope");
+        assert!(unknown.embed("hello").is_err());
+        // A failed request leaves the connection usable
+        match unknown.embed_batch(&[]) {
+            Err(Error::InvalidArgument(message)) => assert!(message.contains("nope")),
+            other => panic!("expected an invalid argument error, got {other:?}"),
+        }
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_serve_unix_keeps_other_files() {
+        let path = std::env::temp_dir().join(format!("ipc-{}.txt", std::process::id()));
+        std::fs::write(&path, "not a socket").unwrap();
+        assert!(IpcServer::new().serve_unix(&path).is_err());
+        assert_eq!("not a socket", std::fs::read_to_string(&path).unwrap());
+        let _ = std::fs::remove_file(path);
+    }
+}
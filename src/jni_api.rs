@@ -0,0 +1,153 @@
+use crate::embedder::{Embedder, LayerSelection};
+use crate::{
+    catch_panic, current_model, embed_default, init_default, install_hooks, set_default_model,
+    unload_model, FfiError, FfiResult, DEFAULT_MODEL_NAME, EMBED_ERR_INVALID_ARGUMENT,
+    EMBED_ERR_INVALID_UTF8, EMBED_ERR_NO_MODEL, EMBED_ERR_NULL_POINTER,
+};
+use jni::objects::{JByteArray, JClass, JString};
+use jni::sys::{jboolean, jfloatArray, jint, JNI_TRUE};
+use jni::JNIEnv;
+
+// Entry points for the static native methods of `com.rustembeddinglib.RustEmbedding` (see
+// bindings/android). Failures are thrown as Java exceptions instead of status codes.
+
+impl From<jni::errors::Error> for FfiError {
+    fn from(e: jni::errors::Error) -> Self {
+        let status = match e {
+            jni::errors::Error::NullPtr(_) | jni::errors::Error::NullDeref(_) => {
+                EMBED_ERR_NULL_POINTER
+            }
+            _ => EMBED_ERR_INVALID_ARGUMENT,
+        };
+        FfiError::new(status, e.to_string())
+    }
+}
+
+// Run a call, throwing its error or panic as a Java exception and returning `default` instead
+fn throwing<'local, T>(
+    env: &mut JNIEnv<'local>,
+    default: T,
+    call: impl FnOnce(&mut JNIEnv<'local>) -> FfiResult<T>,
+) -> T {
+    let e = match catch_panic(|| call(env)) {
+        Ok(value) => return value,
+        Err(e) => e,
+    };
+    // A failed JNI call may already have left an exception pending, which says more
+    if env.exception_check().unwrap_or(true) {
+        return default;
+    }
+    let class = match e.status {
+        EMBED_ERR_NULL_POINTER => "java/lang/NullPointerException",
+        EMBED_ERR_INVALID_UTF8 | EMBED_ERR_INVALID_ARGUMENT => "java/lang/IllegalArgumentException",
+        EMBED_ERR_NO_MODEL => "java/lang/IllegalStateException",
+        _ => "java/lang/RuntimeException",
+    };
+    let _ = env.throw_new(class, e.message);
+    default
+}
+
+fn string(env: &mut JNIEnv, value: &JString, name: &str) -> FfiResult<String> {
+    if value.is_null() {
+        return Err(FfiError::new(
+            EMBED_ERR_NULL_POINTER,
+            format!("{name} is null"),
+        ));
+    }
+    Ok(env.get_string(value)?.into())
+}
+
+fn bytes(env: &mut JNIEnv, value: &JByteArray, name: &str) -> FfiResult<Vec<u8>> {
+    if value.is_null() {
+        return Err(FfiError::new(
+            EMBED_ERR_NULL_POINTER,
+            format!("{name} is null"),
+        ));
+    }
+    Ok(env.convert_byte_array(value)?)
+}
+
+// Function to initialize the default model from files, e.g. ones copied out of the APK assets
+#[no_mangle]
+pub extern "system" fn Java_com_rustembeddinglib_RustEmbedding_initModel<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    config_path: JString<'local>,
+    tokenizer_path: JString<'local>,
+    weights_path: JString<'local>,
+    approximate_gelu: jboolean,
+) {
+    throwing(&mut env, (), |env| {
+        let config_path = string(env, &config_path, "configPath")?;
+        let tokenizer_path = string(env, &tokenizer_path, "tokenizerPath")?;
+        let weights_path = string(env, &weights_path, "weightsPath")?;
+        init_default(
+            &config_path,
+            &tokenizer_path,
+            &weights_path,
+            approximate_gelu == JNI_TRUE,
+        )
+    })
+}
+
+// Function to initialize the default model from the contents of its files, e.g. read with
+// `AssetManager` without extracting them first
+#[no_mangle]
+pub extern "system" fn Java_com_rustembeddinglib_RustEmbedding_initModelFromBuffers<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    config: JByteArray<'local>,
+    tokenizer: JByteArray<'local>,
+    weights: JByteArray<'local>,
+    approximate_gelu: jboolean,
+) {
+    throwing(&mut env, (), |env| {
+        let config = bytes(env, &config, "config")?;
+        let tokenizer = bytes(env, &tokenizer, "tokenizer")?;
+        let weights = bytes(env, &weights, "weights")?;
+        let mut embedder =
+            Embedder::from_buffers(&config, &tokenizer, weights, approximate_gelu == JNI_TRUE)?;
+        install_hooks(&mut embedder, DEFAULT_MODEL_NAME);
+        set_default_model(embedder);
+        Ok(())
+    })
+}
+
+// Function to free the default model
+#[no_mangle]
+pub extern "system" fn Java_com_rustembeddinglib_RustEmbedding_freeModel<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+) {
+    throwing(&mut env, (), |_| unload_model())
+}
+
+// Function to get the length of the default model's embeddings, 0 if none is loaded
+#[no_mangle]
+pub extern "system" fn Java_com_rustembeddinglib_RustEmbedding_getEmbeddingDim<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+) -> jint {
+    throwing(&mut env, 0, |_| {
+        Ok(current_model()
+            .as_deref()
+            .map_or(0, |embedder| embedder.embedding_dim(LayerSelection::Last)) as jint)
+    })
+}
+
+// Function to generate embeddings for UTF-8 text, returned as a new float[]
+#[no_mangle]
+pub extern "system" fn Java_com_rustembeddinglib_RustEmbedding_generateEmbeddings<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    text: JByteArray<'local>,
+) -> jfloatArray {
+    throwing(&mut env, std::ptr::null_mut(), |env| {
+        let text = String::from_utf8(bytes(env, &text, "text")?)
+            .map_err(|_| FfiError::new(EMBED_ERR_INVALID_UTF8, "text is not valid UTF-8"))?;
+        let embedding = embed_default(&text)?;
+        let array = env.new_float_array(embedding.len() as jint)?;
+        env.set_float_array_region(&array, 0, &embedding)?;
+        Ok(array.into_raw())
+    })
+}
@@ -0,0 +1,263 @@
+use crate::power;
+use serde::Serialize;
+use std::sync::OnceLock;
+
+// candle only takes its NEON matmul and activation paths when NEON is enabled at compile time.
+// It is part of every aarch64 baseline, so this only fires for a custom target spec.
+#[cfg(all(target_arch = "aarch64", not(target_feature = "neon")))]
+compile_error!("aarch64 builds need NEON enabled (`-C target-feature=+neon`)");
+
+/// Implementation picked at runtime for the crate's own vector kernels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kernel {
+    Avx2Fma,
+    /// NEON fused multiply-add on aarch64, e.g. Android arm64-v8a and Apple silicon.
+    Neon,
+    /// Plain loops, left to the compiler's auto-vectorization.
+    Portable,
+}
+
+/// SIMD extensions of the running CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct CpuFeatures {
+    pub sse4_1: bool,
+    pub avx: bool,
+    pub avx2: bool,
+    pub fma: bool,
+    pub f16c: bool,
+    pub avx512f: bool,
+    pub neon: bool,
+}
+
+impl CpuFeatures {
+    pub fn detect() -> Self {
+        #[cfg(target_arch = "x86_64")]
+        {
+            CpuFeatures {
+                sse4_1: is_x86_feature_detected!("sse4.1"),
+                avx: is_x86_feature_detected!("avx"),
+                avx2: is_x86_feature_detected!("avx2"),
+                fma: is_x86_feature_detected!("fma"),
+                f16c: is_x86_feature_detected!("f16c"),
+                avx512f: is_x86_feature_detected!("avx512f"),
+                neon: false,
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            CpuFeatures {
+                neon: std::arch::is_aarch64_feature_detected!("neon"),
+                ..Default::default()
+            }
+        }
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        {
+            CpuFeatures::default()
+        }
+    }
+}
+
+/// Which compute paths are active, to check a build is not running on scalar fallbacks.
+#[derive(Debug, Clone, Serialize)]
+pub struct SystemInfo {
+    pub cpu: CpuFeatures,
+    /// The kernel used for similarity, normalization and projection.
+    pub kernel: Kernel,
+    /// candle picks its SIMD paths at compile time (`-C target-cpu`/`target-feature`), so
+    /// these can be false on a CPU that supports them.
+    pub candle_avx: bool,
+    pub candle_neon: bool,
+    pub candle_f16c: bool,
+    pub mkl: bool,
+    pub accelerate: bool,
+    /// Threads an embedding call may use, after any thread limit or low power cap.
+    pub num_threads: usize,
+}
+
+pub fn system_info() -> SystemInfo {
+    SystemInfo {
+        cpu: CpuFeatures::detect(),
+        kernel: kernel(),
+        candle_avx: candle::utils::with_avx(),
+        candle_neon: candle::utils::with_neon(),
+        candle_f16c: candle::utils::with_f16c(),
+        mkl: candle::utils::has_mkl(),
+        accelerate: candle::utils::has_accelerate(),
+        num_threads: power::thread_pool()
+            .map(|pool| pool.current_num_threads())
+            .unwrap_or_else(candle::utils::get_num_threads),
+    }
+}
+
+pub fn kernel() -> Kernel {
+    static KERNEL: OnceLock<Kernel> = OnceLock::new();
+    *KERNEL.get_or_init(|| {
+        let cpu = CpuFeatures::detect();
+        if cpu.avx2 && cpu.fma {
+            Kernel::Avx2Fma
+        } else if cpu.neon {
+            Kernel::Neon
+        } else {
+            Kernel::Portable
+        }
+    })
+}
+
+/// Dot product of the common prefix of `a` and `b`.
+pub(crate) fn dot(a: &[f32], b: &[f32]) -> f32 {
+    match kernel() {
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Avx2Fma => unsafe { dot_avx2_fma(a, b) },
+        #[cfg(target_arch = "aarch64")]
+        Kernel::Neon => unsafe { dot_neon(a, b) },
+        _ => dot_portable(a, b),
+    }
+}
+
+fn dot_portable(a: &[f32], b: &[f32]) -> f32 {
+    // Independent partial sums let the compiler vectorize the loop.
+    let mut acc = [0f32; 8];
+    let chunks_a = a.chunks_exact(8);
+    let chunks_b = b.chunks_exact(8);
+    let tail: f32 = chunks_a
+        .remainder()
+        .iter()
+        .zip(chunks_b.remainder())
+        .map(|(x, y)| x * y)
+        .sum();
+    for (x, y) in chunks_a.zip(chunks_b) {
+        for i in 0..8 {
+            acc[i] += x[i] * y[i];
+        }
+    }
+    acc.iter().sum::<f32>() + tail
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+unsafe fn dot_avx2_fma(a: &[f32], b: &[f32]) -> f32 {
+    use std::arch::x86_64::*;
+
+    let n = a.len().min(b.len());
+    let mut acc = _mm256_setzero_ps();
+    for i in (0..n - n % 8).step_by(8) {
+        let x = _mm256_loadu_ps(a.as_ptr().add(i));
+        let y = _mm256_loadu_ps(b.as_ptr().add(i));
+        acc = _mm256_fmadd_ps(x, y, acc);
+    }
+    let mut lanes = [0f32; 8];
+    _mm256_storeu_ps(lanes.as_mut_ptr(), acc);
+
+    let tail: f32 = (n - n % 8..n).map(|i| a[i] * b[i]).sum();
+    lanes.iter().sum::<f32>() + tail
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn dot_neon(a: &[f32], b: &[f32]) -> f32 {
+    use std::arch::aarch64::*;
+
+    // Two accumulators hide the latency of the dependent fused multiply-adds.
+    let n = a.len().min(b.len());
+    let mut acc0 = vdupq_n_f32(0.0);
+    let mut acc1 = vdupq_n_f32(0.0);
+    for i in (0..n - n % 8).step_by(8) {
+        let (pa, pb) = (a.as_ptr().add(i), b.as_ptr().add(i));
+        acc0 = vfmaq_f32(acc0, vld1q_f32(pa), vld1q_f32(pb));
+        acc1 = vfmaq_f32(acc1, vld1q_f32(pa.add(4)), vld1q_f32(pb.add(4)));
+    }
+
+    let tail: f32 = (n - n % 8..n).map(|i| a[i] * b[i]).sum();
+    vaddvq_f32(vaddq_f32(acc0, acc1)) + tail
+}
+
+/// Squared Euclidean distance over the common prefix of `a` and `b`.
+pub(crate) fn squared_distance(a: &[f32], b: &[f32]) -> f32 {
+    match kernel() {
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Avx2Fma => unsafe { squared_distance_avx2_fma(a, b) },
+        #[cfg(target_arch = "aarch64")]
+        Kernel::Neon => unsafe { squared_distance_neon(a, b) },
+        _ => squared_distance_portable(a, b),
+    }
+}
+
+fn squared_distance_portable(a: &[f32], b: &[f32]) -> f32 {
+    let mut acc = [0f32; 8];
+    let chunks_a = a.chunks_exact(8);
+    let chunks_b = b.chunks_exact(8);
+    let tail: f32 = chunks_a
+        .remainder()
+        .iter()
+        .zip(chunks_b.remainder())
+        .map(|(x, y)| (x - y) * (x - y))
+        .sum();
+    for (x, y) in chunks_a.zip(chunks_b) {
+        for i in 0..8 {
+            let d = x[i] - y[i];
+            acc[i] += d * d;
+        }
+    }
+    acc.iter().sum::<f32>() + tail
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+unsafe fn squared_distance_avx2_fma(a: &[f32], b: &[f32]) -> f32 {
+    use std::arch::x86_64::*;
+
+    let n = a.len().min(b.len());
+    let mut acc = _mm256_setzero_ps();
+    for i in (0..n - n % 8).step_by(8) {
+        let x = _mm256_loadu_ps(a.as_ptr().add(i));
+        let y = _mm256_loadu_ps(b.as_ptr().add(i));
+        let d = _mm256_sub_ps(x, y);
+        acc = _mm256_fmadd_ps(d, d, acc);
+    }
+    let mut lanes = [0f32; 8];
+    _mm256_storeu_ps(lanes.as_mut_ptr(), acc);
+
+    let tail: f32 = (n - n % 8..n).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum();
+    lanes.iter().sum::<f32>() + tail
+}
+
+#[cfg(target_arch = "aarch64")]
+#[target_feature(enable = "neon")]
+unsafe fn squared_distance_neon(a: &[f32], b: &[f32]) -> f32 {
+    use std::arch::aarch64::*;
+
+    let n = a.len().min(b.len());
+    let mut acc0 = vdupq_n_f32(0.0);
+    let mut acc1 = vdupq_n_f32(0.0);
+    for i in (0..n - n % 8).step_by(8) {
+        let (pa, pb) = (a.as_ptr().add(i), b.as_ptr().add(i));
+        let d0 = vsubq_f32(vld1q_f32(pa), vld1q_f32(pb));
+        let d1 = vsubq_f32(vld1q_f32(pa.add(4)), vld1q_f32(pb.add(4)));
+        acc0 = vfmaq_f32(acc0, d0, d0);
+        acc1 = vfmaq_f32(acc1, d1, d1);
+    }
+
+    let tail: f32 = (n - n % 8..n).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum();
+    vaddvq_f32(vaddq_f32(acc0, acc1)) + tail
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dot_kernels_agree() {
+        let a: Vec<f32> = (0..19).map(|i| i as f32 * 0.5 - 3.0).collect();
+        let b: Vec<f32> = (0..19).map(|i| 1.0 - i as f32 * 0.25).collect();
+        let expected: f32 = a.iter().zip(&b).map(|(x, y)| x * y).sum();
+
+        assert!((dot_portable(&a, &b) - expected).abs() < 1e-4);
+        assert!((dot(&a, &b) - expected).abs() < 1e-4);
+        assert_eq!(dot(&a[..3], &b), dot_portable(&a[..3], &b[..3]));
+
+        let expected: f32 = a.iter().zip(&b).map(|(x, y)| (x - y) * (x - y)).sum();
+        assert!((squared_distance_portable(&a, &b) - expected).abs() < 1e-3);
+        assert!((squared_distance(&a, &b) - expected).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,126 @@
+use crate::error::{Error, Result};
+use std::fmt::Write;
+use std::sync::{Arc, OnceLock, RwLock};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::Registry;
+
+/// Receives every diagnostic the library emits at or above the configured level: the level,
+/// the module it came from and the message with its fields, e.g.
+/// `embedded text tokens=12 forward_us=5310`.
+pub type LogHandler = Arc<dyn Fn(Level, &str, &str) + Send + Sync>;
+
+struct HandlerState {
+    handler: LogHandler,
+    max_level: Level,
+}
+
+static HANDLER: RwLock<Option<HandlerState>> = RwLock::new(None);
+
+// Whether our subscriber became the global one, decided by the first handler set
+static INSTALLED: OnceLock<bool> = OnceLock::new();
+
+/// Forward the library's [`tracing`] events (model load time, tokens and forward latency per
+/// call, ...) to `handler`, or with `None` stop forwarding. `max_level` is the most verbose
+/// level passed on, e.g. [`Level::INFO`] drops debug and trace events.
+///
+/// This installs a process-wide tracing subscriber the first time a handler is set, and fails
+/// if the application already installed its own; such applications receive the events through
+/// their subscriber instead.
+pub fn set_log_handler(handler: Option<LogHandler>, max_level: Level) -> Result<()> {
+    if handler.is_some() {
+        let installed = INSTALLED.get_or_init(|| {
+            let subscriber = Registry::default().with(HandlerLayer);
+            tracing::subscriber::set_global_default(subscriber).is_ok()
+        });
+        if !installed {
+            return Err(Error::InvalidArgument(
+                "another tracing subscriber is already installed".to_string(),
+            ));
+        }
+    }
+    *HANDLER.write().unwrap() = handler.map(|handler| HandlerState { handler, max_level });
+    Ok(())
+}
+
+struct HandlerLayer;
+
+impl<S: Subscriber> Layer<S> for HandlerLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let guard = HANDLER.read().unwrap();
+        let Some(state) = guard.as_ref() else {
+            return;
+        };
+        let metadata = event.metadata();
+        // Levels compare by verbosity: TRACE is the greatest
+        if *metadata.level() > state.max_level {
+            return;
+        }
+        let mut message = MessageVisitor::default();
+        event.record(&mut message);
+        (state.handler)(*metadata.level(), metadata.target(), &message.finish());
+    }
+}
+
+// Formats an event as its message followed by ` name=value` for every other field.
+#[derive(Default)]
+struct MessageVisitor {
+    message: String,
+    fields: String,
+}
+
+impl MessageVisitor {
+    fn finish(self) -> String {
+        self.message + &self.fields
+    }
+}
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={value:?}", field.name());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{value:?}");
+        } else {
+            let _ = write!(self.fields, " {}={value:?}", field.name());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_log_handler() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&received);
+        let handler: LogHandler = Arc::new(move |level, target, message| {
+            if target == module_path!() {
+                sink.lock().unwrap().push((level, message.to_string()));
+            }
+        });
+        set_log_handler(Some(handler), Level::INFO).unwrap();
+
+        tracing::info!(tokens = 12, name = "gte", "embedded text");
+        tracing::debug!("dropped by the level filter");
+        set_log_handler(None, Level::INFO).unwrap();
+        tracing::info!("dropped, no handler");
+
+        assert_eq!(
+            vec![(
+                Level::INFO,
+                "embedded text tokens=12 name=\"gte\"".to_string()
+            )],
+            *received.lock().unwrap()
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use crate::bert::Config;
+use crate::error::{Error, Result};
+use candle::{DType, Device, Shape, Tensor};
+use candle_nn::var_builder::SimpleBackend;
+use candle_nn::{Init, VarBuilder};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+// The parts of a PEFT `adapter_config.json` that change the merged weights.
+#[derive(Deserialize)]
+struct AdapterConfig {
+    r: usize,
+    lora_alpha: f64,
+    #[serde(default)]
+    use_rslora: bool,
+    #[serde(default)]
+    fan_in_fan_out: bool,
+}
+
+/// A LoRA adapter in the layout PEFT saves: `adapter_config.json` and
+/// `adapter_model.safetensors` in one directory. Each adapted weight `W` of the base model is
+/// replaced by `W + scale * B A` as the model loads, so embedding costs nothing extra.
+pub(crate) struct LoraAdapter {
+    // `scale * B A` by the path of the weight it adds to, from the encoder's root, e.g.
+    // `encoder.layer.0.attention.self.query.weight`.
+    deltas: HashMap<String, Tensor>,
+}
+
+impl LoraAdapter {
+    pub(crate) fn load(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let config: AdapterConfig =
+            serde_json::from_slice(&std::fs::read(dir.join("adapter_config.json"))?)?;
+        if config.r == 0 || config.fan_in_fan_out {
+            return Err(Error::InvalidArgument(
+                "LoRA adapters need a rank of at least 1 and fan_in_fan_out off".to_string(),
+            ));
+        }
+        let tensors =
+            candle::safetensors::load(dir.join("adapter_model.safetensors"), &Device::Cpu)?;
+        LoraAdapter::from_tensors(&config, &tensors)
+    }
+
+    fn from_tensors(config: &AdapterConfig, tensors: &HashMap<String, Tensor>) -> Result<Self> {
+        let mut deltas = HashMap::new();
+        for (name, a) in tensors {
+            // Linear layers store `lora_A.weight`; embeddings `lora_embedding_A`, transposed.
+            let (module, b_name, embedding) =
+                if let Some(module) = name.strip_suffix(".lora_A.weight") {
+                    (module, format!("{module}.lora_B.weight"), false)
+                } else if let Some(module) = name.strip_suffix(".lora_embedding_A") {
+                    (module, format!("{module}.lora_embedding_B"), true)
+                } else {
+                    continue;
+                };
+            let b = tensors.get(&b_name).ok_or_else(|| {
+                Error::InvalidArgument(format!("LoRA adapter has {name} without {b_name}"))
+            })?;
+            let Some(path) = root_path(module) else {
+                return Err(Error::InvalidArgument(format!(
+                    "LoRA adapter targets {module}, which is not part of a BERT encoder"
+                )));
+            };
+
+            // A module's rank can differ from the config's through `rank_pattern`
+            let rank = a.dim(0)?;
+            let scale = if config.use_rslora {
+                config.lora_alpha / (rank as f64).sqrt()
+            } else {
+                config.lora_alpha / rank as f64
+            };
+            let product = b.to_dtype(DType::F32)?.matmul(&a.to_dtype(DType::F32)?)?;
+            let delta = if embedding { product.t()? } else { product };
+            deltas.insert(format!("{path}.weight"), (delta * scale)?);
+        }
+        if deltas.is_empty() {
+            return Err(Error::InvalidArgument(
+                "LoRA adapter has no lora_A weights".to_string(),
+            ));
+        }
+        Ok(LoraAdapter { deltas })
+    }
+
+    /// `base` with the adapter merged into the weights it reads. Fails if the adapter targets a
+    /// weight `base` doesn't have, rather than silently leaving it out.
+    pub(crate) fn apply<'a>(self, base: VarBuilder<'a>, config: &Config) -> Result<VarBuilder<'a>> {
+        for path in self.deltas.keys() {
+            let nested = config
+                .model_type
+                .as_ref()
+                .is_some_and(|model_type| base.contains_tensor(&format!("{model_type}.{path}")));
+            if !base.contains_tensor(path) && !nested {
+                return Err(Error::InvalidArgument(format!(
+                    "LoRA adapter targets {path}, which the model does not have"
+                )));
+            }
+        }
+        let dtype = base.dtype();
+        let device = base.device().clone();
+        let backend: Box<dyn SimpleBackend + 'a> = Box::new(Merged {
+            base,
+            deltas: self.deltas,
+        });
+        Ok(VarBuilder::new_with_args(backend, dtype, &device))
+    }
+}
+
+// The path of a weight from the encoder's root, dropping whatever it is nested under: PEFT's
+// `base_model.model.`, a task head's `bert.` or a sentence-transformers module's
+// `0.auto_model.`.
+fn root_path(name: &str) -> Option<&str> {
+    ["embeddings.", "encoder."]
+        .iter()
+        .filter_map(|root| {
+            name.match_indices(root)
+                .map(|(i, _)| i)
+                .find(|&i| i == 0 || name.as_bytes()[i - 1] == b'.')
+        })
+        .min()
+        .map(|i| &name[i..])
+}
+
+struct Merged<'a> {
+    base: VarBuilder<'a>,
+    deltas: HashMap<String, Tensor>,
+}
+
+impl SimpleBackend for Merged<'_> {
+    fn get(
+        &self,
+        shape: Shape,
+        name: &str,
+        hints: Init,
+        dtype: DType,
+        device: &Device,
+    ) -> candle::Result<Tensor> {
+        let weight = self.base.get_with_hints(shape, name, hints)?;
+        let weight = weight.to_dtype(dtype)?.to_device(device)?;
+        match root_path(name).and_then(|path| self.deltas.get(path)) {
+            Some(delta) => weight + delta.to_dtype(dtype)?.to_device(device)?,
+            None => Ok(weight),
+        }
+    }
+
+    fn contains_tensor(&self, name: &str) -> bool {
+        self.base.contains_tensor(name)
+    }
+}
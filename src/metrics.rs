@@ -0,0 +1,309 @@
+use crate::batcher::BatcherStats;
+use crate::stats::{PhaseStats, Stats};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+const BATCH_BUCKETS: &[f64] = &[1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0];
+
+// A Prometheus histogram: `counts[i]` holds the observations in bucket `i` alone, the last
+// entry the ones above every bound; they are made cumulative when rendered.
+struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        self.observe_times(value, 1);
+    }
+
+    fn observe_times(&mut self, value: f64, times: u64) {
+        let bucket = self.bounds.partition_point(|&bound| bound < value);
+        self.counts[bucket] += times;
+        self.sum += value * times as f64;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{bound}\"}} {cumulative}");
+        }
+        cumulative += self.counts[self.bounds.len()];
+        let _ = writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {cumulative}");
+        let _ = writeln!(out, "{name}_sum{{{labels}}} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{{{labels}}} {cumulative}");
+    }
+}
+
+struct ModelMetrics {
+    ok: u64,
+    errors: u64,
+    tokens: u64,
+    duration: Histogram,
+}
+
+impl ModelMetrics {
+    fn new() -> Self {
+        ModelMetrics {
+            ok: 0,
+            errors: 0,
+            tokens: 0,
+            duration: Histogram::new(LATENCY_BUCKETS),
+        }
+    }
+}
+
+/// Request metrics of an [`EmbeddingServer`](crate::EmbeddingServer), rendered in the
+/// Prometheus text format for `GET /metrics`.
+#[derive(Default)]
+pub(crate) struct ServerMetrics {
+    models: Mutex<BTreeMap<String, ModelMetrics>>,
+    in_flight: AtomicI64,
+}
+
+/// Counts a request as in flight until dropped.
+pub(crate) struct InFlight<'a>(&'a AtomicI64);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl ServerMetrics {
+    pub(crate) fn start_request(&self) -> InFlight<'_> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(&self.in_flight)
+    }
+
+    /// Record a request routed to `model`; `tokens` holds its number of tokens if it
+    /// succeeded.
+    pub(crate) fn record(&self, model: &str, elapsed: Duration, tokens: Option<usize>) {
+        let mut models = self.models.lock().unwrap();
+        let metrics = models
+            .entry(model.to_string())
+            .or_insert_with(ModelMetrics::new);
+        metrics.duration.observe(elapsed.as_secs_f64());
+        match tokens {
+            Some(tokens) => {
+                metrics.ok += 1;
+                metrics.tokens += tokens as u64;
+            }
+            None => metrics.errors += 1,
+        }
+    }
+
+    /// The metrics in the Prometheus text format, with the batch sizes and queue depth of the
+    /// models served through a [`MicroBatcher`](crate::MicroBatcher).
+    pub(crate) fn render(&self, batchers: &[(&str, BatcherStats)]) -> String {
+        let mut out = String::new();
+        let models = self.models.lock().unwrap();
+        let labels: Vec<(String, &ModelMetrics)> = models
+            .iter()
+            .map(|(name, metrics)| (format!("model=\"{}\"", escape(name)), metrics))
+            .collect();
+
+        header(
+            &mut out,
+            "embed_requests_total",
+            "counter",
+            "Embedding requests answered.",
+        );
+        for (labels, metrics) in &labels {
+            let _ = writeln!(
+                out,
+                "embed_requests_total{{{labels},status=\"ok\"}} {}",
+                metrics.ok
+            );
+            let _ = writeln!(
+                out,
+                "embed_requests_total{{{labels},status=\"error\"}} {}",
+                metrics.errors
+            );
+        }
+        header(
+            &mut out,
+            "embed_tokens_total",
+            "counter",
+            "Tokens in successfully embedded inputs.",
+        );
+        for (labels, metrics) in &labels {
+            let _ = writeln!(out, "embed_tokens_total{{{labels}}} {}", metrics.tokens);
+        }
+        header(
+            &mut out,
+            "embed_request_duration_seconds",
+            "histogram",
+            "Time from routing a request to its response.",
+        );
+        for (labels, metrics) in &labels {
+            metrics
+                .duration
+                .render(&mut out, "embed_request_duration_seconds", labels);
+        }
+        drop(models);
+
+        let batchers: Vec<(String, &BatcherStats)> = batchers
+            .iter()
+            .map(|(name, stats)| (format!("model=\"{}\"", escape(name)), stats))
+            .collect();
+        header(
+            &mut out,
+            "embed_batch_size",
+            "histogram",
+            "Requests per batch flushed by the micro-batcher.",
+        );
+        for (labels, stats) in &batchers {
+            let mut sizes = Histogram::new(BATCH_BUCKETS);
+            for (size, &count) in (1..).zip(&stats.batch_sizes) {
+                sizes.observe_times(size as f64, count);
+            }
+            sizes.render(&mut out, "embed_batch_size", labels);
+        }
+        header(
+            &mut out,
+            "embed_batch_queue_depth",
+            "gauge",
+            "Requests waiting for the micro-batcher to take them into a batch.",
+        );
+        for (labels, stats) in &batchers {
+            let _ = writeln!(
+                out,
+                "embed_batch_queue_depth{{{labels}}} {}",
+                stats.queue_depth
+            );
+        }
+
+        header(
+            &mut out,
+            "embed_requests_in_flight",
+            "gauge",
+            "Requests waiting for or running their forward pass.",
+        );
+        let in_flight = self.in_flight.load(Ordering::Relaxed);
+        let _ = writeln!(out, "embed_requests_in_flight {in_flight}");
+
+        // Process-wide, including embeddings not requested over HTTP. The rate of texts over
+        // the rate of forward passes is the mean batch size.
+        let stats = Stats::current();
+        header(
+            &mut out,
+            "embed_forward_passes_total",
+            "counter",
+            "Model forward passes, one per batch.",
+        );
+        let _ = writeln!(out, "embed_forward_passes_total {}", stats.forward.count);
+        header(
+            &mut out,
+            "embed_texts_total",
+            "counter",
+            "Texts pooled into embeddings.",
+        );
+        let _ = writeln!(out, "embed_texts_total {}", stats.pool.count);
+        header(
+            &mut out,
+            "embed_phase_duration_seconds",
+            "summary",
+            "Latency of each stage of embedding.",
+        );
+        for (phase, phase_stats) in [
+            ("tokenize", &stats.tokenize),
+            ("forward", &stats.forward),
+            ("pool", &stats.pool),
+        ] {
+            render_summary(&mut out, phase, phase_stats);
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn render_summary(out: &mut String, phase: &str, stats: &PhaseStats) {
+    let name = "embed_phase_duration_seconds";
+    for (quantile, us) in [
+        ("0.5", stats.p50_us),
+        ("0.9", stats.p90_us),
+        ("0.99", stats.p99_us),
+    ] {
+        let _ = writeln!(
+            out,
+            "{name}{{phase=\"{phase}\",quantile=\"{quantile}\"}} {}",
+            us as f64 / 1e6
+        );
+    }
+    let _ = writeln!(
+        out,
+        "{name}_sum{{phase=\"{phase}\"}} {}",
+        stats.total_us as f64 / 1e6
+    );
+    let _ = writeln!(out, "{name}_count{{phase=\"{phase}\"}} {}", stats.count);
+}
+
+// Label values escape backslashes, quotes and newlines
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_metrics() {
+        let metrics = ServerMetrics::default();
+        let in_flight = metrics.start_request();
+        metrics.record("gte\"small", Duration::from_millis(20), Some(12));
+        metrics.record("gte\"small", Duration::from_millis(2), None);
+        let batcher = BatcherStats {
+            queue_depth: 2,
+            batch_sizes: vec![1, 0, 3],
+        };
+
+        let text = metrics.render(&[("gte\"small", batcher)]);
+        for line in [
+            "embed_requests_total{model=\"gte\\\"small\",status=\"ok\"} 1",
+            "embed_requests_total{model=\"gte\\\"small\",status=\"error\"} 1",
+            "embed_tokens_total{model=\"gte\\\"small\"} 12",
+            "embed_request_duration_seconds_bucket{model=\"gte\\\"small\",le=\"0.005\"} 1",
+            "embed_request_duration_seconds_bucket{model=\"gte\\\"small\",le=\"0.025\"} 2",
+            "embed_request_duration_seconds_count{model=\"gte\\\"small\"} 2",
+            "embed_batch_size_bucket{model=\"gte\\\"small\",le=\"2\"} 1",
+            "embed_batch_size_bucket{model=\"gte\\\"small\",le=\"4\"} 4",
+            "embed_batch_size_sum{model=\"gte\\\"small\"} 10",
+            "embed_batch_size_count{model=\"gte\\\"small\"} 4",
+            "embed_batch_queue_depth{model=\"gte\\\"small\"} 2",
+            "embed_requests_in_flight 1",
+            "# TYPE embed_phase_duration_seconds summary",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "{line} missing from\n{text}"
+            );
+        }
+        drop(in_flight);
+        assert!(metrics.render(&[]).contains("embed_requests_in_flight 0\n"));
+    }
+}
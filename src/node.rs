@@ -0,0 +1,96 @@
+use crate::embedder::Embedder;
+use crate::error::Error;
+use crate::options::{EmbedOptions, Embedding};
+use napi::bindgen_prelude::{AsyncTask, Float32Array};
+use napi::{Env, Task};
+use napi_derive::napi;
+use std::path::Path;
+use std::sync::Arc;
+
+impl From<Error> for napi::Error {
+    fn from(e: Error) -> Self {
+        napi::Error::from_reason(e.to_string())
+    }
+}
+
+/// `new Embedder(modelDir)` loads `config.json`, `tokenizer.json` and `model.safetensors`
+/// from a model directory; `Embedder.fromFiles(...)` takes the three paths.
+#[napi(js_name = "Embedder")]
+pub struct JsEmbedder {
+    embedder: Arc<Embedder>,
+}
+
+#[napi]
+impl JsEmbedder {
+    #[napi(constructor)]
+    pub fn new(model_dir: String) -> napi::Result<Self> {
+        let dir = Path::new(&model_dir);
+        JsEmbedder::from_files(
+            dir.join("config.json").to_string_lossy().into_owned(),
+            dir.join("tokenizer.json").to_string_lossy().into_owned(),
+            dir.join("model.safetensors").to_string_lossy().into_owned(),
+            None,
+        )
+    }
+
+    #[napi(factory)]
+    pub fn from_files(
+        config_path: String,
+        tokenizer_path: String,
+        weights_path: String,
+        approximate_gelu: Option<bool>,
+    ) -> napi::Result<Self> {
+        let embedder = Embedder::load(
+            config_path,
+            tokenizer_path,
+            weights_path,
+            approximate_gelu.unwrap_or(false),
+        )?;
+        Ok(JsEmbedder {
+            embedder: Arc::new(embedder),
+        })
+    }
+
+    /// Length of the default embedding.
+    #[napi(getter)]
+    pub fn dim(&self) -> u32 {
+        self.embedder.embedding_dim(Default::default()) as u32
+    }
+
+    /// Embed one text on the calling thread.
+    #[napi]
+    pub fn embed(&self, text: String) -> napi::Result<Float32Array> {
+        Ok(self.embedder.embed(&text)?.into())
+    }
+
+    /// Embed several texts in one padded forward pass on the libuv thread pool, resolving to
+    /// one `Float32Array` per text.
+    #[napi(ts_return_type = "Promise<Float32Array[]>")]
+    pub fn embed_batch(&self, texts: Vec<String>) -> AsyncTask<EmbedBatch> {
+        AsyncTask::new(EmbedBatch {
+            embedder: Arc::clone(&self.embedder),
+            texts,
+        })
+    }
+}
+
+pub struct EmbedBatch {
+    embedder: Arc<Embedder>,
+    texts: Vec<String>,
+}
+
+impl Task for EmbedBatch {
+    type Output = Vec<Vec<f32>>;
+    type JsValue = Vec<Float32Array>;
+
+    fn compute(&mut self) -> napi::Result<Self::Output> {
+        let embeddings = self
+            .embedder
+            .embed_batch(&self.texts, &EmbedOptions::default())?;
+        Ok(embeddings.iter().map(Embedding::to_f32).collect())
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> napi::Result<Self::JsValue> {
+        Ok(output.into_iter().map(Float32Array::from).collect())
+    }
+}
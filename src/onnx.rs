@@ -0,0 +1,80 @@
+use crate::error::Result;
+use crate::power;
+use candle::{Device, Tensor};
+use ort::session::Session;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// A BERT-style encoder exported to ONNX (e.g. with `optimum-cli export onnx`), run with
+/// ONNX Runtime.
+///
+/// The graph must take `input_ids` and `attention_mask` (and `token_type_ids` if it declares
+/// it) as int64 and return the final hidden states `(batch, seq_len, hidden_size)` as its
+/// first output. A [`set_thread_limit`](crate::set_thread_limit) in effect at load time caps
+/// its intra-op threads. The ONNX Runtime library is loaded when the first model is, from
+/// `ORT_DYLIB_PATH` or the system library path.
+#[derive(Clone)]
+pub(crate) struct OnnxModel {
+    // Running a session takes it mutably
+    session: Arc<Mutex<Session>>,
+    has_token_type_ids: bool,
+    pub device: Device,
+}
+
+impl OnnxModel {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let mut builder = Session::builder()?;
+        if let Some(num_threads) = power::thread_limit() {
+            builder = builder.with_intra_threads(num_threads)?;
+        }
+        let session = builder.commit_from_file(path)?;
+        let has_token_type_ids = session
+            .inputs
+            .iter()
+            .any(|input| input.name == "token_type_ids");
+        Ok(OnnxModel {
+            session: Arc::new(Mutex::new(session)),
+            has_token_type_ids,
+            device: Device::Cpu,
+        })
+    }
+
+    /// The final hidden states for `(batch, seq_len)` token ids, as a candle tensor.
+    pub fn forward(
+        &self,
+        token_ids: &Tensor,
+        token_type_ids: &Tensor,
+        attention_mask: Option<&Tensor>,
+    ) -> Result<Tensor> {
+        let (batch, seq_len) = token_ids.dims2()?;
+        let shape = [batch, seq_len];
+        let to_i64 = |t: &Tensor| -> Result<Vec<i64>> {
+            Ok(t.flatten_all()?
+                .to_vec1::<u32>()?
+                .into_iter()
+                .map(i64::from)
+                .collect())
+        };
+        let mask = match attention_mask {
+            Some(mask) => to_i64(mask)?,
+            None => vec![1; batch * seq_len],
+        };
+
+        let mut inputs = ort::inputs![
+            "input_ids" => ort::value::Tensor::from_array((shape, to_i64(token_ids)?))?,
+            "attention_mask" => ort::value::Tensor::from_array((shape, mask))?,
+        ];
+        if self.has_token_type_ids {
+            inputs.push((
+                "token_type_ids".into(),
+                ort::value::Tensor::from_array((shape, to_i64(token_type_ids)?))?.into(),
+            ));
+        }
+
+        let mut session = self.session.lock().unwrap();
+        let outputs = session.run(inputs)?;
+        let (dims, hidden) = outputs[0].try_extract_tensor::<f32>()?;
+        let dims: Vec<usize> = dims.iter().map(|&d| d as usize).collect();
+        Ok(Tensor::from_slice(hidden, dims, &self.device)?)
+    }
+}
@@ -0,0 +1,327 @@
+use crate::embedder::LayerSelection;
+use crate::error::{Error, Result};
+use half::f16;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// How token vectors are reduced to one sentence vector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pooling {
+    /// Average over all tokens.
+    #[default]
+    Mean,
+    /// The vector of the first (`[CLS]`) token.
+    Cls,
+    /// Element-wise maximum over all tokens.
+    Max,
+}
+
+/// Which part of an over-long input is kept when truncating to `max_length`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TruncationStrategy {
+    /// Keep the beginning of the text.
+    #[default]
+    Head,
+    /// Keep the end of the text.
+    Tail,
+    /// Keep the beginning and the end of the text, half the tokens each, dropping the middle.
+    HeadTail,
+}
+
+/// What happens to a text longer than the model's truncation length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Overflow {
+    /// Keep the part selected by the [`TruncationStrategy`].
+    #[default]
+    Truncate,
+    /// Split the text into consecutive windows of the truncation length, embed each and
+    /// average them, weighted by how many tokens each holds. A prefix or instruction only
+    /// lands in the first window.
+    Average,
+}
+
+/// Element type of the returned vector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputDtype {
+    #[default]
+    F32,
+    F16,
+    F64,
+}
+
+impl OutputDtype {
+    /// Stable numeric tag used over FFI.
+    pub fn code(&self) -> u32 {
+        match self {
+            OutputDtype::F32 => 0,
+            OutputDtype::F16 => 1,
+            OutputDtype::F64 => 2,
+        }
+    }
+}
+
+/// What a text is embedded for, for models trained with asymmetric query/passage prefixes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Task {
+    Query,
+    Passage,
+}
+
+/// The prefixes a model expects for each [`Task`]; a missing prefix leaves the text as is.
+///
+/// Over FFI they are given as JSON, either a preset name (`"e5"`, `"bge"`, `"none"`) or
+/// `{"query": "...", "passage": "..."}`.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TaskPrefixes {
+    pub query: Option<String>,
+    pub passage: Option<String>,
+}
+
+impl TaskPrefixes {
+    /// `"query: "` / `"passage: "`, as used by the E5 family.
+    pub fn e5() -> Self {
+        TaskPrefixes {
+            query: Some("query: ".to_string()),
+            passage: Some("passage: ".to_string()),
+        }
+    }
+
+    /// The BGE retrieval instruction for queries; passages are embedded as is.
+    pub fn bge() -> Self {
+        TaskPrefixes {
+            query: Some("Represent this sentence for searching relevant passages: ".to_string()),
+            passage: None,
+        }
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "e5" => Some(TaskPrefixes::e5()),
+            "bge" => Some(TaskPrefixes::bge()),
+            "none" => Some(TaskPrefixes::default()),
+            _ => None,
+        }
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Preset(String),
+            Prefixes(TaskPrefixes),
+        }
+
+        match serde_json::from_str(json)? {
+            Repr::Preset(name) => TaskPrefixes::preset(&name)
+                .ok_or_else(|| Error::InvalidArgument(format!("unknown prefix preset {name:?}"))),
+            Repr::Prefixes(prefixes) => Ok(prefixes),
+        }
+    }
+
+    pub fn get(&self, task: Task) -> Option<&str> {
+        match task {
+            Task::Query => self.query.as_deref(),
+            Task::Passage => self.passage.as_deref(),
+        }
+    }
+}
+
+/// Per-call settings for [`Embedder::embed_with_options`](crate::Embedder::embed_with_options).
+///
+/// The defaults reproduce [`Embedder::embed`](crate::Embedder::embed). Over FFI the options are
+/// passed as JSON, e.g. `{"pooling": "cls", "normalize": true, "max_length": 256}`; missing
+/// fields take their default.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmbedOptions {
+    #[serde(with = "layer_selection")]
+    pub layers: LayerSelection,
+    pub pooling: Pooling,
+    /// Scale the result to unit L2 norm.
+    pub normalize: bool,
+    /// Maximum number of tokens, including special tokens. `None` keeps the model's truncation
+    /// (see [`Embedder::set_truncation`](crate::Embedder::set_truncation)), by default the
+    /// one configured in the tokenizer file.
+    pub max_length: Option<usize>,
+    pub truncation: TruncationStrategy,
+    pub overflow: Overflow,
+    /// Embed a text with no tokens (empty, whitespace only, ...) as the model's special tokens
+    /// alone instead of failing with [`Error::EmptyInput`].
+    pub allow_empty: bool,
+    pub dtype: OutputDtype,
+    /// Text prepended to the input, e.g. `"query: "` for E5 models. Takes precedence over
+    /// `task`.
+    pub prefix: Option<String>,
+    /// Prepend the model's prefix for this task (see
+    /// [`Embedder::set_task_prefixes`](crate::Embedder::set_task_prefixes)).
+    pub task: Option<Task>,
+    /// Instruction template overriding the model's (see
+    /// [`Embedder::set_instruction`](crate::Embedder::set_instruction)); `""` disables it.
+    pub instruction: Option<String>,
+    /// Tag written to the model's audit log, if any (see [`AuditLog`](crate::AuditLog)).
+    pub caller: Option<String>,
+    /// Return bit-identical embeddings for the same input on every run, on the same build and
+    /// hardware: each text goes through the encoder alone, unpadded, on a single thread that
+    /// ignores [`PowerMode`](crate::PowerMode) and the thread limit. Batches lose their
+    /// speedup.
+    pub deterministic: bool,
+    /// Fail with [`Error::Timeout`](crate::Error::Timeout) once this many milliseconds have
+    /// passed. Checked before every forward pass: a batch goes through the encoder a few texts
+    /// at a time and stops between them, while a pass already running completes first.
+    pub timeout_ms: Option<u64>,
+}
+
+impl EmbedOptions {
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+}
+
+// `LayerSelection` lives in the embedder, so its JSON form is spelled out here:
+// "last", {"layer": 3}, {"concat_last": 4} or {"mean_last": 4}.
+mod layer_selection {
+    use crate::embedder::LayerSelection;
+    use serde::{Deserialize, Deserializer};
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    enum Repr {
+        Last,
+        Layer(usize),
+        ConcatLast(usize),
+        MeanLast(usize),
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<LayerSelection, D::Error> {
+        Ok(match Repr::deserialize(d)? {
+            Repr::Last => LayerSelection::Last,
+            Repr::Layer(i) => LayerSelection::Layer(i),
+            Repr::ConcatLast(n) => LayerSelection::ConcatLast(n),
+            Repr::MeanLast(n) => LayerSelection::MeanLast(n),
+        })
+    }
+}
+
+/// An embedding in the element type requested through [`EmbedOptions::dtype`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Embedding {
+    F32(Vec<f32>),
+    F16(Vec<f16>),
+    F64(Vec<f64>),
+}
+
+impl Embedding {
+    pub(crate) fn from_f32(values: Vec<f32>, dtype: OutputDtype) -> Self {
+        match dtype {
+            OutputDtype::F32 => Embedding::F32(values),
+            OutputDtype::F16 => Embedding::F16(values.into_iter().map(f16::from_f32).collect()),
+            OutputDtype::F64 => Embedding::F64(values.into_iter().map(f64::from).collect()),
+        }
+    }
+
+    pub fn dtype(&self) -> OutputDtype {
+        match self {
+            Embedding::F32(_) => OutputDtype::F32,
+            Embedding::F16(_) => OutputDtype::F16,
+            Embedding::F64(_) => OutputDtype::F64,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Embedding::F32(v) => v.len(),
+            Embedding::F16(v) => v.len(),
+            Embedding::F64(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn to_f32(&self) -> Vec<f32> {
+        match self {
+            Embedding::F32(v) => v.clone(),
+            Embedding::F16(v) => v.iter().map(|x| x.to_f32()).collect(),
+            Embedding::F64(v) => v.iter().map(|&x| x as f32).collect(),
+        }
+    }
+}
+
+/// Where the time of one embedding call went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Timings {
+    /// Time spent waiting for the model to become available; only measured over FFI.
+    pub queue_wait: Duration,
+    /// Preprocessing, prefixing and tokenization.
+    pub tokenize: Duration,
+    /// The encoder forward pass.
+    pub forward: Duration,
+    /// Reducing the token vectors to one vector.
+    pub pool: Duration,
+    /// Normalization, the model's transforms and the output type conversion.
+    pub postprocess: Duration,
+}
+
+impl Timings {
+    pub fn total(&self) -> Duration {
+        self.queue_wait + self.tokenize + self.forward + self.pool + self.postprocess
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_options_from_json() {
+        let options = EmbedOptions::from_json(
+            r#"{"layers": {"mean_last": 4}, "pooling": "cls", "normalize": true,
+                "max_length": 64, "truncation": "tail", "dtype": "f16", "prefix": "query: "}"#,
+        )
+        .unwrap();
+        assert_eq!(LayerSelection::MeanLast(4), options.layers);
+        assert_eq!(Pooling::Cls, options.pooling);
+        assert!(options.normalize);
+        assert_eq!(Some(64), options.max_length);
+        assert_eq!(TruncationStrategy::Tail, options.truncation);
+        assert_eq!(OutputDtype::F16, options.dtype);
+        assert_eq!(Some("query: ".to_string()), options.prefix);
+
+        assert_eq!(
+            EmbedOptions::default(),
+            EmbedOptions::from_json("{}").unwrap()
+        );
+        assert!(EmbedOptions::from_json(r#"{"pooling": "median"}"#).is_err());
+        assert_eq!(
+            Some("Represent the title: {text}".to_string()),
+            EmbedOptions::from_json(r#"{"instruction": "Represent the title: {text}"}"#)
+                .unwrap()
+                .instruction
+        );
+        assert_eq!(
+            Some(Task::Passage),
+            EmbedOptions::from_json(r#"{"task": "passage"}"#)
+                .unwrap()
+                .task
+        );
+    }
+
+    #[test]
+    fn test_task_prefixes_from_json() {
+        assert_eq!(
+            TaskPrefixes::e5(),
+            TaskPrefixes::from_json(r#""e5""#).unwrap()
+        );
+        let prefixes = TaskPrefixes::from_json(r#"{"query": "search: "}"#).unwrap();
+        assert_eq!(Some("search: "), prefixes.get(Task::Query));
+        assert_eq!(None, prefixes.get(Task::Passage));
+        assert!(TaskPrefixes::from_json(r#""gte""#).is_err());
+    }
+}
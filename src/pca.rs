@@ -0,0 +1,379 @@
+use crate::error::{Error, Result};
+use crate::kernels::dot;
+use candle::{Device, Tensor};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// Subspace iteration stops once the variance along every component changes by less than this
+// fraction from one iteration to the next.
+const TOLERANCE: f64 = 1e-7;
+const MAX_ITERATIONS: usize = 1000;
+// Extra directions iterated alongside the requested ones, which speeds up convergence when
+// the last requested components have variances close to the next ones.
+const OVERSAMPLING: usize = 8;
+
+/// A principal component analysis fitted on a sample of embeddings, to reduce vectors to the
+/// directions of largest variance: fewer dimensions to store and to compare in a search,
+/// for little loss in ranking quality.
+///
+/// Fit it once on a representative sample, [`save`](Pca::save) it, and
+/// [`transform`](Pca::transform) every vector indexed or queried afterwards with the same
+/// one. It can also run as the last step of a model's post-processing through
+/// [`Transform::Pca`](crate::Transform::Pca).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pca {
+    mean: Vec<f32>,
+    // Unit vectors, by decreasing variance.
+    components: Vec<Vec<f32>>,
+    explained_variance: Vec<f32>,
+    total_variance: f32,
+}
+
+impl Pca {
+    /// Fit the `n_components` directions of largest variance of `samples`, which need at
+    /// least two vectors of the same length.
+    pub fn fit<V: AsRef<[f32]>>(samples: &[V], n_components: usize) -> Result<Pca> {
+        if samples.len() < 2 {
+            return Err(Error::InvalidArgument(
+                "PCA needs at least 2 samples".to_string(),
+            ));
+        }
+        let dim = samples[0].as_ref().len();
+        if n_components == 0 || n_components > dim {
+            return Err(Error::InvalidArgument(format!(
+                "can't fit {n_components} components to vectors of length {dim}"
+            )));
+        }
+
+        let mut mean = vec![0f64; dim];
+        for sample in samples {
+            let sample = sample.as_ref();
+            if sample.len() != dim {
+                return Err(Error::InvalidArgument(format!(
+                    "samples of length {dim} and {} can't be fitted together",
+                    sample.len()
+                )));
+            }
+            for (acc, &x) in mean.iter_mut().zip(sample) {
+                *acc += f64::from(x);
+            }
+        }
+        let n = samples.len();
+        mean.iter_mut().for_each(|m| *m /= n as f64);
+
+        let centered: Vec<f32> = samples
+            .iter()
+            .flat_map(|sample| sample.as_ref().iter().zip(&mean))
+            .map(|(&x, m)| (f64::from(x) - m) as f32)
+            .collect();
+        let total_variance = centered
+            .iter()
+            .map(|&x| f64::from(x) * f64::from(x))
+            .sum::<f64>()
+            / (n - 1) as f64;
+        let centered = Tensor::from_vec(centered, (n, dim), &Device::Cpu)?;
+        let covariance = (centered.t()?.matmul(&centered)? / (n - 1) as f64)?;
+
+        let (variances, components) = top_eigenvectors(&covariance, n_components)?;
+        Ok(Pca {
+            mean: mean.into_iter().map(|m| m as f32).collect(),
+            components,
+            explained_variance: variances.into_iter().map(|v| v.max(0.0) as f32).collect(),
+            total_variance: total_variance as f32,
+        })
+    }
+
+    /// Length of the vectors this PCA takes.
+    pub fn input_dim(&self) -> usize {
+        self.mean.len()
+    }
+
+    /// Length of the vectors this PCA returns.
+    pub fn output_dim(&self) -> usize {
+        self.components.len()
+    }
+
+    pub fn mean(&self) -> &[f32] {
+        &self.mean
+    }
+
+    /// The principal directions as unit vectors, by decreasing variance.
+    pub fn components(&self) -> &[Vec<f32>] {
+        &self.components
+    }
+
+    /// Variance of the samples along each component.
+    pub fn explained_variance(&self) -> &[f32] {
+        &self.explained_variance
+    }
+
+    /// Fraction of the samples' total variance along each component; their sum is how much of
+    /// the original information the reduced vectors keep.
+    pub fn explained_variance_ratio(&self) -> Vec<f32> {
+        self.explained_variance
+            .iter()
+            .map(|v| match self.total_variance {
+                0.0 => 0.0,
+                total => v / total,
+            })
+            .collect()
+    }
+
+    /// The coordinates of `vector` along the components.
+    pub fn transform(&self, vector: &[f32]) -> Result<Vec<f32>> {
+        if vector.len() != self.input_dim() {
+            return Err(Error::InvalidArgument(format!(
+                "PCA fitted on vectors of length {} can't reduce one of length {}",
+                self.input_dim(),
+                vector.len()
+            )));
+        }
+        let centered: Vec<f32> = vector.iter().zip(&self.mean).map(|(x, m)| x - m).collect();
+        Ok(self
+            .components
+            .iter()
+            .map(|component| dot(component, &centered))
+            .collect())
+    }
+
+    /// [`Pca::transform`] for every vector, in order.
+    pub fn transform_batch<V: AsRef<[f32]>>(&self, vectors: &[V]) -> Result<Vec<Vec<f32>>> {
+        vectors
+            .iter()
+            .map(|vector| self.transform(vector.as_ref()))
+            .collect()
+    }
+
+    /// Write the PCA as JSON, for [`Pca::load`].
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Pca> {
+        let pca: Pca = serde_json::from_slice(&std::fs::read(path)?)?;
+        let dim = pca.input_dim();
+        if pca
+            .components
+            .iter()
+            .any(|component| component.len() != dim)
+            || pca.explained_variance.len() != pca.components.len()
+        {
+            return Err(Error::InvalidArgument(
+                "PCA file has components of the wrong length".to_string(),
+            ));
+        }
+        Ok(pca)
+    }
+}
+
+// The `k` largest eigenvalues of a symmetric positive semi-definite matrix and their unit
+// eigenvectors, by subspace iteration and a final Rayleigh-Ritz step.
+fn top_eigenvectors(matrix: &Tensor, k: usize) -> Result<(Vec<f64>, Vec<Vec<f32>>)> {
+    let dim = matrix.dim(0)?;
+    let width = (k + OVERSAMPLING).min(dim);
+    let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+    let mut random = move || {
+        // xorshift64, so fitting the same samples gives the same result
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        (seed >> 11) as f64 / (1u64 << 53) as f64 - 0.5
+    };
+    let mut basis: Vec<Vec<f64>> = (0..width)
+        .map(|_| (0..dim).map(|_| random()).collect())
+        .collect();
+    orthonormalize(&mut basis, &mut random);
+
+    let mut variances = vec![0f64; width];
+    for _ in 0..MAX_ITERATIONS {
+        // The basis is stored as rows, so `basis * matrix` is the transpose of `matrix * basis`
+        let product = to_tensor(&basis)?.matmul(matrix)?.to_vec2::<f32>()?;
+        let mut next: Vec<Vec<f64>> = product
+            .into_iter()
+            .map(|row| row.into_iter().map(f64::from).collect())
+            .collect();
+        let rayleigh: Vec<f64> = basis
+            .iter()
+            .zip(&next)
+            .map(|(q, z)| q.iter().zip(z).map(|(a, b)| a * b).sum())
+            .collect();
+        let converged =
+            rayleigh.iter().zip(&variances).take(k).all(|(new, old)| {
+                (new - old).abs() <= TOLERANCE * new.abs().max(f64::MIN_POSITIVE)
+            });
+        variances = rayleigh;
+        orthonormalize(&mut next, &mut random);
+        basis = next;
+        if converged {
+            break;
+        }
+    }
+
+    // The best combinations of the basis vectors, and the variances along them
+    let q = to_tensor(&basis)?;
+    let projected = q.matmul(matrix)?.matmul(&q.t()?)?.to_vec2::<f32>()?;
+    let projected = projected
+        .into_iter()
+        .map(|row| row.into_iter().map(f64::from).collect())
+        .collect();
+    let (values, vectors) = symmetric_eigen(projected);
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| values[b].total_cmp(&values[a]));
+
+    let components = order
+        .iter()
+        .take(k)
+        .map(|&j| {
+            let mut component = vec![0f64; dim];
+            for (weights, q) in vectors.iter().zip(&basis) {
+                for (acc, x) in component.iter_mut().zip(q) {
+                    *acc += weights[j] * x;
+                }
+            }
+            // Signs are arbitrary; make the largest coordinate positive so refits agree
+            let largest = component
+                .iter()
+                .copied()
+                .max_by(|a, b| a.abs().total_cmp(&b.abs()))
+                .unwrap_or(0.0);
+            let norm = component.iter().map(|x| x * x).sum::<f64>().sqrt();
+            let scale = largest.signum() / norm.max(f64::MIN_POSITIVE);
+            component.into_iter().map(|x| (x * scale) as f32).collect()
+        })
+        .collect();
+    let values = order.iter().take(k).map(|&j| values[j]).collect();
+    Ok((values, components))
+}
+
+fn to_tensor(rows: &[Vec<f64>]) -> Result<Tensor> {
+    let dim = rows.first().map_or(0, Vec::len);
+    let values: Vec<f32> = rows.iter().flatten().map(|&x| x as f32).collect();
+    Ok(Tensor::from_vec(values, (rows.len(), dim), &Device::Cpu)?)
+}
+
+// Modified Gram-Schmidt. A vector that depends on the previous ones (the matrix has lower rank
+// than the basis is wide) is replaced by a random one.
+fn orthonormalize(vectors: &mut [Vec<f64>], random: &mut impl FnMut() -> f64) {
+    for i in 0..vectors.len() {
+        loop {
+            let (done, rest) = vectors.split_at_mut(i);
+            let v = &mut rest[0];
+            let before = v.iter().map(|x| x * x).sum::<f64>().sqrt();
+            for u in done.iter() {
+                let projection: f64 = u.iter().zip(v.iter()).map(|(a, b)| a * b).sum();
+                v.iter_mut().zip(u).for_each(|(x, u)| *x -= projection * u);
+            }
+            let norm = v.iter().map(|x| x * x).sum::<f64>().sqrt();
+            if norm > 1e-10 * before.max(f64::MIN_POSITIVE) && norm > 0.0 {
+                v.iter_mut().for_each(|x| *x /= norm);
+                break;
+            }
+            v.iter_mut().for_each(|x| *x = random());
+        }
+    }
+}
+
+// Eigenvalues and eigenvectors (the columns of the returned matrix) of a small symmetric
+// matrix, by cyclic Jacobi rotations.
+fn symmetric_eigen(mut a: Vec<Vec<f64>>) -> (Vec<f64>, Vec<Vec<f64>>) {
+    let n = a.len();
+    let mut v: Vec<Vec<f64>> = (0..n)
+        .map(|i| (0..n).map(|j| f64::from(u8::from(i == j))).collect())
+        .collect();
+    let scale: f64 = a.iter().flatten().map(|x| x * x).sum();
+    for _sweep in 0..100 {
+        let off_diagonal: f64 = (0..n)
+            .flat_map(|i| (i + 1..n).map(move |j| (i, j)))
+            .map(|(i, j)| a[i][j] * a[i][j])
+            .sum();
+        if off_diagonal <= 1e-24 * scale {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[p][q] == 0.0 {
+                    continue;
+                }
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                let rotate = |x: f64, y: f64| (c * x - s * y, s * x + c * y);
+                for row in a.iter_mut().chain(v.iter_mut()) {
+                    (row[p], row[q]) = rotate(row[p], row[q]);
+                }
+                let (above, below) = a.split_at_mut(q);
+                for (x, y) in above[p].iter_mut().zip(below[0].iter_mut()) {
+                    (*x, *y) = rotate(*x, *y);
+                }
+            }
+        }
+    }
+    ((0..n).map(|i| a[i][i]).collect(), v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pca() {
+        // Points spread 10:3:1 along three orthogonal directions of a 6-dimensional space
+        let axes = [
+            [0.5, 0.5, 0.5, 0.5, 0.0, 0.0],
+            [0.5, -0.5, 0.5, -0.5, 0.0, 0.0],
+            [0.0, 0.0, 0.0, 0.0, 0.6, 0.8],
+        ];
+        let offset = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let samples: Vec<Vec<f32>> = (0..200)
+            .map(|i| {
+                let t = i as f32;
+                let weights = [
+                    10.0 * (t * 0.37).sin(),
+                    3.0 * (t * 1.91).cos(),
+                    (t * 0.73).sin(),
+                ];
+                (0..6)
+                    .map(|d| offset[d] + (0..3).map(|a| weights[a] * axes[a][d]).sum::<f32>())
+                    .collect()
+            })
+            .collect();
+
+        let pca = Pca::fit(&samples, 2).unwrap();
+        assert_eq!((6, 2), (pca.input_dim(), pca.output_dim()));
+        for (component, axis) in pca.components().iter().zip(&axes) {
+            assert!(
+                (dot(component, axis).abs() - 1.0).abs() < 1e-4,
+                "{component:?}"
+            );
+        }
+        let ratio: f32 = pca.explained_variance_ratio().iter().sum();
+        assert!(ratio > 0.98 && ratio < 1.0, "{ratio}");
+        assert!(pca.explained_variance()[0] > pca.explained_variance()[1]);
+
+        // The reduced vectors keep the distances along the kept directions
+        let reduced = pca.transform_batch(&samples).unwrap();
+        assert_eq!(2, reduced[0].len());
+        let mean_first: f32 = reduced.iter().map(|r| r[0]).sum::<f32>() / reduced.len() as f32;
+        assert!(mean_first.abs() < 1e-4);
+        assert!(pca.transform(&[0.0; 5]).is_err());
+
+        let path = std::env::temp_dir().join(format!("pca-{}.json", std::process::id()));
+        pca.save(&path).unwrap();
+        let loaded = Pca::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(pca, loaded);
+
+        // Fewer samples than dimensions still gives orthonormal components
+        let pca = Pca::fit(&samples[..3], 4).unwrap();
+        for (i, a) in pca.components().iter().enumerate() {
+            for (j, b) in pca.components().iter().enumerate() {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((dot(a, b) - expected).abs() < 1e-4);
+            }
+        }
+        assert!(Pca::fit(&samples[..1], 1).is_err());
+        assert!(Pca::fit(&samples, 7).is_err());
+    }
+}
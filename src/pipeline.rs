@@ -0,0 +1,844 @@
+use crate::cancel::CancelToken;
+use crate::embedder::Embedder;
+use crate::error::{Error, Result};
+use crate::options::EmbedOptions;
+use crate::splitter::TextSplitter;
+use crate::transform::{apply_all, Transform};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+
+/// How the text to embed is pulled out of a document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Extract {
+    /// Use the text as is.
+    #[default]
+    Text,
+    /// Drop HTML tags, `<script>`/`<style>` contents and decode the common entities.
+    Html,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChunkConfig {
+    pub max_tokens: usize,
+    #[serde(default)]
+    pub overlap: usize,
+    #[serde(default)]
+    pub respect_sentences: bool,
+}
+
+/// Declarative description of an ingestion flow: extract → chunk → prefix → embed →
+/// transform, with the records handed to a [`Sink`] (the store stage).
+///
+/// Serialized as JSON, e.g.
+/// `{"extract": "html", "chunk": {"max_tokens": 256, "overlap": 32}, "prefix": "passage: ",
+///   "embed": {"normalize": true}, "transforms": ["quantize_i8"], "batch_size": 64}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    pub extract: Extract,
+    /// Split documents into chunks; `None` embeds every document whole.
+    pub chunk: Option<ChunkConfig>,
+    /// Prepended to every chunk, overriding `embed.prefix`.
+    pub prefix: Option<String>,
+    pub embed: EmbedOptions,
+    /// Run after the model's own post-processing.
+    pub transforms: Vec<Transform>,
+    /// Chunks embedded per forward pass, 32 by default. A run holds at most this many chunks
+    /// besides those of the document being read, so memory stays bounded however large the
+    /// input is.
+    pub batch_size: usize,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            extract: Extract::default(),
+            chunk: None,
+            prefix: None,
+            embed: EmbedOptions::default(),
+            transforms: Vec::new(),
+            batch_size: 32,
+        }
+    }
+}
+
+impl PipelineConfig {
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+/// An input document.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Document {
+    pub id: String,
+    pub text: String,
+    /// Carried unchanged onto every record of the document.
+    #[serde(default)]
+    pub metadata: Metadata,
+}
+
+pub type Metadata = serde_json::Map<String, serde_json::Value>;
+
+/// One embedded chunk, as handed to the store stage.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PipelineRecord {
+    pub document_id: String,
+    /// See [`chunk_id`]: re-ingesting the same text reproduces it.
+    pub chunk_id: String,
+    pub chunk_index: usize,
+    /// Byte range of the chunk in the extracted text.
+    pub range: Range<usize>,
+    /// The same range counted in characters (Unicode scalar values).
+    pub char_range: Range<usize>,
+    pub text: String,
+    pub embedding: Vec<f32>,
+    #[serde(skip_serializing_if = "Metadata::is_empty")]
+    pub metadata: Metadata,
+}
+
+/// The stable id of a chunk: 32 hex digits of the SHA-256 of its document id, byte range and
+/// text. Chunking is deterministic, so the same document text and chunking config always yield
+/// the same chunks, ranges and ids, while an edited chunk gets a new id.
+pub fn chunk_id(document_id: &str, range: &Range<usize>, text: &str) -> String {
+    Sha256::new()
+        .chain_update((document_id.len() as u64).to_le_bytes())
+        .chain_update(document_id.as_bytes())
+        .chain_update((range.start as u64).to_le_bytes())
+        .chain_update((range.end as u64).to_le_bytes())
+        .chain_update(text.as_bytes())
+        .finalize()[..16]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// The store stage of a [`Pipeline`].
+pub trait Sink {
+    fn write(&mut self, record: PipelineRecord) -> Result<()>;
+
+    /// Called once after the last record of a run, e.g. to flush buffered output.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Sink for Vec<PipelineRecord> {
+    fn write(&mut self, record: PipelineRecord) -> Result<()> {
+        self.push(record);
+        Ok(())
+    }
+}
+
+/// Writes every record as one JSON line.
+pub struct JsonlSink<W: Write>(pub W);
+
+impl<W: Write> Sink for JsonlSink<W> {
+    fn write(&mut self, record: PipelineRecord) -> Result<()> {
+        serde_json::to_writer(&mut self.0, &record)?;
+        self.0.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(self.0.flush()?)
+    }
+}
+
+/// Reads documents from JSON lines of the form `{"id": "...", "text": "..."}`, with an
+/// optional `"metadata"` object.
+pub fn read_jsonl_documents(reader: impl BufRead) -> impl Iterator<Item = Result<Document>> {
+    reader
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+}
+
+/// Reads documents from plain text, one per paragraph: a run of non-blank lines, kept with
+/// their line breaks. A document's id is the line number its paragraph starts on.
+pub fn read_text_documents(reader: impl BufRead) -> impl Iterator<Item = Result<Document>> {
+    let mut lines = reader.lines().enumerate();
+    std::iter::from_fn(move || {
+        let mut paragraph: Option<(usize, String)> = None;
+        for (index, line) in lines.by_ref() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if line.trim().is_empty() {
+                if paragraph.is_some() {
+                    break;
+                }
+                continue;
+            }
+            match &mut paragraph {
+                Some((_, text)) => {
+                    text.push('\n');
+                    text.push_str(&line);
+                }
+                None => paragraph = Some((index + 1, line)),
+            }
+        }
+        paragraph.map(|(line, text)| {
+            Ok(Document {
+                id: line.to_string(),
+                text,
+                metadata: Metadata::new(),
+            })
+        })
+    })
+}
+
+/// Which columns of a CSV/TSV file with a header row make up a [`Document`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvColumns {
+    pub id: String,
+    pub text: String,
+    /// Copied into [`Document::metadata`] as strings.
+    pub metadata: Vec<String>,
+    /// `b','` for CSV, `b'\t'` for TSV.
+    pub delimiter: u8,
+}
+
+impl CsvColumns {
+    pub fn new(id: impl Into<String>, text: impl Into<String>) -> Self {
+        CsvColumns {
+            id: id.into(),
+            text: text.into(),
+            metadata: Vec::new(),
+            delimiter: b',',
+        }
+    }
+}
+
+/// Reads documents from CSV/TSV rows. Fields may be quoted with `"` (doubled inside quotes),
+/// so texts can contain delimiters and newlines. Fails if a mapped column is not in the header.
+pub fn read_csv_documents(
+    reader: impl Read,
+    columns: &CsvColumns,
+) -> Result<impl Iterator<Item = Result<Document>>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(columns.delimiter)
+        .flexible(true)
+        .from_reader(reader);
+    let headers = reader.headers()?.clone();
+    let position = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header == name)
+            .ok_or_else(|| {
+                Error::InvalidArgument(format!("no column named {name:?} in the header"))
+            })
+    };
+    let id = position(&columns.id)?;
+    let text = position(&columns.text)?;
+    let metadata = columns
+        .metadata
+        .iter()
+        .map(|name| Ok((name.clone(), position(name)?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(reader.into_records().map(move |row| {
+        let row = row?;
+        let field = |index: usize| row.get(index).unwrap_or_default().to_string();
+        Ok(Document {
+            id: field(id),
+            text: field(text),
+            metadata: metadata
+                .iter()
+                .map(|(name, index)| (name.clone(), field(*index).into()))
+                .collect(),
+        })
+    }))
+}
+
+/// The layout of a file of documents, for [`read_documents`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DocumentFormat {
+    /// See [`read_jsonl_documents`].
+    Jsonl,
+    /// See [`read_csv_documents`].
+    Csv(CsvColumns),
+    /// See [`read_text_documents`].
+    Text,
+}
+
+impl DocumentFormat {
+    /// Guess the format from the extension of `path`: `.csv` and `.tsv` files are read with
+    /// the columns `id` and `text`, `.txt` and `.md` files as plain text and anything else as
+    /// JSON lines.
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        let extension = path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("csv") => DocumentFormat::Csv(CsvColumns::new("id", "text")),
+            Some("tsv") => DocumentFormat::Csv(CsvColumns {
+                delimiter: b'\t',
+                ..CsvColumns::new("id", "text")
+            }),
+            Some("txt" | "md") => DocumentFormat::Text,
+            _ => DocumentFormat::Jsonl,
+        }
+    }
+}
+
+/// Streams the documents of the file at `path`. The file is read as the documents are pulled,
+/// so a [`Pipeline`] can run over files far larger than memory.
+pub fn read_documents(
+    path: impl AsRef<Path>,
+    format: &DocumentFormat,
+) -> Result<Box<dyn Iterator<Item = Result<Document>>>> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(match format {
+        DocumentFormat::Jsonl => Box::new(read_jsonl_documents(reader)),
+        DocumentFormat::Csv(columns) => Box::new(read_csv_documents(reader, columns)?),
+        DocumentFormat::Text => Box::new(read_text_documents(reader)),
+    })
+}
+
+/// A SQL query against a SQLite file whose result columns make up [`Document`]s.
+#[cfg(feature = "sqlite")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqliteQuery {
+    /// Any `SELECT`, run read-only. Rows are paged through with `LIMIT`/`OFFSET`, so add an
+    /// `ORDER BY` if the database may change during a run.
+    pub sql: String,
+    pub id: String,
+    pub text: String,
+    /// Copied into [`Document::metadata`], numbers staying numbers.
+    pub metadata: Vec<String>,
+    /// Rows fetched from the database at a time.
+    pub batch_size: usize,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteQuery {
+    pub fn new(sql: impl Into<String>, id: impl Into<String>, text: impl Into<String>) -> Self {
+        SqliteQuery {
+            sql: sql.into(),
+            id: id.into(),
+            text: text.into(),
+            metadata: Vec::new(),
+            batch_size: 256,
+        }
+    }
+}
+
+/// Reads documents from the rows of a query against the SQLite file at `path`, `batch_size`
+/// rows at a time. Fails if a mapped column is not in the query result.
+#[cfg(feature = "sqlite")]
+pub fn read_sqlite_documents(
+    path: impl AsRef<std::path::Path>,
+    query: &SqliteQuery,
+) -> Result<impl Iterator<Item = Result<Document>>> {
+    if query.batch_size == 0 {
+        return Err(Error::InvalidArgument(
+            "batch size must be at least 1".to_string(),
+        ));
+    }
+    let connection = rusqlite::Connection::open_with_flags(
+        path,
+        rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    let sql = format!(
+        "SELECT * FROM ({}) LIMIT ?1 OFFSET ?2",
+        query.sql.trim().trim_end_matches(';')
+    );
+
+    let statement = connection.prepare(&sql)?;
+    let position = |name: &str| {
+        statement.column_index(name).map_err(|_| {
+            Error::InvalidArgument(format!("no column named {name:?} in the query result"))
+        })
+    };
+    let id = position(&query.id)?;
+    let text = position(&query.text)?;
+    let metadata = query
+        .metadata
+        .iter()
+        .map(|name| Ok((name.clone(), position(name)?)))
+        .collect::<Result<Vec<_>>>()?;
+    drop(statement);
+
+    Ok(SqliteRows {
+        connection,
+        sql,
+        id,
+        text,
+        metadata,
+        batch_size: query.batch_size,
+        offset: 0,
+        batch: Vec::new().into_iter(),
+        done: false,
+    })
+}
+
+#[cfg(feature = "sqlite")]
+struct SqliteRows {
+    connection: rusqlite::Connection,
+    sql: String,
+    id: usize,
+    text: usize,
+    metadata: Vec<(String, usize)>,
+    batch_size: usize,
+    offset: usize,
+    batch: std::vec::IntoIter<Document>,
+    done: bool,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteRows {
+    fn fetch_batch(&self) -> Result<Vec<Document>> {
+        let mut statement = self.connection.prepare_cached(&self.sql)?;
+        let mut rows = statement.query([self.batch_size as i64, self.offset as i64])?;
+        let mut batch = Vec::with_capacity(self.batch_size);
+        while let Some(row) = rows.next()? {
+            batch.push(Document {
+                id: sqlite_string(row.get_ref(self.id)?),
+                text: sqlite_string(row.get_ref(self.text)?),
+                metadata: self
+                    .metadata
+                    .iter()
+                    .map(|(name, index)| Ok((name.clone(), sqlite_json(row.get_ref(*index)?))))
+                    .collect::<Result<_>>()?,
+            });
+        }
+        Ok(batch)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Iterator for SqliteRows {
+    type Item = Result<Document>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(document) = self.batch.next() {
+            return Some(Ok(document));
+        }
+        if self.done {
+            return None;
+        }
+        match self.fetch_batch() {
+            Ok(batch) => {
+                // A short batch is the last one, saves running the query once more
+                self.done = batch.len() < self.batch_size;
+                self.offset += batch.len();
+                self.batch = batch.into_iter();
+                self.batch.next().map(Ok)
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_string(value: rusqlite::types::ValueRef) -> String {
+    use rusqlite::types::ValueRef;
+    match value {
+        ValueRef::Null => String::new(),
+        ValueRef::Integer(n) => n.to_string(),
+        ValueRef::Real(x) => x.to_string(),
+        ValueRef::Text(bytes) | ValueRef::Blob(bytes) => {
+            String::from_utf8_lossy(bytes).into_owned()
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_json(value: rusqlite::types::ValueRef) -> serde_json::Value {
+    use rusqlite::types::ValueRef;
+    match value {
+        ValueRef::Null => serde_json::Value::Null,
+        ValueRef::Integer(n) => n.into(),
+        ValueRef::Real(x) => x.into(),
+        ValueRef::Text(_) | ValueRef::Blob(_) => sqlite_string(value).into(),
+    }
+}
+
+pub struct Pipeline<'a> {
+    embedder: &'a Embedder,
+    config: PipelineConfig,
+    splitter: Option<TextSplitter>,
+    options: EmbedOptions,
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new(embedder: &'a Embedder, config: PipelineConfig) -> Result<Self> {
+        if config.batch_size == 0 {
+            return Err(Error::InvalidArgument(
+                "batch size must be at least 1".to_string(),
+            ));
+        }
+        let splitter = match config.chunk {
+            Some(chunk) => Some(
+                TextSplitter::new(embedder.tokenizer(), chunk.max_tokens)?
+                    .with_overlap(chunk.overlap)?
+                    .with_sentence_boundaries(chunk.respect_sentences),
+            ),
+            None => None,
+        };
+        let mut options = config.embed.clone();
+        if config.prefix.is_some() {
+            options.prefix = config.prefix.clone();
+        }
+        Ok(Pipeline {
+            embedder,
+            config,
+            splitter,
+            options,
+        })
+    }
+
+    /// Run every document through the pipeline, returning the number of records stored.
+    /// Documents are pulled from `documents` as they are needed and chunks are embedded
+    /// [`PipelineConfig::batch_size`] at a time, the records of each batch reaching `sink` in
+    /// input order before the next one is read.
+    pub fn run(
+        &self,
+        documents: impl IntoIterator<Item = Result<Document>>,
+        sink: &mut dyn Sink,
+    ) -> Result<usize> {
+        self.run_cancellable(documents, sink, &CancelToken::new())
+    }
+
+    /// [`Pipeline::run`], stopping before the next document once `cancel` is cancelled. The
+    /// records of the documents before it are stored and the sink is finished as usual.
+    pub fn run_cancellable(
+        &self,
+        documents: impl IntoIterator<Item = Result<Document>>,
+        sink: &mut dyn Sink,
+        cancel: &CancelToken,
+    ) -> Result<usize> {
+        let mut batch = Vec::with_capacity(self.config.batch_size);
+        let mut written = 0;
+        for document in documents {
+            if cancel.is_cancelled() {
+                break;
+            }
+            let document = document?;
+            let text = match self.config.extract {
+                Extract::Text => document.text,
+                Extract::Html => strip_html(&document.text),
+            };
+
+            let chunks = match &self.splitter {
+                Some(splitter) => splitter
+                    .split(&text)?
+                    .into_iter()
+                    .map(|chunk| (chunk.range, chunk.text))
+                    .collect(),
+                None => vec![(0..text.len(), text.clone())],
+            };
+
+            // Chunks come in order of their start, so char offsets are counted incrementally
+            let (mut byte_offset, mut char_offset) = (0, 0);
+            let mut char_index = |byte: usize| {
+                if byte < byte_offset {
+                    (byte_offset, char_offset) = (0, 0);
+                }
+                char_offset += text[byte_offset..byte].chars().count();
+                byte_offset = byte;
+                char_offset
+            };
+            for (chunk_index, (range, chunk)) in chunks.into_iter().enumerate() {
+                let char_start = char_index(range.start);
+                let char_range = char_start..char_start + chunk.chars().count();
+                batch.push(PipelineRecord {
+                    document_id: document.id.clone(),
+                    chunk_id: chunk_id(&document.id, &range, &chunk),
+                    chunk_index,
+                    range,
+                    char_range,
+                    text: chunk,
+                    embedding: Vec::new(),
+                    metadata: document.metadata.clone(),
+                });
+                if batch.len() == self.config.batch_size {
+                    written += self.store(&mut batch, sink)?;
+                }
+            }
+        }
+        written += self.store(&mut batch, sink)?;
+        sink.finish()?;
+        Ok(written)
+    }
+
+    // Embed the records of `batch` in one pass and hand them to `sink`, emptying the batch.
+    fn store(&self, batch: &mut Vec<PipelineRecord>, sink: &mut dyn Sink) -> Result<usize> {
+        if batch.is_empty() {
+            return Ok(0);
+        }
+        let texts: Vec<&str> = batch.iter().map(|record| record.text.as_str()).collect();
+        let embeddings = self.embedder.embed_batch(&texts, &self.options)?;
+        let stored = batch.len();
+        for (mut record, embedding) in batch.drain(..).zip(embeddings) {
+            record.embedding = embedding.to_f32();
+            apply_all(&self.config.transforms, &mut record.embedding)?;
+            sink.write(record)?;
+        }
+        Ok(stored)
+    }
+}
+
+fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let opens = |tag: &str| {
+            rest.as_bytes()
+                .get(..tag.len())
+                .is_some_and(|start| start.eq_ignore_ascii_case(tag.as_bytes()))
+        };
+        let close = if opens("<script") {
+            "</script>"
+        } else if opens("<style") {
+            "</style>"
+        } else {
+            ">"
+        };
+        // Compared in place, as lowercasing the rest for every tag is quadratic in the length
+        let end = rest
+            .as_bytes()
+            .windows(close.len())
+            .position(|window| window.eq_ignore_ascii_case(close.as_bytes()));
+        match end {
+            Some(end) => {
+                rest = &rest[end + close.len()..];
+                text.push(' ');
+            }
+            None => {
+                rest = "";
+            }
+        }
+    }
+    text.push_str(rest);
+
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_html() {
+        assert_eq!(
+            "Title Fish & chips",
+            strip_html("<h1>Title</h1><script>var x = 1;</script><p>Fish &amp; chips</p>")
+        );
+        assert_eq!(
+            "a b",
+            strip_html("a<STYLE>p > b {}</Style><br/>b<SCRIPT>x</script")
+        );
+    }
+
+    #[test]
+    fn test_read_csv_documents() {
+        let input = "id\ttitle\tbody\n7\tHello\t\"tab\there, \"\"quoted\"\"\nline\"\n";
+        let mut columns = CsvColumns::new("id", "body");
+        columns.metadata.push("title".to_string());
+        columns.delimiter = b'\t';
+        let documents: Vec<Document> = read_csv_documents(input.as_bytes(), &columns)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(1, documents.len());
+        assert_eq!("7", documents[0].id);
+        assert_eq!("tab\there, \"quoted\"\nline", documents[0].text);
+        assert_eq!(Some("Hello"), documents[0].metadata["title"].as_str());
+
+        assert!(read_csv_documents(input.as_bytes(), &CsvColumns::new("id", "text")).is_err());
+    }
+
+    #[test]
+    fn test_read_text_documents() {
+        let input = "\nfirst line\nsecond line\n  \n\n\nlast\n";
+        let documents: Vec<Document> = read_text_documents(input.as_bytes())
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            vec![("2", "first line\nsecond line"), ("7", "last")],
+            documents
+                .iter()
+                .map(|d| (d.id.as_str(), d.text.as_str()))
+                .collect::<Vec<_>>()
+        );
+
+        assert_eq!(DocumentFormat::Text, DocumentFormat::from_path("notes.TXT"));
+        assert_eq!(
+            DocumentFormat::Jsonl,
+            DocumentFormat::from_path("corpus.jsonl")
+        );
+        let path = std::env::temp_dir().join(format!("documents-{}.tsv", std::process::id()));
+        std::fs::write(&path, "id\ttext\n1\tone\n2\ttwo\n").unwrap();
+        let documents: Vec<Document> = read_documents(&path, &DocumentFormat::from_path(&path))
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!("two", documents[1].text);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_read_sqlite_documents() {
+        let path = std::env::temp_dir().join(format!("notes-{}.sqlite", std::process::id()));
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT, stars REAL);
+                 INSERT INTO notes (body, stars) VALUES ('first', 4.5), ('second', NULL),
+                     ('third', 1), ('skipped', 0);",
+            )
+            .unwrap();
+        drop(connection);
+
+        let mut query = SqliteQuery::new(
+            "SELECT id, body, stars FROM notes WHERE stars IS NOT 0 ORDER BY id;",
+            "id",
+            "body",
+        );
+        query.metadata.push("stars".to_string());
+        query.batch_size = 2;
+        let documents: Vec<Document> = read_sqlite_documents(&path, &query)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            vec![("1", "first"), ("2", "second"), ("3", "third")],
+            documents
+                .iter()
+                .map(|d| (d.id.as_str(), d.text.as_str()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some(4.5), documents[0].metadata["stars"].as_f64());
+        assert!(documents[1].metadata["stars"].is_null());
+
+        assert!(read_sqlite_documents(&path, &SqliteQuery::new(query.sql, "id", "text")).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_pipeline_run() {
+        let embedder = Embedder::load(
+            "models/gte-small/config.json",
+            "models/gte-small/tokenizer.json",
+            "models/gte-small/model.safetensors",
+            false,
+        )
+        .unwrap();
+        let config = PipelineConfig::from_json(
+            r#"{"extract": "html", "chunk": {"max_tokens": 4},
+                "prefix": "passage: ", "transforms": ["normalize"]}"#,
+        )
+        .unwrap();
+        let pipeline = Pipeline::new(&embedder, config).unwrap();
+
+        let input = r#"{"id": "a", "text": "<p>one two three four five six</p>"}
+
+{"id": "b", "text": "seven"}"#;
+        let mut records = Vec::new();
+        let written = pipeline
+            .run(read_jsonl_documents(input.as_bytes()), &mut records)
+            .unwrap();
+        assert_eq!(3, written);
+        assert_eq!(
+            vec![("a", 0), ("a", 1), ("b", 0)],
+            records
+                .iter()
+                .map(|r| (r.document_id.as_str(), r.chunk_index))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!("five six", records[1].text);
+        assert_ne!(records[0].chunk_id, records[1].chunk_id);
+
+        // Re-ingesting gives the same ids; char ranges count multi-byte characters once
+        let input = r#"{"id": "c", "text": "héllo wörld ünïcode text here"}"#;
+        let mut again = Vec::new();
+        for _ in 0..2 {
+            pipeline
+                .run(read_jsonl_documents(input.as_bytes()), &mut again)
+                .unwrap();
+        }
+        let (first, second) = again.split_at(again.len() / 2);
+        assert_eq!(first, second);
+        for record in first {
+            let text = "héllo wörld ünïcode text here";
+            assert_eq!(&text[record.range.clone()], record.text);
+            let chars: String = text
+                .chars()
+                .skip(record.char_range.start)
+                .take(record.char_range.len())
+                .collect();
+            assert_eq!(chars, record.text);
+            assert_eq!(chunk_id("c", &record.range, &record.text), record.chunk_id);
+        }
+
+        // Batches span documents, without changing the records
+        let single = PipelineConfig {
+            batch_size: 1,
+            ..PipelineConfig::from_json(r#"{"chunk": {"max_tokens": 4}}"#).unwrap()
+        };
+        let batched = PipelineConfig {
+            batch_size: 2,
+            ..single.clone()
+        };
+        let input = r#"{"id": "a", "text": "one two three four five six"}
+{"id": "b", "text": "seven"}
+{"id": "c", "text": "eight nine"}"#;
+        let mut by_one = Vec::new();
+        let mut by_two = Vec::new();
+        Pipeline::new(&embedder, single)
+            .unwrap()
+            .run(read_jsonl_documents(input.as_bytes()), &mut by_one)
+            .unwrap();
+        Pipeline::new(&embedder, batched)
+            .unwrap()
+            .run(read_jsonl_documents(input.as_bytes()), &mut by_two)
+            .unwrap();
+        assert_eq!(4, by_one.len());
+        assert_eq!(4, by_two.len());
+        for (one, two) in by_one.iter().zip(&by_two) {
+            assert_eq!(one.chunk_id, two.chunk_id);
+            assert!(one
+                .embedding
+                .iter()
+                .zip(&two.embedding)
+                .all(|(a, b)| (a - b).abs() < 1e-4));
+        }
+        let empty = PipelineConfig {
+            batch_size: 0,
+            ..PipelineConfig::default()
+        };
+        assert!(Pipeline::new(&embedder, empty).is_err());
+
+        let expected = embedder.embed("passage: five six").unwrap();
+        let norm = expected.iter().map(|x| x * x).sum::<f32>().sqrt();
+        assert!(records[1]
+            .embedding
+            .iter()
+            .zip(&expected)
+            .all(|(a, b)| (a - b / norm).abs() < 1e-5));
+    }
+}
@@ -0,0 +1,214 @@
+use crate::error::{Error, Result};
+use crate::Instant;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::Duration;
+
+/// How much of the machine embedding may use, process-wide (see [`PowerMode::apply`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PowerMode {
+    /// Use every core.
+    #[default]
+    Performance,
+    /// Run forward passes and batches on at most `max_threads` threads, trading throughput
+    /// for less heat and fan noise.
+    LowPower { max_threads: usize },
+    /// [`PowerMode::LowPower`] while the machine runs on battery (checked on Linux and
+    /// macOS), [`PowerMode::Performance`] otherwise.
+    Auto { max_threads: usize },
+}
+
+struct PowerState {
+    mode: PowerMode,
+    pool: Option<Arc<ThreadPool>>,
+    // The `set_thread_limit` pool, used whenever low power is not capping harder.
+    limited_pool: Option<Arc<ThreadPool>>,
+}
+
+static STATE: RwLock<PowerState> = RwLock::new(PowerState {
+    mode: PowerMode::Performance,
+    pool: None,
+    limited_pool: None,
+});
+static BATTERY: Mutex<Option<(Instant, bool)>> = Mutex::new(None);
+static SERIAL: OnceLock<ThreadPool> = OnceLock::new();
+
+// Checking the power source can mean spawning a process, so the answer is reused for a while.
+const BATTERY_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+impl PowerMode {
+    /// Make this the mode of every embedding call in the process.
+    pub fn apply(self) -> Result<()> {
+        let pool = match self {
+            PowerMode::Performance => None,
+            PowerMode::LowPower { max_threads } | PowerMode::Auto { max_threads } => {
+                if max_threads == 0 {
+                    return Err(Error::InvalidArgument(
+                        "low power mode needs at least one thread".to_string(),
+                    ));
+                }
+                Some(build_pool(max_threads, "embed-low-power")?)
+            }
+        };
+        let mut state = STATE.write().unwrap();
+        state.mode = self;
+        state.pool = pool;
+        Ok(())
+    }
+
+    pub fn current() -> PowerMode {
+        STATE.read().unwrap().mode
+    }
+}
+
+/// Cap the threads every embedding call and batch may use, so a host running its own thread
+/// pools is not oversubscribed; `0` removes the cap. [`PowerMode::LowPower`] still applies
+/// while it is in effect and caps lower.
+pub fn set_thread_limit(num_threads: usize) -> Result<()> {
+    let pool = match num_threads {
+        0 => None,
+        n => Some(build_pool(n, "embed-worker")?),
+    };
+    STATE.write().unwrap().limited_pool = pool;
+    Ok(())
+}
+
+pub fn thread_limit() -> Option<usize> {
+    let state = STATE.read().unwrap();
+    state
+        .limited_pool
+        .as_ref()
+        .map(|pool| pool.current_num_threads())
+}
+
+fn build_pool(num_threads: usize, name: &'static str) -> Result<Arc<ThreadPool>> {
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .thread_name(move |i| format!("{name}-{i}"))
+        .build()
+        .map_err(|e| Error::InvalidArgument(e.to_string()))?;
+    Ok(Arc::new(pool))
+}
+
+/// The pool embedding runs on, if a thread limit or low power is currently in effect.
+pub(crate) fn thread_pool() -> Option<Arc<ThreadPool>> {
+    let state = STATE.read().unwrap();
+    let low_power = match state.mode {
+        PowerMode::Performance => None,
+        PowerMode::LowPower { .. } => state.pool.clone(),
+        PowerMode::Auto { .. } => on_battery().then(|| state.pool.clone()).flatten(),
+    };
+    match (low_power, &state.limited_pool) {
+        (Some(low_power), Some(limited))
+            if limited.current_num_threads() < low_power.current_num_threads() =>
+        {
+            Some(Arc::clone(limited))
+        }
+        (Some(low_power), _) => Some(low_power),
+        (None, limited) => limited.clone(),
+    }
+}
+
+/// Run `f` within the current thread cap, if there is one.
+pub(crate) fn install<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    // Deterministic calls stay on their own thread whatever the cap
+    if SERIAL
+        .get()
+        .is_some_and(|pool| pool.current_thread_index().is_some())
+    {
+        return f();
+    }
+    match thread_pool() {
+        Some(pool) => pool.install(f),
+        None => f(),
+    }
+}
+
+/// Run `f` on a thread of its own, whatever the power mode and thread limit, so the operations
+/// of a forward pass are split and summed in the same order every time.
+pub(crate) fn serial<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    SERIAL
+        .get_or_init(|| {
+            ThreadPoolBuilder::new()
+                .num_threads(1)
+                .thread_name(|_| "embed-deterministic".to_string())
+                .build()
+                .expect("failed to start the deterministic embedding thread")
+        })
+        .install(f)
+}
+
+fn on_battery() -> bool {
+    let mut cached = BATTERY.lock().unwrap();
+    match *cached {
+        Some((checked, on_battery)) if checked.elapsed() < BATTERY_CHECK_INTERVAL => on_battery,
+        _ => {
+            let on_battery = detect_on_battery();
+            *cached = Some((Instant::now(), on_battery));
+            on_battery
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn detect_on_battery() -> bool {
+    let Ok(supplies) = std::fs::read_dir("/sys/class/power_supply") else {
+        return false;
+    };
+    let read = |path: std::path::PathBuf| std::fs::read_to_string(path).unwrap_or_default();
+
+    let mut discharging = false;
+    for supply in supplies.flatten() {
+        let path = supply.path();
+        match read(path.join("type")).trim() {
+            "Mains" if read(path.join("online")).trim() == "1" => return false,
+            "Battery" => discharging |= read(path.join("status")).trim() == "Discharging",
+            _ => {}
+        }
+    }
+    discharging
+}
+
+#[cfg(target_os = "macos")]
+fn detect_on_battery() -> bool {
+    std::process::Command::new("pmset")
+        .args(["-g", "batt"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).contains("'Battery Power'"))
+        .unwrap_or(false)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn detect_on_battery() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thread_caps() {
+        PowerMode::LowPower { max_threads: 2 }.apply().unwrap();
+        assert_eq!(2, install(rayon::current_num_threads));
+        assert!(PowerMode::LowPower { max_threads: 0 }.apply().is_err());
+
+        // The lower of the two caps wins
+        set_thread_limit(3).unwrap();
+        assert_eq!(Some(3), thread_limit());
+        assert_eq!(2, install(rayon::current_num_threads));
+        set_thread_limit(1).unwrap();
+        assert_eq!(1, install(rayon::current_num_threads));
+
+        PowerMode::Performance.apply().unwrap();
+        assert_eq!(PowerMode::Performance, PowerMode::current());
+        assert_eq!(1, install(rayon::current_num_threads));
+
+        set_thread_limit(3).unwrap();
+        assert_eq!(1, serial(|| install(rayon::current_num_threads)));
+
+        set_thread_limit(0).unwrap();
+        assert_eq!(None, thread_limit());
+        assert_eq!(None, thread_pool().map(|pool| pool.current_num_threads()));
+    }
+}
@@ -0,0 +1,151 @@
+use crate::cancel::CancelToken;
+use crate::error::Result;
+use crate::provider::EmbeddingProvider;
+use std::cell::Cell;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Receives `(completed, total)` as a batch job advances: texts embedded by
+/// [`Embedder::embed_batch`](crate::Embedder::embed_batch) and
+/// [`Embedder::embed_batch_parallel`](crate::Embedder::embed_batch_parallel), documents
+/// indexed by a [`Corpus`](crate::Corpus). Every job reports `(0, total)` when it starts and
+/// `(total, total)` when it completes; a job that fails stops reporting.
+pub type ProgressHandler = Arc<dyn Fn(usize, usize) + Send + Sync>;
+
+// Texts embedded per step while a job reports progress.
+pub(crate) const PROGRESS_STEP: usize = 32;
+
+static HANDLER: RwLock<Option<ProgressHandler>> = RwLock::new(None);
+
+thread_local! {
+    // Whether a job is running on this thread, whose steps the nested jobs are part of.
+    static IN_JOB: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Report the progress of batch jobs to `handler`, or with `None` stop reporting. Jobs started
+/// within another one, such as the embedding of a corpus's documents, are reported as part of
+/// it. The handler runs on the thread that advanced the job, a worker thread for parallel
+/// batches.
+pub fn set_progress_handler(handler: Option<ProgressHandler>) {
+    *HANDLER.write().unwrap() = handler;
+}
+
+// A running job. Inactive when no handler is set or within another job.
+pub(crate) struct Progress {
+    handler: Option<ProgressHandler>,
+    total: usize,
+    completed: Mutex<usize>,
+}
+
+impl Progress {
+    pub(crate) fn start(total: usize) -> Self {
+        let handler = match IN_JOB.with(Cell::get) {
+            true => None,
+            false => HANDLER.read().unwrap().clone(),
+        };
+        if let Some(handler) = &handler {
+            IN_JOB.with(|in_job| in_job.set(true));
+            handler(0, total);
+        }
+        Progress {
+            handler,
+            total,
+            completed: Mutex::new(0),
+        }
+    }
+
+    /// Whether updates reach a handler, so the job is worth splitting into steps.
+    pub(crate) fn is_active(&self) -> bool {
+        self.handler.is_some()
+    }
+
+    /// Run `f` on another thread as part of this job, so the jobs it starts are not reported
+    /// on their own.
+    pub(crate) fn part<T>(&self, f: impl FnOnce() -> T) -> T {
+        let outer = IN_JOB.with(|in_job| in_job.replace(true));
+        let result = f();
+        IN_JOB.with(|in_job| in_job.set(outer));
+        result
+    }
+
+    pub(crate) fn advance(&self, steps: usize) {
+        if let Some(handler) = &self.handler {
+            // Held while reporting so updates from several threads arrive in order
+            let mut completed = self.completed.lock().unwrap();
+            *completed = (*completed + steps).min(self.total);
+            handler(*completed, self.total);
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.handler.is_some() {
+            IN_JOB.with(|in_job| in_job.set(false));
+        }
+    }
+}
+
+/// Embed `texts` with `provider`, reporting to `progress` after every step of
+/// [`PROGRESS_STEP`] texts, or as one batch if nothing needs the steps. Once `cancel` is
+/// cancelled no further step starts, and the embeddings of the texts before it are returned.
+pub(crate) fn embed_in_steps(
+    provider: &dyn EmbeddingProvider,
+    texts: &[&str],
+    progress: &Progress,
+    cancel: Option<&CancelToken>,
+) -> Result<Vec<Vec<f32>>> {
+    if !progress.is_active() && cancel.is_none() {
+        return provider.embed_batch(texts);
+    }
+    let mut embeddings = Vec::with_capacity(texts.len());
+    for step in texts.chunks(PROGRESS_STEP) {
+        if cancel.is_some_and(CancelToken::is_cancelled) {
+            break;
+        }
+        embeddings.extend(provider.embed_batch(step)?);
+        progress.advance(step.len());
+    }
+    Ok(embeddings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Corpus, MockProvider};
+
+    #[test]
+    fn test_progress() {
+        // Other tests run jobs concurrently; only this one indexes 70 documents
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&updates);
+        set_progress_handler(Some(Arc::new(move |completed, total| {
+            if total == 70 {
+                recorded.lock().unwrap().push(completed);
+            }
+        })));
+
+        let corpus = Corpus::new(Arc::new(MockProvider::new(8)));
+        let documents: Vec<(String, String)> = (0..70)
+            .map(|i| (i.to_string(), format!("document {i}")))
+            .collect();
+        corpus.add_batch(&documents).unwrap();
+        assert_eq!(70, corpus.len());
+        assert_eq!(vec![0, 32, 64, 70], *updates.lock().unwrap());
+
+        // Jobs within a job are part of it
+        updates.lock().unwrap().clear();
+        let outer = Progress::start(70);
+        assert!(outer.is_active());
+        let inner = Progress::start(70);
+        assert!(!inner.is_active());
+        inner.advance(70);
+        drop(inner);
+        outer.advance(100);
+        drop(outer);
+        assert!(Progress::start(70).is_active());
+        assert_eq!(vec![0, 70, 0], *updates.lock().unwrap());
+
+        set_progress_handler(None);
+        assert!(!Progress::start(70).is_active());
+    }
+}
@@ -0,0 +1,406 @@
+use crate::batcher::MicroBatcher;
+use crate::embedder::{normalize, Embedder};
+use crate::error::{Error, Result};
+use crate::options::{EmbedOptions, Embedding};
+use crate::Instant;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Anything that turns texts into embeddings: a local [`Embedder`], a [`MicroBatcher`], a
+/// remote service or a [`Fallback`] chain of them, so hosts can swap one for another.
+pub trait EmbeddingProvider: Send + Sync {
+    /// Short description for logs and errors, e.g. `"local"` or the remote URL.
+    fn name(&self) -> &str;
+
+    /// Embed every text, returning the embeddings in input order.
+    fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>>;
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        self.embed_batch(&[text])?
+            .pop()
+            .ok_or_else(|| Error::InvalidArgument(format!("{} returned nothing", self.name())))
+    }
+}
+
+impl EmbeddingProvider for Embedder {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        let embeddings = Embedder::embed_batch(self, texts, &EmbedOptions::default())?;
+        Ok(embeddings.iter().map(Embedding::to_f32).collect())
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        Embedder::embed(self, text)
+    }
+}
+
+impl EmbeddingProvider for MicroBatcher {
+    fn name(&self) -> &str {
+        "batcher"
+    }
+
+    fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        MicroBatcher::embed_batch(self, texts)
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        MicroBatcher::embed(self, text)
+    }
+}
+
+/// A model-free provider for tests and development: every text maps to a fixed unit vector
+/// derived from its SHA-256, so equal texts get equal embeddings and nothing is loaded.
+pub struct MockProvider {
+    dim: usize,
+}
+
+impl MockProvider {
+    pub fn new(dim: usize) -> Self {
+        MockProvider { dim }
+    }
+}
+
+impl EmbeddingProvider for MockProvider {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        Ok(texts
+            .iter()
+            .map(|text| {
+                let mut embedding: Vec<f32> = (0..self.dim)
+                    .map(|i| {
+                        let digest = Sha256::new()
+                            .chain_update((i as u64).to_le_bytes())
+                            .chain_update(text.as_bytes())
+                            .finalize();
+                        f32::from(digest[0]) - 127.5
+                    })
+                    .collect();
+                normalize(&mut embedding);
+                embedding
+            })
+            .collect())
+    }
+}
+
+/// Tries its providers in order and returns the first success, e.g. a local model first and
+/// a remote endpoint when it fails. The last provider's error is returned if all fail.
+pub struct Fallback {
+    name: String,
+    providers: Vec<Arc<dyn EmbeddingProvider>>,
+}
+
+impl Fallback {
+    pub fn new(providers: Vec<Arc<dyn EmbeddingProvider>>) -> Result<Self> {
+        if providers.is_empty() {
+            return Err(Error::InvalidArgument(
+                "a fallback needs at least one provider".to_string(),
+            ));
+        }
+        let names: Vec<&str> = providers.iter().map(|provider| provider.name()).collect();
+        Ok(Fallback {
+            name: names.join(" -> "),
+            providers,
+        })
+    }
+
+    fn first_success<T>(&self, call: impl Fn(&dyn EmbeddingProvider) -> Result<T>) -> Result<T> {
+        let (last, rest) = self.providers.split_last().unwrap();
+        for provider in rest {
+            match call(provider.as_ref()) {
+                Ok(value) => return Ok(value),
+                Err(e) => tracing::warn!(
+                    provider = provider.name(),
+                    error = %e,
+                    "embedding failed, falling back"
+                ),
+            }
+        }
+        call(last.as_ref())
+    }
+}
+
+impl EmbeddingProvider for Fallback {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        self.first_success(|provider| provider.embed_batch(texts))
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        self.first_success(|provider| provider.embed(text))
+    }
+}
+
+/// Calls and texts a [`SizeRouter`] route has served, and how long they took.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct RouteStats {
+    pub calls: u64,
+    pub texts: u64,
+    pub errors: u64,
+    pub total_us: u64,
+}
+
+/// [`RouteStats`] of both routes of a [`SizeRouter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct RouterStats {
+    pub small: RouteStats,
+    pub large: RouteStats,
+}
+
+#[derive(Default)]
+struct RouteCounters {
+    calls: AtomicU64,
+    texts: AtomicU64,
+    errors: AtomicU64,
+    total_us: AtomicU64,
+}
+
+impl RouteCounters {
+    fn record<T>(&self, texts: usize, start: Instant, result: &Result<T>) {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        self.texts.fetch_add(texts as u64, Ordering::Relaxed);
+        if result.is_err() {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        let us = start.elapsed().as_micros() as u64;
+        self.total_us.fetch_add(us, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> RouteStats {
+        RouteStats {
+            calls: self.calls.load(Ordering::Relaxed),
+            texts: self.texts.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            total_us: self.total_us.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Sends small calls to one provider and large ones to another, e.g. interactive queries to
+/// the local model and bulk jobs to a remote endpoint, or the reverse.
+///
+/// A call is small when it has at most `max_small_texts` texts totalling at most
+/// `max_small_bytes` bytes.
+pub struct SizeRouter {
+    name: String,
+    small: Arc<dyn EmbeddingProvider>,
+    large: Arc<dyn EmbeddingProvider>,
+    max_small_texts: usize,
+    max_small_bytes: usize,
+    small_stats: RouteCounters,
+    large_stats: RouteCounters,
+}
+
+impl SizeRouter {
+    pub fn new(
+        small: Arc<dyn EmbeddingProvider>,
+        large: Arc<dyn EmbeddingProvider>,
+        max_small_texts: usize,
+        max_small_bytes: usize,
+    ) -> Self {
+        SizeRouter {
+            name: format!("{} | {}", small.name(), large.name()),
+            small,
+            large,
+            max_small_texts,
+            max_small_bytes,
+            small_stats: RouteCounters::default(),
+            large_stats: RouteCounters::default(),
+        }
+    }
+
+    pub fn stats(&self) -> RouterStats {
+        RouterStats {
+            small: self.small_stats.snapshot(),
+            large: self.large_stats.snapshot(),
+        }
+    }
+
+    fn route(&self, texts: &[&str]) -> (&dyn EmbeddingProvider, &RouteCounters) {
+        let bytes: usize = texts.iter().map(|text| text.len()).sum();
+        if texts.len() <= self.max_small_texts && bytes <= self.max_small_bytes {
+            (self.small.as_ref(), &self.small_stats)
+        } else {
+            (self.large.as_ref(), &self.large_stats)
+        }
+    }
+}
+
+impl EmbeddingProvider for SizeRouter {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        let start = Instant::now();
+        let (provider, stats) = self.route(texts);
+        let result = provider.embed_batch(texts);
+        stats.record(texts.len(), start, &result);
+        result
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let start = Instant::now();
+        let (provider, stats) = self.route(&[text]);
+        let result = provider.embed(text);
+        stats.record(1, start, &result);
+        result
+    }
+}
+
+/// An endpoint speaking the OpenAI embeddings API (`POST {base_url}/embeddings`), such as
+/// OpenAI itself or a self-hosted server.
+#[cfg(feature = "remote")]
+pub struct OpenAiProvider {
+    base_url: String,
+    model: String,
+    api_key: Option<String>,
+    agent: ureq::Agent,
+}
+
+#[cfg(feature = "remote")]
+impl OpenAiProvider {
+    /// `base_url` without the `/embeddings` suffix, e.g. `https://api.openai.com/v1`.
+    pub fn new(base_url: impl Into<String>, model: impl Into<String>) -> Self {
+        OpenAiProvider {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            model: model.into(),
+            api_key: None,
+            agent: ureq::AgentBuilder::new()
+                .timeout(std::time::Duration::from_secs(60))
+                .build(),
+        }
+    }
+
+    /// Sent as a bearer token.
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+}
+
+#[cfg(feature = "remote")]
+impl EmbeddingProvider for OpenAiProvider {
+    fn name(&self) -> &str {
+        &self.base_url
+    }
+
+    fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        let body = serde_json::json!({ "model": self.model, "input": texts });
+        let mut request = self
+            .agent
+            .post(&format!("{}/embeddings", self.base_url))
+            .set("Content-Type", "application/json");
+        if let Some(api_key) = &self.api_key {
+            request = request.set("Authorization", &format!("Bearer {api_key}"));
+        }
+        let response = request
+            .send_string(&body.to_string())
+            .map_err(|e| Error::Remote(format!("{}: {e}", self.base_url)))?
+            .into_string()?;
+        parse_openai_response(&response, texts.len())
+    }
+}
+
+// The `data` entries carry their input's index and need not come back in order.
+#[cfg(feature = "remote")]
+fn parse_openai_response(json: &str, expected: usize) -> Result<Vec<Vec<f32>>> {
+    #[derive(serde::Deserialize)]
+    struct Response {
+        data: Vec<Item>,
+    }
+    #[derive(serde::Deserialize)]
+    struct Item {
+        index: usize,
+        embedding: Vec<f32>,
+    }
+
+    let mut data = serde_json::from_str::<Response>(json)?.data;
+    data.sort_by_key(|item| item.index);
+    if data.len() != expected || data.iter().enumerate().any(|(i, item)| item.index != i) {
+        return Err(Error::Remote(format!(
+            "expected embeddings for inputs 0..{expected}, got indices {:?}",
+            data.iter().map(|item| item.index).collect::<Vec<_>>()
+        )));
+    }
+    Ok(data.into_iter().map(|item| item.embedding).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Failing;
+
+    impl EmbeddingProvider for Failing {
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        fn embed_batch(&self, _texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+            Err(Error::Batch("unavailable".to_string()))
+        }
+    }
+
+    #[test]
+    fn test_fallback() {
+        let mock = MockProvider::new(16);
+        let a = mock.embed("a").unwrap();
+        assert_eq!(16, a.len());
+        assert_eq!(a, mock.embed("a").unwrap());
+        assert_ne!(a, mock.embed("b").unwrap());
+
+        let fallback = Fallback::new(vec![Arc::new(Failing), Arc::new(mock)]).unwrap();
+        assert_eq!("failing -> mock", fallback.name());
+        assert_eq!(a, fallback.embed("a").unwrap());
+        assert_eq!(2, fallback.embed_batch(&["a", "b"]).unwrap().len());
+
+        let failing = Fallback::new(vec![Arc::new(Failing)]).unwrap();
+        assert!(matches!(failing.embed("a"), Err(Error::Batch(_))));
+        assert!(Fallback::new(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_size_router() {
+        let router = SizeRouter::new(Arc::new(MockProvider::new(8)), Arc::new(Failing), 2, 16);
+        assert_eq!("mock | failing", router.name());
+        assert_eq!(8, router.embed("short query").unwrap().len());
+        assert_eq!(2, router.embed_batch(&["a", "b"]).unwrap().len());
+        assert!(router.embed_batch(&["a", "b", "c"]).is_err());
+        assert!(router.embed("a query longer than sixteen bytes").is_err());
+
+        let stats = router.stats();
+        assert_eq!(
+            (2, 3, 0),
+            (stats.small.calls, stats.small.texts, stats.small.errors)
+        );
+        assert_eq!(
+            (2, 4, 2),
+            (stats.large.calls, stats.large.texts, stats.large.errors)
+        );
+    }
+
+    #[cfg(feature = "remote")]
+    #[test]
+    fn test_parse_openai_response() {
+        let json = r#"{"object": "list", "data": [
+            {"object": "embedding", "index": 1, "embedding": [0.5, 0.5]},
+            {"object": "embedding", "index": 0, "embedding": [1.0, 0.0]}
+        ], "model": "m"}"#;
+        assert_eq!(
+            vec![vec![1.0, 0.0], vec![0.5, 0.5]],
+            parse_openai_response(json, 2).unwrap()
+        );
+        assert!(parse_openai_response(json, 3).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+// The #[pymethods] expansion converts `PyErr` into itself for every `PyResult` method.
+#![allow(clippy::useless_conversion)]
+
+use crate::device::DeviceKind;
+use crate::embedder::Embedder;
+use crate::error::Error;
+use crate::options::{EmbedOptions, Embedding};
+use numpy::IntoPyArray;
+use pyo3::exceptions::{PyIOError, PyRuntimeError, PyTimeoutError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::path::PathBuf;
+
+impl From<Error> for PyErr {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(_) => PyIOError::new_err(e.to_string()),
+            Error::Json(_)
+            | Error::InvalidLayer(_)
+            | Error::InvalidArgument(_)
+            | Error::EmptyInput => PyValueError::new_err(e.to_string()),
+            Error::Timeout => PyTimeoutError::new_err(e.to_string()),
+            _ => PyRuntimeError::new_err(e.to_string()),
+        }
+    }
+}
+
+/// A BERT embedding model: `Embedder(config_path, tokenizer_path, weights_path,
+/// approximate_gelu=False, device="cpu")`, or `Embedder.from_dir(path)`. The device may be
+/// `"cuda:N"`, `"metal:N"` or `"auto"` for the best one available.
+///
+/// Keyword arguments to `embed` and `embed_batch` are the JSON embed options, e.g.
+/// `embed(text, pooling="cls", normalize=True, dtype="f16")`. Embedding releases the GIL.
+#[pyclass(name = "Embedder", module = "rust_embedding_lib", frozen)]
+struct PyEmbedder {
+    embedder: Embedder,
+}
+
+#[pymethods]
+impl PyEmbedder {
+    #[new]
+    #[pyo3(signature = (
+        config_path, tokenizer_path, weights_path, approximate_gelu = false, device = "cpu"
+    ))]
+    fn new(
+        py: Python<'_>,
+        config_path: PathBuf,
+        tokenizer_path: PathBuf,
+        weights_path: PathBuf,
+        approximate_gelu: bool,
+        device: &str,
+    ) -> PyResult<Self> {
+        let device: DeviceKind = device.parse()?;
+        let embedder = py.allow_threads(|| {
+            Embedder::load_on_device(
+                config_path,
+                tokenizer_path,
+                weights_path,
+                approximate_gelu,
+                device,
+            )
+        })?;
+        Ok(PyEmbedder { embedder })
+    }
+
+    /// Load `config.json`, `tokenizer.json` and `model.safetensors` from a model directory,
+    /// e.g. a Hugging Face hub snapshot.
+    #[staticmethod]
+    #[pyo3(signature = (path, approximate_gelu = false, device = "cpu"))]
+    fn from_dir(
+        py: Python<'_>,
+        path: PathBuf,
+        approximate_gelu: bool,
+        device: &str,
+    ) -> PyResult<Self> {
+        PyEmbedder::new(
+            py,
+            path.join("config.json"),
+            path.join("tokenizer.json"),
+            path.join("model.safetensors"),
+            approximate_gelu,
+            device,
+        )
+    }
+
+    /// The device the model runs on, e.g. `"cuda:0"`; the one picked for `"auto"`.
+    #[getter]
+    fn device(&self) -> String {
+        self.embedder.device().to_string()
+    }
+
+    /// Length of the default embedding.
+    #[getter]
+    fn dim(&self) -> usize {
+        self.embedder.embedding_dim(Default::default())
+    }
+
+    #[pyo3(signature = (text, add_special_tokens = true))]
+    fn count_tokens(&self, text: &str, add_special_tokens: bool) -> PyResult<usize> {
+        Ok(self.embedder.count_tokens(text, add_special_tokens)?)
+    }
+
+    /// Embed one text as a 1-D numpy array.
+    #[pyo3(signature = (text, **options))]
+    fn embed(
+        &self,
+        py: Python<'_>,
+        text: &str,
+        options: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<PyObject> {
+        let options = embed_options(py, options)?;
+        let embedding = py.allow_threads(|| self.embedder.embed_with_options(text, &options))?;
+        Ok(to_numpy(py, embedding))
+    }
+
+    /// Embed several texts in one padded forward pass, as a `(len(texts), dim)` numpy array.
+    #[pyo3(signature = (texts, **options))]
+    fn embed_batch(
+        &self,
+        py: Python<'_>,
+        texts: Vec<String>,
+        options: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<PyObject> {
+        let options = embed_options(py, options)?;
+        let embeddings = py.allow_threads(|| self.embedder.embed_batch(&texts, &options))?;
+        let dim = embeddings.first().map_or_else(
+            || self.embedder.embedding_dim(options.layers),
+            Embedding::len,
+        );
+        // Every element type converts to f32 and back exactly, so the rows can be joined there
+        let values = embeddings.iter().flat_map(Embedding::to_f32).collect();
+        let matrix = to_numpy(py, Embedding::from_f32(values, options.dtype));
+        Ok(matrix
+            .call_method1(py, "reshape", ((embeddings.len(), dim),))?
+            .into_any())
+    }
+}
+
+// Keyword arguments go through their JSON form, so they match the FFI's options exactly.
+fn embed_options(py: Python<'_>, options: Option<&Bound<'_, PyDict>>) -> PyResult<EmbedOptions> {
+    let Some(options) = options else {
+        return Ok(EmbedOptions::default());
+    };
+    let json: String = py
+        .import_bound("json")?
+        .call_method1("dumps", (options,))?
+        .extract()?;
+    Ok(EmbedOptions::from_json(&json).map_err(Error::from)?)
+}
+
+fn to_numpy(py: Python<'_>, embedding: Embedding) -> PyObject {
+    match embedding {
+        Embedding::F32(v) => v.into_pyarray_bound(py).into_any().unbind(),
+        Embedding::F16(v) => v.into_pyarray_bound(py).into_any().unbind(),
+        Embedding::F64(v) => v.into_pyarray_bound(py).into_any().unbind(),
+    }
+}
+
+#[pymodule]
+fn rust_embedding_lib(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyEmbedder>()
+}
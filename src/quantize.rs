@@ -0,0 +1,241 @@
+use crate::bert::QuantizedWeights;
+use crate::embedder::{is_pytorch, pytorch_name, safetensors_shards};
+use crate::error::{Error, Result};
+use candle::quantized::{gguf_file, GgmlDType, QTensor};
+use candle::{DType, Device, Tensor};
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// How [`quantize_model`] stores the weights of the encoder's linear layers. Both formats
+/// quantize blocks of 32 values with one scale per block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuantType {
+    /// 8-bit values, about a quarter of the f32 size with embeddings very close to the
+    /// original model's.
+    #[default]
+    Q8_0,
+    /// 4-bit values, about an eighth of the f32 size at a noticeable cost in accuracy.
+    Q4_0,
+}
+
+impl QuantType {
+    fn ggml(self) -> GgmlDType {
+        match self {
+            QuantType::Q8_0 => GgmlDType::Q8_0,
+            QuantType::Q4_0 => GgmlDType::Q4_0,
+        }
+    }
+}
+
+/// Parses `q8_0` or `q4_0`.
+impl FromStr for QuantType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "q8_0" => Ok(QuantType::Q8_0),
+            "q4_0" => Ok(QuantType::Q4_0),
+            _ => Err(Error::InvalidArgument(format!(
+                "invalid quantization {s:?}, expected q8_0 or q4_0"
+            ))),
+        }
+    }
+}
+
+impl Serialize for QuantType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl fmt::Display for QuantType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QuantType::Q8_0 => write!(f, "q8_0"),
+            QuantType::Q4_0 => write!(f, "q4_0"),
+        }
+    }
+}
+
+/// What [`quantize_model`] wrote.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct QuantizeReport {
+    pub quant_type: QuantType,
+    /// Tensors in the output, quantized or not.
+    pub tensors: usize,
+    /// Tensors stored quantized.
+    pub quantized: usize,
+    /// Bytes of the tensor data read and written, without file headers.
+    pub original_bytes: usize,
+    pub quantized_bytes: usize,
+}
+
+// The metadata key recording the quantization of a converted model.
+const QUANTIZATION_KEY: &str = "rust_embedding_lib.quantization";
+
+/// Convert full-precision weights, any file [`Embedder::load`](crate::Embedder::load) reads,
+/// into a GGUF file with the weights of the encoder's linear layers quantized to `quant_type`.
+/// Loading the output with [`Embedder::load`](crate::Embedder::load) on the CPU runs those
+/// layers as quantized matmuls, so the model takes a fraction of the memory; on other devices
+/// the weights are dequantized as they load.
+///
+/// Embeddings, LayerNorms, biases and any weight whose input size is not a multiple of the
+/// block size are kept as f16 if they are f16 in the input, and as f32 otherwise.
+pub fn quantize_model(
+    weights_path: impl AsRef<Path>,
+    output_path: impl AsRef<Path>,
+    quant_type: QuantType,
+) -> Result<QuantizeReport> {
+    let weights_path = weights_path.as_ref();
+    let mut tensors: Vec<(String, Tensor)> = match is_pytorch(weights_path) {
+        true => candle::pickle::read_all(weights_path)?
+            .into_iter()
+            .map(|(name, tensor)| (pytorch_name(name), tensor))
+            .collect(),
+        false => {
+            let mut tensors = Vec::new();
+            for shard in safetensors_shards(weights_path)? {
+                tensors.extend(candle::safetensors::load(shard, &Device::Cpu)?);
+            }
+            tensors
+        }
+    };
+    // A stable order, so the same checkpoint always converts to the same file
+    tensors.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+    let dtype = quant_type.ggml();
+    let mut original_bytes = 0;
+    let mut quantized = Vec::with_capacity(tensors.len());
+    for (name, tensor) in &tensors {
+        original_bytes += tensor.elem_count() * tensor.dtype().size_in_bytes();
+        let tensor_dtype = if is_quantizable(name, tensor, dtype) {
+            dtype
+        } else if tensor.dtype() == DType::F16 {
+            GgmlDType::F16
+        } else {
+            GgmlDType::F32
+        };
+        quantized.push((name.as_str(), QTensor::quantize(tensor, tensor_dtype)?));
+    }
+
+    let quantization = gguf_file::Value::String(quant_type.to_string());
+    let architecture = gguf_file::Value::String("bert".to_string());
+    let mut writer = BufWriter::new(File::create(output_path)?);
+    gguf_file::write(
+        &mut writer,
+        &[
+            ("general.architecture", &architecture),
+            (QUANTIZATION_KEY, &quantization),
+        ],
+        &quantized
+            .iter()
+            .map(|(name, tensor)| (*name, tensor))
+            .collect::<Vec<_>>(),
+    )?;
+    std::io::Write::flush(&mut writer)?;
+
+    Ok(QuantizeReport {
+        quant_type,
+        tensors: quantized.len(),
+        quantized: quantized
+            .iter()
+            .filter(|(_, tensor)| tensor.dtype() == dtype)
+            .count(),
+        original_bytes,
+        quantized_bytes: quantized
+            .iter()
+            .map(|(_, tensor)| tensor.storage_size_in_bytes())
+            .sum(),
+    })
+}
+
+// The weight matrices of the encoder layers' linear layers, whose rows split into blocks.
+fn is_quantizable(name: &str, tensor: &Tensor, dtype: GgmlDType) -> bool {
+    name.contains("encoder.layer.")
+        && name.ends_with(".weight")
+        && tensor.rank() == 2
+        && tensor.dims()[1].is_multiple_of(dtype.block_size())
+}
+
+pub(crate) fn is_gguf(weights_path: &Path) -> bool {
+    weights_path
+        .extension()
+        .is_some_and(|extension| extension == "gguf")
+}
+
+// The tensors of a GGUF file: dense ones on `device`, and quantized ones kept quantized for
+// the CPU unless `dequantize` is set, in which case every tensor is dense.
+pub(crate) fn read_gguf(
+    weights_path: &Path,
+    device: &Device,
+    dequantize: bool,
+) -> Result<(HashMap<String, Tensor>, QuantizedWeights)> {
+    let mut reader = BufReader::new(File::open(weights_path)?);
+    let content = gguf_file::Content::read(&mut reader)?;
+    let mut dense = HashMap::new();
+    let mut quantized = QuantizedWeights::new();
+    for name in content.tensor_infos.keys() {
+        let tensor = content.tensor(&mut reader, name, &Device::Cpu)?;
+        if dequantize || matches!(tensor.dtype(), GgmlDType::F32 | GgmlDType::F16) {
+            dense.insert(name.clone(), tensor.dequantize(device)?);
+        } else {
+            quantized.insert(name.clone(), Arc::new(tensor));
+        }
+    }
+    Ok((dense, quantized))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::embedder::Embedder;
+    use crate::options::EmbedOptions;
+
+    #[test]
+    fn test_quantize_model() {
+        let output = std::env::temp_dir().join(format!("gte-small-{}.gguf", std::process::id()));
+        let report = quantize_model(
+            "models/gte-small/model.safetensors",
+            &output,
+            QuantType::Q8_0,
+        )
+        .unwrap();
+        // Six linear layers in each of the twelve encoder layers
+        assert_eq!(72, report.quantized);
+        assert!(report.quantized_bytes < report.original_bytes * 3 / 4);
+
+        let load = |weights: &Path| {
+            Embedder::load(
+                "models/gte-small/config.json",
+                "models/gte-small/tokenizer.json",
+                weights,
+                false,
+            )
+            .unwrap()
+        };
+        let full = load(Path::new("models/gte-small/model.safetensors"));
+        let quantized = load(&output);
+        std::fs::remove_file(&output).unwrap();
+        let texts = [
+            "The cat sat on the mat.",
+            "Quantized weights take less memory.",
+        ];
+        let options = EmbedOptions::default();
+        let expected = full.embed_batch(&texts, &options).unwrap();
+        let actual = quantized.embed_batch(&texts, &options).unwrap();
+        for (a, b) in expected.iter().zip(&actual) {
+            let similarity =
+                crate::similarity::cosine_similarity(&a.to_f32(), &b.to_f32()).unwrap();
+            assert!(similarity > 0.99, "{similarity}");
+        }
+
+        assert_eq!(QuantType::Q4_0, "q4_0".parse().unwrap());
+        assert!("q5_1".parse::<QuantType>().is_err());
+    }
+}
@@ -0,0 +1,195 @@
+use crate::embedder::normalize;
+use crate::error::{Error, Result};
+use crate::kernels::dot;
+use crate::provider::EmbeddingProvider;
+use crate::Instant;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Size and lifetime limits of a [`SemanticCache`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SemanticCacheConfig {
+    /// Most responses kept; the least recently used one is evicted to make room.
+    pub max_entries: usize,
+    /// How long a response is served after it was stored, or forever if `None`.
+    pub ttl: Option<Duration>,
+}
+
+impl Default for SemanticCacheConfig {
+    fn default() -> Self {
+        SemanticCacheConfig {
+            max_entries: 1024,
+            ttl: None,
+        }
+    }
+}
+
+/// A cached response returned by [`SemanticCache::get`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheHit {
+    /// The stored prompt that matched.
+    pub prompt: String,
+    pub response: String,
+    /// Cosine similarity between the stored prompt and the one looked up.
+    pub similarity: f32,
+}
+
+struct Entry {
+    prompt: String,
+    embedding: Vec<f32>,
+    response: String,
+    stored: Instant,
+    last_used: Instant,
+}
+
+/// Caches LLM responses by prompt meaning rather than exact text, so a rephrased question can
+/// reuse an answer that was already paid for.
+///
+/// Prompts are embedded with the given provider and compared by cosine similarity with a scan
+/// over every entry, which is fast enough for the few thousand entries a cache usually holds.
+pub struct SemanticCache {
+    provider: Arc<dyn EmbeddingProvider>,
+    config: SemanticCacheConfig,
+    entries: Mutex<Vec<Entry>>,
+}
+
+impl SemanticCache {
+    pub fn new(provider: Arc<dyn EmbeddingProvider>, config: SemanticCacheConfig) -> Result<Self> {
+        if config.max_entries == 0 {
+            return Err(Error::InvalidArgument(
+                "a semantic cache needs room for at least one entry".to_string(),
+            ));
+        }
+        Ok(SemanticCache {
+            provider,
+            config,
+            entries: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// The response of the stored prompt most similar to `prompt`, if its cosine similarity
+    /// is at least `threshold` (e.g. 0.95) and it has not expired.
+    pub fn get(&self, prompt: &str, threshold: f32) -> Result<Option<CacheHit>> {
+        let embedding = self.embed(prompt)?;
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        self.remove_expired(&mut entries, now);
+
+        let best = entries
+            .iter_mut()
+            .map(|entry| (dot(&entry.embedding, &embedding), entry))
+            .filter(|(similarity, _)| *similarity >= threshold)
+            .max_by(|(a, _), (b, _)| a.total_cmp(b));
+        Ok(best.map(|(similarity, entry)| {
+            entry.last_used = now;
+            CacheHit {
+                prompt: entry.prompt.clone(),
+                response: entry.response.clone(),
+                similarity,
+            }
+        }))
+    }
+
+    /// Store `response` for `prompt`, replacing the response of an identical prompt.
+    pub fn insert(&self, prompt: &str, response: &str) -> Result<()> {
+        let embedding = self.embed(prompt)?;
+        let now = Instant::now();
+        let entry = Entry {
+            prompt: prompt.to_string(),
+            embedding,
+            response: response.to_string(),
+            stored: now,
+            last_used: now,
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        self.remove_expired(&mut entries, now);
+        if let Some(existing) = entries.iter_mut().find(|e| e.prompt == prompt) {
+            *existing = entry;
+            return Ok(());
+        }
+        if entries.len() == self.config.max_entries {
+            let lru = (0..entries.len())
+                .min_by_key(|&i| entries[i].last_used)
+                .unwrap();
+            entries.swap_remove(lru);
+        }
+        entries.push(entry);
+        Ok(())
+    }
+
+    /// Entries currently held, including expired ones not yet dropped by a lookup.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    fn embed(&self, prompt: &str) -> Result<Vec<f32>> {
+        let mut embedding = self.provider.embed(prompt)?;
+        normalize(&mut embedding);
+        Ok(embedding)
+    }
+
+    fn remove_expired(&self, entries: &mut Vec<Entry>, now: Instant) {
+        if let Some(ttl) = self.config.ttl {
+            entries.retain(|entry| now.duration_since(entry.stored) < ttl);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::MockProvider;
+
+    #[test]
+    fn test_semantic_cache() {
+        let config = SemanticCacheConfig {
+            max_entries: 2,
+            ttl: None,
+        };
+        assert!(SemanticCache::new(
+            Arc::new(MockProvider::new(8)),
+            SemanticCacheConfig {
+                max_entries: 0,
+                ..config
+            }
+        )
+        .is_err());
+
+        let cache = SemanticCache::new(Arc::new(MockProvider::new(8)), config).unwrap();
+        assert_eq!(None, cache.get("a", -1.0).unwrap());
+
+        cache.insert("a", "first").unwrap();
+        cache.insert("b", "second").unwrap();
+        let hit = cache.get("a", 0.999).unwrap().unwrap();
+        assert_eq!(("a", "first"), (hit.prompt.as_str(), hit.response.as_str()));
+        assert!((hit.similarity - 1.0).abs() < 1e-5);
+
+        // "b" is the least recently used, so it makes room for "c"
+        cache.insert("a", "replaced").unwrap();
+        cache.insert("c", "third").unwrap();
+        assert_eq!(2, cache.len());
+        assert_eq!("replaced", cache.get("a", 0.999).unwrap().unwrap().response);
+        assert_eq!(None, cache.get("b", 0.999).unwrap());
+
+        let expiring = SemanticCache::new(
+            Arc::new(MockProvider::new(8)),
+            SemanticCacheConfig {
+                max_entries: 2,
+                ttl: Some(Duration::ZERO),
+            },
+        )
+        .unwrap();
+        expiring.insert("a", "first").unwrap();
+        assert_eq!(None, expiring.get("a", -1.0).unwrap());
+        assert!(expiring.is_empty());
+    }
+}
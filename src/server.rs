@@ -0,0 +1,563 @@
+use crate::batcher::{BatchConfig, BatcherStats, MicroBatcher};
+use crate::embedder::{normalize, Embedder};
+use crate::error::{Error, Result};
+use crate::metrics::ServerMetrics;
+use crate::options::{EmbedOptions, Embedding};
+use crate::Instant;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+/// How an [`EmbeddingServer`] runs its models.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ServerConfig {
+    /// Coalesce concurrent single-input requests to the same model into batched forward
+    /// passes. Requests with several inputs are always embedded as one batch.
+    pub batch: Option<BatchConfig>,
+    /// Longest input, in tokens, the warm-up before `/readyz` reports ready runs at (at least
+    /// 16), with as many inputs as a batch holds. See [`Embedder::warmup`].
+    pub warmup_tokens: usize,
+}
+
+struct ServedModel {
+    embedder: Arc<Embedder>,
+    batcher: Option<MicroBatcher>,
+    /// Set once a warm-up forward pass has completed.
+    warm: AtomicBool,
+}
+
+/// Serves loaded models over HTTP with the OpenAI embeddings API: `POST /v1/embeddings` and
+/// `GET /v1/models`, so OpenAI clients can point their base URL at it. `GET /metrics` reports
+/// request counts, latencies, token throughput and batch sizes for Prometheus.
+///
+/// `GET /healthz` answers as long as the server runs. `GET /readyz` answers 503 until every
+/// model has completed a warm-up forward pass, started in the background by
+/// [`EmbeddingServer::router`], so orchestrators hold traffic back from a cold instance.
+///
+/// The request's `model` picks one of the models by the name it was added under; the first
+/// model added answers requests that name none. Embeddings are returned at unit length, like
+/// OpenAI's, and `dimensions` keeps a prefix of that length and re-normalizes it.
+///
+/// `encoding_format` is `"float"` (the default) for JSON arrays, `"base64"` for the
+/// little-endian float32 bytes in base64 like OpenAI's, or `"base64_fp16"` for float16 bytes,
+/// which halves the payload again.
+pub struct EmbeddingServer {
+    config: ServerConfig,
+    models: HashMap<String, ServedModel>,
+    default_model: Option<String>,
+    metrics: ServerMetrics,
+}
+
+impl EmbeddingServer {
+    pub fn new(config: ServerConfig) -> Self {
+        EmbeddingServer {
+            config,
+            models: HashMap::new(),
+            default_model: None,
+            metrics: ServerMetrics::default(),
+        }
+    }
+
+    /// Serve `embedder` as `name`, replacing a model added under the same name.
+    pub fn add_model(&mut self, name: impl Into<String>, embedder: Arc<Embedder>) -> Result<()> {
+        let name = name.into();
+        let batcher = match self.config.batch {
+            Some(config) => Some(MicroBatcher::new(Arc::clone(&embedder), config)?),
+            None => None,
+        };
+        self.default_model.get_or_insert_with(|| name.clone());
+        let model = ServedModel {
+            embedder,
+            batcher,
+            warm: AtomicBool::new(false),
+        };
+        self.models.insert(name, model);
+        Ok(())
+    }
+
+    pub fn router(self) -> Router {
+        let server = Arc::new(self);
+        let warming = Arc::clone(&server);
+        std::thread::spawn(move || warming.warm_up());
+        Router::new()
+            .route("/v1/embeddings", post(create_embeddings))
+            .route("/v1/models", get(list_models))
+            .route("/metrics", get(metrics))
+            .route("/healthz", get(healthz))
+            .route("/readyz", get(readyz))
+            .with_state(server)
+    }
+
+    // Forward passes at the largest shapes expected allocate the model's buffers, so real
+    // requests don't pay for it
+    fn warm_up(&self) {
+        let max_batch = self.config.batch.map_or(1, |batch| batch.max_batch_size);
+        let max_tokens = self.config.warmup_tokens.max(16);
+        for (name, model) in &self.models {
+            match model.embedder.warmup(max_batch, max_tokens) {
+                Ok(_) => model.warm.store(true, Ordering::Release),
+                Err(e) => tracing::warn!(model = %name, error = %e, "warm-up failed"),
+            }
+        }
+    }
+
+    /// Answer requests on `listener` until the task is dropped or accepting fails.
+    pub async fn serve(self, listener: TcpListener) -> Result<()> {
+        if self.models.is_empty() {
+            return Err(Error::InvalidArgument(
+                "a server needs at least one model".to_string(),
+            ));
+        }
+        Ok(axum::serve(listener, self.router()).await?)
+    }
+
+    fn model(&self, name: Option<&str>) -> std::result::Result<(&str, &ServedModel), ApiError> {
+        let name = name
+            .filter(|name| !name.is_empty())
+            .or(self.default_model.as_deref())
+            .unwrap_or_default();
+        self.models
+            .get_key_value(name)
+            .map(|(name, model)| (name.as_str(), model))
+            .ok_or_else(|| ApiError {
+                status: StatusCode::NOT_FOUND,
+                message: format!("The model `{name}` does not exist"),
+                kind: "invalid_request_error",
+                code: Some("model_not_found"),
+            })
+    }
+
+    // Embeddings and the total number of tokens of `texts`, as counted by the embedding call
+    fn embed(&self, name: &str, texts: &[String]) -> Result<(Vec<Vec<f32>>, usize)> {
+        let model = &self.models[name];
+        match (&model.batcher, texts) {
+            (Some(batcher), [text]) => {
+                let (embedding, tokens) = batcher.embed_with_usage(text)?;
+                Ok((vec![embedding], tokens))
+            }
+            _ => {
+                let (embeddings, tokens) = model
+                    .embedder
+                    .embed_batch_with_usage(texts, &EmbedOptions::default())?;
+                Ok((embeddings.iter().map(Embedding::to_f32).collect(), tokens))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Input {
+    Text(String),
+    Texts(Vec<String>),
+    // Token ids are only parsed to reject them with a clear message
+    #[allow(dead_code)]
+    Tokens(Vec<u32>),
+    #[allow(dead_code)]
+    TokenLists(Vec<Vec<u32>>),
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum EncodingFormat {
+    #[default]
+    Float,
+    Base64,
+    Base64Fp16,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingRequest {
+    input: Input,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    encoding_format: Option<String>,
+    #[serde(default)]
+    dimensions: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct EmbeddingResponse {
+    object: &'static str,
+    data: Vec<EmbeddingData>,
+    model: String,
+    usage: Usage,
+}
+
+#[derive(Debug, Serialize)]
+struct EmbeddingData {
+    object: &'static str,
+    embedding: EncodedEmbedding,
+    index: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum EncodedEmbedding {
+    Float(Vec<f32>),
+    Base64(String),
+}
+
+impl EncodedEmbedding {
+    fn new(embedding: Vec<f32>, format: EncodingFormat) -> Self {
+        match format {
+            EncodingFormat::Float => EncodedEmbedding::Float(embedding),
+            EncodingFormat::Base64 => {
+                let bytes: Vec<u8> = embedding.iter().flat_map(|x| x.to_le_bytes()).collect();
+                EncodedEmbedding::Base64(base64(&bytes))
+            }
+            EncodingFormat::Base64Fp16 => {
+                let bytes: Vec<u8> = embedding
+                    .iter()
+                    .flat_map(|&x| half::f16::from_f32(x).to_le_bytes())
+                    .collect();
+                EncodedEmbedding::Base64(base64(&bytes))
+            }
+        }
+    }
+}
+
+// Standard base64 with padding.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+            group | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[(group >> (18 - 6 * i) & 63) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+#[derive(Debug, Serialize)]
+struct Usage {
+    prompt_tokens: usize,
+    total_tokens: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct ModelList {
+    object: &'static str,
+    data: Vec<ModelInfo>,
+}
+
+#[derive(Debug, Serialize)]
+struct ModelInfo {
+    id: String,
+    object: &'static str,
+    owned_by: &'static str,
+}
+
+/// An error in OpenAI's shape: `{"error": {"message", "type", "param", "code"}}`.
+#[derive(Debug)]
+struct ApiError {
+    status: StatusCode,
+    message: String,
+    kind: &'static str,
+    code: Option<&'static str>,
+}
+
+impl ApiError {
+    fn invalid(message: impl Into<String>) -> Self {
+        ApiError {
+            status: StatusCode::BAD_REQUEST,
+            message: message.into(),
+            kind: "invalid_request_error",
+            code: None,
+        }
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Json(_)
+            | Error::InvalidLayer(_)
+            | Error::InvalidArgument(_)
+            | Error::EmptyInput => ApiError::invalid(e.to_string()),
+            Error::Timeout => ApiError {
+                status: StatusCode::GATEWAY_TIMEOUT,
+                message: e.to_string(),
+                kind: "server_error",
+                code: Some("timeout"),
+            },
+            _ => ApiError {
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                message: e.to_string(),
+                kind: "server_error",
+                code: None,
+            },
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({
+            "error": {
+                "message": self.message,
+                "type": self.kind,
+                "param": null,
+                "code": self.code,
+            }
+        });
+        (self.status, Json(body)).into_response()
+    }
+}
+
+async fn create_embeddings(
+    State(server): State<Arc<EmbeddingServer>>,
+    request: std::result::Result<Json<EmbeddingRequest>, JsonRejection>,
+) -> std::result::Result<Json<EmbeddingResponse>, ApiError> {
+    let Json(request) = request.map_err(|e| ApiError::invalid(e.body_text()))?;
+    let (name, _) = server.model(request.model.as_deref())?;
+    let name = name.to_string();
+
+    let start = Instant::now();
+    let in_flight = server.metrics.start_request();
+    let response = embed_request(&server, name.clone(), request).await;
+    drop(in_flight);
+    let tokens = response
+        .as_ref()
+        .ok()
+        .map(|response| response.usage.prompt_tokens);
+    server.metrics.record(&name, start.elapsed(), tokens);
+    response.map(Json)
+}
+
+async fn embed_request(
+    server: &Arc<EmbeddingServer>,
+    name: String,
+    request: EmbeddingRequest,
+) -> std::result::Result<EmbeddingResponse, ApiError> {
+    let model = &server.models[&name];
+
+    let texts = match request.input {
+        Input::Text(text) => vec![text],
+        Input::Texts(texts) => texts,
+        Input::Tokens(_) | Input::TokenLists(_) => {
+            return Err(ApiError::invalid("token id inputs are not supported"))
+        }
+    };
+    if texts.is_empty() {
+        return Err(ApiError::invalid("input must not be empty"));
+    }
+    let format = match request.encoding_format.as_deref() {
+        None => EncodingFormat::default(),
+        Some(format) => serde_json::from_value(format.into())
+            .map_err(|_| ApiError::invalid(format!("unsupported encoding_format {format:?}")))?,
+    };
+    let dim = model.embedder.embedding_dim(Default::default());
+    let dimensions = request.dimensions.unwrap_or(dim);
+    if dimensions == 0 || dimensions > dim {
+        return Err(ApiError::invalid(format!(
+            "dimensions must be between 1 and {dim}"
+        )));
+    }
+
+    let embed_server = Arc::clone(server);
+    let embed_name = name.clone();
+    let (embeddings, tokens) =
+        tokio::task::spawn_blocking(move || embed_server.embed(&embed_name, &texts))
+            .await
+            .map_err(|e| ApiError::from(Error::Batch(e.to_string())))??;
+
+    let data = embeddings
+        .into_iter()
+        .enumerate()
+        .map(|(index, mut embedding)| {
+            embedding.truncate(dimensions);
+            normalize(&mut embedding);
+            EmbeddingData {
+                object: "embedding",
+                embedding: EncodedEmbedding::new(embedding, format),
+                index,
+            }
+        })
+        .collect();
+    Ok(EmbeddingResponse {
+        object: "list",
+        data,
+        model: name,
+        usage: Usage {
+            prompt_tokens: tokens,
+            total_tokens: tokens,
+        },
+    })
+}
+
+async fn metrics(State(server): State<Arc<EmbeddingServer>>) -> impl IntoResponse {
+    let mut batchers: Vec<(&str, BatcherStats)> = server
+        .models
+        .iter()
+        .filter_map(|(name, model)| Some((name.as_str(), model.batcher.as_ref()?.stats())))
+        .collect();
+    batchers.sort_by_key(|&(name, _)| name);
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        server.metrics.render(&batchers),
+    )
+}
+
+async fn healthz() -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "status": "ok" }))
+}
+
+async fn readyz(State(server): State<Arc<EmbeddingServer>>) -> impl IntoResponse {
+    let models: serde_json::Map<String, serde_json::Value> = server
+        .models
+        .iter()
+        .map(|(name, model)| (name.clone(), model.warm.load(Ordering::Acquire).into()))
+        .collect();
+    let ready = !models.is_empty() && models.values().all(|warm| warm == true);
+    let (status, label) = match ready {
+        true => (StatusCode::OK, "ready"),
+        false => (StatusCode::SERVICE_UNAVAILABLE, "warming"),
+    };
+    (
+        status,
+        Json(serde_json::json!({ "status": label, "models": models })),
+    )
+}
+
+async fn list_models(State(server): State<Arc<EmbeddingServer>>) -> Json<ModelList> {
+    let mut names: Vec<&String> = server.models.keys().collect();
+    names.sort();
+    Json(ModelList {
+        object: "list",
+        data: names
+            .into_iter()
+            .map(|name| ModelInfo {
+                id: name.clone(),
+                object: "model",
+                owned_by: "rust_embedding_lib",
+            })
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    async fn call(router: &Router, body: serde_json::Value) -> (StatusCode, serde_json::Value) {
+        let request = Request::post("/v1/embeddings")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_openai_embeddings() {
+        let embedder = Embedder::load(
+            "models/gte-small/config.json",
+            "models/gte-small/tokenizer.json",
+            "models/gte-small/model.safetensors",
+            false,
+        )
+        .unwrap();
+        let mut server = EmbeddingServer::new(ServerConfig {
+            batch: Some(BatchConfig::default()),
+            ..ServerConfig::default()
+        });
+        server.add_model("gte-small", Arc::new(embedder)).unwrap();
+        let router = server.router();
+
+        let get = |path: &str| {
+            let request = Request::get(path).body(Body::empty()).unwrap();
+            router.clone().oneshot(request)
+        };
+        assert_eq!(StatusCode::OK, get("/healthz").await.unwrap().status());
+        let deadline = Instant::now() + std::time::Duration::from_secs(30);
+        while get("/readyz").await.unwrap().status() != StatusCode::OK {
+            assert!(Instant::now() < deadline, "the model never became ready");
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let (status, body) = call(
+            &router,
+            serde_json::json!({"input": ["first text", "second text"], "model": "gte-small"}),
+        )
+        .await;
+        assert_eq!(StatusCode::OK, status);
+        assert_eq!("list", body["object"]);
+        assert_eq!(1, body["data"][1]["index"]);
+        assert_eq!(384, body["data"][0]["embedding"].as_array().unwrap().len());
+        assert_eq!(8, body["usage"]["prompt_tokens"]);
+
+        // Unnamed requests go to the first model; `dimensions` keeps a unit-length prefix
+        let (status, body) =
+            call(&router, serde_json::json!({"input": "x", "dimensions": 64})).await;
+        assert_eq!(StatusCode::OK, status);
+        let embedding: Vec<f32> =
+            serde_json::from_value(body["data"][0]["embedding"].clone()).unwrap();
+        assert_eq!(64, embedding.len());
+        assert!((embedding.iter().map(|x| x * x).sum::<f32>() - 1.0).abs() < 1e-4);
+
+        // The binary encodings carry the same values
+        for format in [EncodingFormat::Base64, EncodingFormat::Base64Fp16] {
+            let name = serde_json::to_value(format).unwrap();
+            let request =
+                serde_json::json!({"input": "x", "dimensions": 64, "encoding_format": name});
+            let (status, body) = call(&router, request).await;
+            assert_eq!(StatusCode::OK, status);
+            let expected = match EncodedEmbedding::new(embedding.clone(), format) {
+                EncodedEmbedding::Base64(encoded) => encoded,
+                EncodedEmbedding::Float(_) => unreachable!(),
+            };
+            assert_eq!(expected, body["data"][0]["embedding"]);
+        }
+        assert_eq!(
+            ["TWFu", "TWE=", "TQ==", ""],
+            [&b"Man"[..], b"Ma", b"M", b""].map(base64)
+        );
+
+        let (status, body) =
+            call(&router, serde_json::json!({"input": "x", "model": "nope"})).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+        assert_eq!("model_not_found", body["error"]["code"]);
+
+        for invalid in [
+            serde_json::json!({"input": []}),
+            serde_json::json!({"input": [1, 2, 3]}),
+            serde_json::json!({"input": "x", "encoding_format": "int8"}),
+            serde_json::json!({"input": "x", "dimensions": 1000}),
+            serde_json::json!({"text": "x"}),
+        ] {
+            let (status, body) = call(&router, invalid).await;
+            assert_eq!(StatusCode::BAD_REQUEST, status);
+            assert_eq!("invalid_request_error", body["error"]["type"]);
+        }
+
+        let response = get("/metrics").await.unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        // Requests for unknown models or with unparsable bodies are not routed, so not counted
+        assert!(text.contains("embed_requests_total{model=\"gte-small\",status=\"ok\"} 4\n"));
+        assert!(text.contains("embed_requests_total{model=\"gte-small\",status=\"error\"} 4\n"));
+        assert!(text.contains("embed_requests_in_flight 0\n"));
+        assert!(text.contains("embed_batch_queue_depth{model=\"gte-small\"} 0\n"));
+    }
+}
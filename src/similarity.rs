@@ -0,0 +1,220 @@
+use crate::error::{Error, Result};
+use crate::kernels::{dot, squared_distance};
+use candle::{Device, Tensor};
+
+/// Dot product of two vectors of the same length, which is their cosine similarity if both are
+/// normalized.
+pub fn dot_product(a: &[f32], b: &[f32]) -> Result<f32> {
+    check_lengths(a, b)?;
+    Ok(dot(a, b))
+}
+
+/// Cosine similarity of two vectors of the same length, 0 if either is all zeros.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> Result<f32> {
+    check_lengths(a, b)?;
+    let norms = (dot(a, a) * dot(b, b)).sqrt();
+    if norms == 0.0 {
+        return Ok(0.0);
+    }
+    Ok(dot(a, b) / norms)
+}
+
+/// Euclidean (L2) distance between two vectors of the same length.
+pub fn euclidean_distance(a: &[f32], b: &[f32]) -> Result<f32> {
+    check_lengths(a, b)?;
+    Ok(squared_distance(a, b).sqrt())
+}
+
+/// Cosine similarities of every vector in `a` to every vector in `b`: row `i` holds those of
+/// `a[i]`. They are computed as a single matrix product rather than `a.len() * b.len()`
+/// separate comparisons, for clustering or deduplication jobs. Vectors that are all zeros have
+/// similarity 0 to everything.
+///
+/// [`Embedder::similarity_matrix`](crate::Embedder::similarity_matrix) does the same on the
+/// model's device.
+pub fn similarity_matrix<A: AsRef<[f32]>, B: AsRef<[f32]>>(
+    a: &[A],
+    b: &[B],
+) -> Result<Vec<Vec<f32>>> {
+    similarity_matrix_on(&Device::Cpu, a, b)
+}
+
+pub(crate) fn similarity_matrix_on<A: AsRef<[f32]>, B: AsRef<[f32]>>(
+    device: &Device,
+    a: &[A],
+    b: &[B],
+) -> Result<Vec<Vec<f32>>> {
+    let Some(first) = a.first() else {
+        return Ok(Vec::new());
+    };
+    let dim = first.as_ref().len();
+    let a = normalized_rows(stack(a, dim, device)?)?;
+    let b = normalized_rows(stack(b, dim, device)?)?;
+    Ok(a.matmul(&b.t()?)?.to_vec2()?)
+}
+
+/// The element-wise mean of vectors of the same length, e.g. a topic prototype from example
+/// embeddings. Fails if there are none.
+pub fn centroid<V: AsRef<[f32]>>(vectors: &[V]) -> Result<Vec<f32>> {
+    weighted_average(vectors, &vec![1.0; vectors.len()])
+}
+
+/// `sum(weights[i] * vectors[i]) / sum(weights)` over vectors of the same length. Weights may
+/// be negative, e.g. to move a query away from irrelevant results, but must not sum to 0.
+pub fn weighted_average<V: AsRef<[f32]>>(vectors: &[V], weights: &[f32]) -> Result<Vec<f32>> {
+    if vectors.len() != weights.len() {
+        return Err(Error::InvalidArgument(format!(
+            "{} vectors but {} weights",
+            vectors.len(),
+            weights.len()
+        )));
+    }
+    let Some(first) = vectors.first() else {
+        return Err(Error::InvalidArgument("no vectors to average".to_string()));
+    };
+    let total: f32 = weights.iter().sum();
+    if total == 0.0 || !total.is_finite() {
+        return Err(Error::InvalidArgument(format!("weights sum to {total}")));
+    }
+
+    let mut average = vec![0f32; first.as_ref().len()];
+    for (vector, weight) in vectors.iter().zip(weights) {
+        let vector = vector.as_ref();
+        check_lengths(&average, vector)?;
+        let weight = weight / total;
+        for (acc, value) in average.iter_mut().zip(vector) {
+            *acc += weight * value;
+        }
+    }
+    Ok(average)
+}
+
+/// Spherical interpolation from `a` (`t = 0`) to `b` (`t = 1`): the direction turns along the
+/// great circle between the two, at constant angular speed, while the length changes
+/// linearly. Unlike a plain weighted average, midpoints of unit vectors stay unit vectors.
+/// Vectors pointing the same or opposite ways, or all zeros, are interpolated linearly.
+pub fn slerp(a: &[f32], b: &[f32], t: f32) -> Result<Vec<f32>> {
+    check_lengths(a, b)?;
+    let t = f64::from(t);
+    let (norm_a, norm_b) = (f64::from(dot(a, a)).sqrt(), f64::from(dot(b, b)).sqrt());
+    let lerp = |wa: f64, wb: f64| {
+        a.iter()
+            .zip(b)
+            .map(|(&x, &y)| (wa * f64::from(x) + wb * f64::from(y)) as f32)
+            .collect()
+    };
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return Ok(lerp(1.0 - t, t));
+    }
+    let cos = (f64::from(dot(a, b)) / (norm_a * norm_b)).clamp(-1.0, 1.0);
+    let angle = cos.acos();
+    let sin = angle.sin();
+    if sin < 1e-6 {
+        return Ok(lerp(1.0 - t, t));
+    }
+    let norm = (1.0 - t) * norm_a + t * norm_b;
+    let wa = ((1.0 - t) * angle).sin() / sin * norm / norm_a;
+    let wb = (t * angle).sin() / sin * norm / norm_b;
+    Ok(lerp(wa, wb))
+}
+
+// The vectors as the rows of a matrix, all of which must have `dim` elements
+fn stack<V: AsRef<[f32]>>(vectors: &[V], dim: usize, device: &Device) -> Result<Tensor> {
+    let mut values = Vec::with_capacity(vectors.len() * dim);
+    for vector in vectors {
+        let vector = vector.as_ref();
+        if vector.len() != dim {
+            return Err(Error::InvalidArgument(format!(
+                "vectors of length {dim} and {} can't be compared",
+                vector.len()
+            )));
+        }
+        values.extend_from_slice(vector);
+    }
+    Ok(Tensor::from_vec(values, (vectors.len(), dim), device)?)
+}
+
+// Scale every row to unit length; zero rows stay zero
+fn normalized_rows(matrix: Tensor) -> Result<Tensor> {
+    let norms = matrix
+        .sqr()?
+        .sum_keepdim(1)?
+        .sqrt()?
+        .maximum(f32::MIN_POSITIVE)?;
+    Ok(matrix.broadcast_div(&norms)?)
+}
+
+fn check_lengths(a: &[f32], b: &[f32]) -> Result<()> {
+    if a.len() != b.len() {
+        return Err(Error::InvalidArgument(format!(
+            "vectors of length {} and {} can't be compared",
+            a.len(),
+            b.len()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_similarity() {
+        let a = [3.0, 0.0, 4.0];
+        let b = [0.0, 5.0, 0.0];
+        assert_eq!(0.0, dot_product(&a, &b).unwrap());
+        assert_eq!(25.0, dot_product(&a, &a).unwrap());
+        assert!((cosine_similarity(&a, &[6.0, 0.0, 8.0]).unwrap() - 1.0).abs() < 1e-6);
+        assert_eq!(0.0, cosine_similarity(&a, &[0.0; 3]).unwrap());
+        assert!((euclidean_distance(&a, &b).unwrap() - 50f32.sqrt()).abs() < 1e-6);
+        assert!(cosine_similarity(&a, &b[..2]).is_err());
+    }
+
+    #[test]
+    fn test_embedding_arithmetic() {
+        let vectors = [[1.0, 0.0], [0.0, 1.0], [0.0, 0.0]];
+        assert_eq!(vec![1.0 / 3.0, 1.0 / 3.0], centroid(&vectors).unwrap());
+        assert_eq!(
+            vec![0.75, 0.25],
+            weighted_average(&vectors[..2], &[3.0, 1.0]).unwrap()
+        );
+        assert_eq!(
+            vec![2.0, -1.0],
+            weighted_average(&vectors[..2], &[2.0, -1.0]).unwrap()
+        );
+        assert!(centroid::<[f32; 2]>(&[]).is_err());
+        assert!(weighted_average(&vectors[..2], &[1.0, -1.0]).is_err());
+        assert!(weighted_average(&vectors, &[1.0]).is_err());
+        assert!(centroid(&[vec![1.0], vec![1.0, 2.0]]).is_err());
+
+        let (a, b) = ([1.0, 0.0], [0.0, 2.0]);
+        assert_eq!(a.to_vec(), slerp(&a, &b, 0.0).unwrap());
+        let end = slerp(&a, &b, 1.0).unwrap();
+        assert!(end[0].abs() < 1e-6 && (end[1] - 2.0).abs() < 1e-6);
+        // Halfway turns by 45 degrees, to halfway between the lengths
+        let mid = slerp(&a, &b, 0.5).unwrap();
+        let expected = 1.5 * std::f32::consts::FRAC_1_SQRT_2;
+        assert!((mid[0] - expected).abs() < 1e-6 && (mid[1] - expected).abs() < 1e-6);
+        assert_eq!(vec![2.0, 0.0], slerp(&a, &[3.0, 0.0], 0.5).unwrap());
+        assert!(slerp(&a, &[1.0], 0.5).is_err());
+    }
+
+    #[test]
+    fn test_similarity_matrix() {
+        let a = vec![vec![3.0, 0.0, 4.0], vec![0.0; 3]];
+        let b = [[0.0, 5.0, 0.0], [6.0, 0.0, 8.0], [1.0, 1.0, 0.0]];
+        let matrix = similarity_matrix(&a, &b).unwrap();
+        assert_eq!(2, matrix.len());
+        for (row, vector) in matrix.iter().zip(&a) {
+            for (similarity, other) in row.iter().zip(&b) {
+                let expected = cosine_similarity(vector, other).unwrap();
+                assert!((similarity - expected).abs() < 1e-6);
+            }
+        }
+        assert!(similarity_matrix(&a, &[[1.0, 2.0]]).is_err());
+        assert!(similarity_matrix::<Vec<f32>, _>(&[], &b)
+            .unwrap()
+            .is_empty());
+    }
+}
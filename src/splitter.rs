@@ -0,0 +1,216 @@
+use crate::error::{Error, Result};
+use std::ops::Range;
+use tokenizers::Tokenizer;
+
+/// A piece of the input text produced by [`TextSplitter::split`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextChunk {
+    pub text: String,
+    /// Byte range of the chunk in the original text.
+    pub range: Range<usize>,
+    /// Number of tokens in the chunk, not counting special tokens.
+    pub n_tokens: usize,
+}
+
+/// Splits text into chunks of at most `max_tokens` tokens, measured with the model's own
+/// tokenizer so chunks line up with what the model will actually see.
+///
+/// `max_tokens` does not include the special tokens (`[CLS]`, `[SEP]`) added at embed time,
+/// so for a 512 position model use at most 510.
+#[derive(Clone)]
+pub struct TextSplitter {
+    tokenizer: Tokenizer,
+    max_tokens: usize,
+    overlap: usize,
+    respect_sentences: bool,
+}
+
+impl TextSplitter {
+    pub fn new(tokenizer: &Tokenizer, max_tokens: usize) -> Result<Self> {
+        let mut tokenizer = tokenizer.clone();
+        tokenizer.with_padding(None);
+        tokenizer.with_truncation(None)?;
+        TextSplitter {
+            tokenizer,
+            max_tokens,
+            overlap: 0,
+            respect_sentences: false,
+        }
+        .validated()
+    }
+
+    /// Number of tokens consecutive chunks share. Must be smaller than `max_tokens`.
+    pub fn with_overlap(mut self, overlap: usize) -> Result<Self> {
+        self.overlap = overlap;
+        self.validated()
+    }
+
+    /// Prefer to cut between sentences. Sentences longer than `max_tokens` are still cut
+    /// mid-sentence, and overlap is made of whole sentences.
+    pub fn with_sentence_boundaries(mut self, respect_sentences: bool) -> Self {
+        self.respect_sentences = respect_sentences;
+        self
+    }
+
+    fn validated(self) -> Result<Self> {
+        if self.max_tokens == 0 {
+            return Err(Error::InvalidArgument(
+                "max_tokens must be greater than 0".to_string(),
+            ));
+        }
+        if self.overlap >= self.max_tokens {
+            return Err(Error::InvalidArgument(format!(
+                "overlap ({}) must be smaller than max_tokens ({})",
+                self.overlap, self.max_tokens
+            )));
+        }
+        Ok(self)
+    }
+
+    pub fn split(&self, text: &str) -> Result<Vec<TextChunk>> {
+        let encoding = self.tokenizer.encode(text, false)?;
+        let offsets = encoding.get_offsets();
+        if offsets.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let chunks = if self.respect_sentences {
+            self.pack(self.sentence_units(text, offsets))
+        } else {
+            self.windows(0..offsets.len())
+        };
+
+        Ok(chunks
+            .into_iter()
+            .map(|tokens| {
+                let range = offsets[tokens.start].0..offsets[tokens.end - 1].1;
+                TextChunk {
+                    text: text[range.clone()].to_string(),
+                    range,
+                    n_tokens: tokens.len(),
+                }
+            })
+            .collect())
+    }
+
+    /// Token ranges of the sentences in `text`, with over-long sentences pre-cut into
+    /// `max_tokens` windows.
+    fn sentence_units(&self, text: &str, offsets: &[(usize, usize)]) -> Vec<Range<usize>> {
+        let mut sentences = Vec::new();
+        let mut start = 0;
+        for i in 1..offsets.len() {
+            let gap = &text[offsets[i - 1].1..offsets[i].0];
+            let previous = &text[offsets[i - 1].0..offsets[i - 1].1];
+            let ends_sentence = previous.ends_with(['.', '!', '?']) && !gap.is_empty();
+            if ends_sentence || gap.contains('\n') {
+                sentences.push(start..i);
+                start = i;
+            }
+        }
+        sentences.push(start..offsets.len());
+
+        sentences
+            .into_iter()
+            .flat_map(|sentence| self.windows(sentence))
+            .collect()
+    }
+
+    fn windows(&self, tokens: Range<usize>) -> Vec<Range<usize>> {
+        let mut windows = Vec::new();
+        let mut start = tokens.start;
+        loop {
+            let end = (start + self.max_tokens).min(tokens.end);
+            windows.push(start..end);
+            if end == tokens.end {
+                return windows;
+            }
+            start = end - self.overlap;
+        }
+    }
+
+    /// Greedily packs consecutive units into chunks of at most `max_tokens` tokens.
+    fn pack(&self, units: Vec<Range<usize>>) -> Vec<Range<usize>> {
+        if units.len() == 1 {
+            return self.windows(units[0].clone());
+        }
+
+        let mut chunks = Vec::new();
+        let mut first = 0;
+        while first < units.len() {
+            let mut last = first + 1;
+            while last < units.len() && units[last].end - units[first].start <= self.max_tokens {
+                last += 1;
+            }
+            chunks.push(units[first].start..units[last - 1].end);
+            if last == units.len() {
+                break;
+            }
+
+            // Carry over trailing whole units that fit in the overlap budget, while always
+            // making progress.
+            let mut next = last;
+            while next - 1 > first && units[last - 1].end - units[next - 1].start <= self.overlap {
+                next -= 1;
+            }
+            first = next;
+        }
+        chunks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_tokenizer() -> Tokenizer {
+        Tokenizer::from_file("models/gte-small/tokenizer.json").unwrap()
+    }
+
+    #[test]
+    fn test_split_token_windows() {
+        let text = "one two three four five six seven eight nine ten";
+        let splitter = TextSplitter::new(&test_tokenizer(), 4)
+            .unwrap()
+            .with_overlap(1)
+            .unwrap();
+        let chunks = splitter.split(text).unwrap();
+        let texts: Vec<_> = chunks.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(
+            vec![
+                "one two three four",
+                "four five six seven",
+                "seven eight nine ten"
+            ],
+            texts
+        );
+        assert!(chunks.iter().all(|c| text[c.range.clone()] == c.text));
+    }
+
+    #[test]
+    fn test_split_sentence_boundaries() {
+        let text = "First sentence here. Second one is here! A third? Yes.";
+        let splitter = TextSplitter::new(&test_tokenizer(), 8)
+            .unwrap()
+            .with_sentence_boundaries(true);
+        let texts: Vec<_> = splitter
+            .split(text)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.text)
+            .collect();
+        assert_eq!(
+            vec![
+                "First sentence here.",
+                "Second one is here! A third?",
+                "Yes."
+            ],
+            texts
+        );
+    }
+
+    #[test]
+    fn test_split_rejects_bad_overlap() {
+        let splitter = TextSplitter::new(&test_tokenizer(), 4).unwrap();
+        assert!(splitter.with_overlap(4).is_err());
+    }
+}
@@ -0,0 +1,287 @@
+use crate::embedder::normalize;
+use crate::error::{Error, Result};
+use crate::provider::EmbeddingProvider;
+use candle::{DType, Device};
+use serde::Deserialize;
+use std::path::Path;
+use tokenizers::Tokenizer;
+
+// Tokens embedded per text, as model2vec's `encode` keeps by default.
+const MAX_TOKENS: usize = 512;
+
+// The parts of a model2vec `config.json` that change the embeddings.
+#[derive(Deserialize)]
+struct StaticConfig {
+    #[serde(default)]
+    normalize: bool,
+}
+
+/// A static embedding model in the model2vec layout: one precomputed vector per token, averaged
+/// over the tokens of a text. There is no encoder to run, so embedding takes microseconds
+/// instead of milliseconds, for somewhat lower quality; suited to search-as-you-type and
+/// autocomplete, or as the fast member of a [`SizeRouter`](crate::SizeRouter) or
+/// [`Fallback`](crate::Fallback).
+///
+/// Texts are tokenized without special tokens, unknown tokens are skipped and only the first
+/// 512 tokens count.
+pub struct StaticEmbedder {
+    // The token vectors, `dim` floats each, already scaled by the per-token weights if the
+    // model has them.
+    vectors: Vec<f32>,
+    dim: usize,
+    // The row of `vectors` for each token id, for models whose vocabulary shares rows.
+    mapping: Option<Vec<u32>>,
+    tokenizer: Tokenizer,
+    unk_id: Option<u32>,
+    normalize: bool,
+}
+
+impl StaticEmbedder {
+    /// Load the `config.json`, `tokenizer.json` and `model.safetensors` of a model2vec model.
+    /// The weights hold an `embeddings` matrix of one row per token, and optionally `weights`,
+    /// a factor per token, and `mapping`, the row of each token.
+    pub fn load(
+        config_path: impl AsRef<Path>,
+        tokenizer_path: impl AsRef<Path>,
+        weights_path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let config: StaticConfig = serde_json::from_slice(&std::fs::read(config_path)?)?;
+        let tokenizer_contents = std::fs::read(tokenizer_path)?;
+        let mut tokenizer = Tokenizer::from_bytes(&tokenizer_contents)?;
+        tokenizer.with_padding(None);
+        tokenizer.with_truncation(None)?;
+        // The tokenizers crate doesn't expose the unknown token of every model type
+        let unk_id = serde_json::from_slice::<serde_json::Value>(&tokenizer_contents)?
+            .pointer("/model/unk_token")
+            .and_then(|token| token.as_str())
+            .and_then(|token| tokenizer.token_to_id(token));
+
+        let mut tensors = candle::safetensors::load(weights_path, &Device::Cpu)?;
+        let embeddings = tensors.remove("embeddings").ok_or_else(|| {
+            Error::InvalidArgument("static model has no embeddings tensor".to_string())
+        })?;
+        let (rows, dim) = embeddings.dims2()?;
+        let mut vectors = embeddings.to_dtype(DType::F32)?.flatten_all()?.to_vec1()?;
+        let mapping = match tensors.remove("mapping") {
+            Some(mapping) => {
+                let mapping: Vec<i64> = mapping.to_dtype(DType::I64)?.to_vec1()?;
+                if mapping.iter().any(|&row| row < 0 || row as usize >= rows) {
+                    return Err(Error::InvalidArgument(
+                        "static model maps tokens to missing rows".to_string(),
+                    ));
+                }
+                Some(
+                    mapping
+                        .into_iter()
+                        .map(|row| row as u32)
+                        .collect::<Vec<_>>(),
+                )
+            }
+            None => None,
+        };
+        let vocab_size = mapping.as_ref().map_or(rows, Vec::len);
+        if tokenizer.get_vocab_size(true) > vocab_size {
+            return Err(Error::InvalidArgument(format!(
+                "static model has {vocab_size} token vectors for a vocabulary of {}",
+                tokenizer.get_vocab_size(true)
+            )));
+        }
+
+        // Per-token weights are by token, so shared rows are unshared before scaling them
+        let (vectors, mapping) = match tensors.remove("weights") {
+            Some(weights) => {
+                let weights: Vec<f32> = weights.to_dtype(DType::F32)?.to_vec1()?;
+                if weights.len() != vocab_size {
+                    return Err(Error::InvalidArgument(format!(
+                        "static model has {} token weights for {vocab_size} tokens",
+                        weights.len()
+                    )));
+                }
+                if let Some(mapping) = &mapping {
+                    vectors = mapping
+                        .iter()
+                        .flat_map(|&row| &vectors[row as usize * dim..(row as usize + 1) * dim])
+                        .copied()
+                        .collect();
+                }
+                for (vector, weight) in vectors.chunks_exact_mut(dim).zip(&weights) {
+                    vector.iter_mut().for_each(|x| *x *= weight);
+                }
+                (vectors, None)
+            }
+            None => (vectors, mapping),
+        };
+        Ok(StaticEmbedder {
+            vectors,
+            dim,
+            mapping,
+            tokenizer,
+            unk_id,
+            normalize: config.normalize,
+        })
+    }
+
+    /// Length of the vectors produced.
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Embed `text` as the mean of its token vectors, scaled to unit length if the model's
+    /// config asks for it.
+    pub fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let encoding = self.tokenizer.encode(text, false)?;
+        self.pool(encoding.get_ids())
+    }
+
+    /// [`StaticEmbedder::embed`] for every text, returning the embeddings in input order.
+    pub fn embed_batch<S: AsRef<str>>(&self, texts: &[S]) -> Result<Vec<Vec<f32>>> {
+        let texts: Vec<&str> = texts.iter().map(AsRef::as_ref).collect();
+        self.tokenizer
+            .encode_batch(texts, false)?
+            .iter()
+            .map(|encoding| self.pool(encoding.get_ids()))
+            .collect()
+    }
+
+    fn pool(&self, ids: &[u32]) -> Result<Vec<f32>> {
+        let mut sum = vec![0f32; self.dim];
+        let mut count = 0;
+        for &id in ids
+            .iter()
+            .filter(|&&id| Some(id) != self.unk_id)
+            .take(MAX_TOKENS)
+        {
+            let row = match &self.mapping {
+                Some(mapping) => mapping[id as usize],
+                None => id,
+            } as usize;
+            let vector = &self.vectors[row * self.dim..(row + 1) * self.dim];
+            sum.iter_mut().zip(vector).for_each(|(acc, x)| *acc += x);
+            count += 1;
+        }
+        if count == 0 {
+            return Err(Error::EmptyInput);
+        }
+        sum.iter_mut().for_each(|x| *x /= count as f32);
+        if self.normalize {
+            normalize(&mut sum);
+        }
+        Ok(sum)
+    }
+}
+
+impl EmbeddingProvider for StaticEmbedder {
+    fn name(&self) -> &str {
+        "static"
+    }
+
+    fn embed_batch(&self, texts: &[&str]) -> Result<Vec<Vec<f32>>> {
+        StaticEmbedder::embed_batch(self, texts)
+    }
+
+    fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        StaticEmbedder::embed(self, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::similarity::cosine_similarity;
+    use candle::Tensor;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_static_embedder() {
+        let dir = std::env::temp_dir().join(format!("static-model-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tokenizer = "models/gte-small/tokenizer.json";
+        let vocab = Tokenizer::from_file(tokenizer)
+            .unwrap()
+            .get_vocab_size(true);
+        let dim = 8;
+        let embeddings: Vec<f32> = (0..vocab * dim)
+            .map(|i| ((i * 7919) % 101) as f32 / 50.0 - 1.0)
+            .collect();
+        let save = |tensors: HashMap<String, Tensor>, normalize: bool| {
+            let config = format!(r#"{{"model_type": "model2vec", "normalize": {normalize}}}"#);
+            std::fs::write(dir.join("config.json"), config).unwrap();
+            candle::safetensors::save(&tensors, dir.join("model.safetensors")).unwrap();
+            StaticEmbedder::load(
+                dir.join("config.json"),
+                tokenizer,
+                dir.join("model.safetensors"),
+            )
+        };
+        let matrix = Tensor::from_vec(embeddings.clone(), (vocab, dim), &Device::Cpu).unwrap();
+        let tensors = HashMap::from([("embeddings".to_string(), matrix.clone())]);
+        let model = save(tensors.clone(), false).unwrap();
+        assert_eq!(dim, model.dim());
+
+        // The mean of the token rows, without [CLS], [SEP] or unknown tokens
+        let ids = model.tokenizer.encode("hello world", false).unwrap();
+        let ids = ids.get_ids();
+        let expected: Vec<f32> = (0..dim)
+            .map(|d| {
+                ids.iter()
+                    .map(|&id| embeddings[id as usize * dim + d])
+                    .sum::<f32>()
+                    / 2.0
+            })
+            .collect();
+        assert_eq!(2, ids.len());
+        assert_eq!(expected, model.embed("hello world").unwrap());
+        assert_eq!(
+            model.embed("hello world").unwrap(),
+            model.embed("hello world \u{1F980}").unwrap()
+        );
+        let batch = model.embed_batch(&["hello world", "goodbye"]).unwrap();
+        assert_eq!(expected, batch[0]);
+        assert_eq!(model.embed("goodbye").unwrap(), batch[1]);
+        assert!(matches!(model.embed(" "), Err(Error::EmptyInput)));
+
+        let normalized = save(tensors.clone(), true).unwrap();
+        let unit = normalized.embed("hello world").unwrap();
+        assert!((unit.iter().map(|x| x * x).sum::<f32>() - 1.0).abs() < 1e-5);
+        assert!(cosine_similarity(&unit, &expected).unwrap() > 0.9999);
+
+        // Per-token weights scale rows before averaging; a mapping shares rows between tokens
+        let mut weighted = tensors.clone();
+        let weights: Vec<f32> = (0..vocab)
+            .map(|id| if id == ids[0] as usize { 3.0 } else { 1.0 })
+            .collect();
+        weighted.insert(
+            "weights".to_string(),
+            Tensor::new(weights, &Device::Cpu).unwrap(),
+        );
+        let embedding = save(weighted, false).unwrap().embed("hello world").unwrap();
+        let row = |id: u32| &embeddings[id as usize * dim..(id as usize + 1) * dim];
+        for (d, x) in embedding.iter().enumerate() {
+            let expected = (3.0 * row(ids[0])[d] + row(ids[1])[d]) / 2.0;
+            assert!((x - expected).abs() < 1e-6);
+        }
+        let mut mapped = tensors.clone();
+        let mapping: Vec<i64> = (0..vocab as i64)
+            .map(|id| {
+                if id == i64::from(ids[1]) {
+                    i64::from(ids[0])
+                } else {
+                    id
+                }
+            })
+            .collect();
+        mapped.insert(
+            "mapping".to_string(),
+            Tensor::new(mapping, &Device::Cpu).unwrap(),
+        );
+        assert_eq!(
+            row(ids[0]),
+            save(mapped, false).unwrap().embed("hello world").unwrap()
+        );
+
+        let short = matrix.narrow(0, 0, 100).unwrap();
+        assert!(save(HashMap::from([("embeddings".to_string(), short)]), false).is_err());
+        assert!(save(HashMap::from([("other".to_string(), matrix)]), false).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,204 @@
+use crate::Instant;
+use serde::Serialize;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+/// A stage of an embedding call whose latency is tracked process-wide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Phase {
+    Tokenize,
+    Forward,
+    Pool,
+}
+
+/// Latency of one stage of embedding over every call since the statistics were last reset.
+///
+/// Percentiles come from a log-scaled histogram and are accurate to within 12.5%.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct PhaseStats {
+    pub count: u64,
+    pub total_us: u64,
+    pub p50_us: u64,
+    pub p90_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+}
+
+/// Counts and latencies of every embedding call in the process, from [`Stats::current`].
+///
+/// A batched forward pass counts once for the whole batch; tokenization and pooling count
+/// once per text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Stats {
+    /// Time covered, since the first model was loaded or [`Stats::reset`] was called.
+    pub elapsed_ms: u64,
+    pub tokenize: PhaseStats,
+    pub forward: PhaseStats,
+    pub pool: PhaseStats,
+}
+
+// Values below `LINEAR` microseconds get a bucket each; above that every power of two is
+// split into `SUB_BUCKETS` equal buckets.
+const LINEAR: u64 = 16;
+const SUB_BITS: u32 = 3;
+const SUB_BUCKETS: usize = 1 << SUB_BITS;
+const NUM_BUCKETS: usize = LINEAR as usize + (64 - 4) * SUB_BUCKETS;
+
+struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    total_us: u64,
+    max_us: u64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            buckets: vec![0; NUM_BUCKETS],
+            count: 0,
+            total_us: 0,
+            max_us: 0,
+        }
+    }
+
+    fn record(&mut self, us: u64) {
+        self.buckets[bucket(us)] += 1;
+        self.count += 1;
+        self.total_us = self.total_us.saturating_add(us);
+        self.max_us = self.max_us.max(us);
+    }
+
+    // The lower bound of the bucket holding the `q` quantile, capped at the maximum seen.
+    fn quantile(&self, q: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = ((q * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return bucket_start(index).min(self.max_us);
+            }
+        }
+        self.max_us
+    }
+
+    fn summary(&self) -> PhaseStats {
+        PhaseStats {
+            count: self.count,
+            total_us: self.total_us,
+            p50_us: self.quantile(0.5),
+            p90_us: self.quantile(0.9),
+            p99_us: self.quantile(0.99),
+            max_us: self.max_us,
+        }
+    }
+}
+
+fn bucket(us: u64) -> usize {
+    if us < LINEAR {
+        return us as usize;
+    }
+    let exp = 63 - us.leading_zeros();
+    let sub = (us >> (exp - SUB_BITS)) as usize & (SUB_BUCKETS - 1);
+    LINEAR as usize + (exp as usize - 4) * SUB_BUCKETS + sub
+}
+
+fn bucket_start(index: usize) -> u64 {
+    if index < LINEAR as usize {
+        return index as u64;
+    }
+    let exp = (index - LINEAR as usize) / SUB_BUCKETS + 4;
+    let sub = ((index - LINEAR as usize) % SUB_BUCKETS) as u64;
+    (SUB_BUCKETS as u64 + sub) << (exp as u32 - SUB_BITS)
+}
+
+struct Collector {
+    since: Instant,
+    tokenize: Histogram,
+    forward: Histogram,
+    pool: Histogram,
+}
+
+impl Collector {
+    fn new() -> Self {
+        Collector {
+            since: Instant::now(),
+            tokenize: Histogram::new(),
+            forward: Histogram::new(),
+            pool: Histogram::new(),
+        }
+    }
+}
+
+static COLLECTOR: LazyLock<Mutex<Collector>> = LazyLock::new(|| Mutex::new(Collector::new()));
+
+pub(crate) fn record(phase: Phase, elapsed: Duration) {
+    let us = elapsed.as_micros().min(u64::MAX as u128) as u64;
+    let mut collector = COLLECTOR.lock().unwrap();
+    match phase {
+        Phase::Tokenize => collector.tokenize.record(us),
+        Phase::Forward => collector.forward.record(us),
+        Phase::Pool => collector.pool.record(us),
+    }
+}
+
+/// Start counting from now, e.g. when a model is first loaded. Later calls do nothing.
+pub(crate) fn start() {
+    LazyLock::force(&COLLECTOR);
+}
+
+impl Stats {
+    /// Embedding throughput and latency since the first model was loaded or the last
+    /// [`Stats::reset`].
+    pub fn current() -> Self {
+        let collector = COLLECTOR.lock().unwrap();
+        Stats {
+            elapsed_ms: collector.since.elapsed().as_millis() as u64,
+            tokenize: collector.tokenize.summary(),
+            forward: collector.forward.summary(),
+            pool: collector.pool.summary(),
+        }
+    }
+
+    /// Clear every count and start measuring again from now.
+    pub fn reset() {
+        *COLLECTOR.lock().unwrap() = Collector::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_percentiles() {
+        for us in [0, 1, 15, 16, 17, 100, 1_000, 123_456, u64::MAX] {
+            let index = bucket(us);
+            assert!(index < NUM_BUCKETS);
+            assert!(bucket_start(index) <= us);
+            assert!(us - bucket_start(index) <= us / 8, "{us}");
+        }
+
+        let mut histogram = Histogram::new();
+        assert_eq!(PhaseStats::default(), histogram.summary());
+        for us in 1..=1000 {
+            histogram.record(us);
+        }
+        let summary = histogram.summary();
+        assert_eq!(1000, summary.count);
+        assert_eq!(500_500, summary.total_us);
+        assert_eq!(1000, summary.max_us);
+        for (p, expected) in [
+            (summary.p50_us, 500),
+            (summary.p90_us, 900),
+            (summary.p99_us, 990),
+        ] {
+            assert!(
+                p <= expected && p >= expected - expected / 8,
+                "{p} vs {expected}"
+            );
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use crate::bert::{BertModel, Config, DTYPE};
+use crate::embedder::load_weights;
+use crate::error::{Error, Result};
+use crate::options::Pooling;
+use candle::{DType, Device, Tensor, D};
+use candle_nn::{AdamW, Optimizer, ParamsAdamW, VarBuilder, VarMap};
+use serde::Deserialize;
+use std::path::Path;
+use tokenizers::Tokenizer;
+
+/// A training example: a query, a text that should embed close to it and one that should not.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Triplet {
+    pub query: String,
+    pub positive: String,
+    pub negative: String,
+}
+
+/// Settings of a [`Trainer`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrainConfig {
+    pub learning_rate: f64,
+    pub weight_decay: f64,
+    /// Triplets per optimizer step. Every query is contrasted with all the positives and
+    /// negatives of its batch, so larger batches give a stronger signal.
+    pub batch_size: usize,
+    pub epochs: usize,
+    /// Factor applied to the cosine similarities before the softmax, the inverse of the
+    /// temperature.
+    pub scale: f64,
+    /// Must match how the fine-tuned model will be embedded with.
+    pub pooling: Pooling,
+}
+
+impl Default for TrainConfig {
+    fn default() -> Self {
+        TrainConfig {
+            learning_rate: 2e-5,
+            weight_decay: 0.01,
+            batch_size: 16,
+            epochs: 1,
+            scale: 20.0,
+            pooling: Pooling::Mean,
+        }
+    }
+}
+
+/// Fine-tunes a model on `(query, positive, negative)` triplets, to adapt it to a domain on the
+/// device that will run it. Enabled by the `train` feature.
+///
+/// The loss is the cross entropy of picking each query's positive among every positive and
+/// negative of its batch (the multiple negatives ranking loss of sentence-transformers, with
+/// hard negatives). Gradients come from candle's autograd and the weights are updated with
+/// AdamW; [`Trainer::save`] writes them as safetensors for [`Embedder::load`](crate::Embedder::load)
+/// with the original config and tokenizer.
+pub struct Trainer {
+    model: BertModel,
+    weights: VarMap,
+    optimizer: AdamW,
+    tokenizer: Tokenizer,
+    pad_id: u32,
+    config: TrainConfig,
+}
+
+impl Trainer {
+    /// Load the files [`Embedder::load`](crate::Embedder::load) takes into trainable weights.
+    pub fn load(
+        config_path: impl AsRef<Path>,
+        tokenizer_path: impl AsRef<Path>,
+        weights_path: impl AsRef<Path>,
+        config: TrainConfig,
+    ) -> Result<Self> {
+        if config.batch_size == 0 || config.learning_rate <= 0.0 {
+            return Err(Error::InvalidArgument(
+                "training needs a batch size and a learning rate above 0".to_string(),
+            ));
+        }
+        let model_config: Config = serde_json::from_slice(&std::fs::read(config_path)?)?;
+        let device = Device::Cpu;
+
+        // Build the model on fresh variables, then fill them from the checkpoint
+        let weights = VarMap::new();
+        let model = BertModel::load(
+            VarBuilder::from_varmap(&weights, DTYPE, &device),
+            &model_config,
+        )?;
+        let checkpoint = load_weights(weights_path.as_ref(), &device)?;
+        for (name, var) in weights.data().lock().unwrap().iter() {
+            let shape = var.shape().clone();
+            let tensor = match checkpoint.get(shape.clone(), name) {
+                Ok(tensor) => tensor,
+                Err(err) => match &model_config.model_type {
+                    Some(model_type) => checkpoint
+                        .pp(model_type)
+                        .get(shape, name)
+                        .map_err(|_| err)?,
+                    None => return Err(err.into()),
+                },
+            };
+            var.set(&tensor)?;
+        }
+
+        let params = ParamsAdamW {
+            lr: config.learning_rate,
+            weight_decay: config.weight_decay,
+            ..ParamsAdamW::default()
+        };
+        let optimizer = AdamW::new(weights.all_vars(), params)?;
+        Ok(Trainer {
+            model,
+            weights,
+            optimizer,
+            tokenizer: Tokenizer::from_file(tokenizer_path)?,
+            pad_id: model_config.pad_token_id as u32,
+            config,
+        })
+    }
+
+    /// Run one optimizer step on `batch`, returning its loss before the update.
+    pub fn train_step(&mut self, batch: &[Triplet]) -> Result<f32> {
+        if batch.is_empty() {
+            return Err(Error::InvalidArgument("empty training batch".to_string()));
+        }
+        let queries: Vec<&str> = batch.iter().map(|t| t.query.as_str()).collect();
+        let candidates: Vec<&str> = batch
+            .iter()
+            .map(|t| t.positive.as_str())
+            .chain(batch.iter().map(|t| t.negative.as_str()))
+            .collect();
+        let queries = unit_rows(&self.embed_tensor(&queries)?)?;
+        let candidates = unit_rows(&self.embed_tensor(&candidates)?)?;
+
+        // Row `i` of the similarities should peak at column `i`, its own positive
+        let logits = (queries.matmul(&candidates.t()?)? * self.config.scale)?;
+        let labels = Tensor::arange(0, batch.len() as u32, &Device::Cpu)?;
+        let loss = candle_nn::loss::cross_entropy(&logits, &labels)?;
+        self.optimizer.backward_step(&loss)?;
+        Ok(loss.to_scalar::<f32>()?)
+    }
+
+    /// Train for the configured number of epochs over `triplets`, in order and in batches,
+    /// returning the mean loss of every epoch.
+    pub fn train(&mut self, triplets: &[Triplet]) -> Result<Vec<f32>> {
+        let batches = triplets.len().div_ceil(self.config.batch_size);
+        (0..self.config.epochs)
+            .map(|epoch| {
+                let mut total = 0.0;
+                for batch in triplets.chunks(self.config.batch_size) {
+                    total += self.train_step(batch)?;
+                }
+                let loss = total / batches.max(1) as f32;
+                tracing::info!(epoch, loss, "trained an epoch");
+                Ok(loss)
+            })
+            .collect()
+    }
+
+    /// The current model's embedding of `text`, as the fine-tuned model will embed it.
+    pub fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let embedding = self.embed_tensor(&[text])?.detach()?;
+        Ok(embedding.squeeze(0)?.to_vec1::<f32>()?)
+    }
+
+    /// Write the current weights as a safetensors file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        Ok(self.weights.save(path)?)
+    }
+
+    // The pooled embeddings of `texts`, `(texts, dim)`, tracked for gradients. Each text is
+    // tokenized on its own, truncated and padded as the tokenizer file says, and pooled over
+    // all of its tokens like `Embedder::embed` does; only the padding to the batch's longest
+    // text is masked.
+    fn embed_tensor(&self, texts: &[&str]) -> Result<Tensor> {
+        let encodings = texts
+            .iter()
+            .map(|text| self.tokenizer.encode(*text, true))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let max_len = encodings.iter().map(|e| e.len()).max().unwrap_or(0);
+        let mut ids = Vec::with_capacity(texts.len() * max_len);
+        let mut mask = Vec::with_capacity(texts.len() * max_len);
+        for encoding in &encodings {
+            let padding = max_len - encoding.len();
+            ids.extend(encoding.get_ids().iter().copied());
+            ids.extend(std::iter::repeat_n(self.pad_id, padding));
+            mask.extend(std::iter::repeat_n(1u32, encoding.len()));
+            mask.extend(std::iter::repeat_n(0u32, padding));
+        }
+        let shape = (texts.len(), max_len);
+        let ids = Tensor::from_vec(ids, shape, &Device::Cpu)?;
+        let mask = Tensor::from_vec(mask, shape, &Device::Cpu)?;
+        let hidden = self.model.forward(&ids, &ids.zeros_like()?, Some(&mask))?;
+
+        let mask = mask.to_dtype(DTYPE)?.unsqueeze(2)?;
+        Ok(match self.config.pooling {
+            Pooling::Mean => hidden
+                .broadcast_mul(&mask)?
+                .sum(1)?
+                .broadcast_div(&mask.sum(1)?)?,
+            Pooling::Cls => hidden.narrow(1, 0, 1)?.squeeze(1)?,
+            // Padding is pushed far below every real value before taking the maximum
+            Pooling::Max => hidden.broadcast_add(&((mask - 1.0)? * 1e4)?)?.max(1)?,
+        })
+    }
+}
+
+// Scale every row to unit L2 norm.
+fn unit_rows(xs: &Tensor) -> Result<Tensor> {
+    let norms = xs.sqr()?.sum_keepdim(D::Minus1)?.sqrt()?;
+    Ok(xs.broadcast_div(&(norms + 1e-12)?.to_dtype(DType::F32)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::similarity::cosine_similarity;
+    use crate::Embedder;
+
+    #[test]
+    fn test_contrastive_training() {
+        let triplet = |query: &str, positive: &str, negative: &str| Triplet {
+            query: query.to_string(),
+            positive: positive.to_string(),
+            negative: negative.to_string(),
+        };
+        let triplets = [
+            triplet("zorblax", "a red fruit", "a large mammal"),
+            triplet("quindle", "a large mammal", "a red fruit"),
+        ];
+        let config = TrainConfig {
+            learning_rate: 5e-5,
+            batch_size: 2,
+            epochs: 4,
+            ..TrainConfig::default()
+        };
+        let mut trainer = Trainer::load(
+            "models/gte-small/config.json",
+            "models/gte-small/tokenizer.json",
+            "models/gte-small/model.safetensors",
+            config,
+        )
+        .unwrap();
+
+        let base = Embedder::load(
+            "models/gte-small/config.json",
+            "models/gte-small/tokenizer.json",
+            "models/gte-small/model.safetensors",
+            false,
+        )
+        .unwrap();
+        let before = trainer.embed("a red fruit").unwrap();
+        let expected = base.embed("a red fruit").unwrap();
+        assert!(cosine_similarity(&before, &expected).unwrap() > 0.9999);
+
+        let losses = trainer.train(&triplets).unwrap();
+        assert_eq!(4, losses.len());
+        assert!(losses[3] < losses[0], "{losses:?}");
+        assert!(trainer.train_step(&[]).is_err());
+
+        // The saved weights embed like the trainer, and have moved the made-up words
+        let path = std::env::temp_dir().join(format!("trained-{}.safetensors", std::process::id()));
+        trainer.save(&path).unwrap();
+        let tuned = Embedder::load(
+            "models/gte-small/config.json",
+            "models/gte-small/tokenizer.json",
+            &path,
+            false,
+        )
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let similarity = |embedder: &Embedder, a: &str, b: &str| {
+            cosine_similarity(&embedder.embed(a).unwrap(), &embedder.embed(b).unwrap()).unwrap()
+        };
+        let after = trainer.embed("a red fruit").unwrap();
+        let loaded = tuned.embed("a red fruit").unwrap();
+        assert!(cosine_similarity(&after, &loaded).unwrap() > 0.9999);
+        assert!(
+            similarity(&tuned, "zorblax", "a red fruit")
+                - similarity(&tuned, "zorblax", "a large mammal")
+                > similarity(&base, "zorblax", "a red fruit")
+                    - similarity(&base, "zorblax", "a large mammal")
+        );
+    }
+}
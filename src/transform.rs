@@ -0,0 +1,121 @@
+use crate::embedder::normalize;
+use crate::error::{Error, Result};
+use crate::kernels::dot;
+use crate::pca::Pca;
+use serde::Deserialize;
+use std::fmt;
+use std::sync::Arc;
+
+/// An in-place transformation of an embedding; it must not change the length.
+pub type TransformFn = Arc<dyn Fn(&mut [f32]) + Send + Sync>;
+
+/// A step of the post-processing chain run on every embedding before it is returned.
+///
+/// Over FFI the chain is given as JSON, e.g.
+/// `["normalize", {"project": {"matrix": [...], "out_dim": 64}}, "quantize_i8"]`.
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transform {
+    /// Scale to unit L2 norm.
+    Normalize,
+    /// Multiply by a row-major `out_dim x input_dim` matrix.
+    Project { matrix: Vec<f32>, out_dim: usize },
+    /// Round every value to one of 255 symmetric levels spanning the vector's largest
+    /// magnitude, i.e. what survives storing the vector as int8 with a per-vector scale.
+    QuantizeI8,
+    /// Reduce to the components of a fitted [`Pca`].
+    #[serde(skip)]
+    Pca(Arc<Pca>),
+    #[serde(skip)]
+    Custom(TransformFn),
+}
+
+impl fmt::Debug for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transform::Normalize => write!(f, "Normalize"),
+            Transform::Project { out_dim, .. } => write!(f, "Project {{ out_dim: {out_dim} }}"),
+            Transform::QuantizeI8 => write!(f, "QuantizeI8"),
+            Transform::Pca(pca) => write!(f, "Pca {{ out_dim: {} }}", pca.output_dim()),
+            Transform::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+impl Transform {
+    pub fn chain_from_json(json: &str) -> Result<Vec<Transform>> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn apply(&self, embedding: &mut Vec<f32>) -> Result<()> {
+        match self {
+            Transform::Normalize => normalize(embedding),
+            Transform::Project { matrix, out_dim } => {
+                let in_dim = embedding.len();
+                if matrix.len() != out_dim * in_dim {
+                    return Err(Error::InvalidArgument(format!(
+                        "projection matrix has {} values, expected {out_dim} x {in_dim}",
+                        matrix.len()
+                    )));
+                }
+                *embedding = matrix
+                    .chunks_exact(in_dim)
+                    .map(|row| dot(row, embedding))
+                    .collect();
+            }
+            Transform::QuantizeI8 => {
+                let max = embedding.iter().fold(0f32, |max, x| max.max(x.abs()));
+                if max > 0.0 {
+                    let scale = max / 127.0;
+                    embedding
+                        .iter_mut()
+                        .for_each(|x| *x = (*x / scale).round() * scale);
+                }
+            }
+            Transform::Pca(pca) => *embedding = pca.transform(embedding)?,
+            Transform::Custom(f) => f(embedding),
+        }
+        Ok(())
+    }
+}
+
+impl From<Pca> for Transform {
+    fn from(pca: Pca) -> Self {
+        Transform::Pca(Arc::new(pca))
+    }
+}
+
+pub(crate) fn apply_all(transforms: &[Transform], embedding: &mut Vec<f32>) -> Result<()> {
+    transforms
+        .iter()
+        .try_for_each(|transform| transform.apply(embedding))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transform_chain() {
+        let chain = Transform::chain_from_json(
+            r#"["normalize", {"project": {"matrix": [1, 0, 0, 0, 0, 1], "out_dim": 2}}]"#,
+        )
+        .unwrap();
+        let mut embedding = vec![3.0, 0.0, 4.0];
+        apply_all(&chain, &mut embedding).unwrap();
+        assert_eq!(vec![0.6, 0.8], embedding);
+
+        let mut embedding = vec![1.0, 0.5, -0.25];
+        Transform::QuantizeI8.apply(&mut embedding).unwrap();
+        assert_eq!(1.0, embedding[0]);
+        assert!((embedding[1] - 64.0 / 127.0).abs() < 1e-6);
+
+        let mut embedding = vec![1.0, 2.0];
+        assert!(Transform::Project {
+            matrix: vec![1.0],
+            out_dim: 1
+        }
+        .apply(&mut embedding)
+        .is_err());
+    }
+}
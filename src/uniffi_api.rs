@@ -0,0 +1,104 @@
+use crate::embedder::Embedder;
+use crate::error::Error;
+use crate::options::{EmbedOptions, Embedding};
+use std::fmt;
+use std::sync::Arc;
+
+/// Why a call from Swift or Kotlin failed; the message is the crate error's.
+#[derive(Debug, uniffi::Error)]
+#[uniffi(flat_error)]
+pub enum EmbedError {
+    Io(String),
+    InvalidArgument(String),
+    Model(String),
+}
+
+impl fmt::Display for EmbedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmbedError::Io(msg) | EmbedError::InvalidArgument(msg) | EmbedError::Model(msg) => {
+                write!(f, "{msg}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EmbedError {}
+
+impl From<Error> for EmbedError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(_) => EmbedError::Io(e.to_string()),
+            Error::Json(_)
+            | Error::Csv(_)
+            | Error::InvalidLayer(_)
+            | Error::InvalidArgument(_)
+            | Error::EmptyInput => EmbedError::InvalidArgument(e.to_string()),
+            _ => EmbedError::Model(e.to_string()),
+        }
+    }
+}
+
+/// A loaded model as a UniFFI object: Swift and Kotlin hold a reference-counted handle and
+/// the model is freed when the last one goes away.
+#[derive(uniffi::Object)]
+pub struct EmbeddingModel {
+    embedder: Embedder,
+}
+
+#[uniffi::export]
+impl EmbeddingModel {
+    #[uniffi::constructor]
+    pub fn new(
+        config_path: String,
+        tokenizer_path: String,
+        weights_path: String,
+        approximate_gelu: bool,
+    ) -> Result<Arc<Self>, EmbedError> {
+        let embedder = Embedder::load(config_path, tokenizer_path, weights_path, approximate_gelu)?;
+        Ok(Arc::new(EmbeddingModel { embedder }))
+    }
+
+    /// Load from the contents of the config, tokenizer and safetensors files, e.g. app bundle
+    /// resources read into memory.
+    #[uniffi::constructor]
+    pub fn from_buffers(
+        config: Vec<u8>,
+        tokenizer: Vec<u8>,
+        weights: Vec<u8>,
+        approximate_gelu: bool,
+    ) -> Result<Arc<Self>, EmbedError> {
+        let embedder = Embedder::from_buffers(&config, &tokenizer, weights, approximate_gelu)?;
+        Ok(Arc::new(EmbeddingModel { embedder }))
+    }
+
+    /// Length of the default embedding.
+    pub fn dim(&self) -> u32 {
+        self.embedder.embedding_dim(Default::default()) as u32
+    }
+
+    pub fn count_tokens(&self, text: String) -> Result<u32, EmbedError> {
+        Ok(self.embedder.count_tokens(&text, true)? as u32)
+    }
+
+    pub fn embed(&self, text: String) -> Result<Vec<f32>, EmbedError> {
+        Ok(self.embedder.embed(&text)?)
+    }
+
+    /// [`EmbeddingModel::embed`] with the JSON embed options, e.g. `{"normalize": true}`.
+    pub fn embed_with_options(
+        &self,
+        text: String,
+        options: String,
+    ) -> Result<Vec<f32>, EmbedError> {
+        let options = EmbedOptions::from_json(&options).map_err(Error::from)?;
+        Ok(self.embedder.embed_with_options(&text, &options)?.to_f32())
+    }
+
+    pub fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, EmbedError> {
+        let embeddings = self
+            .embedder
+            .embed_batch(&texts, &EmbedOptions::default())?;
+        Ok(embeddings.iter().map(Embedding::to_f32).collect())
+    }
+}
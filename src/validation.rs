@@ -0,0 +1,277 @@
+use crate::bert::Config;
+use crate::embedder::{is_pytorch, pytorch_name, safetensors_shards};
+use crate::quantize::is_gguf;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use tokenizers::Tokenizer;
+
+/// Which of the files given to [`check_model`] a [`Diagnostic`] is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelFile {
+    Config,
+    Tokenizer,
+    Weights,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// The model would fail to load.
+    Error,
+    /// The model loads, but probably not as intended.
+    Warning,
+}
+
+/// One problem found by [`check_model`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: ModelFile,
+    pub message: String,
+}
+
+/// Everything [`check_model`] found; `valid` is false if any diagnostic is an error.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ModelReport {
+    pub valid: bool,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl ModelReport {
+    fn push(&mut self, severity: Severity, file: ModelFile, message: String) {
+        self.valid &= severity != Severity::Error;
+        self.diagnostics.push(Diagnostic {
+            severity,
+            file,
+            message,
+        });
+    }
+}
+
+/// Check the files [`Embedder::load`](crate::Embedder::load) takes without loading the model:
+/// that they exist, the config parses, the tokenizer's ids fit the config's vocabulary, and
+/// the weights hold every tensor the config calls for with the expected shape.
+///
+/// Every problem is reported rather than the first one, and only the headers of the weights
+/// are read, so this is cheap enough to run before offering a model to the user.
+pub fn check_model(
+    config_path: impl AsRef<Path>,
+    tokenizer_path: impl AsRef<Path>,
+    weights_path: impl AsRef<Path>,
+) -> ModelReport {
+    let mut report = ModelReport {
+        valid: true,
+        diagnostics: Vec::new(),
+    };
+    let config = check_config(config_path.as_ref(), &mut report);
+    check_tokenizer(tokenizer_path.as_ref(), config.as_ref(), &mut report);
+    check_weights(weights_path.as_ref(), config.as_ref(), &mut report);
+    report
+}
+
+fn check_config(path: &Path, report: &mut ModelReport) -> Option<Config> {
+    let mut error = |message| report.push(Severity::Error, ModelFile::Config, message);
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            error(format!("{}: {e}", path.display()));
+            return None;
+        }
+    };
+    let config: Config = match serde_json::from_slice(&contents) {
+        Ok(config) => config,
+        Err(e) => {
+            error(format!("{} is not a BERT config: {e}", path.display()));
+            return None;
+        }
+    };
+    if config.num_attention_heads == 0
+        || !config
+            .hidden_size
+            .is_multiple_of(config.num_attention_heads)
+    {
+        error(format!(
+            "hidden_size {} is not a multiple of num_attention_heads {}",
+            config.hidden_size, config.num_attention_heads
+        ));
+    }
+    Some(config)
+}
+
+fn check_tokenizer(path: &Path, config: Option<&Config>, report: &mut ModelReport) {
+    let mut error = |message| report.push(Severity::Error, ModelFile::Tokenizer, message);
+    if let Err(e) = std::fs::metadata(path) {
+        return error(format!("{}: {e}", path.display()));
+    }
+    let tokenizer = match Tokenizer::from_file(path) {
+        Ok(tokenizer) => tokenizer,
+        Err(e) => return error(format!("{} is not a tokenizer: {e}", path.display())),
+    };
+    let Some(config) = config else {
+        return;
+    };
+    // Ids past the embedding table fail at inference, on the first text that uses them
+    let max_id = tokenizer.get_vocab(true).into_values().max().unwrap_or(0);
+    if max_id as usize >= config.vocab_size {
+        error(format!(
+            "token ids go up to {max_id} but the config's vocab_size is {}",
+            config.vocab_size
+        ));
+    }
+}
+
+fn check_weights(path: &Path, config: Option<&Config>, report: &mut ModelReport) {
+    let tensors = match tensor_shapes(path) {
+        Ok(tensors) => tensors,
+        Err(message) => return report.push(Severity::Error, ModelFile::Weights, message),
+    };
+    let Some(config) = config else {
+        return;
+    };
+    // The same fallback as `BertModel::load`, for checkpoints exported from a task head
+    let prefix = match &config.model_type {
+        Some(model_type) if !tensors.contains_key("embeddings.word_embeddings.weight") => {
+            format!("{model_type}.")
+        }
+        _ => String::new(),
+    };
+    for (name, expected) in config.tensor_shapes() {
+        let name = format!("{prefix}{name}");
+        let message = match tensors.get(&name) {
+            None => format!("missing tensor {name}"),
+            Some(shape) if *shape != expected => {
+                format!("{name} has shape {shape:?}, the config expects {expected:?}")
+            }
+            Some(_) => continue,
+        };
+        report.push(Severity::Error, ModelFile::Weights, message);
+    }
+    let extra_layer = format!("{prefix}encoder.layer.{}.", config.num_hidden_layers);
+    if tensors.keys().any(|name| name.starts_with(&extra_layer)) {
+        report.push(
+            Severity::Warning,
+            ModelFile::Weights,
+            format!(
+                "the weights have more than num_hidden_layers {} layers; the rest are ignored",
+                config.num_hidden_layers
+            ),
+        );
+    }
+}
+
+// An error message naming the file it is about.
+fn at(path: &Path) -> impl Fn(crate::Error) -> String + '_ {
+    move |e| format!("{}: {e}", path.display())
+}
+
+// The name and shape of every tensor in a checkpoint, from its headers only.
+fn tensor_shapes(path: &Path) -> Result<HashMap<String, Vec<usize>>, String> {
+    if is_pytorch(path) {
+        let tensors = candle::pickle::read_pth_tensor_info(path, false)
+            .map_err(crate::Error::from)
+            .map_err(at(path))?;
+        return Ok(tensors
+            .into_iter()
+            .map(|info| (pytorch_name(info.name), info.layout.shape().dims().to_vec()))
+            .collect());
+    }
+    if is_gguf(path) {
+        return gguf_header(path).map_err(at(path));
+    }
+    let mut shapes = HashMap::new();
+    for shard in safetensors_shards(path).map_err(at(path))? {
+        shapes.extend(safetensors_header(&shard).map_err(at(&shard))?);
+    }
+    Ok(shapes)
+}
+
+fn gguf_header(path: &Path) -> crate::Result<HashMap<String, Vec<usize>>> {
+    let content =
+        candle::quantized::gguf_file::Content::read(&mut BufReader::new(File::open(path)?))?;
+    Ok(content
+        .tensor_infos
+        .into_iter()
+        .map(|(name, info)| (name, info.shape.dims().to_vec()))
+        .collect())
+}
+
+fn safetensors_header(path: &Path) -> crate::Result<HashMap<String, Vec<usize>>> {
+    #[derive(Deserialize)]
+    struct TensorInfo {
+        shape: Vec<usize>,
+    }
+    let mut file = File::open(path)?;
+    let mut len = [0; 8];
+    file.read_exact(&mut len)?;
+    let len = u64::from_le_bytes(len);
+    if len > file.metadata()?.len().saturating_sub(8) {
+        return Err(crate::Error::InvalidArgument(
+            "not a safetensors file".to_string(),
+        ));
+    }
+    let mut header = vec![0; len as usize];
+    file.read_exact(&mut header)?;
+    let mut header: HashMap<String, serde_json::Value> = serde_json::from_slice(&header)?;
+    header.remove("__metadata__");
+    header
+        .into_iter()
+        .map(|(name, info)| Ok((name, serde_json::from_value::<TensorInfo>(info)?.shape)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_model() {
+        let report = check_model(
+            "models/gte-small/config.json",
+            "models/gte-small/tokenizer.json",
+            "models/gte-small/model.safetensors",
+        );
+        assert!(report.valid, "{report:?}");
+        assert!(report.diagnostics.is_empty());
+
+        // A config for a bigger model than the weights, and a missing tokenizer
+        let path = std::env::temp_dir().join(format!("config-{}.json", std::process::id()));
+        let mut config: serde_json::Value =
+            serde_json::from_slice(&std::fs::read("models/gte-small/config.json").unwrap())
+                .unwrap();
+        config["num_hidden_layers"] = 13.into();
+        config["intermediate_size"] = 2048.into();
+        std::fs::write(&path, config.to_string()).unwrap();
+        let report = check_model(
+            &path,
+            "models/gte-small/missing.json",
+            "models/gte-small/model.safetensors",
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert!(!report.valid);
+        let errors = |file| {
+            report
+                .diagnostics
+                .iter()
+                .filter(|d| d.file == file && d.severity == Severity::Error)
+                .count()
+        };
+        assert_eq!(1, errors(ModelFile::Tokenizer));
+        // Three tensors per layer have the wrong shape, and the 13th layer is missing
+        assert_eq!(12 * 3 + 16, errors(ModelFile::Weights));
+        assert!(report
+            .diagnostics
+            .iter()
+            .any(|d| d.message == "missing tensor encoder.layer.12.attention.self.query.weight"));
+
+        let report = check_model(
+            "models/gte-small/tokenizer.json",
+            "models/gte-small/tokenizer.json",
+            "models/gte-small/config.json",
+        );
+        assert_eq!(2, report.diagnostics.len(), "{report:?}");
+    }
+}
@@ -0,0 +1,63 @@
+use crate::embedder::Embedder;
+use crate::options::EmbedOptions;
+use wasm_bindgen::prelude::*;
+
+/// An embedder for the browser. `new Embedder(config, tokenizer, weights)` takes the bytes of
+/// `config.json`, `tokenizer.json` and `model.safetensors` as `Uint8Array`s, e.g.
+/// `new Uint8Array(await (await fetch(url)).arrayBuffer())`.
+#[wasm_bindgen(js_name = Embedder)]
+pub struct WasmEmbedder {
+    embedder: Embedder,
+}
+
+#[wasm_bindgen(js_class = Embedder)]
+impl WasmEmbedder {
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        config: &[u8],
+        tokenizer: &[u8],
+        weights: Vec<u8>,
+        approximate_gelu: Option<bool>,
+    ) -> Result<WasmEmbedder, JsError> {
+        let embedder = Embedder::from_buffers(
+            config,
+            tokenizer,
+            weights,
+            approximate_gelu.unwrap_or(false),
+        )?;
+        Ok(WasmEmbedder { embedder })
+    }
+
+    /// Length of the default embedding.
+    #[wasm_bindgen(getter)]
+    pub fn dim(&self) -> usize {
+        self.embedder.embedding_dim(Default::default())
+    }
+
+    #[wasm_bindgen(js_name = countTokens)]
+    pub fn count_tokens(&self, text: &str) -> Result<usize, JsError> {
+        Ok(self.embedder.count_tokens(text, true)?)
+    }
+
+    /// Embed one text as a `Float32Array`.
+    pub fn embed(&self, text: &str) -> Result<Vec<f32>, JsError> {
+        Ok(self.embedder.embed(text)?)
+    }
+
+    /// [`WasmEmbedder::embed`] with the JSON embed options, e.g. `'{"normalize": true}'`.
+    #[wasm_bindgen(js_name = embedWithOptions)]
+    pub fn embed_with_options(&self, text: &str, options: &str) -> Result<Vec<f32>, JsError> {
+        let options = EmbedOptions::from_json(options)?;
+        Ok(self.embedder.embed_with_options(text, &options)?.to_f32())
+    }
+
+    /// Embed several texts in one forward pass, as one `Float32Array` of `texts.length * dim`
+    /// values, row by row.
+    #[wasm_bindgen(js_name = embedBatch)]
+    pub fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<f32>, JsError> {
+        let embeddings = self
+            .embedder
+            .embed_batch(&texts, &EmbedOptions::default())?;
+        Ok(embeddings.iter().flat_map(|e| e.to_f32()).collect())
+    }
+}